@@ -1,38 +1,581 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::time::{interval, Duration};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
 
-use crate::diagnostics::{ServiceProfile, StreamDiagnostics};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{interval, sleep_until, timeout, Duration};
+
+use crate::diagnostics::{ServiceProfile, SharedSink, StderrSink};
 use crate::display;
-use crate::flv::audio::AudioAnalyzer;
-use crate::flv::video::VideoAnalyzer;
-use crate::rtmp::chunk::ChunkReader;
+use crate::log::{LogState, SharedCsv, SharedDump, SharedLog};
+use crate::metrics::{self, SharedMetrics, StreamMetrics};
+use crate::report::{ReportFormat, StreamReport};
+use crate::rtmp::chunk::{ChunkReader, RtmpMessage, TimestampMonotonicity, TimestampStep};
 use crate::rtmp::handshake;
-use crate::rtmp::message::{MessageHandler, RtmpEvent};
-use crate::stats::StreamStats;
+use crate::rtmp::message::{AllowedKeys, MessageHandler, NetStatus, RtmpEvent, ShortCommand};
+use crate::session::StreamSession;
+
+/// Per-connection settings taken from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    /// Emit an end-of-stream report in this format instead of running the TUI
+    pub report: Option<ReportFormat>,
+    /// Where to write the report (stdout when unset)
+    pub report_file: Option<PathBuf>,
+    /// Registry to publish live snapshots into for the /metrics endpoint
+    pub metrics: Option<SharedMetrics>,
+    /// File that diagnostic transitions are appended to
+    pub log: Option<SharedLog>,
+    /// Where command and data messages are dumped as text
+    pub dump_amf: Option<SharedDump>,
+    /// Time series that every stream appends a row to each second
+    pub csv: Option<SharedCsv>,
+    /// Service rules to check streams against
+    pub profile: ServiceProfile,
+    /// Per-app overrides of `profile`, matched against the connect app name
+    pub app_profiles: Vec<(String, ServiceProfile)>,
+    /// Override for the chunk reader's message size cap, in bytes
+    pub max_message_size: Option<usize>,
+    /// Tally NAL unit types in video frames
+    pub nal_stats: bool,
+    /// Print a status line per second instead of drawing the TUI
+    pub plain: bool,
+    /// Send a Ping Request this often and time the responses
+    pub ping_interval: Option<Duration>,
+    /// Drop the connection after receiving nothing for this long
+    pub idle_timeout: Option<Duration>,
+    /// Don't log connects, publishes, disconnects and raised diagnostics to stderr
+    pub quiet: bool,
+    /// Minimum time between diagnostic passes (500ms when unset)
+    pub check_interval: Option<Duration>,
+    /// Span of the rolling bitrate and fps windows (2s when unset)
+    pub stats_window: Option<Duration>,
+    /// Span of the fps window alone, overriding `stats_window`
+    pub fps_window: Option<Duration>,
+    /// Told about every diagnostic as it's raised on any stream
+    pub sinks: Vec<SharedSink>,
+    /// End the connection this long after its first stream started publishing
+    pub duration: Option<Duration>,
+    /// Read no faster than this many kilobits per second
+    pub max_ingress_kbps: Option<u64>,
+    /// Stream keys allowed to publish; None allows every key
+    pub allowed_keys: Option<AllowedKeys>,
+    /// How often the TUI or status lines are redrawn (1s when unset)
+    pub refresh: Option<Duration>,
+}
+
+impl ConnectionOptions {
+    pub fn profile_for(&self, app_name: &str) -> ServiceProfile {
+        self.app_profiles
+            .iter()
+            .find(|(app, _)| app == app_name)
+            .map_or(&self.profile, |(_, profile)| profile)
+            .clone()
+    }
+
+    /// The TUI owns stdout, so it's disabled whenever a report goes there.
+    pub fn tui_enabled(&self) -> bool {
+        self.report.is_none() && !self.plain
+    }
+
+    /// Status lines share stdout with nothing but a report written to a file.
+    pub fn status_lines_enabled(&self) -> bool {
+        self.plain && (self.report.is_none() || self.report_file.is_some())
+    }
+}
+
+/// Where a client connected from, as shown in log lines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix socket clients are unnamed, so they're told apart by the order
+    /// they connected in
+    Unix { path: PathBuf, connection: u64 },
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix { path, connection } => write!(f, "unix:{}#{}", path.display(), connection),
+        }
+    }
+}
+
+/// How the streams on a connection fared in the final check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionOutcome {
+    /// Streams published, ended ones included
+    pub streams: usize,
+    /// Error diagnostics across those streams
+    pub errors: usize,
+    /// Warning diagnostics across those streams
+    pub warnings: usize,
+}
+
+impl ConnectionOutcome {
+    /// Process exit status for this outcome: 0 if healthy, 1 if there were
+    /// only warnings, 2 if there were errors.
+    pub fn exit_code(&self) -> i32 {
+        if self.errors > 0 {
+            2
+        } else if self.warnings > 0 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Why a connection ended, as given in its summary line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisconnectReason {
+    /// The client closed the connection
+    Eof,
+    ReadError,
+    WriteError,
+    HandshakeFailed,
+    IdleTimeout,
+    PingTimeout,
+    /// The stream key isn't in the keys file
+    Rejected,
+    /// --duration elapsed
+    Duration,
+    /// Disconnected from the TUI
+    Keyboard,
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisconnectReason::Eof => "eof",
+            DisconnectReason::ReadError => "read_error",
+            DisconnectReason::WriteError => "write_error",
+            DisconnectReason::HandshakeFailed => "handshake_failed",
+            DisconnectReason::IdleTimeout => "idle_timeout",
+            DisconnectReason::PingTimeout => "ping_timeout",
+            DisconnectReason::Rejected => "rejected",
+            DisconnectReason::Duration => "duration",
+            DisconnectReason::Keyboard => "keyboard",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Token bucket pacing reads to a byte rate, so a capped connection pushes
+/// back on the encoder through TCP flow control.
+struct IngressThrottle {
+    bytes_per_sec: f64,
+    /// Bytes that may be read now; negative after a read overdrew it
+    allowance: f64,
+    last_refill: Instant,
+}
+
+impl IngressThrottle {
+    fn new(kbps: u64, now: Instant) -> Self {
+        let bytes_per_sec = kbps as f64 * 1000.0 / 8.0;
+        Self { bytes_per_sec, allowance: 0.0, last_refill: now }
+    }
+
+    /// Bursts are limited to a tenth of a second's worth
+    fn burst(&self) -> f64 {
+        self.bytes_per_sec / 10.0
+    }
+
+    /// How many bytes the next read may take, at most `buf_len`.
+    fn read_len(&self, buf_len: usize) -> usize {
+        (self.burst() as usize).clamp(1, buf_len)
+    }
+
+    /// Account for `bytes` read at `now`, returning when reading may resume
+    /// if the rate was exceeded.
+    fn consume(&mut self, bytes: usize, now: Instant) -> Option<Instant> {
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.bytes_per_sec;
+        self.allowance = (self.allowance + refill).min(self.burst()) - bytes as f64;
+        self.last_refill = now;
+        (self.allowance < 0.0).then(|| now + Duration::from_secs_f64(-self.allowance / self.bytes_per_sec))
+    }
+}
+
+/// Unanswered pings in a row after which the encoder is considered gone
+const MAX_MISSED_PINGS: u32 = 3;
+
+/// Seconds each stream stays on screen when several share a connection
+const STREAM_CYCLE_TICKS: u64 = 5;
+
+/// One stream published on the connection, keyed by its message stream id.
+pub(crate) struct PublishedStream {
+    pub(crate) stream_id: u32,
+    pub(crate) stream_key: String,
+    pub(crate) session: StreamSession,
+    /// Cleared once the stream is unpublished; it's kept for the report
+    pub(crate) active: bool,
+    metrics_id: u64,
+    metrics: StreamMetrics,
+    pub(crate) log_state: LogState,
+}
+
+impl PublishedStream {
+    /// Stop updating the stream and retire its metrics and log entries.
+    pub(crate) fn end(&mut self, app_name: &str, options: &ConnectionOptions) {
+        self.active = false;
+        if let Some(registry) = &options.metrics
+            && let Ok(mut registry) = registry.lock()
+        {
+            registry.remove(self.metrics_id);
+        }
+        // Whatever was still active goes away with the stream
+        if let Some(log) = &options.log
+            && let Ok(mut log) = log.lock()
+        {
+            log.record(app_name, &self.stream_key, &mut self.log_state, &[]);
+        }
+    }
+}
+
+/// The active stream media on `stream_id` belongs to. Clients that publish
+/// a single stream sometimes send media on another message stream id, so a
+/// lone active stream takes everything.
+fn stream_for(streams: &mut [PublishedStream], stream_id: u32) -> Option<&mut PublishedStream> {
+    let pos = streams.iter().position(|s| s.active && s.stream_id == stream_id).or_else(|| {
+        let mut active = streams.iter().enumerate().filter(|(_, s)| s.active);
+        let (only, _) = active.next()?;
+        if active.next().is_some() { None } else { Some(only) }
+    })?;
+    Some(&mut streams[pos])
+}
+
+/// The streams published on a connection, with what concerns the connection
+/// rather than any one stream: every stream, published before or after,
+/// carries its protocol problems. Live connections and `--replay` both apply
+/// the handler's events through this, and differ only in what they print,
+/// draw and time around it.
+pub(crate) struct ConnectionState {
+    pub(crate) streams: Vec<PublishedStream>,
+    timestamps: TimestampMonotonicity,
+    profile: ServiceProfile,
+    /// When the handshake started and completed; a replay has none
+    handshake: Option<(Instant, Instant)>,
+    /// When connect arrived, with the objectEncoding requested and replied
+    connected: Option<(Instant, Option<f64>, u8)>,
+    invalid_chunk_sizes: Vec<u32>,
+    rejected_messages: Vec<(u8, u32)>,
+    net_status: Vec<NetStatus>,
+    short_commands: Vec<ShortCommand>,
+}
+
+impl ConnectionState {
+    pub(crate) fn new(options: &ConnectionOptions, handshake: Option<(Instant, Instant)>) -> Self {
+        Self {
+            streams: Vec::new(),
+            timestamps: TimestampMonotonicity::new(),
+            profile: options.profile.clone(),
+            handshake,
+            connected: None,
+            invalid_chunk_sizes: Vec::new(),
+            rejected_messages: Vec::new(),
+            net_status: Vec::new(),
+            short_commands: Vec::new(),
+        }
+    }
+
+    /// The chunk reader skipped an oversized message.
+    pub(crate) fn record_rejected_message(&mut self, type_id: u8, length: u32) {
+        self.rejected_messages.push((type_id, length));
+        for s in self.streams.iter_mut().filter(|s| s.active) {
+            s.session.diagnostics.record_rejected_message(type_id, length);
+        }
+    }
+
+    /// Check `msg`'s timestamp against the last of its kind, charging a step
+    /// back to the stream it belongs to.
+    pub(crate) fn check_timestamp(&mut self, msg: &RtmpMessage) {
+        if let TimestampStep::Regression { previous } = self.timestamps.check(msg)
+            && let Some(s) = stream_for(&mut self.streams, msg.stream_id)
+        {
+            trace_event!(warn, cs_id = msg.chunk_stream_id, previous, timestamp = msg.timestamp, "chunk stream timestamp went backwards");
+            s.session.diagnostics.record_chunk_timestamp_regression(msg.chunk_stream_id, previous, msg.timestamp);
+        }
+    }
+
+    /// Apply `event` to the streams it concerns. `clock` is the media clock
+    /// of a replay; live connections pass None to go by the wall clock.
+    pub(crate) fn apply(&mut self, event: &RtmpEvent, app_name: &str, options: &ConnectionOptions, clock: Option<Instant>) {
+        let now = clock.unwrap_or_else(Instant::now);
+        match event {
+            RtmpEvent::Connected { app_name, requested_encoding, object_encoding } => {
+                self.profile = options.profile_for(app_name);
+                self.connected = Some((now, *requested_encoding, *object_encoding));
+            }
+            RtmpEvent::Publishing { stream_key, stream_id, .. } => {
+                trace_event!(info, app = app_name, stream_key = stream_key.as_str(), stream_id, "publishing");
+                // Republishing on the same id starts over
+                if let Some(old) = self.streams.iter_mut().find(|s| s.active && s.stream_id == *stream_id) {
+                    old.end(app_name, options);
+                }
+                self.timestamps.reset_stream(*stream_id);
+                // A restarted stream gets a fresh session and clock; only
+                // what concerns the connection carries over. The ended one
+                // stays behind for the report
+                let mut session = StreamSession::new(options);
+                session.diagnostics.set_profile(self.profile.clone());
+                if let Some(clock) = clock {
+                    session.set_clock(clock);
+                }
+                if let Some((started, completed)) = self.handshake {
+                    session.diagnostics.record_handshake(started, completed);
+                }
+                if let Some((at, requested_encoding, object_encoding)) = self.connected {
+                    session.diagnostics.record_connect(at, requested_encoding, object_encoding);
+                }
+                session.diagnostics.record_stream_start();
+                for &requested in &self.invalid_chunk_sizes {
+                    session.diagnostics.record_invalid_chunk_size(requested);
+                }
+                for &(type_id, length) in &self.rejected_messages {
+                    session.diagnostics.record_rejected_message(type_id, length);
+                }
+                for status in &self.net_status {
+                    session.diagnostics.record_net_status(status);
+                }
+                for command in &self.short_commands {
+                    session.diagnostics.record_short_command(command);
+                }
+                self.streams.push(PublishedStream {
+                    stream_id: *stream_id,
+                    stream_key: stream_key.clone(),
+                    session,
+                    active: true,
+                    metrics_id: metrics::next_stream_id(),
+                    metrics: StreamMetrics::default(),
+                    log_state: LogState::default(),
+                });
+            }
+            RtmpEvent::Metadata { stream_id, properties } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_metadata(properties);
+                }
+            }
+            RtmpEvent::MetadataCleared { stream_id } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_metadata_cleared();
+                }
+            }
+            RtmpEvent::TextData { stream_id, timestamp, properties } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_text_data(*timestamp, properties);
+                }
+            }
+            RtmpEvent::CuePoint { stream_id, timestamp, properties } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_cue_point(*timestamp, properties);
+                }
+            }
+            RtmpEvent::VideoData { stream_id, timestamp, data } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_video(*timestamp, data);
+                }
+            }
+            RtmpEvent::AudioData { stream_id, timestamp, data } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.handle_audio(*timestamp, data);
+                }
+            }
+            RtmpEvent::CorruptAggregate { stream_id, back_pointer_mismatches, skipped_bytes } => {
+                trace_event!(warn, stream_id, back_pointer_mismatches, skipped_bytes, "corrupt aggregate message");
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.diagnostics.record_flv_tag_errors(*back_pointer_mismatches, *skipped_bytes);
+                }
+            }
+            RtmpEvent::ShortCommand { command, .. } => {
+                for s in self.streams.iter_mut().filter(|s| s.active) {
+                    s.session.diagnostics.record_short_command(command);
+                }
+                self.short_commands.push(command.clone());
+            }
+            RtmpEvent::InvalidChunkSize { requested, .. } => {
+                self.invalid_chunk_sizes.push(*requested);
+                for s in self.streams.iter_mut().filter(|s| s.active) {
+                    s.session.diagnostics.record_invalid_chunk_size(*requested);
+                }
+            }
+            RtmpEvent::PingResponse { rtt } => {
+                let rtt_ms = rtt.as_secs_f64() * 1000.0;
+                for s in self.streams.iter_mut().filter(|s| s.active) {
+                    s.session.stats.rtt_ms = Some(rtt_ms);
+                }
+            }
+            RtmpEvent::StreamDry { stream_id } => {
+                if let Some(s) = stream_for(&mut self.streams, *stream_id) {
+                    s.session.diagnostics.record_stream_dry();
+                }
+            }
+            RtmpEvent::Status { stream_id, status } => {
+                // Statuses on a stream's own id are its alone;
+                // NetConnection ones concern every stream
+                match self.streams.iter_mut().find(|s| s.active && s.stream_id == *stream_id) {
+                    Some(s) => s.session.diagnostics.record_net_status(status),
+                    // Left over from an ended stream; don't pin it on the next one
+                    None if *stream_id != 0 => {}
+                    None => {
+                        for s in self.streams.iter_mut().filter(|s| s.active) {
+                            s.session.diagnostics.record_net_status(status);
+                        }
+                        self.net_status.retain(|s| s.code != status.code);
+                        self.net_status.push(status.clone());
+                    }
+                }
+                if status.is_error() {
+                    trace_event!(warn, stream_id, code = status.code.as_str(), "peer reported {}", status.description);
+                }
+            }
+            RtmpEvent::StreamEnded { stream_id } => {
+                // Other streams on the connection keep it alive
+                if let Some(s) = self.streams.iter_mut().find(|s| s.active && s.stream_id == *stream_id) {
+                    s.end(app_name, options);
+                }
+            }
+            RtmpEvent::UnknownData { .. }
+            | RtmpEvent::BufferLength { .. }
+            | RtmpEvent::Playing { .. }
+            | RtmpEvent::PublishRejected { .. } => {}
+        }
+    }
+}
+
+/// e.g. "1.2.3.4:5678 disconnected after 62.1s, live/key: 1863 video and 2910 audio frames"
+fn disconnect_summary(addr: &PeerAddr, connected_at: Instant, app_name: &str, streams: &[PublishedStream]) -> String {
+    let mut line = format!("{} disconnected after {:.1}s", addr, connected_at.elapsed().as_secs_f64());
+    if streams.is_empty() {
+        line.push_str(" without publishing");
+    }
+    for s in streams {
+        line.push_str(&format!(", {}/{}: {} video and {} audio frames",
+            app_name,
+            s.stream_key,
+            s.session.video_analyzer.total_video_frames,
+            s.session.audio_analyzer.total_audio_frames));
+    }
+    line
+}
+
+/// The connection as key=value lines for log aggregation, one per published
+/// stream or a single one without, e.g. "peer=1.2.3.4:5678 app=live key=abc
+/// duration_secs=62.1 video_codec=H.264/AVC resolution=1920x1080 fps=30.00
+/// video_kbps=5980 audio_kbps=160 errors=0 warnings=1 reason=eof". Unknown
+/// values are left out.
+fn summary_lines(
+    addr: &PeerAddr,
+    connected_at: Instant,
+    app_name: &str,
+    streams: &[PublishedStream],
+    reason: DisconnectReason,
+) -> String {
+    let mut connection = format!("peer={}", logfmt_value(&addr.to_string()));
+    if !app_name.is_empty() {
+        connection.push_str(&format!(" app={}", logfmt_value(app_name)));
+    }
+    let duration = format!(" duration_secs={:.1}", connected_at.elapsed().as_secs_f64());
+    let ending = format!(" reason={}", reason);
+    if streams.is_empty() {
+        return format!("{}{}{}", connection, duration, ending);
+    }
+
+    let mut lines = Vec::new();
+    for s in streams {
+        let video = &s.session.video_analyzer;
+        let audio = &s.session.audio_analyzer;
+        let stats = &s.session.stats;
+        let mut line = format!("{} key={}{}", connection, logfmt_value(&s.stream_key), duration);
+        if let Some(codec) = video.codec {
+            line.push_str(&format!(" video_codec={}", logfmt_value(&codec.to_string())));
+        }
+        if let (Some(width), Some(height)) = (video.width, video.height) {
+            line.push_str(&format!(" resolution={}x{}", width, height));
+        }
+        if let Some(fps) = video.fps.or_else(|| stats.current_fps()) {
+            line.push_str(&format!(" fps={:.2}", fps));
+        }
+        if let Some(codec) = audio.codec {
+            line.push_str(&format!(" audio_codec={}", logfmt_value(&codec.to_string())));
+        }
+        if let Some(kbps) = stats.average_video_bitrate_kbps() {
+            line.push_str(&format!(" video_kbps={:.0}", kbps));
+        }
+        if let Some(kbps) = stats.average_audio_bitrate_kbps() {
+            line.push_str(&format!(" audio_kbps={:.0}", kbps));
+        }
+        line.push_str(&format!(" errors={} warnings={}{}",
+            s.session.diagnostics.error_count(),
+            s.session.diagnostics.warning_count(),
+            ending));
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Quote `value` if it would otherwise break the key=value pair.
+fn logfmt_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
 
-pub async fn handle_connection(mut stream: TcpStream, addr: std::net::SocketAddr) {
+/// Serve one client on any byte stream, normally TCP or a Unix socket.
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "connection", skip_all, fields(peer = %addr)))]
+pub async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    addr: PeerAddr,
+    options: ConnectionOptions,
+) -> ConnectionOutcome {
     // Phase 1: Handshake
-    let remaining = match handshake::perform_handshake(&mut stream).await {
+    let handshake_start = Instant::now();
+    trace_event!(info, "connected");
+    if !options.quiet {
+        eprintln!("{} connected", addr);
+    }
+    let handshake = handshake::perform_handshake(&mut stream);
+    let handshake_result = match options.idle_timeout {
+        // A client can stall mid-handshake just as well
+        Some(idle) => timeout(idle, handshake).await.unwrap_or_else(|_| Err("timed out".into())),
+        None => handshake.await,
+    };
+    let remaining = match handshake_result {
         Ok(r) => r,
         Err(e) => {
+            trace_event!(warn, error = %e, "handshake failed");
             eprintln!("Handshake failed for {}: {}", addr, e);
-            return;
+            if !options.quiet && !options.tui_enabled() {
+                eprintln!("{}", summary_lines(&addr, handshake_start, "", &[], DisconnectReason::HandshakeFailed));
+            }
+            return ConnectionOutcome::default();
         }
     };
+    let handshake_complete = Instant::now();
 
     // Phase 2: RTMP session
     let mut chunk_reader = ChunkReader::new();
+    if let Some(max) = options.max_message_size {
+        chunk_reader.set_max_message_size(max);
+    }
     let mut handler = MessageHandler::new();
-    let mut video_analyzer = VideoAnalyzer::new();
-    let mut audio_analyzer = AudioAnalyzer::new();
-    let mut stats = StreamStats::new();
-    let mut diagnostics = StreamDiagnostics::new();
-    let mut encoder_name: Option<String> = None;
-    let mut publishing = false;
-
-    // Default to Twitch profile for now (most strict)
-    diagnostics.set_profile(ServiceProfile::Twitch);
+    if let Some(dump) = &options.dump_amf {
+        handler.set_dump(dump.clone(), addr.to_string());
+    }
+    if let Some(keys) = &options.allowed_keys {
+        handler.set_allowed_keys(keys.clone());
+    }
+    let mut state = ConnectionState::new(&options, Some((handshake_start, handshake_complete)));
+    // When the first stream started publishing, for --duration
+    let mut publish_started: Option<Instant> = None;
+    // The TUI's alternate screen, from the first publish until this returns
+    let mut terminal: Option<display::TerminalGuard> = None;
+    let mut ticks: u64 = 0;
+    // Data message names already logged as unhandled
+    let mut unknown_data_frames: Vec<String> = Vec::new();
 
     // Feed any remaining bytes from handshake
     if !remaining.is_empty() {
@@ -40,17 +583,36 @@ pub async fn handle_connection(mut stream: TcpStream, addr: std::net::SocketAddr
     }
 
     let mut buf = vec![0u8; 65536];
-    let mut display_interval = interval(Duration::from_secs(1));
+    let mut throttle = options.max_ingress_kbps.map(|kbps| IngressThrottle::new(kbps, Instant::now()));
+    // Set while the throttle holds off reading
+    let mut paused_until: Option<Instant> = None;
+    let mut last_read = Instant::now();
+    // Why the session ended, printed once the terminal is restored
+    let mut end_message: Option<String> = None;
+    let mut display_interval = interval(options.refresh.unwrap_or(Duration::from_secs(1)));
     display_interval.tick().await; // consume the immediate first tick
+    // History graphs and the CSV stay one sample per second at any refresh
+    let mut sample_interval = interval(Duration::from_secs(1));
+    sample_interval.tick().await;
+    // The period doesn't matter when pings are off; the branch never runs
+    let mut ping_interval = interval(options.ping_interval.unwrap_or(Duration::from_secs(1)));
+    ping_interval.tick().await;
 
-    loop {
+    let reason = 'session: loop {
+        let read_len = throttle.as_ref().map_or(buf.len(), |t| t.read_len(buf.len()));
         tokio::select! {
-            result = stream.read(&mut buf) => {
+            _ = sleep_until(paused_until.unwrap_or(last_read).into()), if paused_until.is_some() => {
+                paused_until = None;
+            }
+            result = stream.read(&mut buf[..read_len]), if paused_until.is_none() => {
                 match result {
                     Ok(0) => {
-                        break;
+                        break 'session DisconnectReason::Eof;
                     }
                     Ok(n) => {
+                        last_read = Instant::now();
+                        paused_until = throttle.as_mut().and_then(|t| t.consume(n, last_read));
+
                         // Track bytes for window acknowledgement
                         if let Some(ack_data) = handler.track_bytes(n) {
                             let _ = stream.write_all(&ack_data).await;
@@ -59,150 +621,369 @@ pub async fn handle_connection(mut stream: TcpStream, addr: std::net::SocketAddr
                         chunk_reader.extend(&buf[..n]);
                         let messages = chunk_reader.read_messages();
 
+                        for rejected in chunk_reader.take_rejected() {
+                            state.record_rejected_message(rejected.type_id, rejected.length);
+                            trace_event!(warn, type_id = rejected.type_id, len = rejected.length, "oversized message skipped");
+                            if !options.tui_enabled() {
+                                eprintln!("{} sent an oversized message (type {}, {} bytes), skipped",
+                                    addr, rejected.type_id, rejected.length);
+                            }
+                        }
+
                         for msg in messages {
+                            state.check_timestamp(&msg);
                             let result = handler.handle(msg);
 
-                            // Apply chunk size change
-                            if let Some(new_size) = result.new_chunk_size {
-                                chunk_reader.set_chunk_size(new_size);
-                            }
-
                             // Send responses
                             for response in &result.responses {
                                 if let Err(e) = stream.write_all(response).await {
                                     eprintln!("Write error: {}", e);
-                                    return;
+                                    break 'session DisconnectReason::WriteError;
                                 }
                             }
 
                             // Handle events
-                            if let Some(event) = result.event {
+                            for event in result.events {
+                                state.apply(&event, handler.app_name(), &options, None);
+                                // What's left is telling the user about it
                                 match event {
-                                    RtmpEvent::Connected { .. } => {}
-                                    RtmpEvent::Publishing { .. } => {
-                                        publishing = true;
-                                        diagnostics.record_stream_start();
-                                        display::init_terminal();
-                                    }
-                                    RtmpEvent::Metadata { ref properties } => {
-                                        let mut has_dims = false;
-                                        let mut has_fps = false;
-                                        let mut has_bitrate = false;
-
-                                        for (key, value) in properties {
-                                            match key.as_str() {
-                                                "encoder" => {
-                                                    if let Some(s) = value.as_str() {
-                                                        encoder_name = Some(s.to_string());
-                                                    }
-                                                }
-                                                "width" | "height" => has_dims = true,
-                                                "framerate" | "fps" => has_fps = true,
-                                                "videodatarate" | "audiodatarate" => has_bitrate = true,
-                                                _ => {}
+                                    RtmpEvent::Publishing { stream_key, .. } => {
+                                        publish_started.get_or_insert_with(Instant::now);
+                                        let restarted = state.streams.iter().any(|s| !s.active && s.stream_key == stream_key);
+                                        if let Some(s) = state.streams.last_mut() {
+                                            // Alerts would scribble over the TUI
+                                            #[cfg(feature = "tracing")]
+                                            s.session.diagnostics.add_sink(Box::new(crate::diagnostics::TracingSink::new(handler.app_name(), stream_key.as_str())));
+                                            if !options.quiet && !options.tui_enabled() {
+                                                let label = format!("{} {}/{}", addr, handler.app_name(), stream_key);
+                                                s.session.diagnostics.add_sink(Box::new(StderrSink::new(label)));
                                             }
                                         }
-
-                                        diagnostics.record_metadata(has_dims, has_fps, has_bitrate);
+                                        if !options.quiet && terminal.is_none() {
+                                            let verb = if restarted { "restarted publishing" } else { "publishing" };
+                                            let tc_url = handler.connect_params().tc_url.as_deref().map(|url| format!(" via {}", url)).unwrap_or_default();
+                                            eprintln!("{} {} {}/{}{}", addr, verb, handler.app_name(), stream_key, tc_url);
+                                        }
+                                        // The TUI cycle starts over with the new stream
+                                        if restarted {
+                                            ticks = 0;
+                                        }
+                                        if options.tui_enabled() && terminal.is_none() {
+                                            terminal = Some(display::TerminalGuard::enter());
+                                        }
                                     }
-                                    RtmpEvent::VideoData { timestamp, ref data } => {
-                                        let byte_count = data.len();
-
-                                        // Track diagnostics before processing
-                                        diagnostics.record_video_timestamp(timestamp);
-
-                                        // Check for AVC sequence header
-                                        if data.len() >= 2 {
-                                            let codec_id = data[0] & 0x0F;
-                                            if codec_id == 7 && data[1] == 0 {
-                                                diagnostics.record_avc_seq_header();
+                                    RtmpEvent::UnknownData { name, .. } => {
+                                        // Once per name; some encoders send theirs with every frame
+                                        if !unknown_data_frames.contains(&name) {
+                                            trace_event!(debug, name = name.as_str(), "unhandled data message");
+                                            if !options.tui_enabled() {
+                                                eprintln!("{} sent an unhandled data message \"{}\", ignored", addr, name);
                                             }
+                                            unknown_data_frames.push(name);
                                         }
-
-                                        // Process video
-                                        video_analyzer.process(data, timestamp);
-
-                                        // Track frame types
-                                        let is_keyframe = !data.is_empty() && ((data[0] >> 4) & 0x0F) == 1;
-                                        if is_keyframe {
-                                            diagnostics.record_keyframe(stats.keyframe_interval_secs);
+                                    }
+                                    RtmpEvent::ShortCommand { stream_id, command } => {
+                                        trace_event!(warn, stream_id, command = command.name.as_str(), values = command.values, undecoded_bytes = command.undecoded_bytes, "short command");
+                                        if !options.tui_enabled() {
+                                            eprintln!("{} sent {} on stream {} with {} of {} expected values ({} bytes undecoded)",
+                                                addr, command.name, stream_id, command.values, command.required, command.undecoded_bytes);
                                         }
-
-                                        // Check for B-frames (composition time offset != 0)
-                                        if data.len() >= 5 && (data[0] & 0x0F) == 7 && data[1] == 1 {
-                                            let cto = ((data[2] as i32) << 16)
-                                                | ((data[3] as i32) << 8)
-                                                | (data[4] as i32);
-                                            if cto != 0 {
-                                                diagnostics.record_b_frame();
+                                    }
+                                    RtmpEvent::InvalidChunkSize { requested, applied } => {
+                                        trace_event!(warn, requested, applied, "invalid chunk size");
+                                        if !options.tui_enabled() {
+                                            match applied {
+                                                Some(size) => eprintln!("{} requested chunk size {}, capped to {}", addr, requested, size),
+                                                None => eprintln!("{} requested chunk size {}, ignored", addr, requested),
                                             }
                                         }
-
-                                        stats.record_video_frame(byte_count, is_keyframe);
                                     }
-                                    RtmpEvent::AudioData { timestamp, ref data } => {
-                                        let byte_count = data.len();
-
-                                        // Track diagnostics
-                                        diagnostics.record_audio_timestamp(timestamp);
-
-                                        // Check for AAC sequence header
-                                        let is_aac_seq_header = data.len() >= 2
-                                            && ((data[0] >> 4) & 0x0F) == 10
-                                            && data[1] == 0;
-
-                                        if is_aac_seq_header {
-                                            diagnostics.record_aac_seq_header();
+                                    RtmpEvent::BufferLength { stream_id, buffer_ms } => {
+                                        trace_event!(debug, stream_id, buffer_ms, "client set buffer length");
+                                        if !options.quiet && !options.tui_enabled() {
+                                            eprintln!("{} set a {} ms buffer on stream {}", addr, buffer_ms, stream_id);
                                         }
-
-                                        // Process audio
-                                        audio_analyzer.process(data, timestamp);
-
-                                        if !is_aac_seq_header {
-                                            stats.record_audio_frame(byte_count);
+                                    }
+                                    RtmpEvent::Status { status, .. } if status.is_error() => {
+                                        if !options.tui_enabled() {
+                                            eprintln!("{} reported {}: {}", addr, status.code, status.description);
                                         }
+                                        // Show why the stream was rejected without waiting a tick
+                                        display_interval.reset_immediately();
                                     }
-                                    RtmpEvent::StreamEnded => {
-                                        publishing = false;
-                                        display::restore_terminal();
-                                        break;
+                                    RtmpEvent::Playing { stream_key, stream_id } => {
+                                        trace_event!(info, stream_key = stream_key.as_str(), stream_id, "playing");
+                                        if !options.quiet && !options.tui_enabled() {
+                                            eprintln!("{} playing {}/{} on stream {} (nothing is relayed, so no media will follow)", addr, handler.app_name(), stream_key, stream_id);
+                                        }
                                     }
+                                    RtmpEvent::PublishRejected { stream_key, .. } => {
+                                        trace_event!(warn, stream_key = stream_key.as_str(), "stream key not allowed");
+                                        // The BadName status is already on its way
+                                        end_message = Some(format!("{} rejected: stream key \"{}\" is not in the keys file; disconnecting", addr, stream_key));
+                                        break 'session DisconnectReason::Rejected;
+                                    }
+                                    RtmpEvent::Connected { .. }
+                                    | RtmpEvent::Metadata { .. }
+                                    | RtmpEvent::MetadataCleared { .. }
+                                    | RtmpEvent::TextData { .. }
+                                    | RtmpEvent::CuePoint { .. }
+                                    | RtmpEvent::VideoData { .. }
+                                    | RtmpEvent::AudioData { .. }
+                                    | RtmpEvent::CorruptAggregate { .. }
+                                    | RtmpEvent::PingResponse { .. }
+                                    | RtmpEvent::StreamDry { .. }
+                                    | RtmpEvent::Status { .. }
+                                    | RtmpEvent::StreamEnded { .. } => {}
                                 }
                             }
                         }
                     }
                     Err(_) => {
-                        break;
+                        break 'session DisconnectReason::ReadError;
+                    }
+                }
+            }
+            _ = sleep_until((last_read + options.idle_timeout.unwrap_or_default()).into()), if options.idle_timeout.is_some() => {
+                let idle_secs = last_read.elapsed().as_secs();
+                let mut froze = false;
+                for s in state.streams.iter_mut().filter(|s| s.active) {
+                    s.session.diagnostics.record_idle_timeout(idle_secs);
+                    froze = true;
+                }
+                end_message = Some(if froze {
+                    format!("{} stopped sending while publishing, no data for {}s; disconnecting", addr, idle_secs)
+                } else {
+                    format!("{} never started publishing, no data for {}s; disconnecting", addr, idle_secs)
+                });
+                break 'session DisconnectReason::IdleTimeout;
+            }
+            _ = sleep_until((publish_started.unwrap_or(last_read) + options.duration.unwrap_or_default()).into()),
+                if options.duration.is_some() && publish_started.is_some() =>
+            {
+                if !options.quiet {
+                    end_message = Some(format!("{} analyzed for {:.1}s; disconnecting",
+                        addr, options.duration.unwrap_or_default().as_secs_f64()));
+                }
+                break 'session DisconnectReason::Duration;
+            }
+            // Redraw right away when a keypress switched screens or pages
+            _ = display::view_changed(), if terminal.is_some() => {
+                display_interval.reset_immediately();
+            }
+            _ = display::disconnect_requested(), if terminal.is_some() => {
+                end_message = Some(format!("{} disconnected from the keyboard", addr));
+                break 'session DisconnectReason::Keyboard;
+            }
+            _ = ping_interval.tick(), if options.ping_interval.is_some() => {
+                let ping = handler.ping_request();
+                let missed = handler.missed_pings();
+                if missed >= MAX_MISSED_PINGS {
+                    for s in state.streams.iter_mut().filter(|s| s.active) {
+                        s.session.diagnostics.record_ping_timeout(missed);
+                    }
+                    trace_event!(warn, missed, "pings unanswered");
+                    if !options.tui_enabled() {
+                        eprintln!("{} left {} pings unanswered, disconnecting", addr, missed);
+                    }
+                    break 'session DisconnectReason::PingTimeout;
+                }
+                if let Err(e) = stream.write_all(&ping).await {
+                    eprintln!("Write error: {}", e);
+                    break 'session DisconnectReason::WriteError;
+                }
+            }
+            _ = sample_interval.tick() => {
+                ticks += 1;
+                for s in state.streams.iter_mut().filter(|s| s.active) {
+                    s.session.stats.sample_history();
+                    if let Some(csv) = &options.csv
+                        && let Ok(mut csv) = csv.lock()
+                    {
+                        csv.record(handler.app_name(), &s.stream_key, &s.session);
                     }
                 }
             }
             _ = display_interval.tick() => {
-                if publishing {
-                    // Run diagnostic checks
-                    let results = diagnostics.check_all(
-                        video_analyzer.width,
-                        video_analyzer.height,
-                        video_analyzer.profile.as_deref(),
-                        audio_analyzer.effective_sample_rate(),
-                        audio_analyzer.effective_channels(),
-                        audio_analyzer.aac_profile.as_deref(),
-                        stats.keyframe_interval_secs,
-                    );
-
-                    display::render(
-                        handler.app_name(),
-                        handler.stream_key(),
-                        &stats,
-                        &video_analyzer,
-                        &audio_analyzer,
-                        &encoder_name,
-                        &diagnostics,
-                        &results,
-                    );
-                }
-            }
-        }
-    }
-
-    display::restore_terminal();
+                let active_count = state.streams.iter().filter(|s| s.active).count();
+                if active_count == 0 {
+                    continue;
+                }
+                // The TUI shows one stream at a time, rotating every few seconds
+                let shown = (ticks / STREAM_CYCLE_TICKS) as usize % active_count;
+
+                let ingress_kbps = handler.ingress_bytes_per_sec().map(|rate| rate * 8.0 / 1000.0);
+                for (index, s) in state.streams.iter_mut().filter(|s| s.active).enumerate() {
+                    let session = &mut s.session;
+                    session.stats.ingress_kbps = ingress_kbps;
+
+                    // Throttled by the check interval, so a fast refresh
+                    // redraws the same diagnostics until the next pass
+                    let results = session.run_checks();
+
+                    if let Some(log) = &options.log
+                        && let Ok(mut log) = log.lock()
+                    {
+                        log.record(handler.app_name(), &s.stream_key, &mut s.log_state, &results);
+                    }
+
+                    if let Some(registry) = &options.metrics {
+                        s.metrics.update(
+                            handler.app_name(),
+                            &s.stream_key,
+                            &session.stats,
+                            &session.video_analyzer,
+                            &session.audio_analyzer,
+                            &results,
+                        );
+                        if let Ok(mut registry) = registry.lock() {
+                            registry.publish(s.metrics_id, s.metrics.clone());
+                        }
+                    }
+
+                    if options.tui_enabled() {
+                        // While paused, the frame on screen stays put
+                        if index == shown && !display::paused() {
+                            if display::full_diagnostics() {
+                                display::render_diagnostics(handler.app_name(), &s.stream_key, &results, (index, active_count));
+                            } else {
+                                display::render(
+                                    handler.app_name(),
+                                    &s.stream_key,
+                                    session,
+                                    handler.connect_params(),
+                                    &results,
+                                    (index, active_count),
+                                );
+                            }
+                        }
+                    } else if options.status_lines_enabled() {
+                        display::print_status_line(
+                            handler.app_name(),
+                            &s.stream_key,
+                            &session.stats,
+                            &session.video_analyzer,
+                            &session.audio_analyzer,
+                            &results,
+                        );
+                    }
+                }
+            }
+        }
+    };
+
+    drop(terminal);
+    if let Some(message) = end_message {
+        trace_event!(info, "{}", message);
+        eprintln!("{}", message);
+    }
+    trace_event!(info, streams = state.streams.len(), reason = %reason, "disconnected");
+    // Without the TUI, the key=value summary below stands in for this
+    if !options.quiet && options.tui_enabled() {
+        eprintln!("{}", disconnect_summary(&addr, handshake_start, handler.app_name(), &state.streams));
+    }
+
+    for s in state.streams.iter_mut().filter(|s| s.active) {
+        s.end(handler.app_name(), &options);
+    }
+
+    // A last pass over everything received, for the report and the outcome
+    let results: Vec<_> = state.streams.iter_mut().map(|s| s.session.check_now()).collect();
+    let outcome = ConnectionOutcome {
+        streams: state.streams.len(),
+        errors: state.streams.iter().map(|s| s.session.diagnostics.error_count()).sum(),
+        warnings: state.streams.iter().map(|s| s.session.diagnostics.warning_count()).sum(),
+    };
+    if !options.quiet && !options.tui_enabled() {
+        eprintln!("{}", summary_lines(&addr, handshake_start, handler.app_name(), &state.streams, reason));
+    }
+
+    if !state.streams.is_empty() && let Some(format) = options.report {
+        let chunk_streams = chunk_reader.chunk_stream_stats();
+        let reports: Vec<_> = state.streams
+            .iter()
+            .zip(&results)
+            .map(|(s, results)| StreamReport {
+                chunk_streams: &chunk_streams,
+                connect: Some(handler.connect_params()),
+                ..s.session.report(handler.app_name(), &s.stream_key, results)
+            })
+            .collect();
+        if let Err(e) = StreamReport::emit_all(&reports, format, options.report_file.as_deref()) {
+            eprintln!("Failed to write report for {}: {}", addr, e);
+        }
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> PeerAddr {
+        PeerAddr::Tcp("1.2.3.4:5678".parse().unwrap())
+    }
+
+    #[test]
+    fn throttled_reads_are_limited_to_a_burst() {
+        let now = Instant::now();
+        // 8 Mbps is 1MB/s, so bursts of 100KB
+        assert_eq!(IngressThrottle::new(8000, now).read_len(65_536), 65_536);
+        assert_eq!(IngressThrottle::new(800, now).read_len(65_536), 10_000);
+        assert_eq!(IngressThrottle::new(0, now).read_len(65_536), 1);
+    }
+
+    #[test]
+    fn throttle_allowance_is_capped_and_overdrafts_are_waited_out() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = IngressThrottle::new(8000, start);
+
+        // Ten idle seconds still only buy one burst
+        assert_eq!(throttle.consume(0, at(10_000)), None);
+        assert_eq!(throttle.allowance, 100_000.0);
+        assert_eq!(throttle.consume(100_000, at(10_000)), None);
+
+        // Overdrawn by 50KB: 50ms at 1MB/s
+        assert_eq!(throttle.consume(50_000, at(10_000)), Some(at(10_050)));
+        assert_eq!(throttle.allowance, -50_000.0);
+        // 20ms later the debt is down to 30KB
+        assert_eq!(throttle.consume(0, at(10_020)), Some(at(10_050)));
+        assert_eq!(throttle.consume(0, at(10_050)), None);
+    }
+
+    #[test]
+    fn logfmt_values_are_quoted_only_when_needed() {
+        assert_eq!(logfmt_value("abc-123/x"), "abc-123/x");
+        assert_eq!(logfmt_value(""), "\"\"");
+        assert_eq!(logfmt_value("H.264/AVC main"), "\"H.264/AVC main\"");
+        assert_eq!(logfmt_value("tab\there"), "\"tab\there\"");
+        assert_eq!(logfmt_value("a=b"), "\"a=b\"");
+        assert_eq!(logfmt_value(r#"say "hi""#), r#""say \"hi\"""#);
+        // Backslashes are only escaped inside quotes
+        assert_eq!(logfmt_value(r"C:\key"), r"C:\key");
+        assert_eq!(logfmt_value(r#"C:\"key""#), r#""C:\\\"key\"""#);
+    }
+
+    #[test]
+    fn a_failed_handshake_gets_a_single_line() {
+        let line = summary_lines(&peer(), Instant::now(), "", &[], DisconnectReason::HandshakeFailed);
+        assert_eq!(line, "peer=1.2.3.4:5678 duration_secs=0.0 reason=handshake_failed");
+    }
+
+    #[test]
+    fn each_published_stream_gets_a_line() {
+        let options = ConnectionOptions::default();
+        let mut state = ConnectionState::new(&options, None);
+        for (stream_key, stream_id) in [("main", 1), ("backup key", 2)] {
+            let event = RtmpEvent::Publishing { app_name: "live".to_string(), stream_key: stream_key.to_string(), stream_id };
+            state.apply(&event, "live", &options, None);
+        }
+
+        let lines = summary_lines(&peer(), Instant::now(), "live", &state.streams, DisconnectReason::Eof);
+        assert_eq!(lines.lines().collect::<Vec<_>>(), [
+            "peer=1.2.3.4:5678 app=live key=main duration_secs=0.0 errors=0 warnings=0 reason=eof",
+            "peer=1.2.3.4:5678 app=live key=\"backup key\" duration_secs=0.0 errors=0 warnings=0 reason=eof",
+        ]);
+    }
 }
@@ -1,4 +1,14 @@
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::encoder::EncoderInfo;
+use crate::flv::metadata::StreamMetadata;
+use crate::flv::video::{PixelFormat, VideoCodec};
+use crate::json::JsonValue;
+use crate::rtmp::message::{NetStatus, ShortCommand};
 
 /// Severity level for diagnostic warnings
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -8,6 +18,16 @@ pub enum Severity {
     Error,
 }
 
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
 /// A diagnostic warning or issue detected in the stream
 #[derive(Debug, Clone)]
 pub struct Diagnostic {
@@ -28,90 +48,696 @@ impl Diagnostic {
     pub fn error(category: &'static str, message: impl Into<String>) -> Self {
         Self { severity: Severity::Error, category, message: message.into() }
     }
+
+    /// Identity of the diagnostic across check passes. Messages embed live
+    /// values ("Bitrate 7444 kbps ..."), so numbers are masked out; otherwise
+    /// every tick would look like a new diagnostic.
+    pub fn dedup_key(&self) -> String {
+        let mut key = format!("{}:{}:", self.severity.as_str(), self.category);
+        let mut in_number = false;
+        for c in self.message.chars() {
+            if c.is_ascii_digit() || (in_number && c == '.') {
+                if !in_number {
+                    key.push('#');
+                    in_number = true;
+                }
+            } else {
+                key.push(c);
+                in_number = false;
+            }
+        }
+        key
+    }
+}
+
+/// Receives each diagnostic once when it's raised, rather than on every
+/// check pass it stays active for.
+pub trait DiagnosticSink: Send {
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic);
+}
+
+impl fmt::Debug for dyn DiagnosticSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DiagnosticSink")
+    }
+}
+
+/// A sink shared by every stream, e.g. one alerting hook for the server.
+pub type SharedSink = Arc<Mutex<dyn DiagnosticSink>>;
+
+impl<S: DiagnosticSink + ?Sized> DiagnosticSink for Arc<Mutex<S>> {
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        if let Ok(mut sink) = self.lock() {
+            sink.on_diagnostic(diagnostic);
+        }
+    }
+}
+
+/// Emits raised diagnostics as `tracing` events, at the level of their severity.
+#[cfg(feature = "tracing")]
+pub struct TracingSink {
+    app_name: String,
+    stream_key: String,
+}
+
+#[cfg(feature = "tracing")]
+impl TracingSink {
+    pub fn new(app_name: impl Into<String>, stream_key: impl Into<String>) -> Self {
+        Self { app_name: app_name.into(), stream_key: stream_key.into() }
+    }
 }
 
-/// Known streaming service profiles for compatibility checking
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
-pub enum ServiceProfile {
+#[cfg(feature = "tracing")]
+impl DiagnosticSink for TracingSink {
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let (app, stream_key, category) = (&self.app_name, &self.stream_key, &diagnostic.category);
+        match diagnostic.severity {
+            Severity::Error => tracing::error!(app, stream_key, category, "{}", diagnostic.message),
+            Severity::Warning => tracing::warn!(app, stream_key, category, "{}", diagnostic.message),
+            Severity::Info => tracing::info!(app, stream_key, category, "{}", diagnostic.message),
+        }
+    }
+}
+
+/// Prints raised diagnostics to stderr, prefixed with the stream they're for.
+pub struct StderrSink {
+    label: String,
+}
+
+impl StderrSink {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self { label: label.into() }
+    }
+}
+
+impl DiagnosticSink for StderrSink {
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        eprintln!("{} {} [{}] {}", self.label, diagnostic.severity.as_str(), diagnostic.category, diagnostic.message);
+    }
+}
+
+/// Common landscape frame sizes, largest first. Portrait streams use the
+/// same sizes turned on their side.
+pub const STANDARD_RESOLUTIONS: &[(u32, u32)] = &[
+    (7680, 4320), (5120, 2880), (3840, 2160), (3440, 1440), (2560, 1440),
+    (2560, 1080), (1920, 1200), (1920, 1080), (1680, 1050), (1600, 900),
+    (1440, 900), (1366, 768), (1280, 800), (1280, 720), (1080, 1080),
+    (1024, 768), (1024, 576), (960, 540), (854, 480), (848, 480),
+    (800, 600), (768, 432), (720, 720), (720, 576), (720, 480),
+    (640, 480), (640, 360), (480, 270), (426, 240),
+];
+
+/// The standard sizes that fit within `max_width` x `max_height`.
+fn resolutions_up_to(max_width: u32, max_height: u32) -> Vec<(u32, u32)> {
+    STANDARD_RESOLUTIONS
+        .iter()
+        .copied()
+        .filter(|&(w, h)| w <= max_width && h <= max_height)
+        .collect()
+}
+
+/// Aspect ratio categories and their width/height ratios
+const ASPECT_RATIOS: &[(&str, f64)] = &[
+    ("16:9", 16.0 / 9.0),
+    ("16:10", 16.0 / 10.0),
+    ("4:3", 4.0 / 3.0),
+    ("21:9", 64.0 / 27.0),
+    ("1:1", 1.0),
+    ("9:16", 9.0 / 16.0),
+    ("10:16", 10.0 / 16.0),
+    ("3:4", 3.0 / 4.0),
+    ("4:5", 4.0 / 5.0),
+    ("9:21", 27.0 / 64.0),
+];
+
+/// The aspect ratio category of a frame, e.g. "16:9". Sizes are rounded to
+/// whole macroblocks, so 854x480 still counts as 16:9; 21:9 covers the
+/// 2.37:1 and 2.39:1 ultrawide sizes.
+fn aspect_ratio_name(width: u32, height: u32) -> Option<&'static str> {
+    let ratio = width as f64 / height as f64;
+    ASPECT_RATIOS
+        .iter()
+        .find(|(_, r)| (ratio / r - 1.0).abs() < 0.02)
+        .map(|(name, _)| *name)
+}
+
+/// Ingest limits of a streaming service, used for compatibility checking
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceProfile {
+    pub name: String,
+    /// Longest acceptable keyframe interval in seconds
+    pub max_keyframe_interval: f64,
+    pub allowed_sample_rates: Vec<u32>,
+    pub max_audio_channels: u8,
+    /// Landscape frame sizes the service takes; the largest sets its limit
+    pub resolutions: Vec<(u32, u32)>,
+    /// Audio bitrate ceiling, if the service publishes one
+    pub max_audio_bitrate_kbps: Option<f64>,
+    /// Combined audio + video bitrate ceiling, if the service publishes one
+    pub max_bitrate_kbps: Option<f64>,
+    /// When false, B-frames are flagged as a latency risk
+    pub allow_b_frames: bool,
+    /// When false, HE-AAC is flagged as a compatibility risk
+    pub allow_he_aac: bool,
+    /// When true, video other than 8-bit 4:2:0 is flagged as likely rejected
+    pub require_8bit_420: bool,
+    /// When false, interlaced video is an error rather than a warning
+    pub allow_interlaced: bool,
+    /// When true, GOPs that aren't a whole number of audio frames are
+    /// flagged, as HLS segments cut at keyframes then drift against audio
+    pub require_av_gop_alignment: bool,
+}
+
+impl ServiceProfile {
+    pub fn twitch() -> Self {
+        Self {
+            name: "Twitch".to_string(),
+            max_keyframe_interval: 2.0,
+            allowed_sample_rates: vec![44100, 48000],
+            max_audio_channels: 2,
+            resolutions: resolutions_up_to(1920, 1080),
+            max_audio_bitrate_kbps: Some(160.0),
+            max_bitrate_kbps: Some(6000.0),
+            allow_b_frames: false,
+            allow_he_aac: false,
+            require_8bit_420: true,
+            allow_interlaced: false,
+            require_av_gop_alignment: false,
+        }
+    }
+
+    pub fn youtube() -> Self {
+        Self {
+            name: "YouTube".to_string(),
+            max_keyframe_interval: 4.0,
+            allowed_sample_rates: vec![44100, 48000, 96000],
+            max_audio_channels: 8,
+            resolutions: resolutions_up_to(3840, 2160),
+            max_audio_bitrate_kbps: None,
+            max_bitrate_kbps: None,
+            allow_b_frames: true,
+            allow_he_aac: true,
+            require_8bit_420: false,
+            allow_interlaced: false,
+            require_av_gop_alignment: false,
+        }
+    }
+
+    pub fn kick() -> Self {
+        Self {
+            name: "Kick".to_string(),
+            max_keyframe_interval: 2.0,
+            allowed_sample_rates: vec![44100, 48000],
+            max_audio_channels: 2,
+            resolutions: resolutions_up_to(1920, 1080),
+            max_audio_bitrate_kbps: Some(160.0),
+            max_bitrate_kbps: Some(8000.0),
+            allow_b_frames: true,
+            allow_he_aac: true,
+            require_8bit_420: true,
+            allow_interlaced: true,
+            require_av_gop_alignment: false,
+        }
+    }
+
+    pub fn facebook() -> Self {
+        Self {
+            name: "Facebook".to_string(),
+            max_keyframe_interval: 2.0,
+            allowed_sample_rates: vec![48000],
+            max_audio_channels: 2,
+            resolutions: resolutions_up_to(1920, 1080),
+            max_audio_bitrate_kbps: Some(256.0),
+            max_bitrate_kbps: Some(9000.0),
+            allow_b_frames: true,
+            allow_he_aac: true,
+            require_8bit_420: true,
+            allow_interlaced: true,
+            require_av_gop_alignment: false,
+        }
+    }
+
+    pub fn generic() -> Self {
+        Self {
+            name: "Generic".to_string(),
+            max_keyframe_interval: 4.0,
+            allowed_sample_rates: vec![22050, 44100, 48000, 96000],
+            max_audio_channels: 8,
+            resolutions: STANDARD_RESOLUTIONS.to_vec(),
+            max_audio_bitrate_kbps: None,
+            max_bitrate_kbps: None,
+            allow_b_frames: true,
+            allow_he_aac: true,
+            require_8bit_420: false,
+            allow_interlaced: true,
+            require_av_gop_alignment: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The longest and shortest side of any accepted resolution, so that
+    /// portrait frames are held to the same limit turned around.
+    pub fn max_resolution(&self) -> Option<(u32, u32)> {
+        let long = self.resolutions.iter().map(|&(w, h)| w.max(h)).max()?;
+        let short = self.resolutions.iter().map(|&(w, h)| w.min(h)).max()?;
+        Some((long, short))
+    }
+
+    /// Whether `width` x `height` is one of the service's sizes, either way up.
+    pub fn is_standard_resolution(&self, width: u32, height: u32) -> bool {
+        self.resolutions.iter().any(|&(w, h)| (w, h) == (width, height) || (h, w) == (width, height))
+    }
+
+    /// Load a custom profile from a JSON file. Missing fields keep the Generic defaults.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let json = JsonValue::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_json(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, String> {
+        if !matches!(json, JsonValue::Object(_)) {
+            return Err("profile must be a JSON object".to_string());
+        }
+
+        let mut profile = Self { name: "Custom".to_string(), ..Self::generic() };
+
+        if let Some(v) = json.get("name") {
+            profile.name = v.as_str().ok_or("\"name\" must be a string")?.to_string();
+        }
+        if let Some(v) = json.get("max_keyframe_interval") {
+            profile.max_keyframe_interval = v
+                .as_f64()
+                .filter(|n| *n > 0.0)
+                .ok_or("\"max_keyframe_interval\" must be a positive number")?;
+        }
+        if let Some(v) = json.get("allowed_sample_rates") {
+            profile.allowed_sample_rates = v
+                .as_array()
+                .and_then(|rates| {
+                    rates
+                        .iter()
+                        .map(|r| r.as_f64().filter(|n| *n > 0.0 && n.fract() == 0.0).map(|n| n as u32))
+                        .collect::<Option<Vec<u32>>>()
+                })
+                .ok_or("\"allowed_sample_rates\" must be an array of sample rates")?;
+        }
+        if let Some(v) = json.get("max_audio_channels") {
+            profile.max_audio_channels = v
+                .as_f64()
+                .filter(|n| (1.0..=255.0).contains(n) && n.fract() == 0.0)
+                .map(|n| n as u8)
+                .ok_or("\"max_audio_channels\" must be an integer from 1 to 255")?;
+        }
+        if let Some(v) = json.get("max_bitrate_kbps") {
+            profile.max_bitrate_kbps = match v {
+                JsonValue::Null => None,
+                v => Some(
+                    v.as_f64()
+                        .filter(|n| *n > 0.0)
+                        .ok_or("\"max_bitrate_kbps\" must be a positive number or null")?,
+                ),
+            };
+        }
+        if let Some(v) = json.get("resolutions") {
+            profile.resolutions = v
+                .as_array()
+                .and_then(|sizes| {
+                    sizes
+                        .iter()
+                        .map(|size| {
+                            let (w, h) = size.as_str()?.split_once('x')?;
+                            Some((w.parse().ok()?, h.parse().ok()?))
+                        })
+                        .collect::<Option<Vec<(u32, u32)>>>()
+                })
+                .filter(|sizes| !sizes.is_empty())
+                .ok_or("\"resolutions\" must be a non-empty array of sizes like \"1920x1080\"")?;
+        }
+        if let Some(v) = json.get("max_audio_bitrate_kbps") {
+            profile.max_audio_bitrate_kbps = match v {
+                JsonValue::Null => None,
+                v => Some(
+                    v.as_f64()
+                        .filter(|n| *n > 0.0)
+                        .ok_or("\"max_audio_bitrate_kbps\" must be a positive number or null")?,
+                ),
+            };
+        }
+        if let Some(v) = json.get("allow_b_frames") {
+            profile.allow_b_frames = v.as_bool().ok_or("\"allow_b_frames\" must be a boolean")?;
+        }
+        if let Some(v) = json.get("allow_he_aac") {
+            profile.allow_he_aac = v.as_bool().ok_or("\"allow_he_aac\" must be a boolean")?;
+        }
+        if let Some(v) = json.get("require_8bit_420") {
+            profile.require_8bit_420 = v.as_bool().ok_or("\"require_8bit_420\" must be a boolean")?;
+        }
+        if let Some(v) = json.get("allow_interlaced") {
+            profile.allow_interlaced = v.as_bool().ok_or("\"allow_interlaced\" must be a boolean")?;
+        }
+        if let Some(v) = json.get("require_av_gop_alignment") {
+            profile.require_av_gop_alignment = v.as_bool().ok_or("\"require_av_gop_alignment\" must be a boolean")?;
+        }
+
+        Ok(profile)
+    }
+}
+
+impl Default for ServiceProfile {
+    fn default() -> Self {
+        Self::twitch()
+    }
+}
+
+/// Built-in profiles selectable by name on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KnownProfile {
     Twitch,
+    #[value(name = "youtube")]
     YouTube,
+    Kick,
+    Facebook,
     Generic,
 }
 
-impl ServiceProfile {
-    pub fn name(&self) -> &'static str {
+impl KnownProfile {
+    pub fn profile(self) -> ServiceProfile {
         match self {
-            ServiceProfile::Twitch => "Twitch",
-            ServiceProfile::YouTube => "YouTube",
-            ServiceProfile::Generic => "Generic",
+            KnownProfile::Twitch => ServiceProfile::twitch(),
+            KnownProfile::YouTube => ServiceProfile::youtube(),
+            KnownProfile::Kick => ServiceProfile::kick(),
+            KnownProfile::Facebook => ServiceProfile::facebook(),
+            KnownProfile::Generic => ServiceProfile::generic(),
+        }
+    }
+}
+
+/// Desync samples averaged for the A/V sync check (one per check pass)
+const AV_DESYNC_WINDOW: usize = 5;
+/// Encoders legitimately start audio and video clocks apart
+const AV_DESYNC_WARMUP: Duration = Duration::from_secs(1);
+/// Bitrate samples averaged before comparing against the declared rate
+const BITRATE_DEVIATION_WINDOW: usize = 10;
+/// Allowed relative difference between measured and declared bitrate
+const BITRATE_DEVIATION_LIMIT: f64 = 0.25;
+/// Minimum time between two full diagnostic passes unless one is forced
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Fraction of the profile's keyframe interval limit past which the
+/// interval is reported as close to it
+const KEYFRAME_INTERVAL_NEAR_LIMIT: f64 = 0.9;
+/// Keyframe intervals kept for the interval statistics
+const KEYFRAME_INTERVAL_HISTORY: usize = 10;
+/// Intervals needed before their spread is judged
+const KEYFRAME_JITTER_MIN_SAMPLES: usize = 3;
+/// Standard deviation, relative to the mean, past which keyframes look
+/// placed by scene changes rather than a fixed GOP
+const KEYFRAME_JITTER_LIMIT: f64 = 0.2;
+/// Share of off-cadence frame intervals past which the stream counts as VFR
+const VFR_LIMIT: f64 = 0.1;
+/// Network jitter (ms) above which a player's buffer may run dry
+pub(crate) const NETWORK_JITTER_LIMIT_MS: f64 = 100.0;
+/// Health score points lost per active diagnostic, by severity
+const SCORE_ERROR_PENALTY: u32 = 25;
+const SCORE_WARNING_PENALTY: u32 = 8;
+/// Highest health score a stream with any active error can get
+const SCORE_ERROR_CAP: u32 = 59;
+/// Most health score points each measured metric can cost
+const SCORE_METRIC_MAX_PENALTY: f64 = 10.0;
+/// A/V drift that costs no health score points
+const SCORE_DESYNC_ALLOWANCE_MS: f64 = 50.0;
+/// Handshakes slower than this point at the network between encoder and server
+const SLOW_HANDSHAKE_MS: f64 = 200.0;
+
+/// Spread of the recent keyframe intervals, in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyframeIntervalStats {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub stddev: f64,
+    pub samples: usize,
+}
+
+impl KeyframeIntervalStats {
+    fn from_intervals(intervals: &[f64]) -> Option<Self> {
+        if intervals.is_empty() {
+            return None;
         }
+        let n = intervals.len() as f64;
+        let mean = intervals.iter().sum::<f64>() / n;
+        let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / n;
+        Some(Self {
+            mean,
+            min: intervals.iter().copied().fold(f64::INFINITY, f64::min),
+            max: intervals.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            stddev: variance.sqrt(),
+            samples: intervals.len(),
+        })
+    }
+
+    /// The mean interval in frames at `fps`.
+    pub fn mean_frames(&self, fps: f64) -> f64 {
+        self.mean * fps
+    }
+}
+
+/// How long each step of bringing a stream up took, in milliseconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StartupTimeline {
+    /// Connection accepted to handshake complete
+    pub handshake_ms: Option<f64>,
+    /// Handshake complete to the connect command
+    pub connect_ms: Option<f64>,
+    /// connect to publish
+    pub publish_ms: Option<f64>,
+    /// publish to the first keyframe
+    pub first_keyframe_ms: Option<f64>,
+}
+
+/// A 0-100 verdict on the stream from the last check pass, with what it
+/// lost points for. Starting from 100, a stream loses:
+///
+/// - 25 points per active error and 8 per warning; info costs nothing
+/// - 1 point per percent of frames dropped, at most 10
+/// - 1 point per 5% of frames off-cadence (variable frame rate), at most 10
+/// - 1 point per 10 ms of A/V drift past the first 50 ms, at most 10
+///
+/// Any active error caps the score at 59, so that e.g. a keyframe interval
+/// over the service limit can never look healthy however good the rest is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthScore {
+    pub score: u8,
+    /// (reason, points lost), in the order applied
+    pub deductions: Vec<(String, u32)>,
+}
+
+impl Default for HealthScore {
+    fn default() -> Self {
+        Self { score: 100, deductions: Vec::new() }
     }
 }
 
+/// What the checks need from a stream's analyzers and stats, gathered once
+/// per pass by `StreamSession::run_checks`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckInputs<'a> {
+    pub video_codec: Option<VideoCodec>,
+    pub video_width: Option<u32>,
+    pub video_height: Option<u32>,
+    pub video_profile: Option<&'a str>,
+    pub sample_aspect_ratio: Option<(u32, u32)>,
+    pub pixel_format: Option<PixelFormat>,
+    pub interlace_mode: Option<&'a str>,
+    /// (SPS, PPS) counts from the AVC sequence header
+    pub avc_parameter_sets: Option<(usize, usize)>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u8>,
+    /// The AAC profile, e.g. "HE-AAC 24kHz→48kHz"
+    pub aac_profile: Option<&'a str>,
+    pub audio_frame_duration_ms: Option<f64>,
+    /// (measured, nominal from the AudioSpecificConfig)
+    pub aac_bitrate_kbps: Option<(f64, u32)>,
+    /// Latest keyframe interval in seconds, used until there are enough for a mean
+    pub current_keyframe_interval: Option<f64>,
+    pub video_bitrate_kbps: Option<f64>,
+    pub audio_bitrate_kbps: Option<f64>,
+    pub max_reorder_depth: Option<u32>,
+    pub max_composition_offset: Option<i32>,
+    pub b_pyramid: bool,
+    pub gop_length: Option<u32>,
+    /// Nominal frame rate from the SPS, else the measured one
+    pub fps: Option<f64>,
+    pub non_idr_keyframes: u64,
+    pub recovery_point_keyframes: u64,
+    pub malformed_access_units: u64,
+    pub dropped_frame_rate: Option<f64>,
+    pub off_cadence_rate: Option<f64>,
+    pub peak_video_bitrate_kbps: Option<f64>,
+    pub network_jitter_ms: Option<f64>,
+}
+
 /// Tracks stream health and compatibility issues
 pub struct StreamDiagnostics {
     pub profile: ServiceProfile,
 
     // Sequence headers
-    pub avc_seq_header_received: bool,
-    pub avc_seq_header_time: Option<Instant>,
+    /// Video decoder configuration (avcC, hvcC, av1C or vpcC), whatever
+    /// the codec
+    pub video_config_received: bool,
+    pub video_config_time: Option<Instant>,
+    /// Codec of the first video configuration
+    pub video_config_codec: Option<VideoCodec>,
     pub aac_seq_header_received: bool,
     pub aac_seq_header_time: Option<Instant>,
 
-    // First keyframe
-    pub first_keyframe_time: Option<Instant>,
+    // Startup, in order; a recording has no handshake or connect
+    pub handshake_start_time: Option<Instant>,
+    pub handshake_complete_time: Option<Instant>,
+    pub connect_time: Option<Instant>,
     pub stream_start_time: Option<Instant>,
+    pub first_keyframe_time: Option<Instant>,
 
     // Timestamp tracking
     pub last_video_ts: Option<u32>,
     pub last_audio_ts: Option<u32>,
     pub video_ts_rollbacks: u32,
     pub audio_ts_rollbacks: u32,
+    /// Media messages whose chunk header timestamp went backward on their
+    /// chunk stream, and the largest such step as (chunk stream, from, to)
+    chunk_timestamp_regressions: u32,
+    worst_chunk_timestamp_regression: Option<(u32, u32, u32)>,
     pub max_video_ts_gap: u32,
     pub max_audio_ts_gap: u32,
-    pub max_av_desync_ms: i64,
+    // Wall-clock arrival of the latest timestamp on each track
+    last_video_arrival: Option<Instant>,
+    last_audio_arrival: Option<Instant>,
+    av_desync_samples: VecDeque<f64>,
+    /// Rolling average of video minus audio position, in ms
+    pub av_desync_ms: Option<f64>,
 
     // Metadata
     pub metadata_received: bool,
+    /// Arrival of the first onMetaData, and of the first audio or video
+    /// frame (sequence headers aside)
+    pub metadata_time: Option<Instant>,
+    pub first_media_time: Option<Instant>,
+    /// Video frames received before onMetaData, when frames came first
+    pub metadata_late_after_frames: Option<u64>,
     pub metadata_has_dimensions: bool,
     pub metadata_has_framerate: bool,
     pub metadata_has_bitrate: bool,
+    pub metadata: Option<StreamMetadata>,
+    video_bitrate_samples: VecDeque<f64>,
+    audio_bitrate_samples: VecDeque<f64>,
 
     // Frame analysis
     pub has_b_frames: bool,
+    pub has_mp3_audio: bool,
+    /// Sequence headers that changed the configuration, as (before, after)
+    pub video_config_changes: Vec<(String, String)>,
+    pub audio_config_changes: Vec<(String, String)>,
     pub keyframe_intervals: Vec<f64>,
 
+    // Protocol
+    pub invalid_chunk_size: Option<u32>,
+    /// Commands that decoded to fewer values than they need
+    pub short_commands: Vec<ShortCommand>,
+    pub rejected_messages: u32,
+    pub largest_rejected_message: Option<(u8, u32)>,
+    /// Seconds without any data before the stream was dropped as frozen
+    pub idle_timeout_secs: Option<u64>,
+    /// objectEncoding the client asked for in connect and the one we replied with
+    pub requested_encoding: Option<f64>,
+    pub object_encoding: Option<u8>,
+    /// Consecutive pings the client left unanswered before we gave up
+    pub ping_timeout: Option<u32>,
+    /// StreamDry events: the publisher ran out of data to send
+    pub stream_dry_count: u32,
+    /// Latest status reported by the peer for each code
+    pub net_status: Vec<NetStatus>,
+    /// FLV tags (in a file or in Aggregate messages) whose size didn't match
+    /// their back-pointer, and bytes skipped to find the next tag
+    pub flv_back_pointer_mismatches: u32,
+    pub flv_skipped_bytes: usize,
+
     // Collected diagnostics
     diagnostics: Vec<Diagnostic>,
+    health: HealthScore,
     last_check_time: Option<Instant>,
+    check_interval: Duration,
+    /// Notified of diagnostics raised since the previous pass
+    sinks: Vec<Box<dyn DiagnosticSink>>,
+    raised_keys: Vec<String>,
+
+    // Replaces the wall clock when replaying a recording
+    clock: Option<Instant>,
 }
 
 impl StreamDiagnostics {
     pub fn new() -> Self {
         Self {
-            profile: ServiceProfile::Generic,
-            avc_seq_header_received: false,
-            avc_seq_header_time: None,
+            profile: ServiceProfile::generic(),
+            video_config_received: false,
+            video_config_time: None,
+            video_config_codec: None,
             aac_seq_header_received: false,
             aac_seq_header_time: None,
-            first_keyframe_time: None,
+            handshake_start_time: None,
+            handshake_complete_time: None,
+            connect_time: None,
             stream_start_time: None,
+            first_keyframe_time: None,
             last_video_ts: None,
             last_audio_ts: None,
             video_ts_rollbacks: 0,
             audio_ts_rollbacks: 0,
+            chunk_timestamp_regressions: 0,
+            worst_chunk_timestamp_regression: None,
             max_video_ts_gap: 0,
             max_audio_ts_gap: 0,
-            max_av_desync_ms: 0,
+            last_video_arrival: None,
+            last_audio_arrival: None,
+            av_desync_samples: VecDeque::with_capacity(AV_DESYNC_WINDOW),
+            av_desync_ms: None,
             metadata_received: false,
+            metadata_time: None,
+            first_media_time: None,
+            metadata_late_after_frames: None,
             metadata_has_dimensions: false,
             metadata_has_framerate: false,
             metadata_has_bitrate: false,
+            metadata: None,
+            video_bitrate_samples: VecDeque::with_capacity(BITRATE_DEVIATION_WINDOW),
+            audio_bitrate_samples: VecDeque::with_capacity(BITRATE_DEVIATION_WINDOW),
             has_b_frames: false,
+            has_mp3_audio: false,
+            video_config_changes: Vec::new(),
+            audio_config_changes: Vec::new(),
             keyframe_intervals: Vec::new(),
+            invalid_chunk_size: None,
+            short_commands: Vec::new(),
+            rejected_messages: 0,
+            largest_rejected_message: None,
+            idle_timeout_secs: None,
+            requested_encoding: None,
+            object_encoding: None,
+            ping_timeout: None,
+            stream_dry_count: 0,
+            net_status: Vec::new(),
+            flv_back_pointer_mismatches: 0,
+            flv_skipped_bytes: 0,
             diagnostics: Vec::new(),
+            health: HealthScore::default(),
             last_check_time: None,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            sinks: Vec::new(),
+            raised_keys: Vec::new(),
+            clock: None,
         }
     }
 
@@ -119,39 +745,88 @@ impl StreamDiagnostics {
         self.profile = profile;
     }
 
+    /// Notify `sink` of every diagnostic raised from the next check on.
+    pub fn add_sink(&mut self, sink: Box<dyn DiagnosticSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Measure time from `now` instead of the wall clock from here on.
+    pub fn set_clock(&mut self, now: Instant) {
+        self.clock = Some(now);
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.unwrap_or_else(Instant::now)
+    }
+
+    pub fn record_handshake(&mut self, started: Instant, completed: Instant) {
+        self.handshake_start_time = Some(started);
+        self.handshake_complete_time = Some(completed);
+    }
+
+    pub fn record_connect(&mut self, at: Instant, requested_encoding: Option<f64>, object_encoding: u8) {
+        self.connect_time = Some(at);
+        self.requested_encoding = requested_encoding;
+        self.object_encoding = Some(object_encoding);
+    }
+
+    pub fn startup_timeline(&self) -> StartupTimeline {
+        let ms = |from: Option<Instant>, to: Option<Instant>| match (from, to) {
+            (Some(from), Some(to)) => Some(to.saturating_duration_since(from).as_secs_f64() * 1000.0),
+            _ => None,
+        };
+        StartupTimeline {
+            handshake_ms: ms(self.handshake_start_time, self.handshake_complete_time),
+            connect_ms: ms(self.handshake_complete_time, self.connect_time),
+            publish_ms: ms(self.connect_time, self.stream_start_time),
+            first_keyframe_ms: ms(self.stream_start_time, self.first_keyframe_time),
+        }
+    }
+
     pub fn record_stream_start(&mut self) {
         if self.stream_start_time.is_none() {
-            self.stream_start_time = Some(Instant::now());
+            self.stream_start_time = Some(self.now());
         }
     }
 
-    pub fn record_avc_seq_header(&mut self) {
-        if !self.avc_seq_header_received {
-            self.avc_seq_header_received = true;
-            self.avc_seq_header_time = Some(Instant::now());
+    pub fn record_video_config(&mut self, codec: Option<VideoCodec>) {
+        if !self.video_config_received {
+            self.video_config_received = true;
+            self.video_config_time = Some(self.now());
+            self.video_config_codec = codec;
         }
     }
 
     pub fn record_aac_seq_header(&mut self) {
         if !self.aac_seq_header_received {
             self.aac_seq_header_received = true;
-            self.aac_seq_header_time = Some(Instant::now());
+            self.aac_seq_header_time = Some(self.now());
         }
     }
 
     pub fn record_keyframe(&mut self, interval_secs: Option<f64>) {
         if self.first_keyframe_time.is_none() {
-            self.first_keyframe_time = Some(Instant::now());
+            self.first_keyframe_time = Some(self.now());
         }
         if let Some(interval) = interval_secs {
             self.keyframe_intervals.push(interval);
-            // Keep last 10 intervals
-            if self.keyframe_intervals.len() > 10 {
+            if self.keyframe_intervals.len() > KEYFRAME_INTERVAL_HISTORY {
                 self.keyframe_intervals.remove(0);
             }
         }
     }
 
+    pub fn keyframe_interval_stats(&self) -> Option<KeyframeIntervalStats> {
+        KeyframeIntervalStats::from_intervals(&self.keyframe_intervals)
+    }
+
+    pub fn record_chunk_timestamp_regression(&mut self, cs_id: u32, previous: u32, timestamp: u32) {
+        self.chunk_timestamp_regressions += 1;
+        if self.worst_chunk_timestamp_regression.is_none_or(|(_, from, to)| previous - timestamp > from - to) {
+            self.worst_chunk_timestamp_regression = Some((cs_id, previous, timestamp));
+        }
+    }
+
     pub fn record_video_timestamp(&mut self, ts: u32) {
         if let Some(last) = self.last_video_ts {
             if ts < last && (last - ts) < 0x80000000 {
@@ -165,7 +840,7 @@ impl StreamDiagnostics {
             }
         }
         self.last_video_ts = Some(ts);
-        self.update_av_desync();
+        self.last_video_arrival = Some(self.now());
     }
 
     pub fn record_audio_timestamp(&mut self, ts: u32) {
@@ -180,59 +855,224 @@ impl StreamDiagnostics {
             }
         }
         self.last_audio_ts = Some(ts);
-        self.update_av_desync();
+        self.last_audio_arrival = Some(self.now());
     }
 
-    fn update_av_desync(&mut self) {
-        if let (Some(v), Some(a)) = (self.last_video_ts, self.last_audio_ts) {
-            let desync = (v as i64) - (a as i64);
-            if desync.abs() > self.max_av_desync_ms.abs() {
-                self.max_av_desync_ms = desync;
-            }
+    /// Sample video minus audio position as of `now`. Each track's latest
+    /// timestamp is advanced by the wall-clock time since it arrived, so both
+    /// are compared at the same moment regardless of interleaving.
+    fn sample_av_desync(&mut self, now: Instant) {
+        if self
+            .stream_start_time
+            .is_none_or(|start| now.duration_since(start) < AV_DESYNC_WARMUP)
+        {
+            return;
+        }
+        let (Some(v), Some(a), Some(v_at), Some(a_at)) = (
+            self.last_video_ts,
+            self.last_audio_ts,
+            self.last_video_arrival,
+            self.last_audio_arrival,
+        ) else {
+            return;
+        };
+
+        let video_pos = v as f64 + now.duration_since(v_at).as_secs_f64() * 1000.0;
+        let audio_pos = a as f64 + now.duration_since(a_at).as_secs_f64() * 1000.0;
+
+        if self.av_desync_samples.len() == AV_DESYNC_WINDOW {
+            self.av_desync_samples.pop_front();
         }
+        self.av_desync_samples.push_back(video_pos - audio_pos);
+        self.av_desync_ms = Some(
+            self.av_desync_samples.iter().sum::<f64>() / self.av_desync_samples.len() as f64,
+        );
     }
 
     pub fn record_b_frame(&mut self) {
         self.has_b_frames = true;
     }
 
-    pub fn record_metadata(&mut self, has_dimensions: bool, has_framerate: bool, has_bitrate: bool) {
+    pub fn record_mp3_audio(&mut self) {
+        self.has_mp3_audio = true;
+    }
+
+    pub fn record_video_config_change(&mut self, before: String, after: String) {
+        self.video_config_changes.push((before, after));
+    }
+
+    pub fn record_audio_config_change(&mut self, before: String, after: String) {
+        self.audio_config_changes.push((before, after));
+    }
+
+    pub fn record_invalid_chunk_size(&mut self, requested: u32) {
+        self.invalid_chunk_size = Some(requested);
+    }
+
+    pub fn record_short_command(&mut self, command: &ShortCommand) {
+        if !self.short_commands.contains(command) {
+            self.short_commands.push(command.clone());
+        }
+    }
+
+    pub fn record_rejected_message(&mut self, type_id: u8, length: u32) {
+        self.rejected_messages += 1;
+        if self.largest_rejected_message.is_none_or(|(_, largest)| length > largest) {
+            self.largest_rejected_message = Some((type_id, length));
+        }
+    }
+
+    pub fn set_check_interval(&mut self, interval: Duration) {
+        self.check_interval = interval;
+    }
+
+    /// Make the next `check_all` run in full, however soon it comes.
+    pub fn force_next_check(&mut self) {
+        self.last_check_time = None;
+    }
+
+    pub fn record_idle_timeout(&mut self, secs: u64) {
+        self.idle_timeout_secs = Some(secs);
+    }
+
+    pub fn record_ping_timeout(&mut self, missed: u32) {
+        self.ping_timeout = Some(missed);
+    }
+
+    pub fn record_stream_dry(&mut self) {
+        self.stream_dry_count += 1;
+    }
+
+    pub fn record_flv_tag_errors(&mut self, back_pointer_mismatches: u32, skipped_bytes: usize) {
+        self.flv_back_pointer_mismatches += back_pointer_mismatches;
+        self.flv_skipped_bytes += skipped_bytes;
+    }
+
+    pub fn record_net_status(&mut self, status: &NetStatus) {
+        self.net_status.retain(|s| s.code != status.code);
+        self.net_status.push(status.clone());
+        // Let an upstream error show up on the very next pass
+        self.force_next_check();
+    }
+
+    /// An audio or video frame arrived; sequence headers don't count.
+    pub fn record_media_frame(&mut self) {
+        if self.first_media_time.is_none() {
+            self.first_media_time = Some(self.now());
+        }
+    }
+
+    /// `video_frames` is how many video frames came before this metadata.
+    pub fn record_metadata(&mut self, metadata: &StreamMetadata, video_frames: u64) {
+        if self.metadata_time.is_none() {
+            self.metadata_time = Some(self.now());
+            if self.first_media_time.is_some() {
+                self.metadata_late_after_frames = Some(video_frames);
+            }
+        }
         self.metadata_received = true;
-        self.metadata_has_dimensions = has_dimensions;
-        self.metadata_has_framerate = has_framerate;
-        self.metadata_has_bitrate = has_bitrate;
+        self.metadata_has_dimensions = metadata.has_dimensions();
+        self.metadata_has_framerate = metadata.framerate.is_some();
+        self.metadata_has_bitrate = metadata.has_bitrate();
+        self.metadata = Some(metadata.clone());
+    }
+
+    /// Forget the stored metadata; it still counts as having been received.
+    pub fn clear_metadata(&mut self) {
+        self.metadata_has_dimensions = false;
+        self.metadata_has_framerate = false;
+        self.metadata_has_bitrate = false;
+        self.metadata = None;
     }
 
     /// Run all diagnostic checks and return warnings
-    pub fn check_all(
-        &mut self,
-        video_width: Option<u32>,
-        video_height: Option<u32>,
-        video_profile: Option<&str>,
-        audio_sample_rate: Option<u32>,
-        audio_channels: Option<u8>,
-        aac_profile: Option<&str>,
-        current_keyframe_interval: Option<f64>,
-    ) -> Vec<Diagnostic> {
-        // Throttle checks to once per second
-        let now = Instant::now();
-        if let Some(last) = self.last_check_time {
-            if now.duration_since(last).as_millis() < 500 {
-                return self.diagnostics.clone();
-            }
+    pub fn check_all(&mut self, inputs: &CheckInputs) -> Vec<Diagnostic> {
+        let CheckInputs {
+            video_codec,
+            video_width,
+            video_height,
+            video_profile,
+            sample_aspect_ratio,
+            pixel_format,
+            interlace_mode,
+            avc_parameter_sets,
+            audio_sample_rate,
+            audio_channels,
+            aac_profile,
+            audio_frame_duration_ms,
+            aac_bitrate_kbps,
+            current_keyframe_interval,
+            video_bitrate_kbps,
+            audio_bitrate_kbps,
+            max_reorder_depth,
+            max_composition_offset,
+            b_pyramid,
+            gop_length,
+            fps,
+            non_idr_keyframes,
+            recovery_point_keyframes,
+            malformed_access_units,
+            dropped_frame_rate,
+            off_cadence_rate,
+            peak_video_bitrate_kbps,
+            network_jitter_ms,
+        } = *inputs;
+        // Repeated calls within the check interval reuse the last pass
+        let now = self.now();
+        if let Some(last) = self.last_check_time
+            && now.duration_since(last) < self.check_interval
+        {
+            return self.diagnostics.clone();
         }
         self.last_check_time = Some(now);
 
         self.diagnostics.clear();
 
         // === SEQUENCE HEADERS ===
-        if !self.avc_seq_header_received {
-            self.diagnostics.push(Diagnostic::error("Video", "No AVC sequence header received"));
+        // Before any video arrives, expect the usual AVC; codecs like VP6
+        // have no configuration to send
+        if !self.video_config_received {
+            if video_codec.is_none_or(|codec| codec.has_config_record()) {
+                let codec = video_codec.map_or("AVC", |codec| codec.short_name());
+                self.diagnostics.push(Diagnostic::error("Video", format!("No {} sequence header received", codec)));
+            }
+        } else if avc_parameter_sets.is_some_and(|(_, pps)| pps == 0) {
+            self.diagnostics.push(Diagnostic::error(
+                "Video",
+                "AVC sequence header carries no PPS (most decoders can't start without one)"
+            ));
         }
-        if !self.aac_seq_header_received {
+        // MP3 has no sequence header; it gets its own warning below
+        if !self.aac_seq_header_received && !self.has_mp3_audio {
             self.diagnostics.push(Diagnostic::error("Audio", "No AAC sequence header received"));
         }
 
+        // === CONFIG CHANGES ===
+        // Most players can't follow a new codec configuration mid-stream
+        for (category, changes) in [("Video", &self.video_config_changes), ("Audio", &self.audio_config_changes)] {
+            if let Some((before, after)) = changes.last() {
+                let count = if changes.len() > 1 {
+                    format!(" ({} changes)", changes.len())
+                } else {
+                    String::new()
+                };
+                self.diagnostics.push(Diagnostic::error(
+                    category,
+                    format!("{} config changed mid-stream: {} → {}{}", category, before, after, count)
+                ));
+            }
+        }
+
+        // === HANDSHAKE ===
+        if let Some(handshake_ms) = self.startup_timeline().handshake_ms
+            && handshake_ms > SLOW_HANDSHAKE_MS
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Timing",
+                format!("Handshake took {:.0} ms (slow network between encoder and server?)", handshake_ms)
+            ));
+        }
+
         // === FIRST KEYFRAME TIMING ===
         if let Some(start) = self.stream_start_time {
             if self.first_keyframe_time.is_none() {
@@ -255,84 +1095,251 @@ impl StreamDiagnostics {
         }
 
         // === KEYFRAME INTERVAL ===
-        if let Some(interval) = current_keyframe_interval {
-            let max_interval = match self.profile {
-                ServiceProfile::Twitch => 2.0,
-                ServiceProfile::YouTube => 4.0,
-                ServiceProfile::Generic => 4.0,
-            };
+        // Judged on the recent mean so one odd GOP doesn't trip it
+        let interval_stats = self.keyframe_interval_stats();
+        if let Some(interval) = interval_stats.map(|s| s.mean).or(current_keyframe_interval) {
+            let max_interval = self.profile.max_keyframe_interval;
             if interval > max_interval {
                 self.diagnostics.push(Diagnostic::error(
                     "Video",
-                    format!("Keyframe interval {:.1}s exceeds {} max ({:.0}s)",
+                    format!("Keyframe interval {:.1}s exceeds {} max ({}s)",
                         interval, self.profile.name(), max_interval)
                 ));
-            } else if interval > max_interval * 0.9 {
+            } else if interval > max_interval * KEYFRAME_INTERVAL_NEAR_LIMIT {
                 self.diagnostics.push(Diagnostic::warning(
                     "Video",
-                    format!("Keyframe interval {:.1}s near {} limit ({:.0}s)",
+                    format!("Keyframe interval {:.1}s near {} limit ({}s)",
                         interval, self.profile.name(), max_interval)
                 ));
             }
         }
 
+        if let Some(stats) = interval_stats
+            && stats.samples >= KEYFRAME_JITTER_MIN_SAMPLES
+            && stats.stddev > stats.mean * KEYFRAME_JITTER_LIMIT
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Keyframe interval varies {:.1}-{:.1}s (±{:.2}s): keyframes follow scene changes, not a fixed GOP",
+                    stats.min, stats.max, stats.stddev)
+            ));
+        }
+
+        // === NON-IDR KEYFRAMES ===
+        if non_idr_keyframes > 0 {
+            let recovery = if recovery_point_keyframes > 0 {
+                format!(", {} with recovery point SEI", recovery_point_keyframes)
+            } else {
+                String::new()
+            };
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("{} keyframe(s) without an IDR slice{} (joining viewers may see corrupt frames)",
+                    non_idr_keyframes, recovery)
+            ));
+        }
+
+        // === NAL UNIT FRAMING ===
+        if malformed_access_units > 0 {
+            self.diagnostics.push(Diagnostic::error(
+                "Video",
+                format!("{} malformed video frame(s): NAL unit lengths don't fit the payload (encoder bitstream bug?)",
+                    malformed_access_units)
+            ));
+        }
+
+        // === GOP LENGTH ===
+        if let (Some(frames), Some(fps)) = (gop_length, fps)
+            && fps > 0.0
+        {
+            let max_frames = (self.profile.max_keyframe_interval * fps).round() as u32;
+            if frames > max_frames {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Video",
+                    format!("GOP of {} frames exceeds {} max of {} ({}s at {:.0} fps)",
+                        frames, self.profile.name(), max_frames, self.profile.max_keyframe_interval, fps)
+                ));
+            }
+        }
+
         // === B-FRAMES ===
         if self.has_b_frames {
-            match self.profile {
-                ServiceProfile::Twitch => {
-                    self.diagnostics.push(Diagnostic::warning(
-                        "Video",
-                        "B-frames detected (may increase latency on Twitch)"
-                    ));
-                }
-                _ => {
-                    self.diagnostics.push(Diagnostic::info(
-                        "Video",
-                        "B-frames detected"
-                    ));
-                }
+            let structure = if b_pyramid { "B-frames detected, in a B-pyramid" } else { "B-frames detected" };
+            if self.profile.allow_b_frames {
+                self.diagnostics.push(Diagnostic::info(
+                    "Video",
+                    structure
+                ));
+            } else {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Video",
+                    format!("{} (may increase latency on {})", structure, self.profile.name())
+                ));
             }
         }
 
+        // === DROPPED FRAMES ===
+        if let Some(rate) = dropped_frame_rate
+            && rate > 0.01
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Encoder dropping frames ({:.1}% of expected frames missing)", rate * 100.0)
+            ));
+        }
+
+        // === VARIABLE FRAME RATE ===
+        if let Some(rate) = off_cadence_rate
+            && rate > VFR_LIMIT
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Variable frame rate detected ({:.0}% of frames off-cadence)", rate * 100.0)
+            ));
+        }
+
+        // === NETWORK JITTER ===
+        // Frames arriving unevenly against their own timestamps: the network,
+        // not the encoder
+        if let Some(jitter) = network_jitter_ms
+            && jitter > NETWORK_JITTER_LIMIT_MS
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Timing",
+                format!("Bursty network: video frames arrive with {:.0} ms of jitter against their timestamps (risks buffer underruns downstream)", jitter)
+            ));
+        }
+
+        // === FRAME REORDERING ===
+        // Presentation trails decode by the largest composition offset, which
+        // the player has to buffer before it can show anything
+        if let (Some(depth), Some(offset_ms)) = (max_reorder_depth, max_composition_offset)
+            && offset_ms >= 100
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Frame reordering adds {}ms latency ({} frames of lookahead)", offset_ms, depth)
+            ));
+        }
+
         // === VIDEO PROFILE ===
-        if let Some(profile) = video_profile {
-            if profile.contains("Baseline") {
-                self.diagnostics.push(Diagnostic::info(
+        if let Some(profile) = video_profile
+            && profile.contains("Baseline")
+        {
+            self.diagnostics.push(Diagnostic::info(
+                "Video",
+                "Baseline profile (consider Main/High for better compression)"
+            ));
+        }
+
+        // === PIXEL FORMAT ===
+        if let Some(format) = pixel_format
+            && self.profile.require_8bit_420
+            && !format.is_8bit_420()
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("{} video is likely to be rejected by {} (only 4:2:0 8-bit is accepted)", format, self.profile.name)
+            ));
+        }
+
+        // === INTERLACING ===
+        if let Some(mode) = interlace_mode {
+            let message = format!("Interlaced video ({})", mode);
+            if self.profile.allow_interlaced {
+                self.diagnostics.push(Diagnostic::warning(
                     "Video",
-                    "Baseline profile (consider Main/High for better compression)"
+                    format!("{}: players may show combing (deinterlace in the encoder)", message)
+                ));
+            } else {
+                self.diagnostics.push(Diagnostic::error(
+                    "Video",
+                    format!("{} is rejected by {} (only progressive video is accepted)", message, self.profile.name())
                 ));
             }
         }
 
         // === RESOLUTION ===
         if let (Some(w), Some(h)) = (video_width, video_height) {
-            // Check for non-standard resolutions
-            let is_standard = matches!(
-                (w, h),
-                (1920, 1080) | (1280, 720) | (854, 480) | (640, 360) |
-                (2560, 1440) | (3840, 2160) | (1080, 1920) | (720, 1280)
-            );
-            if !is_standard && w % 2 != 0 || h % 2 != 0 {
+            let max = self.profile.max_resolution();
+            if w % 2 != 0 || h % 2 != 0 {
                 self.diagnostics.push(Diagnostic::error(
                     "Video",
                     format!("Resolution {}x{} has odd dimensions (must be even)", w, h)
                 ));
+            } else if let Some((long, short)) = max
+                && (w.max(h) > long || w.min(h) > short)
+            {
+                self.diagnostics.push(Diagnostic::error(
+                    "Video",
+                    format!("Resolution {}x{} exceeds {} max ({}x{})", w, h, self.profile.name(), long, short)
+                ));
+            } else if !self.profile.is_standard_resolution(w, h) {
+                self.diagnostics.push(Diagnostic::info(
+                    "Video",
+                    format!("Non-standard resolution {}x{} (valid, but players will scale it)", w, h)
+                ));
+            }
+
+            if h > 0 {
+                let aspect = aspect_ratio_name(w, h)
+                    .map_or_else(|| format!("{:.2}:1", w as f64 / h as f64), str::to_string);
+                self.diagnostics.push(Diagnostic::info("Video", format!("Aspect ratio {}", aspect)));
             }
         }
 
-        // === AUDIO SAMPLE RATE ===
-        if let Some(sr) = audio_sample_rate {
-            let allowed = match self.profile {
-                ServiceProfile::Twitch => matches!(sr, 44100 | 48000),
-                ServiceProfile::YouTube => matches!(sr, 44100 | 48000 | 96000),
-                ServiceProfile::Generic => matches!(sr, 22050 | 44100 | 48000 | 96000),
+        // === PIXEL ASPECT RATIO ===
+        if let Some((sar_w, sar_h)) = sample_aspect_ratio
+            && sar_w != sar_h
+        {
+            let resolution = match (video_width, video_height) {
+                (Some(w), Some(h)) => format!(
+                    ": coded {}x{} displays as {}x{}",
+                    w,
+                    h,
+                    w as u64 * sar_w as u64 / sar_h as u64,
+                    h
+                ),
+                _ => String::new(),
             };
-            if !allowed {
-                self.diagnostics.push(Diagnostic::error(
-                    "Audio",
-                    format!("{} Hz sample rate not supported by {}", sr, self.profile.name())
-                ));
-            }
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Non-square pixels (SAR {}:{}){}", sar_w, sar_h, resolution)
+            ));
+        }
+
+        // === BITRATE ===
+        let current_bitrate_kbps = match (video_bitrate_kbps, audio_bitrate_kbps) {
+            (None, None) => None,
+            (video, audio) => Some(video.unwrap_or(0.0) + audio.unwrap_or(0.0)),
+        };
+        if let (Some(kbps), Some(max_kbps)) = (current_bitrate_kbps, self.profile.max_bitrate_kbps)
+            && kbps > max_kbps
+        {
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Bitrate {:.0} kbps exceeds {} max ({:.0} kbps)",
+                    kbps, self.profile.name(), max_kbps)
+            ));
+        } else if let (Some(peak), Some(max_kbps)) = (peak_video_bitrate_kbps, self.profile.max_bitrate_kbps)
+            && peak > max_kbps
+        {
+            // Short spikes over the ceiling can still be throttled or dropped by the ingest
+            self.diagnostics.push(Diagnostic::warning(
+                "Video",
+                format!("Peak video bitrate {:.0} kbps exceeded {} max ({:.0} kbps)",
+                    peak, self.profile.name(), max_kbps)
+            ));
+        }
+
+        // === AUDIO SAMPLE RATE ===
+        if let Some(sr) = audio_sample_rate
+            && !self.profile.allowed_sample_rates.contains(&sr)
+        {
+            self.diagnostics.push(Diagnostic::error(
+                "Audio",
+                format!("{} Hz sample rate not supported by {}", sr, self.profile.name())
+            ));
         }
 
         // === AUDIO CHANNELS ===
@@ -342,16 +1349,12 @@ impl StreamDiagnostics {
                     "Audio",
                     "Mono audio (stereo recommended for streaming)"
                 ));
-            } else if ch > 2 {
-                match self.profile {
-                    ServiceProfile::Twitch => {
-                        self.diagnostics.push(Diagnostic::error(
-                            "Audio",
-                            format!("{} channels not supported by Twitch (max 2)", ch)
-                        ));
-                    }
-                    _ => {}
-                }
+            } else if ch > self.profile.max_audio_channels {
+                self.diagnostics.push(Diagnostic::error(
+                    "Audio",
+                    format!("{} channels not supported by {} (max {})",
+                        ch, self.profile.name(), self.profile.max_audio_channels)
+                ));
             }
         }
 
@@ -362,19 +1365,63 @@ impl StreamDiagnostics {
                     "Audio",
                     "AAC Main profile (AAC-LC recommended for compatibility)"
                 ));
-            } else if profile.contains("HE-AAC") || profile.contains("SBR") {
-                match self.profile {
-                    ServiceProfile::Twitch => {
-                        self.diagnostics.push(Diagnostic::warning(
-                            "Audio",
-                            "HE-AAC may have compatibility issues on Twitch"
-                        ));
-                    }
-                    _ => {}
-                }
+            } else if (profile.contains("HE-AAC") || profile.contains("SBR"))
+                && !self.profile.allow_he_aac
+            {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Audio",
+                    format!("{} may have compatibility issues on {}", profile, self.profile.name())
+                ));
+            }
+        }
+
+        // === AUDIO/GOP ALIGNMENT ===
+        if let (Some(frame_ms), Some(frames), Some(fps)) = (audio_frame_duration_ms, gop_length, fps)
+            && fps > 0.0
+        {
+            let gop_ms = frames as f64 * 1000.0 / fps;
+            let audio_frames = gop_ms / frame_ms;
+            // The frame rate is only known to a couple of decimals
+            let aligned = (audio_frames - audio_frames.round()).abs() < 0.01;
+            let message = format!("GOP of {:.0} ms spans {:.2} audio frames of {:.2} ms", gop_ms, audio_frames, frame_ms);
+            if aligned || !self.profile.require_av_gop_alignment {
+                self.diagnostics.push(Diagnostic::info("Audio", message));
+            } else {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Audio",
+                    format!("{} (not a whole number: segments will drift against audio)", message)
+                ));
+            }
+        }
+
+        // === AAC BITRATE ===
+        // Measured over the frames' play time against the nearest standard setting
+        if let Some((measured, nominal)) = aac_bitrate_kbps {
+            if let Some(max_kbps) = self.profile.max_audio_bitrate_kbps
+                && measured > max_kbps * 1.05
+            {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Audio",
+                    format!("AAC bitrate {:.0} kbps exceeds {} audio max ({:.0} kbps)",
+                        measured, self.profile.name(), max_kbps)
+                ));
+            } else if (measured - nominal as f64).abs() > nominal as f64 * 0.1 {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Audio",
+                    format!("AAC bitrate {:.0} kbps is far from any standard rate (nearest {} kbps); check the encoder's audio settings",
+                        measured, nominal)
+                ));
             }
         }
 
+        // === MP3 AUDIO ===
+        if self.has_mp3_audio {
+            self.diagnostics.push(Diagnostic::warning(
+                "Audio",
+                "MP3 audio is deprecated by most streaming services (use AAC)"
+            ));
+        }
+
         // === TIMESTAMP ISSUES ===
         if self.video_ts_rollbacks > 0 {
             self.diagnostics.push(Diagnostic::error(
@@ -389,6 +1436,16 @@ impl StreamDiagnostics {
             ));
         }
 
+        // Backward in the chunk headers themselves: the encoder's RTMP muxer
+        // rather than its media clock
+        if let Some((cs_id, from, to)) = self.worst_chunk_timestamp_regression {
+            self.diagnostics.push(Diagnostic::warning(
+                "Protocol",
+                format!("{} message timestamp(s) went backward on their chunk stream (worst: {}ms to {}ms on chunk stream {})",
+                    self.chunk_timestamp_regressions, from, to, cs_id)
+            ));
+        }
+
         // Large timestamp gaps (> 1 second = 1000ms)
         if self.max_video_ts_gap > 1000 {
             self.diagnostics.push(Diagnostic::warning(
@@ -403,33 +1460,255 @@ impl StreamDiagnostics {
             ));
         }
 
-        // A/V desync
-        if self.max_av_desync_ms.abs() > 500 {
+        // A/V desync: only a full window above threshold counts, not a spike
+        self.sample_av_desync(now);
+        if let Some(desync) = self.av_desync_ms
+            && self.av_desync_samples.len() == AV_DESYNC_WINDOW
+            && desync.abs() > 500.0
+        {
             self.diagnostics.push(Diagnostic::warning(
                 "Timing",
-                format!("A/V desync detected ({}ms)", self.max_av_desync_ms)
+                format!("Sustained A/V desync ({:+.0}ms averaged over {} checks)", desync, AV_DESYNC_WINDOW)
             ));
         }
 
         // === METADATA ===
         if !self.metadata_received {
             // Only warn after stream has been going for a bit
-            if let Some(start) = self.stream_start_time {
-                if now.duration_since(start).as_secs() > 2 {
+            if let Some(start) = self.stream_start_time
+                && now.duration_since(start).as_secs() > 2
+            {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Metadata",
+                    "No onMetaData received from encoder"
+                ));
+            }
+        } else if let Some(frames) = self.metadata_late_after_frames {
+            let delay = match (self.first_media_time, self.metadata_time) {
+                (Some(media), Some(metadata)) => format!(", {:.1}s into the stream", metadata.saturating_duration_since(media).as_secs_f64()),
+                _ => String::new(),
+            };
+            self.diagnostics.push(Diagnostic::warning(
+                "Metadata",
+                format!("onMetaData arrived late, after {} video frame(s){} (some players miss it)", frames, delay)
+            ));
+        }
+
+        // Declared format vs what the SPS actually says
+        if let Some(meta) = &self.metadata {
+            if let (Some(mw), Some(mh), Some(w), Some(h)) = (meta.width, meta.height, video_width, video_height)
+                && (mw, mh) != (w, h)
+            {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Metadata",
+                    format!("onMetaData declares {}x{} but the video is {}x{}", mw, mh, w, h)
+                ));
+            }
+            // Allow for measurement jitter when the SPS has no timing info
+            if let (Some(declared), Some(actual)) = (meta.framerate, fps)
+                && (declared - actual).abs() / actual > 0.1
+            {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Metadata",
+                    format!("onMetaData declares {:.2} fps but the video runs at {:.2} fps", declared, actual)
+                ));
+            }
+
+            // Declared vs measured bitrate, averaged so rate-control swings don't trip it
+            let tracks = [
+                ("Video", "video", meta.video_data_rate, video_bitrate_kbps, &mut self.video_bitrate_samples),
+                ("Audio", "audio", meta.audio_data_rate, audio_bitrate_kbps, &mut self.audio_bitrate_samples),
+            ];
+            for (category, track, declared, measured, samples) in tracks {
+                let (Some(declared), Some(measured)) = (declared, measured) else {
+                    continue;
+                };
+                if samples.len() == BITRATE_DEVIATION_WINDOW {
+                    samples.pop_front();
+                }
+                samples.push_back(measured);
+                if samples.len() < BITRATE_DEVIATION_WINDOW {
+                    continue;
+                }
+                let average = samples.iter().sum::<f64>() / samples.len() as f64;
+                let deviation = (average - declared) / declared;
+                if deviation.abs() > BITRATE_DEVIATION_LIMIT {
                     self.diagnostics.push(Diagnostic::warning(
-                        "Metadata",
-                        "No onMetaData received from encoder"
+                        category,
+                        format!("Measured {} bitrate {:.0} kbps is {:.0}% {} the declared {:.0} kbps",
+                            track, average, deviation.abs() * 100.0,
+                            if deviation > 0.0 { "above" } else { "below" }, declared)
                     ));
                 }
             }
         }
 
+        // === ENCODER ===
+        let encoder = self.metadata.as_ref().and_then(|m| m.encoder.as_deref()).and_then(EncoderInfo::identify);
+        if let Some(encoder) = encoder {
+            for issue in encoder.issues(self.profile.allow_b_frames) {
+                self.diagnostics.push(Diagnostic {
+                    severity: issue.severity,
+                    category: "Metadata",
+                    message: format!("{}: {}", encoder, issue.message.replace("{profile}", self.profile.name())),
+                });
+            }
+        }
+
+        // === PROTOCOL ===
+        if let Some(requested) = self.invalid_chunk_size {
+            let action = if requested == 0 { "ignored" } else { "capped at 16 MB" };
+            self.diagnostics.push(Diagnostic::warning(
+                "Protocol",
+                format!("Encoder requested chunk size {} ({})", requested, action)
+            ));
+        }
+
+        for command in &self.short_commands {
+            let name = if command.name.is_empty() { "(unnamed)" } else { command.name.as_str() };
+            // Leftover bytes point at our decoding, none at the client's message
+            let cause = if command.undecoded_bytes > 0 {
+                format!("decoding stopped with {} byte(s) left: malformed or unsupported AMF", command.undecoded_bytes)
+            } else {
+                "the client sent it that way".to_string()
+            };
+            self.diagnostics.push(Diagnostic::warning(
+                "Protocol",
+                format!("Command {} decoded to {} of {} expected values ({})", name, command.values, command.required, cause)
+            ));
+        }
+
+        if let Some((type_id, length)) = self.largest_rejected_message {
+            self.diagnostics.push(Diagnostic::error(
+                "Protocol",
+                format!("{} oversized message(s) dropped (largest: type {}, {} bytes)",
+                    self.rejected_messages, type_id, length)
+            ));
+        }
+
+        match (self.requested_encoding, self.object_encoding) {
+            (Some(requested), Some(replied)) if requested != replied as f64 => {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Protocol",
+                    format!("Client requested objectEncoding {}, downgraded to AMF{}", requested, replied)
+                ));
+            }
+            (_, Some(3)) => {
+                self.diagnostics.push(Diagnostic::info("Protocol", "Client negotiated AMF3 object encoding"));
+            }
+            _ => {}
+        }
+
+        if self.flv_skipped_bytes > 0 {
+            self.diagnostics.push(Diagnostic::error(
+                "Protocol",
+                format!("Corrupt FLV tag(s): {} bytes skipped to find the next tag", self.flv_skipped_bytes)
+            ));
+        } else if self.flv_back_pointer_mismatches > 0 {
+            self.diagnostics.push(Diagnostic::warning(
+                "Protocol",
+                format!("{} FLV tag(s) with a PreviousTagSize not matching the tag", self.flv_back_pointer_mismatches)
+            ));
+        }
+
+        if let Some(secs) = self.idle_timeout_secs {
+            self.diagnostics.push(Diagnostic::error(
+                "Timing",
+                format!("Stream froze: no data for {}s, disconnected", secs)
+            ));
+        }
+
+        if let Some(missed) = self.ping_timeout {
+            self.diagnostics.push(Diagnostic::error(
+                "Protocol",
+                format!("Encoder left {} pings unanswered, disconnected", missed)
+            ));
+        }
+
+        if self.stream_dry_count > 0 {
+            self.diagnostics.push(Diagnostic::warning(
+                "Protocol",
+                format!("Publisher ran out of data {} time(s) (StreamDry)", self.stream_dry_count)
+            ));
+        }
+
+        for status in &self.net_status {
+            let message = if status.description.is_empty() {
+                status.code.clone()
+            } else {
+                format!("{}: {}", status.code, status.description)
+            };
+            self.diagnostics.push(match status.level.as_str() {
+                "error" => Diagnostic::error("Protocol", message),
+                "warning" => Diagnostic::warning("Protocol", message),
+                _ => Diagnostic::info("Protocol", message),
+            });
+        }
+
         // Sort by severity (errors first)
-        self.diagnostics.sort_by(|a, b| b.severity.cmp(&a.severity));
+        self.diagnostics.sort_by_key(|d| std::cmp::Reverse(d.severity));
 
+        self.health = self.score_health(dropped_frame_rate, off_cadence_rate);
+        self.notify_sinks();
         self.diagnostics.clone()
     }
 
+    /// Score the diagnostics just collected, as documented on `HealthScore`.
+    fn score_health(&self, dropped_frame_rate: Option<f64>, off_cadence_rate: Option<f64>) -> HealthScore {
+        let mut deductions = Vec::new();
+        for diag in &self.diagnostics {
+            let points = match diag.severity {
+                Severity::Error => SCORE_ERROR_PENALTY,
+                Severity::Warning => SCORE_WARNING_PENALTY,
+                Severity::Info => continue,
+            };
+            deductions.push((diag.message.clone(), points));
+        }
+
+        let metric_points = |raw: f64| raw.clamp(0.0, SCORE_METRIC_MAX_PENALTY) as u32;
+        if let Some(rate) = dropped_frame_rate {
+            deductions.push((format!("{:.1}% of frames dropped", rate * 100.0), metric_points(rate * 100.0)));
+        }
+        if let Some(rate) = off_cadence_rate {
+            deductions.push((format!("{:.0}% of frames off-cadence", rate * 100.0), metric_points(rate * 100.0 / 5.0)));
+        }
+        if let Some(desync) = self.av_desync_ms {
+            let excess = desync.abs() - SCORE_DESYNC_ALLOWANCE_MS;
+            deductions.push((format!("A/V drift of {:.0} ms", desync.abs()), metric_points(excess / 10.0)));
+        }
+        deductions.retain(|(_, points)| *points > 0);
+
+        let lost: u32 = deductions.iter().map(|(_, points)| points).sum();
+        let mut score = 100u32.saturating_sub(lost);
+        if self.diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            score = score.min(SCORE_ERROR_CAP);
+        }
+        HealthScore { score: score as u8, deductions }
+    }
+
+    /// Health score from the most recent check pass.
+    pub fn health(&self) -> &HealthScore {
+        &self.health
+    }
+
+    /// Pass diagnostics that weren't active after the previous pass to the sinks.
+    fn notify_sinks(&mut self) {
+        let keys: Vec<String> = self.diagnostics.iter().map(Diagnostic::dedup_key).collect();
+        for (diag, key) in self.diagnostics.iter().zip(&keys) {
+            if !self.raised_keys.contains(key) {
+                for sink in &mut self.sinks {
+                    sink.on_diagnostic(diag);
+                }
+            }
+        }
+        self.raised_keys = keys;
+    }
+
+    /// Results of the most recent check pass.
+    pub fn latest(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     pub fn error_count(&self) -> usize {
         self.diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
     }
@@ -438,3 +1717,33 @@ impl StreamDiagnostics {
         self.diagnostics.iter().filter(|d| d.severity == Severity::Warning).count()
     }
 }
+
+impl Default for StreamDiagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_read_only_the_inputs_they_are_given() {
+        let mut diagnostics = StreamDiagnostics::new();
+        diagnostics.profile = ServiceProfile::twitch();
+
+        let results = diagnostics.check_all(&CheckInputs {
+            current_keyframe_interval: Some(4.0),
+            ..Default::default()
+        });
+        assert!(results.iter().any(|d| d.severity == Severity::Error && d.message.starts_with("Keyframe interval 4.0s exceeds")));
+
+        diagnostics.force_next_check();
+        let results = diagnostics.check_all(&CheckInputs {
+            current_keyframe_interval: Some(1.0),
+            ..Default::default()
+        });
+        assert!(!results.iter().any(|d| d.message.starts_with("Keyframe interval")));
+    }
+}
@@ -1,49 +1,129 @@
+use std::collections::VecDeque;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-use crate::diagnostics::{Diagnostic, Severity, StreamDiagnostics};
+use tokio::sync::Notify;
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::encoder::EncoderInfo;
 use crate::flv::audio::AudioAnalyzer;
-use crate::flv::video::VideoAnalyzer;
+use crate::flv::video::{VideoAnalyzer, VideoCodec};
+use crate::rtmp::message::ConnectParams;
+use crate::session::StreamSession;
 use crate::stats::StreamStats;
 
 // ANSI color codes
 #[allow(dead_code)]
 mod colors {
-    pub const RESET: &str = "\x1b[0m";
-    pub const BOLD: &str = "\x1b[1m";
-    pub const DIM: &str = "\x1b[2m";
-
-    pub const RED: &str = "\x1b[31m";
-    pub const GREEN: &str = "\x1b[32m";
-    pub const YELLOW: &str = "\x1b[33m";
-    pub const BLUE: &str = "\x1b[34m";
-    pub const MAGENTA: &str = "\x1b[35m";
-    pub const CYAN: &str = "\x1b[36m";
-    pub const WHITE: &str = "\x1b[37m";
-
-    pub const BRIGHT_RED: &str = "\x1b[91m";
-    pub const BRIGHT_GREEN: &str = "\x1b[92m";
-    pub const BRIGHT_YELLOW: &str = "\x1b[93m";
-    pub const BRIGHT_CYAN: &str = "\x1b[96m";
+    use std::fmt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static ENABLED: AtomicBool = AtomicBool::new(true);
+
+    /// Turn color off (or back on) for everything rendered from now on.
+    pub fn set_enabled(enabled: bool) {
+        ENABLED.store(enabled, Ordering::Relaxed);
+    }
+
+    /// An SGR escape that formats as nothing while color is disabled.
+    #[derive(Clone, Copy)]
+    pub struct Color(&'static str);
+
+    impl fmt::Display for Color {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if ENABLED.load(Ordering::Relaxed) {
+                f.write_str(self.0)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    pub const RESET: Color = Color("\x1b[0m");
+    pub const BOLD: Color = Color("\x1b[1m");
+    pub const DIM: Color = Color("\x1b[2m");
+
+    pub const RED: Color = Color("\x1b[31m");
+    pub const GREEN: Color = Color("\x1b[32m");
+    pub const YELLOW: Color = Color("\x1b[33m");
+    pub const BLUE: Color = Color("\x1b[34m");
+    pub const MAGENTA: Color = Color("\x1b[35m");
+    pub const CYAN: Color = Color("\x1b[36m");
+    pub const WHITE: Color = Color("\x1b[37m");
+
+    pub const BRIGHT_RED: Color = Color("\x1b[91m");
+    pub const BRIGHT_GREEN: Color = Color("\x1b[92m");
+    pub const BRIGHT_YELLOW: Color = Color("\x1b[93m");
+    pub const BRIGHT_CYAN: Color = Color("\x1b[96m");
 }
 use colors::*;
+pub use colors::set_enabled as set_color_enabled;
+
+/// Layout width when the terminal size can't be queried
+const DEFAULT_WIDTH: usize = 90;
+/// Rows assumed when the terminal size can't be queried
+const DEFAULT_HEIGHT: usize = 40;
+/// Narrowest layout; below this the two panels no longer fit side by side
+const MIN_WIDTH: usize = 80;
+/// Indent before the video panel plus the "  │  " separator
+const COLUMN_GUTTERS: usize = 7;
 
-const WIDTH: usize = 90;
-const COL_WIDTH: usize = 42;
+/// Set while the alternate screen is up, so the terminal is restored once
+/// whether a guard, the panic hook or shutdown gets there first.
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
 
 pub fn init_terminal() {
     #[cfg(windows)]
     {
         enable_windows_ansi();
     }
+    install_panic_hook();
+    TERMINAL_ACTIVE.store(true, Ordering::Relaxed);
     print!("\x1b[?1049h\x1b[2J\x1b[H\x1b[?25l");
     let _ = io::stdout().flush();
 }
 
+/// Show the cursor, reset colors and leave the alternate screen, if
+/// `init_terminal` entered it.
 pub fn restore_terminal() {
+    if !TERMINAL_ACTIVE.swap(false, Ordering::Relaxed) {
+        return;
+    }
     print!("\x1b[?25h\x1b[0m\x1b[?1049l");
     let _ = io::stdout().flush();
 }
 
+/// Keeps the TUI on screen for as long as it lives: the terminal is
+/// restored when it drops, on an early return or a panic unwinding.
+pub struct TerminalGuard(());
+
+impl TerminalGuard {
+    pub fn enter() -> Self {
+        init_terminal();
+        Self(())
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// A panic mustn't leave the terminal on the alternate screen or without
+/// echo, even when it aborts instead of unwinding.
+fn install_panic_hook() {
+    static INSTALLED: std::sync::Once = std::sync::Once::new();
+    INSTALLED.call_once(|| {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            stop_key_input();
+            default_hook(info);
+        }));
+    });
+}
+
 #[cfg(windows)]
 fn enable_windows_ansi() {
     use std::os::windows::io::AsRawHandle;
@@ -61,6 +141,210 @@ fn enable_windows_ansi() {
     }
 }
 
+/// Width of the render area: the terminal's column count, re-read every frame so
+/// a resize reflows on the next tick.
+fn layout_width() -> usize {
+    terminal_size()
+        .map(|(columns, _)| columns)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+        .max(MIN_WIDTH)
+}
+
+/// Rows of the render area, re-read like the width.
+fn layout_height() -> usize {
+    terminal_size()
+        .map(|(_, rows)| rows)
+        .or_else(|| std::env::var("LINES").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_HEIGHT)
+}
+
+/// Columns and rows of the terminal on stdout.
+#[cfg(unix)]
+fn terminal_size() -> Option<(usize, usize)> {
+    #[repr(C)]
+    #[derive(Default)]
+    struct WinSize {
+        ws_row: u16,
+        ws_col: u16,
+        ws_xpixel: u16,
+        ws_ypixel: u16,
+    }
+    unsafe extern "C" {
+        fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x4008_7468;
+
+    let mut size = WinSize::default();
+    let ok = unsafe { ioctl(1, TIOCGWINSZ, &mut size as *mut WinSize) } == 0;
+    (ok && size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col as usize, size.ws_row as usize))
+}
+
+#[cfg(windows)]
+fn terminal_size() -> Option<(usize, usize)> {
+    use std::os::windows::io::AsRawHandle;
+    #[repr(C)]
+    #[derive(Default)]
+    struct Coord {
+        x: i16,
+        y: i16,
+    }
+    #[repr(C)]
+    #[derive(Default)]
+    struct SmallRect {
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    }
+    #[repr(C)]
+    #[derive(Default)]
+    struct ConsoleScreenBufferInfo {
+        size: Coord,
+        cursor_position: Coord,
+        attributes: u16,
+        window: SmallRect,
+        maximum_window_size: Coord,
+    }
+    unsafe extern "system" {
+        fn GetConsoleScreenBufferInfo(handle: *mut std::ffi::c_void, info: *mut ConsoleScreenBufferInfo) -> i32;
+    }
+    let mut info = ConsoleScreenBufferInfo::default();
+    let handle = io::stdout().as_raw_handle();
+    if unsafe { GetConsoleScreenBufferInfo(handle as *mut _, &mut info) } == 0 {
+        return None;
+    }
+    let columns = info.window.right - info.window.left + 1;
+    let rows = info.window.bottom - info.window.top + 1;
+    (columns > 0 && rows > 0).then_some((columns as usize, rows as usize))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_size() -> Option<(usize, usize)> {
+    None
+}
+
+// Keyboard input: which screen the TUI shows is shared by every connection
+static FULL_DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+static DIAGNOSTICS_PAGE: AtomicUsize = AtomicUsize::new(0);
+static KEYS_ENABLED: AtomicBool = AtomicBool::new(false);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static VIEW_CHANGED: Notify = Notify::const_new();
+static DISCONNECT_REQUESTED: Notify = Notify::const_new();
+/// `stty -g` settings from before keyboard input was switched on
+#[cfg(unix)]
+static SAVED_TTY: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Show the full diagnostics screen instead of the dashboard.
+pub fn set_full_diagnostics(enabled: bool) {
+    FULL_DIAGNOSTICS.store(enabled, Ordering::Relaxed);
+    DIAGNOSTICS_PAGE.store(0, Ordering::Relaxed);
+}
+
+pub fn full_diagnostics() -> bool {
+    FULL_DIAGNOSTICS.load(Ordering::Relaxed)
+}
+
+/// Whether drawing is on hold so the screen can be read; analysis goes on.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Resolves when a keypress changed what the TUI should show.
+pub async fn view_changed() {
+    VIEW_CHANGED.notified().await;
+}
+
+/// Resolves when 'q' asked for the clients on screen to be disconnected.
+pub async fn disconnect_requested() {
+    DISCONNECT_REQUESTED.notified().await;
+}
+
+/// Read single keypresses from the terminal on stdin: 'd' toggles the full
+/// diagnostics screen, 'n' and 'p' page through it, space pauses drawing and
+/// 'q' disconnects the client on screen. Only on Unix, where `stty` can turn
+/// off line buffering (signals stay on, so Ctrl+C still works); call
+/// `stop_key_input` before exiting to turn it back on.
+pub fn start_key_input() {
+    #[cfg(unix)]
+    {
+        use std::process::{Command, Stdio};
+        use tokio::io::AsyncReadExt;
+
+        // From a background job, changing the terminal would stop the process
+        unsafe extern "C" {
+            fn tcgetpgrp(fd: i32) -> i32;
+            fn getpgrp() -> i32;
+        }
+        if unsafe { tcgetpgrp(0) != getpgrp() } {
+            return;
+        }
+        let stty = |args: &[&str]| Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok().filter(|o| o.status.success());
+        let Some(saved) = stty(&["-g"]) else {
+            return;
+        };
+        if stty(&["-icanon", "-echo"]).is_none() {
+            return;
+        }
+        if let Ok(mut tty) = SAVED_TTY.lock() {
+            *tty = Some(String::from_utf8_lossy(&saved.stdout).trim().to_string());
+        }
+        KEYS_ENABLED.store(true, Ordering::Relaxed);
+        install_panic_hook();
+
+        tokio::spawn(async {
+            let mut stdin = tokio::io::stdin();
+            let mut buf = [0u8; 16];
+            while let Ok(n) = stdin.read(&mut buf).await
+                && n > 0
+            {
+                buf[..n].iter().for_each(|&key| handle_key(key));
+            }
+        });
+    }
+}
+
+/// Put the terminal back the way `start_key_input` found it.
+pub fn stop_key_input() {
+    #[cfg(unix)]
+    if let Ok(mut tty) = SAVED_TTY.lock()
+        && let Some(saved) = tty.take()
+    {
+        let _ = std::process::Command::new("stty").arg(&saved).stdin(std::process::Stdio::inherit()).status();
+    }
+}
+
+#[cfg(unix)]
+fn handle_key(key: u8) {
+    match key {
+        b'd' | b'D' => set_full_diagnostics(!full_diagnostics()),
+        b' ' => {
+            let paused = !PAUSED.fetch_xor(true, Ordering::Relaxed);
+            if paused {
+                // Below the frozen frame; resuming redraws over it
+                print!("\n  {BRIGHT_YELLOW}{BOLD}❚❚ Paused{RESET} {DIM}(analysis continues; press space to resume){RESET}");
+                let _ = io::stdout().flush();
+                return;
+            }
+        }
+        b'q' | b'Q' => {
+            DISCONNECT_REQUESTED.notify_waiters();
+            return;
+        }
+        b'n' if full_diagnostics() => {
+            DIAGNOSTICS_PAGE.fetch_add(1, Ordering::Relaxed);
+        }
+        b'p' if full_diagnostics() => {
+            let _ = DIAGNOSTICS_PAGE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |page| page.checked_sub(1));
+        }
+        _ => return,
+    }
+    VIEW_CHANGED.notify_waiters();
+}
+
 /// Center a string within a given width
 fn center(s: &str, width: usize) -> String {
     let visible_len = visible_length(s);
@@ -89,6 +373,17 @@ fn visible_length(s: &str) -> usize {
     len
 }
 
+/// Shorten plain text to `max` characters, marking the cut with an ellipsis
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        let mut out: String = s.chars().take(max.saturating_sub(1)).collect();
+        out.push('…');
+        out
+    }
+}
+
 /// Pad a string to a given visible width
 fn pad_to(s: &str, width: usize) -> String {
     let visible_len = visible_length(s);
@@ -102,14 +397,19 @@ fn pad_to(s: &str, width: usize) -> String {
 pub fn render(
     app_name: &str,
     stream_key: &str,
-    stats: &StreamStats,
-    video: &VideoAnalyzer,
-    audio: &AudioAnalyzer,
-    encoder_name: &Option<String>,
-    diagnostics: &StreamDiagnostics,
+    session: &StreamSession,
+    connect: &ConnectParams,
     diagnostic_results: &[Diagnostic],
+    stream_position: (usize, usize),
 ) {
+    let stats = &session.stats;
+    let video = &session.video_analyzer;
+    let audio = &session.audio_analyzer;
+    let diagnostics = &session.diagnostics;
+    let flash_ver = connect.flash_ver.as_deref();
     let mut out = String::with_capacity(8192);
+    let width = layout_width();
+    let col_width = (width - COLUMN_GUTTERS) / 2;
 
     // Clear screen and home
     out.push_str("\x1b[2J\x1b[H");
@@ -118,22 +418,22 @@ pub fn render(
     // CENTERED LOGO
     // ══════════════════════════════════════════════════════════════════════════════
     out.push_str(&format!("{BRIGHT_RED}{BOLD}"));
-    out.push_str(&center("╔════════════════════════════════════════╗", WIDTH));
+    out.push_str(&center("╔════════════════════════════════════════╗", width));
     out.push('\n');
-    out.push_str(&center("║  ____           _____ __  __ ____      ║", WIDTH));
+    out.push_str(&center("║  ____           _____ __  __ ____      ║", width));
     out.push('\n');
-    out.push_str(&center("║ |  _ \\ _   _ __|_   _|  \\/  |  _ \\     ║", WIDTH));
+    out.push_str(&center("║ |  _ \\ _   _ __|_   _|  \\/  |  _ \\     ║", width));
     out.push('\n');
-    out.push_str(&center("║ | |_) | | | / __|| | | |\\/| | |_) |    ║", WIDTH));
+    out.push_str(&center("║ | |_) | | | / __|| | | |\\/| | |_) |    ║", width));
     out.push('\n');
-    out.push_str(&center("║ |  _ <| |_| \\__ \\| | | |  | |  __/     ║", WIDTH));
+    out.push_str(&center("║ |  _ <| |_| \\__ \\| | | |  | |  __/     ║", width));
     out.push('\n');
-    out.push_str(&center("║ |_| \\_\\\\__,_|___/|_| |_|  |_|_|        ║", WIDTH));
+    out.push_str(&center("║ |_| \\_\\\\__,_|___/|_| |_|  |_|_|        ║", width));
     out.push('\n');
-    out.push_str(&center("╚════════════════════════════════════════╝", WIDTH));
+    out.push_str(&center("╚════════════════════════════════════════╝", width));
     out.push('\n');
-    out.push_str(&format!("{RESET}"));
-    out.push_str(&center(&format!("{DIM}Stream Analyzer v0.1.0{RESET}"), WIDTH));
+    out.push_str(&RESET.to_string());
+    out.push_str(&center(&format!("{DIM}Stream Analyzer v0.1.0{RESET}"), width));
     out.push_str("\n\n");
 
     // ══════════════════════════════════════════════════════════════════════════════
@@ -142,7 +442,21 @@ pub fn render(
     let stream_path = format!("{}/{}",
         if app_name.is_empty() { "?" } else { app_name },
         if stream_key.is_empty() { "?" } else { stream_key });
-    let encoder_str = encoder_name.as_deref().unwrap_or("-");
+    // Streams sharing a connection take turns on screen
+    let (index, count) = stream_position;
+    let stream_path = if count > 1 {
+        format!("{} ({}/{})", stream_path, index + 1, count)
+    } else {
+        stream_path
+    };
+    // Known encoders by their friendly name, anything else as sent. Until
+    // metadata names one, the connect flashVer stands in verbatim: most
+    // encoders claim to be FMLE there, so it isn't worth identifying
+    let encoder_str = match (session.encoder_name.as_deref(), flash_ver) {
+        (Some(name), _) => EncoderInfo::identify(name).map_or_else(|| name.to_string(), |e| e.to_string()),
+        (None, Some(flash_ver)) => flash_ver.to_string(),
+        (None, None) => "-".to_string(),
+    };
     let duration_str = format_duration(stats.duration_secs);
 
     // Status
@@ -156,16 +470,26 @@ pub fn render(
         format!("{BRIGHT_GREEN}healthy{RESET}")
     };
 
-    out.push_str(&center(&format!("{DIM}┌────────────────────────────────────────────────────────────────────────────────┐{RESET}"), WIDTH));
-    out.push('\n');
-    out.push_str(&center(&format!("{DIM}│{RESET}  {CYAN}Stream:{RESET} {BRIGHT_GREEN}{:<20}{RESET}  {CYAN}Encoder:{RESET} {:<20}  {CYAN}Duration:{RESET} {BRIGHT_YELLOW}{:<8}{RESET}  {DIM}│{RESET}",
-        stream_path, encoder_str, duration_str), WIDTH));
+    // Box spans the layout less a two-column margin each side
+    let box_inner = width - 6;
+    let rule = "─".repeat(box_inner);
+    let box_row = |content: String| format!("  {DIM}│{RESET}{}{DIM}│{RESET}", pad_to(&content, box_inner));
+    out.push_str(&format!("  {DIM}┌{}┐{RESET}\n", rule));
+    // Everything on the stream row but the encoder name is fixed width
+    let encoder_room = box_inner.saturating_sub(61);
+    out.push_str(&box_row(format!("  {CYAN}Stream:{RESET} {BRIGHT_GREEN}{:<20}{RESET}  {CYAN}Encoder:{RESET} {:<room$}  {CYAN}Duration:{RESET} {BRIGHT_YELLOW}{}{RESET}",
+        stream_path, truncate(&encoder_str, encoder_room), duration_str, room = encoder_room)));
     out.push('\n');
-    out.push_str(&center(&format!("{DIM}│{RESET}  {CYAN}Profile:{RESET} {BRIGHT_CYAN}{:<18}{RESET}  {CYAN}Status:{RESET} {:<42} {DIM}│{RESET}",
-        diagnostics.profile.name(), status_str), WIDTH));
+    let score = diagnostics.health().score;
+    let score_color = if score >= 80 { BRIGHT_GREEN } else if score >= 60 { BRIGHT_YELLOW } else { BRIGHT_RED };
+    out.push_str(&box_row(format!("  {CYAN}Profile:{RESET} {BRIGHT_CYAN}{:<18}{RESET}  {CYAN}Health:{RESET} {score_color}{BOLD}{:>3}/100{RESET}  {CYAN}Status:{RESET} {}",
+        diagnostics.profile.name(), score, status_str)));
     out.push('\n');
-    out.push_str(&center(&format!("{DIM}└────────────────────────────────────────────────────────────────────────────────┘{RESET}"), WIDTH));
-    out.push_str("\n\n");
+    if let Some(frame) = session.last_data_frame.as_deref() {
+        out.push_str(&box_row(format!("  {CYAN}Last cue:{RESET} {}", truncate(frame, box_inner.saturating_sub(13)))));
+        out.push('\n');
+    }
+    out.push_str(&format!("  {DIM}└{}┘{RESET}\n\n", rule));
 
     // ══════════════════════════════════════════════════════════════════════════════
     // VIDEO & AUDIO SIDE BY SIDE
@@ -174,13 +498,15 @@ pub fn render(
     // Prepare video lines
     let mut video_lines: Vec<String> = Vec::new();
     video_lines.push(format!("{MAGENTA}{BOLD}▶ VIDEO{RESET}"));
-    video_lines.push(format!("{DIM}──────────────────────────────────────{RESET}"));
+    let panel_rule = "─".repeat(col_width.saturating_sub(4));
+    video_lines.push(format!("{DIM}{}{RESET}", panel_rule));
 
     let codec = video.codec.as_ref().map(|c| c.to_string()).unwrap_or_else(|| "-".into());
     video_lines.push(format!("  {DIM}Codec:{RESET}      {BRIGHT_GREEN}{}{RESET}", codec));
 
     if let (Some(w), Some(h)) = (video.width, video.height) {
-        video_lines.push(format!("  {DIM}Resolution:{RESET} {BRIGHT_YELLOW}{}x{}{RESET}", w, h));
+        let scan = if video.is_interlaced { " interlaced" } else { "" };
+        video_lines.push(format!("  {DIM}Resolution:{RESET} {BRIGHT_YELLOW}{}x{}{RESET}{}", w, h, scan));
     } else {
         video_lines.push(format!("  {DIM}Resolution:{RESET} -"));
     }
@@ -192,31 +518,88 @@ pub fn render(
         video_lines.push(format!("  {DIM}Profile:{RESET}    -"));
     }
 
-    let fps = stats.current_fps().unwrap_or(0.0);
+    match video.pixel_format {
+        Some(format) if format.is_8bit_420() => video_lines.push(format!("  {DIM}Format:{RESET}     {}", format)),
+        Some(format) => video_lines.push(format!("  {DIM}Format:{RESET}     {BRIGHT_YELLOW}{}{RESET}", format)),
+        None => video_lines.push(format!("  {DIM}Format:{RESET}     -")),
+    }
+
+    // Prefer the encoder's nominal SPS framerate; show the measured rate alongside it
+    let measured_fps = stats.current_fps().unwrap_or(0.0);
+    let fps = video.fps.unwrap_or(measured_fps);
     let fps_color = if fps >= 29.0 { BRIGHT_GREEN } else if fps >= 24.0 { YELLOW } else { BRIGHT_RED };
-    video_lines.push(format!("  {DIM}FPS:{RESET}        {}{:.1}{RESET}", fps_color, fps));
+    if video.fps.is_some() {
+        video_lines.push(format!("  {DIM}FPS:{RESET}        {}{:.2}{RESET} {DIM}({:.1} measured){RESET}", fps_color, fps, measured_fps));
+    } else {
+        video_lines.push(format!("  {DIM}FPS:{RESET}        {}{:.1}{RESET}", fps_color, fps));
+    }
+
+    video_lines.push(format!("  {DIM}Bitrate:{RESET}    {}",
+        format_bitrate_summary(stats.current_video_bitrate_kbps(), stats.average_video_bitrate_kbps(), stats.peak_video_bitrate_kbps)));
 
-    video_lines.push(format!("  {DIM}Bitrate:{RESET}    {BRIGHT_CYAN}{}{RESET}",
-        format_bitrate(stats.current_video_bitrate_kbps().unwrap_or(0.0))));
+    let drop_rate = stats.dropped_frame_rate().unwrap_or(0.0) * 100.0;
+    let drop_color = if drop_rate > 1.0 { BRIGHT_RED } else if stats.estimated_dropped_frames > 0 { YELLOW } else { RESET };
+    video_lines.push(format!("  {DIM}Dropped:{RESET}    {}{}{RESET} {DIM}({:.1}% now){RESET}",
+        drop_color, stats.estimated_dropped_frames, drop_rate));
 
-    let kf_int = stats.keyframe_interval_secs.map(|s| format!("{:.1}s", s)).unwrap_or_else(|| "-".into());
-    video_lines.push(format!("  {DIM}Keyframes:{RESET}  {} {DIM}(int: {}){RESET}", video.keyframe_count, kf_int));
+    let interval_stats = diagnostics.keyframe_interval_stats();
+    let kf_int = match interval_stats {
+        Some(s) => format!("{:.1}s ±{:.2}", s.mean, s.stddev),
+        None => stats.keyframe_interval_secs.map(|s| format!("{:.1}s", s)).unwrap_or_else(|| "-".into()),
+    };
+    let idr = if video.codec == Some(VideoCodec::Avc) {
+        format!(", {} IDR", video.idr_count)
+    } else {
+        String::new()
+    };
+    video_lines.push(format!("  {DIM}Keyframes:{RESET}  {} {DIM}(int: {}{}){RESET}", video.keyframe_count, kf_int, idr));
+    if let Some(s) = interval_stats {
+        let frames = video.fps.or_else(|| stats.current_fps())
+            .map(|fps| format!(", ~{:.0} frames", s.mean_frames(fps)))
+            .unwrap_or_default();
+        video_lines.push(format!("  {DIM}KF range:{RESET}   {:.1}-{:.1}s{}", s.min, s.max, frames));
+    }
+    if let Some(nal) = video.nal_stats() {
+        video_lines.push(format!("  {DIM}Inline SPS:{RESET} {}/{} keyframes", nal.keyframes_with_sps, video.keyframe_count));
+    }
     video_lines.push(format!("  {DIM}P-frames:{RESET}   {}", video.inter_frame_count));
-    video_lines.push(format!("  {DIM}B-frames:{RESET}   {}", video.b_frame_count));
+    let pyramid = if video.b_pyramid { " (pyramid)" } else { "" };
+    video_lines.push(format!("  {DIM}B-frames:{RESET}   {}{}", video.b_frame_count, pyramid));
+    if video.other_frame_count > 0 {
+        video_lines.push(format!("  {DIM}Other:{RESET}      {}", video.other_frame_count));
+    }
+    video_lines.push(format!("  {DIM}Seq hdrs:{RESET}   {}", video.sequence_header_count));
+
+    let gop = match video.gop_length {
+        Some(n) => format!("{} frames, {}", n, if video.open_gop { "open" } else { "closed" }),
+        None => "-".into(),
+    };
+    video_lines.push(format!("  {DIM}GOP:{RESET}        {}", gop));
+    // Older frames are dropped from the front when the column is too narrow
+    let pattern = video.gop_pattern();
+    let pattern_room = col_width.saturating_sub(14);
+    let pattern = &pattern[pattern.len().saturating_sub(pattern_room)..];
+    video_lines.push(format!("  {DIM}Pattern:{RESET}    {}", pattern));
+
+    if let (Some(min), Some(max)) = (video.min_composition_offset, video.max_composition_offset) {
+        let depth = video.max_reorder_depth().map(|d| d.to_string()).unwrap_or_else(|| "?".into());
+        video_lines.push(format!("  {DIM}CTO:{RESET}        {}..{} ms {DIM}(reorder: {}){RESET}", min, max, depth));
+    } else {
+        video_lines.push(format!("  {DIM}CTO:{RESET}        -"));
+    }
 
-    let total_video_frames = video.keyframe_count + video.inter_frame_count + video.b_frame_count;
-    let video_kb = stats.total_video_bytes as f64 / 1024.0;
-    video_lines.push(format!("{DIM}Total: {} frames, {:.1} KB{RESET}", total_video_frames, video_kb));
+    let video_kb = video.total_video_bytes as f64 / 1024.0;
+    video_lines.push(format!("{DIM}Total: {} frames, {:.1} KB{RESET}", video.total_video_frames, video_kb));
 
     // Prepare audio lines
     let mut audio_lines: Vec<String> = Vec::new();
     audio_lines.push(format!("{BLUE}{BOLD}♪ AUDIO{RESET}"));
-    audio_lines.push(format!("{DIM}──────────────────────────────────────{RESET}"));
+    audio_lines.push(format!("{DIM}{}{RESET}", panel_rule));
 
     let acodec = audio.codec.as_ref().map(|c| c.to_string()).unwrap_or_else(|| "-".into());
     audio_lines.push(format!("  {DIM}Codec:{RESET}      {BRIGHT_GREEN}{}{RESET}", acodec));
 
-    if let Some(ref p) = audio.aac_profile {
+    if let Some(p) = audio.aac_description().or_else(|| audio.mp3_header.map(|h| h.to_string())) {
         audio_lines.push(format!("  {DIM}Profile:{RESET}    {}", p));
     } else {
         audio_lines.push(format!("  {DIM}Profile:{RESET}    -"));
@@ -232,8 +615,25 @@ pub fn render(
     let bit_depth = audio.sample_size.map(|s| format!("{}-bit", s)).unwrap_or_else(|| "-".into());
     audio_lines.push(format!("  {DIM}Bit Depth:{RESET}  {}", bit_depth));
 
-    audio_lines.push(format!("  {DIM}Bitrate:{RESET}    {BRIGHT_CYAN}{}{RESET}",
-        format_bitrate(stats.current_audio_bitrate_kbps().unwrap_or(0.0))));
+    let frame_str = match audio.frame_duration_ms() {
+        Some(frame_ms) => match (video.gop_length, video.fps) {
+            (Some(frames), Some(fps)) if fps > 0.0 => {
+                format!("{:.2} ms ({:.2} per GOP)", frame_ms, frames as f64 * 1000.0 / fps / frame_ms)
+            }
+            _ => format!("{:.2} ms", frame_ms),
+        },
+        None => "-".to_string(),
+    };
+    audio_lines.push(format!("  {DIM}Frame:{RESET}      {}", frame_str));
+
+    audio_lines.push(format!("  {DIM}Bitrate:{RESET}    {}",
+        format_bitrate_summary(stats.current_audio_bitrate_kbps(), stats.average_audio_bitrate_kbps(), stats.peak_audio_bitrate_kbps)));
+
+    let encoded_str = match (audio.aac_bitrate_kbps(), audio.nominal_aac_bitrate_kbps()) {
+        (Some(measured), Some(nominal)) => format!("{:.0} kbps {DIM}(nearest {} kbps){RESET}", measured, nominal),
+        _ => "-".to_string(),
+    };
+    audio_lines.push(format!("  {DIM}Encoded:{RESET}    {}", encoded_str));
 
     // Pad audio to match video line count
     while audio_lines.len() < video_lines.len() - 1 {
@@ -249,13 +649,26 @@ pub fn render(
         let v_line = video_lines.get(i).map(|s| s.as_str()).unwrap_or("");
         let a_line = audio_lines.get(i).map(|s| s.as_str()).unwrap_or("");
         out.push_str("  ");
-        out.push_str(&pad_to(v_line, COL_WIDTH));
+        out.push_str(&pad_to(v_line, col_width));
         out.push_str("  │  ");
         out.push_str(a_line);
         out.push('\n');
     }
     out.push('\n');
 
+    // History graphs, last minute
+    if !stats.video_bitrate_history.is_empty() {
+        let peak = stats.video_bitrate_history.iter().copied().fold(0.0, f64::max);
+        // Label and the "max ..." suffix take about 30 columns
+        let graph_width = width.saturating_sub(30);
+        out.push_str(&format!("  {DIM}Bitrate{RESET}  {BRIGHT_CYAN}{}{RESET}  {DIM}max {}{RESET}\n",
+            sparkline(&stats.video_bitrate_history, graph_width), format_bitrate(peak)));
+        let top_fps = stats.fps_history.iter().copied().fold(0.0, f64::max);
+        out.push_str(&format!("  {DIM}FPS{RESET}      {BRIGHT_GREEN}{}{RESET}  {DIM}max {:.1}{RESET}\n",
+            sparkline(&stats.fps_history, graph_width), top_fps));
+        out.push('\n');
+    }
+
     // ══════════════════════════════════════════════════════════════════════════════
     // DIAGNOSTICS SECTION
     // ══════════════════════════════════════════════════════════════════════════════
@@ -266,7 +679,7 @@ pub fn render(
     } else {
         out.push_str(&format!("  {BRIGHT_GREEN}{BOLD}✓ STATUS: OK{RESET}\n"));
     }
-    out.push_str(&format!("  {DIM}────────────────────────────────────────────────────────────────────────────────────{RESET}\n"));
+    out.push_str(&format!("  {DIM}{}{RESET}\n", "─".repeat(width - 6)));
 
     if diagnostic_results.is_empty() {
         out.push_str(&format!("    {DIM}No issues detected{RESET}\n"));
@@ -289,10 +702,11 @@ pub fn render(
     // ══════════════════════════════════════════════════════════════════════════════
     out.push('\n');
     out.push_str(&format!("  {DIM}Headers:{RESET} "));
-    let avc_status = if diagnostics.avc_seq_header_received {
-        format!("{GREEN}AVC{RESET}")
+    let video_tag = diagnostics.video_config_codec.or(video.codec).map_or("AVC", |codec| codec.short_name());
+    let video_status = if diagnostics.video_config_received {
+        format!("{GREEN}{}{RESET}", video_tag)
     } else {
-        format!("{RED}AVC{RESET}")
+        format!("{RED}{}{RESET}", video_tag)
     };
     let aac_status = if diagnostics.aac_seq_header_received {
         format!("{GREEN}AAC{RESET}")
@@ -304,18 +718,144 @@ pub fn render(
     } else {
         format!("{YELLOW}META{RESET}")
     };
-    out.push_str(&avc_status);
-    out.push_str(" ");
+    out.push_str(&video_status);
+    out.push(' ');
     out.push_str(&aac_status);
-    out.push_str(" ");
+    out.push(' ');
     out.push_str(&meta_status);
+    if let Some(rtt) = stats.rtt_ms {
+        out.push_str(&format!("   {DIM}RTT:{RESET} {:.0} ms", rtt));
+    }
+    if let Some(jitter) = stats.network_jitter_ms() {
+        out.push_str(&format!("   {DIM}Jitter:{RESET} {:.0} ms", jitter));
+    }
+    if let Some(kbps) = stats.ingress_kbps {
+        let media = stats.current_video_bitrate_kbps().unwrap_or(0.0) + stats.current_audio_bitrate_kbps().unwrap_or(0.0);
+        out.push_str(&format!("   {DIM}Ingress:{RESET} {} {DIM}(media {}){RESET}", format_bitrate(kbps), format_bitrate(media)));
+    }
+
+    let startup = diagnostics.startup_timeline();
+    let step = |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{:.0} ms", ms));
+    out.push_str(&format!("\n  {DIM}Startup:{RESET} handshake {} → connect {} → publish {} → keyframe {}",
+        step(startup.handshake_ms), step(startup.connect_ms), step(startup.publish_ms), step(startup.first_keyframe_ms)));
+
+    if KEYS_ENABLED.load(Ordering::Relaxed) {
+        out.push_str(&format!("\n\n  {DIM}Press d for all diagnostics, space to pause, q to disconnect, Ctrl+C to stop{RESET}\n"));
+    } else {
+        out.push_str(&format!("\n\n  {DIM}Press Ctrl+C to stop{RESET}\n"));
+    }
+
+    print!("{}", out);
+    let _ = io::stdout().flush();
+}
+
+/// One uncolored line for logs, e.g.
+/// Draw every diagnostic from the last check pass, grouped by category with
+/// the worst first, a page at a time.
+pub fn render_diagnostics(app_name: &str, stream_key: &str, diagnostic_results: &[Diagnostic], stream_position: (usize, usize)) {
+    let mut out = String::with_capacity(4096);
+    let width = layout_width();
 
-    out.push_str(&format!("\n\n  {DIM}Press Ctrl+C to stop{RESET}\n"));
+    out.push_str("\x1b[2J\x1b[H");
+    let (index, count) = stream_position;
+    let position = if count > 1 { format!(" ({}/{})", index + 1, count) } else { String::new() };
+    let tally = |severity: Severity| diagnostic_results.iter().filter(|d| d.severity == severity).count();
+    out.push_str(&format!("\n  {BOLD}Diagnostics{RESET} {DIM}for{RESET} {BRIGHT_GREEN}{}/{}{}{RESET}   {BRIGHT_RED}{} errors{RESET}  {BRIGHT_YELLOW}{} warnings{RESET}  {DIM}{} info{RESET}\n",
+        app_name, stream_key, position, tally(Severity::Error), tally(Severity::Warning), tally(Severity::Info)));
+    out.push_str(&format!("  {DIM}{}{RESET}\n", "─".repeat(width - 6)));
+
+    // Categories in order of their worst diagnostic, then by name
+    let mut categories: Vec<(&str, Severity)> = Vec::new();
+    for diag in diagnostic_results {
+        match categories.iter_mut().find(|(c, _)| *c == diag.category) {
+            Some((_, worst)) => *worst = (*worst).max(diag.severity),
+            None => categories.push((diag.category, diag.severity)),
+        }
+    }
+    categories.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut lines: Vec<String> = Vec::new();
+    for &(category, _) in &categories {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(format!("  {CYAN}{BOLD}{}{RESET}", category));
+        let mut in_category: Vec<&Diagnostic> = diagnostic_results.iter().filter(|d| d.category == category).collect();
+        in_category.sort_by_key(|d| std::cmp::Reverse(d.severity));
+        for diag in in_category {
+            let (icon, color) = match diag.severity {
+                Severity::Error => ("✖", BRIGHT_RED),
+                Severity::Warning => ("!", BRIGHT_YELLOW),
+                Severity::Info => ("·", DIM),
+            };
+            lines.push(format!("    {color}{icon}{RESET} {}", diag.message));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(format!("    {DIM}No issues detected{RESET}"));
+    }
+
+    // The title takes three rows and the footer two
+    let page_rows = layout_height().saturating_sub(5).max(1);
+    let pages = lines.len().div_ceil(page_rows);
+    let page = DIAGNOSTICS_PAGE.load(Ordering::Relaxed).min(pages - 1);
+    DIAGNOSTICS_PAGE.store(page, Ordering::Relaxed);
+    for line in lines.iter().skip(page * page_rows).take(page_rows) {
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    let keys = KEYS_ENABLED.load(Ordering::Relaxed);
+    let paging = match (pages > 1, keys) {
+        (true, true) => format!("Page {}/{}: n for next, p for previous. ", page + 1, pages),
+        (true, false) => format!("Page {}/{}. ", page + 1, pages),
+        _ => String::new(),
+    };
+    let back = if keys { "Press d to go back, space to pause, q to disconnect, " } else { "Press " };
+    out.push_str(&format!("\n  {DIM}{}{}Ctrl+C to stop{RESET}", paging, back));
 
     print!("{}", out);
     let _ = io::stdout().flush();
 }
 
+/// `[live/key] 1080p H.264/AVC 6000kbps 30fps | AAC 160kbps | 1 warning: Keyframe interval 3.1s ...`
+pub fn print_status_line(
+    app_name: &str,
+    stream_key: &str,
+    stats: &StreamStats,
+    video: &VideoAnalyzer,
+    audio: &AudioAnalyzer,
+    diagnostic_results: &[Diagnostic],
+) {
+    let mut line = format!("[{}/{}]", app_name, stream_key);
+    if let Some(height) = video.height {
+        line.push_str(&format!(" {}p", height));
+    }
+    if let Some(codec) = video.codec {
+        line.push_str(&format!(" {}", codec));
+    }
+    line.push_str(&format!(" {:.0}kbps", stats.current_video_bitrate_kbps().unwrap_or(0.0)));
+    if let Some(fps) = video.fps.or_else(|| stats.current_fps()) {
+        line.push_str(&format!(" {:.0}fps", fps));
+    }
+    if let Some(codec) = audio.codec {
+        line.push_str(&format!(" | {} {:.0}kbps", codec, stats.current_audio_bitrate_kbps().unwrap_or(0.0)));
+    }
+
+    // Report the count at the worst severity and lead with its first message
+    let worst = diagnostic_results.iter().map(|d| d.severity).max();
+    match worst {
+        Some(severity) if severity > Severity::Info => {
+            let matching: Vec<&Diagnostic> = diagnostic_results.iter().filter(|d| d.severity == severity).collect();
+            line.push_str(&format!(" | {} {}{}: {}",
+                matching.len(), severity.as_str(), if matching.len() > 1 { "s" } else { "" }, matching[0].message));
+        }
+        _ => line.push_str(" | OK"),
+    }
+
+    println!("{}", line);
+}
+
 fn format_bitrate(kbps: f64) -> String {
     if kbps >= 1000.0 {
         format!("{:.1} Mbps", kbps / 1000.0)
@@ -326,6 +866,35 @@ fn format_bitrate(kbps: f64) -> String {
     }
 }
 
+/// One block character for each of the last `max_len` samples, scaled from
+/// zero to the largest of them.
+fn sparkline(samples: &VecDeque<f64>, max_len: usize) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let recent = samples.range(samples.len().saturating_sub(max_len)..);
+    let max = recent.clone().copied().fold(0.0, f64::max);
+    recent
+        .map(|&v| {
+            if max <= 0.0 {
+                return BLOCKS[0];
+            }
+            let level = (v / max * (BLOCKS.len() - 1) as f64).round() as usize;
+            BLOCKS[level.min(BLOCKS.len() - 1)]
+        })
+        .collect()
+}
+
+/// "cur / avg / peak", all in the unit picked for the peak so the column stays narrow.
+fn format_bitrate_summary(current: Option<f64>, average: Option<f64>, peak: Option<f64>) -> String {
+    let largest = [current, average, peak].into_iter().flatten().fold(0.0, f64::max);
+    if largest <= 0.0 {
+        return "-".into();
+    }
+    let (scale, unit, precision) = if largest >= 1000.0 { (1000.0, "Mbps", 1) } else { (1.0, "kbps", 0) };
+    let fmt = |v: Option<f64>| v.map(|v| format!("{:.*}", precision, v / scale)).unwrap_or_else(|| "-".into());
+    format!("{BRIGHT_CYAN}{}{RESET} {DIM}/{RESET} {} {DIM}/{RESET} {} {DIM}{}{RESET}",
+        fmt(current), fmt(average), fmt(peak), unit)
+}
+
 fn format_duration(secs: f64) -> String {
     let total = secs as u64;
     let h = total / 3600;
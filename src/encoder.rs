@@ -0,0 +1,141 @@
+use std::fmt;
+
+use crate::diagnostics::Severity;
+
+/// An encoder family, recognized by a substring of the metadata `encoder`
+/// string. Later entries are only tried when earlier ones don't match.
+struct EncoderPattern {
+    needle: &'static str,
+    name: &'static str,
+    /// Text right before the version number, if the string carries one
+    version_after: Option<&'static str>,
+}
+
+const KNOWN_ENCODERS: &[EncoderPattern] = &[
+    // e.g. "obs-output module (libobs version 30.0.2)"
+    EncoderPattern { needle: "obs-output", name: "OBS Studio", version_after: Some("libobs version ") },
+    // e.g. "Lavf58.29.100"; the version is libavformat's, not FFmpeg's
+    EncoderPattern { needle: "Lavf", name: "FFmpeg libavformat", version_after: Some("Lavf") },
+    // e.g. "FMLE/3.0 (compatible; FMSc/1.0)"
+    EncoderPattern { needle: "FMLE", name: "Flash Media Live Encoder", version_after: Some("FMLE/") },
+    EncoderPattern { needle: "Elgato", name: "Elgato", version_after: None },
+    EncoderPattern { needle: "Wirecast", name: "Wirecast", version_after: Some("Wirecast/") },
+    EncoderPattern { needle: "vMix", name: "vMix", version_after: None },
+    EncoderPattern { needle: "XSplit", name: "XSplit", version_after: None },
+    EncoderPattern { needle: "Larix", name: "Larix Broadcaster", version_after: None },
+];
+
+/// When an encoder issue applies, beyond the encoder and version matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueCondition {
+    Always,
+    /// The service profile doesn't allow B-frames
+    BFramesDisallowed,
+}
+
+/// A known problem with an encoder family, optionally fixed in some version.
+pub struct EncoderIssue {
+    /// `name` of the encoder family in `KNOWN_ENCODERS`
+    pub encoder: &'static str,
+    /// Versions from this one on are unaffected; None if all versions are
+    pub fixed_in: Option<Version>,
+    pub condition: IssueCondition,
+    pub severity: Severity,
+    /// Shown after the encoder name and version; "{profile}" is replaced
+    /// with the service profile name
+    pub message: &'static str,
+}
+
+pub const ENCODER_ISSUES: &[EncoderIssue] = &[
+    EncoderIssue {
+        encoder: "FFmpeg libavformat",
+        fixed_in: Some(Version(58, 0, 0)),
+        condition: IssueCondition::Always,
+        severity: Severity::Warning,
+        message: "older than FFmpeg 4.0, so missing years of RTMP and FLV muxer fixes (upgrade it)",
+    },
+    EncoderIssue {
+        encoder: "FFmpeg libavformat",
+        fixed_in: None,
+        condition: IssueCondition::BFramesDisallowed,
+        severity: Severity::Info,
+        message: "libx264 encodes B-frames unless told not to (pass -bf 0 for {profile})",
+    },
+    EncoderIssue {
+        encoder: "OBS Studio",
+        fixed_in: Some(Version(29, 1, 0)),
+        condition: IssueCondition::Always,
+        severity: Severity::Info,
+        message: "versions before 29.1 can't stream HEVC or AV1 over RTMP",
+    },
+    EncoderIssue {
+        encoder: "Flash Media Live Encoder",
+        fixed_in: None,
+        condition: IssueCondition::Always,
+        severity: Severity::Warning,
+        message: "discontinued and limited to H.264 (consider a maintained encoder)",
+    },
+];
+
+/// A dotted version number; missing components count as zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    /// Parse the leading "1", "1.2" or "1.2.3" of `s`, ignoring what follows.
+    fn parse_prefix(s: &str) -> Option<Self> {
+        let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let mut parts = s[..end].split('.').map(|p| p.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next().flatten().unwrap_or(0);
+        let patch = parts.next().flatten().unwrap_or(0);
+        Some(Self(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// An encoder identified from its metadata `encoder` string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderInfo {
+    pub name: &'static str,
+    pub version: Option<Version>,
+}
+
+impl EncoderInfo {
+    /// Match `encoder` against the known encoder table.
+    pub fn identify(encoder: &str) -> Option<Self> {
+        let pattern = KNOWN_ENCODERS.iter().find(|p| encoder.contains(p.needle))?;
+        let version = pattern.version_after.and_then(|prefix| {
+            let start = encoder.find(prefix)? + prefix.len();
+            Version::parse_prefix(&encoder[start..])
+        });
+        Some(Self { name: pattern.name, version })
+    }
+
+    /// Known issues affecting this encoder. An unknown version is taken to
+    /// be unaffected by issues fixed in some version.
+    pub fn issues(&self, b_frames_allowed: bool) -> impl Iterator<Item = &'static EncoderIssue> + '_ {
+        ENCODER_ISSUES.iter().filter(move |issue| {
+            issue.encoder == self.name
+                && issue.fixed_in.is_none_or(|fixed| self.version.is_some_and(|v| v < fixed))
+                && match issue.condition {
+                    IssueCondition::Always => true,
+                    IssueCondition::BFramesDisallowed => !b_frames_allowed,
+                }
+        })
+    }
+}
+
+impl fmt::Display for EncoderInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => f.write_str(self.name),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::connection::ConnectionOptions;
+use crate::flv::reader::FlvReader;
+use crate::log::LogState;
+use crate::report::ReportFormat;
+use crate::rtmp::chunk::RtmpMessage;
+use crate::rtmp::message::{MessageHandler, RtmpEvent};
+use crate::session::StreamSession;
+
+/// Reported as the app name of a recording
+const FILE_APP_NAME: &str = "file";
+
+/// Run a recorded FLV through the same analysis as a live stream and emit the
+/// report (JSON unless `--report` says otherwise).
+///
+/// Timing checks follow the tag timestamps rather than the wall clock, so a
+/// file is analyzed as if it had been streamed in real time.
+pub fn analyze_file(path: &Path, options: &ConnectionOptions) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut reader = FlvReader::new(&data)?;
+    let stream_key = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut handler = MessageHandler::new();
+    if let Some(dump) = &options.dump_amf {
+        handler.set_dump(dump.clone(), stream_key.clone());
+    }
+    let mut session = StreamSession::new(options);
+    session.diagnostics.set_profile(options.profile_for(FILE_APP_NAME));
+    let mut log_state = LogState::default();
+
+    let start = Instant::now();
+    session.set_clock(start);
+    session.diagnostics.record_stream_start();
+
+    // Tag timestamps are made relative to the first one; checks run once per
+    // second of media time, like the live display tick
+    let mut first_timestamp: Option<u32> = None;
+    let mut next_check_ms: u64 = 1000;
+    let mut tags = 0u64;
+
+    while let Some(tag) = reader.next_tag() {
+        tags += 1;
+        let origin = *first_timestamp.get_or_insert(tag.timestamp);
+        let media_ms = tag.timestamp.saturating_sub(origin) as u64;
+
+        while media_ms >= next_check_ms {
+            session.set_clock(start + Duration::from_millis(next_check_ms));
+            session.stats.sample_history();
+            let results = session.run_checks();
+            if let Some(log) = &options.log
+                && let Ok(mut log) = log.lock()
+            {
+                log.record(FILE_APP_NAME, &stream_key, &mut log_state, &results);
+            }
+            if let Some(csv) = &options.csv
+                && let Ok(mut csv) = csv.lock()
+            {
+                csv.record(FILE_APP_NAME, &stream_key, &session);
+            }
+            next_check_ms += 1000;
+        }
+        session.set_clock(start + Duration::from_millis(media_ms));
+
+        let msg = RtmpMessage {
+            chunk_stream_id: 0,
+            timestamp: tag.timestamp,
+            type_id: tag.tag_type,
+            stream_id: 1,
+            payload: tag.data,
+        };
+        for event in handler.handle(msg).events {
+            match event {
+                RtmpEvent::Metadata { ref properties, .. } => session.handle_metadata(properties),
+                RtmpEvent::MetadataCleared { .. } => session.handle_metadata_cleared(),
+                RtmpEvent::TextData { timestamp, ref properties, .. } => session.handle_text_data(timestamp, properties),
+                RtmpEvent::CuePoint { timestamp, ref properties, .. } => session.handle_cue_point(timestamp, properties),
+                RtmpEvent::VideoData { timestamp, ref data, .. } => session.handle_video(timestamp, data),
+                RtmpEvent::AudioData { timestamp, ref data, .. } => session.handle_audio(timestamp, data),
+                _ => {}
+            }
+        }
+    }
+
+    if tags == 0 {
+        return Err("no tags in file".into());
+    }
+    if reader.trailing_bytes() > 0 {
+        eprintln!("{}: last tag truncated, {} bytes ignored", path.display(), reader.trailing_bytes());
+    }
+    session.diagnostics.record_flv_tag_errors(reader.back_pointer_mismatches(), reader.skipped_bytes());
+
+    let results = session.check_now();
+    if let Some(log) = &options.log
+        && let Ok(mut log) = log.lock()
+    {
+        log.record(FILE_APP_NAME, &stream_key, &mut log_state, &results);
+        log.record(FILE_APP_NAME, &stream_key, &mut log_state, &[]);
+    }
+
+    let report = session.report(FILE_APP_NAME, &stream_key, &results);
+    report
+        .emit(options.report.unwrap_or(ReportFormat::Json), options.report_file.as_deref())
+        .map_err(|e| format!("writing report: {}", e))
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use super::video::BitstreamReader;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AudioCodec {
     LinearPcmPlatformEndian,
@@ -15,7 +17,11 @@ pub enum AudioCodec {
     Speex,
     Mp3_8k,
     DeviceSpecific,
+    Opus,
+    Flac,
+    Ac3,
     Unknown(u8),
+    UnknownFourCc([u8; 4]),
 }
 
 impl fmt::Display for AudioCodec {
@@ -34,7 +40,13 @@ impl fmt::Display for AudioCodec {
             AudioCodec::Speex => write!(f, "Speex"),
             AudioCodec::Mp3_8k => write!(f, "MP3 8kHz"),
             AudioCodec::DeviceSpecific => write!(f, "Device Specific"),
+            AudioCodec::Opus => write!(f, "Opus"),
+            AudioCodec::Flac => write!(f, "FLAC"),
+            AudioCodec::Ac3 => write!(f, "AC-3"),
             AudioCodec::Unknown(id) => write!(f, "Unknown ({})", id),
+            AudioCodec::UnknownFourCc(fourcc) => {
+                write!(f, "Unknown ({})", String::from_utf8_lossy(fourcc))
+            }
         }
     }
 }
@@ -58,8 +70,133 @@ impl AudioCodec {
             _ => AudioCodec::Unknown(id),
         }
     }
+
+    fn from_fourcc(fourcc: [u8; 4]) -> Self {
+        match &fourcc {
+            b"Opus" => AudioCodec::Opus,
+            b"fLaC" => AudioCodec::Flac,
+            b"ac-3" => AudioCodec::Ac3,
+            b".mp3" => AudioCodec::Mp3,
+            b"mp4a" => AudioCodec::Aac,
+            _ => AudioCodec::UnknownFourCc(fourcc),
+        }
+    }
+}
+
+/// The fields of an MPEG audio frame header that describe the stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mp3FrameHeader {
+    pub version: &'static str,
+    pub layer: u8,
+    /// None for free-format streams
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: u32,
+    pub channel_mode: &'static str,
+}
+
+impl Mp3FrameHeader {
+    /// Parse the 4-byte header at the start of `data`.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 4 || data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version_bits = (data[1] >> 3) & 0x03;
+        let layer_bits = (data[1] >> 1) & 0x03;
+        let bitrate_index = (data[2] >> 4) & 0x0F;
+        let sample_rate_index = (data[2] >> 2) & 0x03;
+        let channel_bits = (data[3] >> 6) & 0x03;
+
+        // Version 01, layer 00, bitrate 1111 and rate 11 are reserved
+        let (version, rate_divisor) = match version_bits {
+            0 => ("MPEG-2.5", 4),
+            2 => ("MPEG-2", 2),
+            3 => ("MPEG-1", 1),
+            _ => return None,
+        };
+        let layer = match layer_bits {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        if bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+
+        let bitrates = match (version_bits == 3, layer) {
+            (true, 1) => &MP3_BITRATES_V1_L1,
+            (true, 2) => &MP3_BITRATES_V1_L2,
+            (true, _) => &MP3_BITRATES_V1_L3,
+            (false, 1) => &MP3_BITRATES_V2_L1,
+            (false, _) => &MP3_BITRATES_V2_L2_L3,
+        };
+        let bitrate_kbps = (bitrate_index > 0).then(|| bitrates[bitrate_index as usize - 1]);
+
+        Some(Self {
+            version,
+            layer,
+            bitrate_kbps,
+            sample_rate: MP3_SAMPLE_RATES[sample_rate_index as usize] / rate_divisor,
+            channel_mode: match channel_bits {
+                0 => "stereo",
+                1 => "joint stereo",
+                2 => "dual channel",
+                _ => "mono",
+            },
+        })
+    }
+
+    pub fn channels(&self) -> u8 {
+        if self.channel_mode == "mono" { 1 } else { 2 }
+    }
+}
+
+impl fmt::Display for Mp3FrameHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layer = match self.layer {
+            1 => "I",
+            2 => "II",
+            _ => "III",
+        };
+        write!(f, "{} Layer {}", self.version, layer)?;
+        match self.bitrate_kbps {
+            Some(kbps) => write!(f, ", {} kbps", kbps)?,
+            None => write!(f, ", free format")?,
+        }
+        write!(f, ", {}", self.channel_mode)
+    }
+}
+
+/// Bitrates in kbps for bitrate indexes 1-14
+const MP3_BITRATES_V1_L1: [u32; 14] = [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448];
+const MP3_BITRATES_V1_L2: [u32; 14] = [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384];
+const MP3_BITRATES_V1_L3: [u32; 14] = [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MP3_BITRATES_V2_L1: [u32; 14] = [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256];
+const MP3_BITRATES_V2_L2_L3: [u32; 14] = [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+/// MPEG-1 sample rates; MPEG-2 halves them and MPEG-2.5 quarters them
+const MP3_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Enhanced RTMP audio packet types (ExAudioTagHeader).
+mod ex_packet_type {
+    pub const SEQUENCE_START: u8 = 0;
+    pub const CODED_FRAMES: u8 = 1;
 }
 
+/// SoundFormat value signalling an Enhanced RTMP audio header.
+const SOUND_FORMAT_EX_HEADER: u8 = 9;
+
+/// Common AAC-LC encoder settings, in kbps
+const AAC_LC_NOMINAL_KBPS: [u32; 6] = [96, 128, 160, 192, 256, 320];
+
+/// Common HE-AAC settings: SBR carries the top octave in a few kbps, so the
+/// same output rate is encoded at a fraction of the LC bitrate
+const HE_AAC_NOMINAL_KBPS: [u32; 6] = [24, 32, 48, 64, 80, 96];
+
+/// AAC frames needed before the measured bitrate means anything (about a
+/// second at 48kHz)
+const AAC_BITRATE_MIN_FRAMES: u64 = 50;
+
 pub struct AudioAnalyzer {
     pub codec: Option<AudioCodec>,
     pub sample_rate: Option<u32>,
@@ -71,9 +208,23 @@ pub struct AudioAnalyzer {
     pub asc_sample_rate: Option<u32>,
     pub asc_channels: Option<u8>,
     pub asc_received: bool,
+    /// Output rate after SBR, when the config signals it explicitly
+    /// (`asc_sample_rate` is then the core rate)
+    pub sbr_sample_rate: Option<u32>,
+    /// Parametric stereo signalled: a mono core decodes to stereo
+    pub ps_present: bool,
+
+    /// Encoder input rate from the OpusHead, when it gives one; Opus itself
+    /// always decodes at 48kHz
+    pub opus_input_sample_rate: Option<u32>,
+
+    // MP3-specific, from the first frame header
+    pub mp3_header: Option<Mp3FrameHeader>,
 
     pub total_audio_bytes: u64,
     pub total_audio_frames: u64,
+    /// Raw AAC frame bytes, without tag headers or sequence headers
+    pub aac_payload_bytes: u64,
 }
 
 impl AudioAnalyzer {
@@ -87,19 +238,99 @@ impl AudioAnalyzer {
             asc_sample_rate: None,
             asc_channels: None,
             asc_received: false,
+            sbr_sample_rate: None,
+            ps_present: false,
+            opus_input_sample_rate: None,
+            mp3_header: None,
             total_audio_bytes: 0,
             total_audio_frames: 0,
+            aac_payload_bytes: 0,
         }
     }
 
-    /// Get the effective sample rate (ASC overrides FLV header for AAC).
+    /// Get the effective sample rate (ASC overrides FLV header for AAC, as
+    /// the frame header does for MP3). For HE-AAC this is the decoder's
+    /// output rate, not the core rate.
     pub fn effective_sample_rate(&self) -> Option<u32> {
-        self.asc_sample_rate.or(self.sample_rate)
+        self.sbr_sample_rate
+            .or(self.asc_sample_rate)
+            .or(self.mp3_header.map(|h| h.sample_rate))
+            .or(self.sample_rate)
     }
 
-    /// Get the effective channel count (ASC overrides FLV header for AAC).
+    /// Get the effective channel count (ASC overrides FLV header for AAC, as
+    /// the frame header does for MP3).
     pub fn effective_channels(&self) -> Option<u8> {
-        self.asc_channels.or(self.channels)
+        if self.ps_present && self.asc_channels == Some(1) {
+            return Some(2);
+        }
+        self.asc_channels
+            .or(self.mp3_header.map(|h| h.channels()))
+            .or(self.channels)
+    }
+
+    /// How long one audio frame plays: 1024 samples at the core rate for AAC,
+    /// or what the MPEG version and layer imply for MP3.
+    pub fn frame_duration_ms(&self) -> Option<f64> {
+        let (samples, rate) = match self.codec? {
+            AudioCodec::Aac => (1024, self.asc_sample_rate.or(self.sample_rate)?),
+            AudioCodec::Mp3 | AudioCodec::Mp3_8k => {
+                let header = self.mp3_header?;
+                let samples = match header.layer {
+                    1 => 384,
+                    3 if header.version != "MPEG-1" => 576,
+                    _ => 1152,
+                };
+                (samples, header.sample_rate)
+            }
+            _ => return None,
+        };
+        Some(samples as f64 * 1000.0 / rate as f64)
+    }
+
+    /// The AAC bitrate over the time the frames play rather than the time
+    /// they took to arrive, so it shows the encoder's setting whatever the
+    /// network does.
+    pub fn aac_bitrate_kbps(&self) -> Option<f64> {
+        if self.codec != Some(AudioCodec::Aac) || self.total_audio_frames < AAC_BITRATE_MIN_FRAMES {
+            return None;
+        }
+        let play_ms = self.total_audio_frames as f64 * self.frame_duration_ms()?;
+        Some(self.aac_payload_bytes as f64 * 8.0 / play_ms)
+    }
+
+    /// The standard encoder setting nearest the measured AAC bitrate, from
+    /// the HE-AAC rates when SBR is signalled.
+    pub fn nominal_aac_bitrate_kbps(&self) -> Option<u32> {
+        let measured = self.aac_bitrate_kbps()?;
+        let rates = if self.sbr_sample_rate.is_some() { &HE_AAC_NOMINAL_KBPS } else { &AAC_LC_NOMINAL_KBPS };
+        rates.iter().copied().min_by(|a, b| (*a as f64 - measured).abs().total_cmp(&(*b as f64 - measured).abs()))
+    }
+
+    /// Sample rate and channel layout, e.g. "48kHz stereo", for spotting a
+    /// new sequence header that changes them.
+    pub fn config_summary(&self) -> String {
+        let rate = self.effective_sample_rate().map_or_else(|| "unknown rate".to_string(), format_khz);
+        let channels = match self.effective_channels() {
+            Some(1) => "mono".to_string(),
+            Some(2) => "stereo".to_string(),
+            Some(6) => "5.1".to_string(),
+            Some(8) => "7.1".to_string(),
+            Some(n) => format!("{} channels", n),
+            None => "unknown channels".to_string(),
+        };
+        format!("{} {}", rate, channels)
+    }
+
+    /// The AAC profile, with the core and output rates when SBR doubles
+    /// them, e.g. "HE-AAC 24kHz→48kHz".
+    pub fn aac_description(&self) -> Option<String> {
+        let profile = self.aac_profile.as_ref()?;
+        let (Some(core), Some(output)) = (self.asc_sample_rate, self.sbr_sample_rate) else {
+            return Some(profile.clone());
+        };
+        let name = if self.ps_present { "HE-AAC v2" } else { "HE-AAC" };
+        Some(format!("{} {}→{}", name, format_khz(core), format_khz(output)))
     }
 
     pub fn process(&mut self, data: &[u8], _timestamp: u32) {
@@ -111,6 +342,13 @@ impl AudioAnalyzer {
 
         let first_byte = data[0];
         let sound_format = (first_byte >> 4) & 0x0F;
+
+        // Enhanced RTMP: SoundFormat 9 followed by a FourCC
+        if sound_format == SOUND_FORMAT_EX_HEADER {
+            self.process_enhanced(data);
+            return;
+        }
+
         let sound_rate_idx = (first_byte >> 2) & 0x03;
         let sound_size_flag = (first_byte >> 1) & 0x01;
         let sound_type_flag = first_byte & 0x01;
@@ -130,64 +368,396 @@ impl AudioAnalyzer {
             // AAC
             let aac_packet_type = data[1];
             match aac_packet_type {
-                0 => {
+                0 if data.len() >= 4 => {
                     // AAC Sequence Header (AudioSpecificConfig)
-                    if data.len() >= 4 {
-                        self.parse_audio_specific_config(&data[2..]);
-                    }
-                    return; // Don't count sequence header as audio frame
+                    // Don't count sequence header as audio frame
+                    self.parse_audio_specific_config(&data[2..]);
                 }
                 1 => {
-                    // Raw AAC data
+                    // Raw AAC data — some encoders wrap it in ADTS anyway
+                    if !self.asc_received && data.len() >= 2 + 7 {
+                        self.parse_adts_header(&data[2..]);
+                    }
                     self.total_audio_frames += 1;
+                    self.aac_payload_bytes += (data.len() - 2) as u64;
                 }
                 _ => {}
             }
         } else {
+            // MP3 tags carry the frame straight after the tag header
+            if matches!(sound_format, 2 | 14) {
+                self.parse_mp3_frame(&data[1..]);
+            }
             self.total_audio_frames += 1;
         }
     }
 
-    fn parse_audio_specific_config(&mut self, data: &[u8]) {
-        if data.len() < 2 {
+    /// Read the stream parameters from the first MP3 frame that has a valid
+    /// header. The FLV header can only express 5.5/11/22/44kHz, so this is the
+    /// only place 48kHz or 32kHz MP3 shows up.
+    fn parse_mp3_frame(&mut self, data: &[u8]) {
+        if self.mp3_header.is_none() {
+            self.mp3_header = Mp3FrameHeader::parse(data);
+        }
+    }
+
+    /// Handle an Enhanced RTMP audio tag:
+    /// SoundFormat(4) + PacketType(4) + FourCC(4) + body.
+    /// The legacy rate/size/channel bits don't exist here, so those fields only
+    /// get populated from the codec's own configuration.
+    fn process_enhanced(&mut self, data: &[u8]) {
+        if data.len() < 5 {
             return;
         }
 
-        let byte0 = data[0];
-        let byte1 = data[1];
+        let packet_type = data[0] & 0x0F;
+        let codec = AudioCodec::from_fourcc([data[1], data[2], data[3], data[4]]);
+        self.codec = Some(codec);
 
-        // audioObjectType: 5 bits from MSB of byte0
-        let audio_object_type = (byte0 >> 3) & 0x1F;
+        let body = &data[5..];
+        match packet_type {
+            // Codec configuration — not counted as an audio frame
+            ex_packet_type::SEQUENCE_START => match codec {
+                AudioCodec::Aac => self.parse_audio_specific_config(body),
+                AudioCodec::Opus => self.parse_opus_head(body),
+                _ => {}
+            },
+            ex_packet_type::CODED_FRAMES => {
+                if codec == AudioCodec::Mp3 {
+                    self.parse_mp3_frame(body);
+                } else if codec == AudioCodec::Aac {
+                    self.aac_payload_bytes += body.len() as u64;
+                }
+                self.total_audio_frames += 1;
+            }
+            _ => {
+                // Sequence end, multichannel config, multitrack — not frames
+            }
+        }
+    }
 
-        // samplingFrequencyIndex: 4 bits (lower 3 of byte0 + upper 1 of byte1)
-        let sample_freq_index = ((byte0 & 0x07) << 1) | ((byte1 >> 7) & 0x01);
+    /// Parse the Opus identification header (RFC 7845 §5.1).
+    fn parse_opus_head(&mut self, data: &[u8]) {
+        // "OpusHead" + version(1) + channels(1) + pre_skip(2) + input_sample_rate(4, LE)
+        if data.len() < 16 || &data[0..8] != b"OpusHead" {
+            return;
+        }
 
-        // channelConfiguration: 4 bits from byte1 bits [6:3]
-        let channel_config = (byte1 >> 3) & 0x0F;
+        let channels = data[9];
+        let input_sample_rate = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
 
-        self.aac_profile = Some(match audio_object_type {
-            1 => "AAC Main".to_string(),
-            2 => "AAC-LC".to_string(),
-            3 => "AAC SSR".to_string(),
-            4 => "AAC LTP".to_string(),
-            5 => "HE-AAC (SBR)".to_string(),
-            6 => "AAC Scalable".to_string(),
-            23 => "ER AAC LD".to_string(),
-            29 => "HE-AAC v2 (SBR+PS)".to_string(),
-            39 => "ER AAC ELD".to_string(),
-            _ => format!("AAC Object Type {}", audio_object_type),
-        });
+        self.channels = Some(channels);
+        // Opus always decodes at 48kHz; the input rate is informational and may be 0
+        self.sample_rate = Some(48000);
+        self.opus_input_sample_rate = (input_sample_rate > 0).then_some(input_sample_rate);
+    }
+
+    /// Parse an AudioSpecificConfig (ISO 14496-3 §1.6.2.1), including the
+    /// SBR/PS extension signalled either up front (object type 5 or 29) or
+    /// after the GASpecificConfig behind a sync extension.
+    fn parse_audio_specific_config(&mut self, data: &[u8]) {
+        if data.len() < 2 {
+            return;
+        }
+
+        let mut reader = BitstreamReader::new(data);
+        let mut audio_object_type = read_audio_object_type(&mut reader);
+        let sample_rate = read_sampling_frequency(&mut reader);
+        let channel_config = reader.read_bits(4) as u8;
 
-        const SAMPLE_RATES: [u32; 13] = [
-            96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000,
-            7350,
-        ];
+        let mut sbr_sample_rate = None;
+        let mut ps_present = false;
 
-        if (sample_freq_index as usize) < SAMPLE_RATES.len() {
-            self.asc_sample_rate = Some(SAMPLE_RATES[sample_freq_index as usize]);
+        // Hierarchical signalling: the SBR extension comes first and the
+        // core object type follows
+        let signalled_type = audio_object_type;
+        if signalled_type == AOT_SBR || signalled_type == AOT_PS {
+            ps_present = signalled_type == AOT_PS;
+            sbr_sample_rate = read_sampling_frequency(&mut reader);
+            audio_object_type = read_audio_object_type(&mut reader);
         }
 
+        // Backward-compatible signalling, appended after the core config.
+        // Only the plain GASpecificConfig of the common object types is
+        // walked; anything else leaves the extension unread
+        if sbr_sample_rate.is_none() && matches!(audio_object_type, 1..=4) && channel_config != 0 {
+            skip_ga_specific_config(&mut reader);
+            if reader.bits_left() >= 16
+                && reader.read_bits(11) == SYNC_EXTENSION_SBR
+                && read_audio_object_type(&mut reader) == AOT_SBR
+                && reader.read_bits(1) == 1
+            {
+                sbr_sample_rate = read_sampling_frequency(&mut reader);
+                if reader.bits_left() >= 12 && reader.read_bits(11) == SYNC_EXTENSION_PS {
+                    ps_present = reader.read_bits(1) == 1;
+                }
+            }
+        }
+
+        self.aac_profile = Some(aac_object_type_name(match (sbr_sample_rate, ps_present) {
+            (Some(_), true) => AOT_PS,
+            (Some(_), false) => AOT_SBR,
+            _ => signalled_type,
+        }));
+        self.asc_sample_rate = sample_rate;
+        self.sbr_sample_rate = sbr_sample_rate;
+        self.ps_present = ps_present;
         self.asc_channels = Some(channel_config);
         self.asc_received = true;
     }
+
+    /// Parse the fixed ADTS header when an encoder sends ADTS-framed AAC
+    /// instead of raw frames. Only used until an AudioSpecificConfig arrives.
+    fn parse_adts_header(&mut self, data: &[u8]) {
+        // syncword: 12 bits of 1s
+        if data.len() < 7 || data[0] != 0xFF || (data[1] & 0xF0) != 0xF0 {
+            return;
+        }
+
+        // profile (2 bits) is audioObjectType - 1
+        let profile = (data[2] >> 6) & 0x03;
+        let sample_freq_index = (data[2] >> 2) & 0x0F;
+        let channel_config = ((data[2] & 0x01) << 2) | ((data[3] >> 6) & 0x03);
+
+        if self.aac_profile.is_none() {
+            self.aac_profile = Some(aac_object_type_name(profile + 1));
+        }
+
+        if (sample_freq_index as usize) < AAC_SAMPLE_RATES.len() {
+            self.asc_sample_rate = Some(AAC_SAMPLE_RATES[sample_freq_index as usize]);
+        }
+
+        // Channel config 0 means it's signalled in-band; leave the FLV value in place
+        if channel_config != 0 {
+            self.asc_channels = Some(channel_config);
+        }
+    }
+}
+
+impl Default for AudioAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Audio object types that carry the SBR (and PS) extension
+const AOT_SBR: u8 = 5;
+const AOT_PS: u8 = 29;
+/// Sync words introducing backward-compatible extension signalling
+const SYNC_EXTENSION_SBR: u64 = 0x2B7;
+const SYNC_EXTENSION_PS: u64 = 0x548;
+
+/// audioObjectType: 5 bits, with 31 escaping to 32 + 6 more bits.
+fn read_audio_object_type(reader: &mut BitstreamReader) -> u8 {
+    match reader.read_bits(5) as u8 {
+        31 => 32 + reader.read_bits(6) as u8,
+        aot => aot,
+    }
+}
+
+/// samplingFrequencyIndex, with index 15 escaping to an explicit 24-bit rate.
+fn read_sampling_frequency(reader: &mut BitstreamReader) -> Option<u32> {
+    match reader.read_bits(4) as usize {
+        15 => Some(reader.read_bits(24) as u32).filter(|rate| *rate > 0),
+        index => AAC_SAMPLE_RATES.get(index).copied(),
+    }
+}
+
+/// Step over the GASpecificConfig of object types 1-4.
+fn skip_ga_specific_config(reader: &mut BitstreamReader) {
+    // frameLengthFlag
+    reader.skip_bits(1);
+    // dependsOnCoreCoder, then coreCoderDelay
+    if reader.read_bits(1) == 1 {
+        reader.skip_bits(14);
+    }
+    // extensionFlag: only meaningful for the error resilient types
+    reader.skip_bits(1);
+}
+
+fn format_khz(rate: u32) -> String {
+    if rate.is_multiple_of(1000) {
+        format!("{}kHz", rate / 1000)
+    } else {
+        format!("{:.1}kHz", rate as f64 / 1000.0)
+    }
+}
+
+fn aac_object_type_name(audio_object_type: u8) -> String {
+    match audio_object_type {
+        1 => "AAC Main".to_string(),
+        2 => "AAC-LC".to_string(),
+        3 => "AAC SSR".to_string(),
+        4 => "AAC LTP".to_string(),
+        5 => "HE-AAC (SBR)".to_string(),
+        6 => "AAC Scalable".to_string(),
+        23 => "ER AAC LD".to_string(),
+        29 => "HE-AAC v2 (SBR+PS)".to_string(),
+        39 => "ER AAC ELD".to_string(),
+        _ => format!("AAC Object Type {}", audio_object_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An Enhanced RTMP Opus sequence start carrying an OpusHead.
+    fn opus_sequence_start(channels: u8, input_sample_rate: u32) -> Vec<u8> {
+        let mut tag = vec![(SOUND_FORMAT_EX_HEADER << 4) | ex_packet_type::SEQUENCE_START];
+        tag.extend_from_slice(b"Opus");
+        tag.extend_from_slice(b"OpusHead");
+        tag.extend_from_slice(&[1, channels, 0x38, 0x01]);
+        tag.extend_from_slice(&input_sample_rate.to_le_bytes());
+        tag.extend_from_slice(&[0, 0, 0]);
+        tag
+    }
+
+    /// A raw AAC frame tag (FLV flags 44kHz 16-bit stereo) carrying `frame`.
+    fn aac_frame(frame: &[u8]) -> Vec<u8> {
+        [&[0xAF, 0x01][..], frame].concat()
+    }
+
+    #[test]
+    fn adts_headers_stand_in_for_a_missing_config() {
+        // LC, 44.1kHz, stereo, no CRC
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&aac_frame(&[0xFF, 0xF1, 0x50, 0x80, 0x2E, 0x7F, 0xFC, 0x21]), 0);
+        assert_eq!(audio.aac_profile.as_deref(), Some("AAC-LC"));
+        assert_eq!(audio.effective_sample_rate(), Some(44100));
+        assert_eq!(audio.effective_channels(), Some(2));
+
+        // LC, 48kHz, mono, against FLV flags that say 44kHz stereo
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&aac_frame(&[0xFF, 0xF1, 0x4C, 0x40, 0x2E, 0x7F, 0xFC, 0x21]), 0);
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(1));
+        assert_eq!(audio.config_summary(), "48kHz mono");
+
+        // protection_absent 0: a CRC follows the 7-byte header
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&aac_frame(&[0xFF, 0xF0, 0x54, 0x80, 0x2E, 0xBF, 0xFC, 0x5A, 0xA5, 0x21]), 0);
+        assert_eq!(audio.effective_sample_rate(), Some(32000));
+        assert_eq!(audio.effective_channels(), Some(2));
+        assert_eq!(audio.total_audio_frames, 1);
+    }
+
+    #[test]
+    fn adts_headers_are_ignored_after_a_config() {
+        let mut audio = AudioAnalyzer::new();
+        // AudioSpecificConfig: LC, 48kHz, stereo
+        audio.process(&[0xAF, 0x00, 0x11, 0x90], 0);
+        audio.process(&aac_frame(&[0xFF, 0xF1, 0x50, 0x40, 0x2E, 0x7F, 0xFC, 0x21]), 0);
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(2));
+    }
+
+    /// The analyzer after an AAC sequence header carrying `config`.
+    fn with_audio_specific_config(config: &[u8]) -> AudioAnalyzer {
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&[&[0xAF, 0x00][..], config].concat(), 0);
+        assert!(audio.asc_received);
+        audio
+    }
+
+    #[test]
+    fn explicit_sbr_and_ps_signalling() {
+        // Object type 5, 24kHz core, stereo, 48kHz output, then LC
+        let audio = with_audio_specific_config(&[0x2B, 0x11, 0x88, 0x00]);
+        assert_eq!(audio.aac_profile.as_deref(), Some("HE-AAC (SBR)"));
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(2));
+        assert_eq!(audio.aac_description().as_deref(), Some("HE-AAC 24kHz→48kHz"));
+        // AAC frames are 1024 samples at the core rate
+        assert_eq!(audio.frame_duration_ms(), Some(1024.0 * 1000.0 / 24000.0));
+
+        // Object type 29, mono core: parametric stereo decodes to two channels
+        let audio = with_audio_specific_config(&[0xEB, 0x09, 0x88, 0x00]);
+        assert_eq!(audio.aac_profile.as_deref(), Some("HE-AAC v2 (SBR+PS)"));
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(2));
+        assert_eq!(audio.aac_description().as_deref(), Some("HE-AAC v2 24kHz→48kHz"));
+    }
+
+    #[test]
+    fn backward_compatible_sbr_and_ps_signalling() {
+        // LC at 24kHz, then sync extension 0x2B7 with SBR at 48kHz
+        let audio = with_audio_specific_config(&[0x13, 0x10, 0x56, 0xE5, 0x98]);
+        assert_eq!(audio.aac_profile.as_deref(), Some("HE-AAC (SBR)"));
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(2));
+
+        // Mono LC at 24kHz, SBR, then sync extension 0x548 with PS
+        let audio = with_audio_specific_config(&[0x13, 0x08, 0x56, 0xE5, 0x9D, 0x48, 0x80]);
+        assert_eq!(audio.aac_profile.as_deref(), Some("HE-AAC v2 (SBR+PS)"));
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.effective_channels(), Some(2));
+
+        // The same LC config without the extension stays at the core rate
+        let audio = with_audio_specific_config(&[0x13, 0x10]);
+        assert_eq!(audio.aac_profile.as_deref(), Some("AAC-LC"));
+        assert_eq!(audio.effective_sample_rate(), Some(24000));
+        assert_eq!(audio.aac_description().as_deref(), Some("AAC-LC"));
+    }
+
+    fn mp3(header: [u8; 4]) -> Option<(&'static str, u8, Option<u32>, u32, &'static str)> {
+        Mp3FrameHeader::parse(&header).map(|h| (h.version, h.layer, h.bitrate_kbps, h.sample_rate, h.channel_mode))
+    }
+
+    #[test]
+    fn mp3_headers_use_the_tables_of_their_version_and_layer() {
+        assert_eq!(mp3([0xFF, 0xFB, 0x90, 0x64]), Some(("MPEG-1", 3, Some(128), 44100, "joint stereo")));
+        assert_eq!(mp3([0xFF, 0xFD, 0xE4, 0x00]), Some(("MPEG-1", 2, Some(384), 48000, "stereo")));
+        assert_eq!(mp3([0xFF, 0xFF, 0xE8, 0x80]), Some(("MPEG-1", 1, Some(448), 32000, "dual channel")));
+        assert_eq!(mp3([0xFF, 0xF3, 0x80, 0xC0]), Some(("MPEG-2", 3, Some(64), 22050, "mono")));
+        assert_eq!(mp3([0xFF, 0xF7, 0xE4, 0x00]), Some(("MPEG-2", 1, Some(256), 24000, "stereo")));
+        assert_eq!(mp3([0xFF, 0xE3, 0x18, 0xC0]), Some(("MPEG-2.5", 3, Some(8), 8000, "mono")));
+        // Bitrate index 0 is free format
+        assert_eq!(mp3([0xFF, 0xFB, 0x04, 0x00]), Some(("MPEG-1", 3, None, 48000, "stereo")));
+
+        let header = Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x64]).unwrap();
+        assert_eq!(header.to_string(), "MPEG-1 Layer III, 128 kbps, joint stereo");
+    }
+
+    #[test]
+    fn mp3_headers_without_a_sync_word_or_with_reserved_fields_are_rejected() {
+        assert_eq!(mp3([0xFE, 0xFB, 0x90, 0x64]), None);
+        assert_eq!(mp3([0xFF, 0x1B, 0x90, 0x64]), None);
+        // Reserved version, layer, bitrate and sample rate
+        assert_eq!(mp3([0xFF, 0xEB, 0x90, 0x64]), None);
+        assert_eq!(mp3([0xFF, 0xF9, 0x90, 0x64]), None);
+        assert_eq!(mp3([0xFF, 0xFB, 0xF0, 0x64]), None);
+        assert_eq!(mp3([0xFF, 0xFB, 0x9C, 0x64]), None);
+        assert!(Mp3FrameHeader::parse(&[0xFF, 0xFB, 0x90]).is_none());
+    }
+
+    #[test]
+    fn mp3_tags_report_the_frame_header_rate() {
+        let mut audio = AudioAnalyzer::new();
+        // FLV flags say 44kHz stereo; the MPEG-2 frame says 22.05kHz mono
+        audio.process(&[0x2F, 0xFF, 0xF3, 0x80, 0xC0, 0x00], 0);
+        assert_eq!(audio.codec, Some(AudioCodec::Mp3));
+        assert_eq!(audio.effective_sample_rate(), Some(22050));
+        assert_eq!(audio.effective_channels(), Some(1));
+        assert_eq!(audio.frame_duration_ms(), Some(576.0 * 1000.0 / 22050.0));
+    }
+
+    #[test]
+    fn opus_decodes_at_48khz_whatever_the_input_rate() {
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&opus_sequence_start(2, 44100), 0);
+        assert_eq!(audio.codec, Some(AudioCodec::Opus));
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.opus_input_sample_rate, Some(44100));
+        assert_eq!(audio.effective_channels(), Some(2));
+
+        let mut audio = AudioAnalyzer::new();
+        audio.process(&opus_sequence_start(1, 0), 0);
+        assert_eq!(audio.effective_sample_rate(), Some(48000));
+        assert_eq!(audio.opus_input_sample_rate, None);
+    }
 }
@@ -0,0 +1,65 @@
+use crate::rtmp::amf0::Amf0Value;
+
+/// What the encoder declared about the stream in onMetaData.
+#[derive(Debug, Clone, Default)]
+pub struct StreamMetadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub framerate: Option<f64>,
+    /// Declared video bitrate in kbps
+    pub video_data_rate: Option<f64>,
+    /// Declared audio bitrate in kbps
+    pub audio_data_rate: Option<f64>,
+    /// FLV codec id ("7") or FourCC ("avc1"), as sent
+    pub video_codec_id: Option<String>,
+    pub audio_codec_id: Option<String>,
+    pub stereo: Option<bool>,
+    pub encoder: Option<String>,
+}
+
+impl StreamMetadata {
+    pub fn from_properties(properties: &[(String, Amf0Value)]) -> Self {
+        let mut meta = Self::default();
+        for (key, value) in properties {
+            match key.as_str() {
+                "width" => meta.width = positive(value).map(|w| w.round() as u32),
+                "height" => meta.height = positive(value).map(|h| h.round() as u32),
+                "framerate" | "fps" => meta.framerate = positive(value),
+                "videodatarate" => meta.video_data_rate = positive(value),
+                "audiodatarate" => meta.audio_data_rate = positive(value),
+                "videocodecid" => meta.video_codec_id = codec_id(value),
+                "audiocodecid" => meta.audio_codec_id = codec_id(value),
+                "stereo" => {
+                    if let Amf0Value::Boolean(stereo) = value {
+                        meta.stereo = Some(*stereo);
+                    }
+                }
+                "encoder" => meta.encoder = value.as_str().map(str::to_string),
+                _ => {}
+            }
+        }
+        meta
+    }
+
+    pub fn has_dimensions(&self) -> bool {
+        self.width.is_some() || self.height.is_some()
+    }
+
+    pub fn has_bitrate(&self) -> bool {
+        self.video_data_rate.is_some() || self.audio_data_rate.is_some()
+    }
+}
+
+/// Encoders fill unknown fields with 0, which is as good as absent
+fn positive(value: &Amf0Value) -> Option<f64> {
+    value.as_f64().filter(|v| *v > 0.0 && v.is_finite())
+}
+
+fn codec_id(value: &Amf0Value) -> Option<String> {
+    match value {
+        Amf0Value::Number(n) if n.fract() == 0.0 => Some(format!("{}", *n as i64)),
+        Amf0Value::Number(n) => Some(n.to_string()),
+        Amf0Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
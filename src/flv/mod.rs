@@ -1,2 +1,5 @@
 pub mod audio;
+pub mod metadata;
+pub mod reader;
+pub mod tag;
 pub mod video;
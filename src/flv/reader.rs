@@ -0,0 +1,43 @@
+use super::tag::{FlvTag, TagParser};
+
+const HEADER_LEN: usize = 9;
+
+/// Reads the tags of an in-memory FLV file in order.
+pub struct FlvReader<'a> {
+    tags: TagParser<'a>,
+}
+
+impl<'a> FlvReader<'a> {
+    /// Check the file header and position the reader at the first tag.
+    pub fn new(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < HEADER_LEN || &data[..3] != b"FLV" {
+            return Err("not an FLV file".into());
+        }
+        let header_len = u32::from_be_bytes([data[5], data[6], data[7], data[8]]) as usize;
+        if header_len < HEADER_LEN || header_len > data.len() {
+            return Err(format!("invalid FLV header size {}", header_len));
+        }
+        // The header is followed by PreviousTagSize0
+        Ok(Self { tags: TagParser::at(data, header_len + 4) })
+    }
+
+    /// The next tag, or None at the end of the file.
+    pub fn next_tag(&mut self) -> Option<FlvTag> {
+        self.tags.next_tag()
+    }
+
+    /// Bytes left over after the last complete tag.
+    pub fn trailing_bytes(&self) -> usize {
+        self.tags.trailing_bytes()
+    }
+
+    /// Tags whose PreviousTagSize didn't match their size.
+    pub fn back_pointer_mismatches(&self) -> u32 {
+        self.tags.back_pointer_mismatches()
+    }
+
+    /// Bytes skipped to get past corrupt tags.
+    pub fn skipped_bytes(&self) -> usize {
+        self.tags.skipped_bytes()
+    }
+}
@@ -0,0 +1,125 @@
+/// One tag from an FLV file. The body has the same layout as the payload of
+/// the RTMP message of the same type (8 audio, 9 video, 18 script data).
+#[derive(Debug, Clone)]
+pub struct FlvTag {
+    pub tag_type: u8,
+    pub timestamp: u32,
+    pub data: Vec<u8>,
+}
+
+const TAG_HEADER_LEN: usize = 11;
+const BACK_POINTER_LEN: usize = 4;
+
+/// Splits a run of FLV tags, each followed by its PreviousTagSize
+/// back-pointer, as found in a file body or an RTMP Aggregate message.
+///
+/// A back-pointer that doesn't match its tag is counted. If what follows
+/// doesn't look like a tag either, or the header itself is implausible, the
+/// tag is taken to be corrupt and the parser skips ahead to the next
+/// plausible tag boundary.
+pub struct TagParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    back_pointer_mismatches: u32,
+    skipped_bytes: usize,
+}
+
+impl<'a> TagParser<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::at(data, 0)
+    }
+
+    /// Parse tags starting `pos` bytes into `data`.
+    pub fn at(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos, back_pointer_mismatches: 0, skipped_bytes: 0 }
+    }
+
+    /// The next tag, or None at the end of the data. A truncated final tag,
+    /// as left behind by an interrupted recording, also ends the data.
+    pub fn next_tag(&mut self) -> Option<FlvTag> {
+        loop {
+            if self.pos + TAG_HEADER_LEN > self.data.len() {
+                return None;
+            }
+            let Some((tag_type, size)) = self.header_at(self.pos) else {
+                // Nothing to resync to means the last tag was cut short
+                if self.resync() {
+                    continue;
+                }
+                return None;
+            };
+            let body_start = self.pos + TAG_HEADER_LEN;
+            let next = body_start + size + BACK_POINTER_LEN;
+
+            let intact = match self.back_pointer_at(body_start + size) {
+                Some(back_pointer) if back_pointer != (TAG_HEADER_LEN + size) as u32 => {
+                    self.back_pointer_mismatches += 1;
+                    // Some muxers just write the wrong size; the tag is fine
+                    // as long as the next one starts where it should
+                    next >= self.data.len() || self.header_at(next).is_some()
+                }
+                _ => true,
+            };
+            // With no later tag to resync to, the tag is as good as any
+            if !intact && self.resync() {
+                continue;
+            }
+
+            let header = &self.data[self.pos..body_start];
+            // 24-bit timestamp with the upper 8 bits stored after it
+            let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+            let data = self.data[body_start..body_start + size].to_vec();
+            self.pos = next;
+            return Some(FlvTag { tag_type, timestamp, data });
+        }
+    }
+
+    /// Bytes left over after the last complete tag.
+    pub fn trailing_bytes(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    /// Tags whose PreviousTagSize didn't match their size.
+    pub fn back_pointer_mismatches(&self) -> u32 {
+        self.back_pointer_mismatches
+    }
+
+    /// Bytes thrown away looking for a tag boundary after a corrupt tag.
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped_bytes
+    }
+
+    /// Type and body size of a complete tag starting at `pos`, if the header
+    /// there is plausible: a known type, the always-zero stream id, and a body
+    /// that fits in the data.
+    fn header_at(&self, pos: usize) -> Option<(u8, usize)> {
+        let header = self.data.get(pos..pos + TAG_HEADER_LEN)?;
+        // Bit 5 flags an encrypted tag; the low 5 bits are the type
+        let tag_type = header[0] & 0x1F;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let plausible = matches!(tag_type, 8 | 9 | 15 | 18) && header[8..11] == [0, 0, 0];
+        (plausible && pos + TAG_HEADER_LEN + size <= self.data.len()).then_some((tag_type, size))
+    }
+
+    fn back_pointer_at(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + BACK_POINTER_LEN)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Move to the next position holding a plausible tag whose own
+    /// back-pointer checks out. False, staying put, if there is none.
+    fn resync(&mut self) -> bool {
+        let start = self.pos;
+        for pos in start + 1..self.data.len() {
+            if let Some((_, size)) = self.header_at(pos) {
+                let end = pos + TAG_HEADER_LEN + size;
+                if self.back_pointer_at(end).is_none_or(|back_pointer| back_pointer == (TAG_HEADER_LEN + size) as u32) {
+                    self.skipped_bytes += pos - start;
+                    self.pos = pos;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
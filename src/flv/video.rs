@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -8,7 +9,11 @@ pub enum VideoCodec {
     VP6Alpha,
     ScreenV2,
     Avc, // H.264
+    Hevc, // H.265 (Enhanced RTMP)
+    Av1,
+    Vp9,
     Unknown(u8),
+    UnknownFourCc([u8; 4]),
 }
 
 impl fmt::Display for VideoCodec {
@@ -20,12 +25,38 @@ impl fmt::Display for VideoCodec {
             VideoCodec::VP6Alpha => write!(f, "VP6 Alpha"),
             VideoCodec::ScreenV2 => write!(f, "Screen Video V2"),
             VideoCodec::Avc => write!(f, "H.264/AVC"),
+            VideoCodec::Hevc => write!(f, "H.265/HEVC"),
+            VideoCodec::Av1 => write!(f, "AV1"),
+            VideoCodec::Vp9 => write!(f, "VP9"),
             VideoCodec::Unknown(id) => write!(f, "Unknown ({})", id),
+            VideoCodec::UnknownFourCc(fourcc) => {
+                write!(f, "Unknown ({})", String::from_utf8_lossy(fourcc))
+            }
         }
     }
 }
 
 impl VideoCodec {
+    /// Short tag for tight spaces, e.g. "AVC" or "HEVC"
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            VideoCodec::H263 => "H.263",
+            VideoCodec::Screen | VideoCodec::ScreenV2 => "Screen",
+            VideoCodec::VP6 | VideoCodec::VP6Alpha => "VP6",
+            VideoCodec::Avc => "AVC",
+            VideoCodec::Hevc => "HEVC",
+            VideoCodec::Av1 => "AV1",
+            VideoCodec::Vp9 => "VP9",
+            VideoCodec::Unknown(_) | VideoCodec::UnknownFourCc(_) => "Video",
+        }
+    }
+
+    /// Whether decoders need a configuration record (sequence header)
+    /// before the first frame
+    pub fn has_config_record(&self) -> bool {
+        matches!(self, VideoCodec::Avc | VideoCodec::Hevc | VideoCodec::Av1 | VideoCodec::Vp9)
+    }
+
     fn from_id(id: u8) -> Self {
         match id {
             2 => VideoCodec::H263,
@@ -37,6 +68,16 @@ impl VideoCodec {
             _ => VideoCodec::Unknown(id),
         }
     }
+
+    fn from_fourcc(fourcc: [u8; 4]) -> Self {
+        match &fourcc {
+            b"avc1" => VideoCodec::Avc,
+            b"hvc1" => VideoCodec::Hevc,
+            b"av01" => VideoCodec::Av1,
+            b"vp09" => VideoCodec::Vp9,
+            _ => VideoCodec::UnknownFourCc(fourcc),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -49,21 +90,147 @@ pub enum FrameType {
     Unknown(u8),
 }
 
+impl FrameType {
+    fn from_id(id: u8) -> Self {
+        match id {
+            1 => FrameType::Keyframe,
+            2 => FrameType::Inter,
+            3 => FrameType::DisposableInter,
+            4 => FrameType::GeneratedKeyframe,
+            5 => FrameType::VideoInfo,
+            _ => FrameType::Unknown(id),
+        }
+    }
+}
+
+/// Chroma subsampling and sample bit depths, as signalled in the SPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    /// 0 monochrome, 1 4:2:0, 2 4:2:2, 3 4:4:4
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+}
+
+impl PixelFormat {
+    /// What every profile below High implies
+    const DEFAULT: PixelFormat = PixelFormat { chroma_format_idc: 1, bit_depth_luma: 8, bit_depth_chroma: 8 };
+
+    pub fn chroma_subsampling(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            3 => "4:4:4",
+            _ => "unknown chroma",
+        }
+    }
+
+    /// SubWidthC and SubHeightC: luma samples per chroma sample across and
+    /// down. Monochrome counts as 1 so crops are in luma samples.
+    pub fn chroma_subsampling_factors(&self) -> (u64, u64) {
+        match self.chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// The only format many ingest services and players accept
+    pub fn is_8bit_420(&self) -> bool {
+        self.chroma_format_idc == 1 && self.bit_depth_luma == 8 && self.bit_depth_chroma == 8
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bit_depth_luma == self.bit_depth_chroma || self.chroma_format_idc == 0 {
+            write!(f, "{} {}-bit", self.chroma_subsampling(), self.bit_depth_luma)
+        } else {
+            write!(f, "{} {}-bit luma/{}-bit chroma", self.chroma_subsampling(), self.bit_depth_luma, self.bit_depth_chroma)
+        }
+    }
+}
+
+/// Enhanced RTMP video packet types (ExVideoTagHeader).
+mod ex_packet_type {
+    pub const SEQUENCE_START: u8 = 0;
+    pub const CODED_FRAMES: u8 = 1;
+    pub const SEQUENCE_END: u8 = 2;
+    pub const CODED_FRAMES_X: u8 = 3;
+}
+
+/// Number of recent frames kept for the GOP pattern string
+const GOP_PATTERN_LEN: usize = 24;
+
 pub struct VideoAnalyzer {
     pub codec: Option<VideoCodec>,
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub profile: Option<String>,
     pub level: Option<String>,
+    /// Nominal framerate from the SPS VUI timing info
+    pub fps: Option<f64>,
+    /// Sample (pixel) aspect ratio from the SPS VUI, as width:height
+    pub sample_aspect_ratio: Option<(u32, u32)>,
+    pub pixel_format: Option<PixelFormat>,
+    /// The SPS allows field coding (frame_mbs_only_flag 0)
+    pub is_interlaced: bool,
+    /// Frames may switch between frame and field coding per macroblock pair
+    pub mb_adaptive_frame_field: bool,
 
     pub avc_config_received: bool,
+    /// SPS and PPS units in the last AVC configuration record
+    pub avc_parameter_sets: Option<(usize, usize)>,
+    pub hevc_config_received: bool,
     nalu_length_size: u8,
 
     pub keyframe_count: u64,
     pub inter_frame_count: u64,
     pub b_frame_count: u64,
+    /// Frames whose FLV frame type is none of the above
+    pub other_frame_count: u64,
+    /// Decoder configuration records received, resends included
+    pub sequence_header_count: u64,
+    /// Keyframes (AVC) that contained an IDR slice
+    pub idr_count: u64,
+    /// Keyframes (AVC) flagged in the FLV header but carrying no IDR slice
+    pub non_idr_keyframe_count: u64,
+    /// Of those, how many at least had a recovery point SEI
+    pub recovery_point_keyframe_count: u64,
+    /// AVC/HEVC frames whose length-prefixed NAL units didn't add up to the
+    /// payload, or started with a corrupt NAL header
+    pub malformed_access_unit_count: u64,
+    /// Per-NAL-type tallies (AVC), only collected once enabled
+    nal_stats: Option<NalStats>,
+    /// Keyframes + P + B + other frames; sequence headers aren't frames
     pub total_video_frames: u64,
+    /// Bytes of those frames
     pub total_video_bytes: u64,
+
+    // Composition time offsets (PTS - DTS, ms) for codecs that carry them
+    pub min_composition_offset: Option<i32>,
+    pub max_composition_offset: Option<i32>,
+    composition_offset_sum: i64,
+    composition_offset_samples: u64,
+
+    // Decode timestamps of counted frames, for the mean frame interval
+    first_dts: Option<u32>,
+    last_dts: Option<u32>,
+
+    // GOP structure
+    recent_frames: VecDeque<char>,
+    max_pts: Option<i64>,
+    keyframe_pts: Option<i64>,
+    frames_since_keyframe: Option<u32>,
+    /// Frames from one keyframe to the next, for the last complete GOP
+    pub gop_length: Option<u32>,
+    /// A B-frame following a keyframe was presented before it (the GOP
+    /// references the previous one, so the keyframe isn't a clean entry point)
+    pub open_gop: bool,
+    /// A B slice (AVC) was marked as a reference for other frames, as
+    /// encoders do when they build a B-pyramid
+    pub b_pyramid: bool,
 }
 
 impl VideoAnalyzer {
@@ -74,38 +241,126 @@ impl VideoAnalyzer {
             height: None,
             profile: None,
             level: None,
+            fps: None,
+            sample_aspect_ratio: None,
+            pixel_format: None,
+            is_interlaced: false,
+            mb_adaptive_frame_field: false,
             avc_config_received: false,
+            avc_parameter_sets: None,
+            hevc_config_received: false,
             nalu_length_size: 4,
             keyframe_count: 0,
             inter_frame_count: 0,
             b_frame_count: 0,
+            other_frame_count: 0,
+            sequence_header_count: 0,
+            idr_count: 0,
+            non_idr_keyframe_count: 0,
+            recovery_point_keyframe_count: 0,
+            malformed_access_unit_count: 0,
+            nal_stats: None,
             total_video_frames: 0,
             total_video_bytes: 0,
+            min_composition_offset: None,
+            max_composition_offset: None,
+            composition_offset_sum: 0,
+            composition_offset_samples: 0,
+            first_dts: None,
+            last_dts: None,
+            recent_frames: VecDeque::with_capacity(GOP_PATTERN_LEN),
+            max_pts: None,
+            keyframe_pts: None,
+            frames_since_keyframe: None,
+            gop_length: None,
+            open_gop: false,
+            b_pyramid: false,
+        }
+    }
+
+    /// Start tallying NAL unit types. Off by default since it walks every frame.
+    pub fn enable_nal_stats(&mut self) {
+        self.nal_stats.get_or_insert_with(NalStats::default);
+    }
+
+    pub fn nal_stats(&self) -> Option<&NalStats> {
+        self.nal_stats.as_ref()
+    }
+
+    /// The most recent frame types in decode order, e.g. "IPBBPBBP"
+    pub fn gop_pattern(&self) -> String {
+        self.recent_frames.iter().collect()
+    }
+
+    /// Resolution and profile, e.g. "1920x1080 High", for spotting a new
+    /// sequence header that changes them.
+    pub fn config_summary(&self) -> String {
+        let resolution = match (self.width, self.height) {
+            (Some(w), Some(h)) => format!("{}x{}", w, h),
+            _ => "unknown resolution".to_string(),
+        };
+        match &self.profile {
+            Some(profile) => format!("{} {}", resolution, profile),
+            None => resolution,
+        }
+    }
+
+    /// How interlaced video is coded, e.g. "MBAFF"; None for progressive.
+    pub fn interlace_mode(&self) -> Option<&'static str> {
+        match (self.is_interlaced, self.mb_adaptive_frame_field) {
+            (false, _) => None,
+            (true, true) => Some("MBAFF"),
+            (true, false) => Some("field pictures"),
         }
     }
 
-    pub fn process(&mut self, data: &[u8], _timestamp: u32) {
+    pub fn mean_composition_offset(&self) -> Option<f64> {
+        if self.composition_offset_samples == 0 {
+            return None;
+        }
+        Some(self.composition_offset_sum as f64 / self.composition_offset_samples as f64)
+    }
+
+    /// Mean spacing between decode timestamps, in ms
+    pub fn mean_frame_interval_ms(&self) -> Option<f64> {
+        let (first, last) = (self.first_dts?, self.last_dts?);
+        if self.total_video_frames < 2 || last <= first {
+            return None;
+        }
+        Some((last - first) as f64 / (self.total_video_frames - 1) as f64)
+    }
+
+    /// How many frame intervals a frame's presentation can trail its decode,
+    /// i.e. the encoder's reordering lookahead
+    pub fn max_reorder_depth(&self) -> Option<u32> {
+        let max_offset = self.max_composition_offset?;
+        if max_offset <= 0 {
+            return Some(0);
+        }
+        let interval = self.mean_frame_interval_ms()?;
+        Some((max_offset as f64 / interval).round() as u32)
+    }
+
+    pub fn process(&mut self, data: &[u8], timestamp: u32) {
         if data.is_empty() {
             return;
         }
 
-        self.total_video_bytes += data.len() as u64;
-
         let first_byte = data[0];
+
+        // Enhanced RTMP: IsExHeader bit set, codec identified by FourCC
+        if first_byte & 0x80 != 0 {
+            self.process_enhanced(data, timestamp);
+            return;
+        }
+
         let frame_type_id = (first_byte >> 4) & 0x0F;
         let codec_id = first_byte & 0x0F;
 
         let codec = VideoCodec::from_id(codec_id);
         self.codec = Some(codec);
 
-        let frame_type = match frame_type_id {
-            1 => FrameType::Keyframe,
-            2 => FrameType::Inter,
-            3 => FrameType::DisposableInter,
-            4 => FrameType::GeneratedKeyframe,
-            5 => FrameType::VideoInfo,
-            _ => FrameType::Unknown(frame_type_id),
-        };
+        let frame_type = FrameType::from_id(frame_type_id);
 
         // Don't count info/command frames
         if matches!(frame_type, FrameType::VideoInfo) {
@@ -114,61 +369,225 @@ impl VideoAnalyzer {
 
         if codec == VideoCodec::Avc && data.len() >= 5 {
             let avc_packet_type = data[1];
-            let composition_time = ((data[2] as i32) << 16)
-                | ((data[3] as i32) << 8)
-                | (data[4] as i32);
-            // Sign-extend from 24-bit
-            let composition_time = if composition_time & 0x800000 != 0 {
-                composition_time | !0xFFFFFF_u32 as i32
-            } else {
-                composition_time
-            };
+            let composition_time = read_composition_time(&data[2..5]);
 
             match avc_packet_type {
-                0 => {
+                0 if data.len() > 5 => {
                     // AVC Sequence Header
-                    if data.len() > 5 {
-                        self.parse_avc_sequence_header(&data[5..]);
-                    }
-                    return; // Don't count sequence headers as frames
+                    // Don't count sequence headers as frames
+                    self.sequence_header_count += 1;
+                    self.parse_avc_sequence_header(&data[5..]);
                 }
                 1 => {
                     // AVC NALU — count frames
-                    self.total_video_frames += 1;
-
-                    match frame_type {
-                        FrameType::Keyframe | FrameType::GeneratedKeyframe => {
-                            self.keyframe_count += 1;
-                        }
-                        FrameType::Inter | FrameType::DisposableInter => {
-                            if composition_time != 0 {
-                                self.b_frame_count += 1;
-                            } else {
-                                self.inter_frame_count += 1;
-                            }
-                        }
-                        _ => {}
-                    }
+                    let b_slice = self.inspect_avc_nalus(frame_type, &data[5..]);
+                    self.count_frame(frame_type, Some(composition_time), b_slice, timestamp, data.len());
                 }
                 2 => {
                     // End of sequence
-                    return;
                 }
                 _ => {}
             }
         } else {
             // Non-AVC codec — just count frames
-            self.total_video_frames += 1;
-            match frame_type {
-                FrameType::Keyframe | FrameType::GeneratedKeyframe => {
-                    self.keyframe_count += 1;
+            self.count_frame(frame_type, None, None, timestamp, data.len());
+        }
+    }
+
+    /// Handle an Enhanced RTMP video tag:
+    /// IsExHeader(1) + FrameType(3) + PacketType(4) + FourCC(4) + body.
+    fn process_enhanced(&mut self, data: &[u8], timestamp: u32) {
+        if data.len() < 5 {
+            return;
+        }
+
+        let frame_type = FrameType::from_id((data[0] >> 4) & 0x07);
+        let packet_type = data[0] & 0x0F;
+        let codec = VideoCodec::from_fourcc([data[1], data[2], data[3], data[4]]);
+        self.codec = Some(codec);
+
+        if matches!(frame_type, FrameType::VideoInfo) {
+            return;
+        }
+
+        let body = &data[5..];
+        let has_composition_time = matches!(codec, VideoCodec::Avc | VideoCodec::Hevc);
+        match packet_type {
+            // Decoder configuration record — not counted as a frame
+            ex_packet_type::SEQUENCE_START => {
+                self.sequence_header_count += 1;
+                match codec {
+                    VideoCodec::Avc => self.parse_avc_sequence_header(body),
+                    VideoCodec::Hevc => self.parse_hevc_sequence_header(body),
+                    _ => {}
                 }
-                FrameType::Inter | FrameType::DisposableInter => {
-                    self.inter_frame_count += 1;
+            }
+            ex_packet_type::CODED_FRAMES => {
+                // AVC and HEVC carry a composition time offset; other codecs don't
+                let composition_time = if has_composition_time {
+                    if body.len() < 3 {
+                        return;
+                    }
+                    Some(read_composition_time(&body[..3]))
+                } else {
+                    None
+                };
+                let b_slice = match codec {
+                    VideoCodec::Avc => self.inspect_avc_nalus(frame_type, &body[3..]),
+                    VideoCodec::Hevc => {
+                        self.check_nalu_framing(&body[3..]);
+                        None
+                    }
+                    _ => None,
+                };
+                self.count_frame(frame_type, composition_time, b_slice, timestamp, data.len());
+            }
+            ex_packet_type::CODED_FRAMES_X => {
+                // Coded frames with an implied composition time of zero
+                let b_slice = match codec {
+                    VideoCodec::Avc => self.inspect_avc_nalus(frame_type, body),
+                    VideoCodec::Hevc => {
+                        self.check_nalu_framing(body);
+                        None
+                    }
+                    _ => None,
+                };
+                self.count_frame(frame_type, has_composition_time.then_some(0), b_slice, timestamp, data.len());
+            }
+            ex_packet_type::SEQUENCE_END => {}
+            _ => {
+                // Metadata, MPEG-2 TS sequence start, multitrack — not frames
+            }
+        }
+    }
+
+    /// Walk the NAL units of an AVC frame: check their framing, tally them when
+    /// NAL stats are on, and check that a frame flagged as a keyframe really
+    /// starts with an IDR. Some encoders flag recovery-point frames instead,
+    /// which players can't join on.
+    ///
+    /// Returns whether the frame's first slice is a B slice, if it has one.
+    fn inspect_avc_nalus(&mut self, frame_type: FrameType, nalus: &[u8]) -> Option<bool> {
+        let is_keyframe = matches!(frame_type, FrameType::Keyframe);
+
+        let mut b_slice = None;
+        let mut has_idr = false;
+        let mut has_recovery_point = false;
+        let mut has_sps = false;
+        let mut iter = NaluIter::new(nalus, self.nalu_length_size as usize);
+        for nal in iter.by_ref() {
+            let nal_type = nal[0] & 0x1F;
+            if matches!(nal_type, 1 | 5) && b_slice.is_none() {
+                let is_b = avc_slice_type(&nal[1..]) == Some(SLICE_TYPE_B);
+                // nal_ref_idc != 0: later frames predict from this one
+                if is_b && nal[0] & 0x60 != 0 {
+                    self.b_pyramid = true;
                 }
+                b_slice = Some(is_b);
+            }
+            match nal_type {
+                5 => has_idr = true,
+                6 => has_recovery_point |= sei_has_recovery_point(&nal[1..]),
+                7 => has_sps = true,
                 _ => {}
             }
+            if let Some(stats) = self.nal_stats.as_mut() {
+                stats.counts[nal_type as usize] += 1;
+            }
+        }
+
+        if iter.malformed {
+            self.malformed_access_unit_count += 1;
+        }
+        if let Some(stats) = self.nal_stats.as_mut() {
+            if iter.truncated {
+                stats.truncated += 1;
+            }
+            if is_keyframe && has_sps {
+                stats.keyframes_with_sps += 1;
+            }
         }
+
+        if !is_keyframe {
+            return b_slice;
+        }
+        if has_idr {
+            self.idr_count += 1;
+        } else if !iter.malformed {
+            // A broken walk may just have missed the IDR
+            self.non_idr_keyframe_count += 1;
+            if has_recovery_point {
+                self.recovery_point_keyframe_count += 1;
+            }
+        }
+        b_slice
+    }
+
+    /// Check the length-prefixed framing of a frame whose NAL units aren't
+    /// otherwise inspected.
+    fn check_nalu_framing(&mut self, nalus: &[u8]) {
+        let mut iter = NaluIter::new(nalus, self.nalu_length_size as usize);
+        iter.by_ref().for_each(drop);
+        if iter.malformed {
+            self.malformed_access_unit_count += 1;
+        }
+    }
+
+    /// `b_slice` is the slice type when the bitstream was parsed; without it,
+    /// inter frames presented out of decode order are taken to be B-frames.
+    fn count_frame(&mut self, frame_type: FrameType, composition_time: Option<i32>, b_slice: Option<bool>, dts: u32, bytes: usize) {
+        self.total_video_frames += 1;
+        self.total_video_bytes += bytes as u64;
+        self.first_dts.get_or_insert(dts);
+        self.last_dts = Some(dts);
+
+        if let Some(cto) = composition_time {
+            self.min_composition_offset = Some(self.min_composition_offset.map_or(cto, |m| m.min(cto)));
+            self.max_composition_offset = Some(self.max_composition_offset.map_or(cto, |m| m.max(cto)));
+            self.composition_offset_sum += cto as i64;
+            self.composition_offset_samples += 1;
+        }
+
+        // A frame presented before one already decoded was reordered
+        let pts = dts as i64 + composition_time.unwrap_or(0) as i64;
+        let reordered = self.max_pts.is_some_and(|max| pts < max);
+        self.max_pts = Some(self.max_pts.map_or(pts, |max| max.max(pts)));
+
+        let symbol = match frame_type {
+            FrameType::Keyframe | FrameType::GeneratedKeyframe => {
+                self.keyframe_count += 1;
+                if let Some(frames) = self.frames_since_keyframe {
+                    self.gop_length = Some(frames);
+                }
+                self.frames_since_keyframe = Some(0);
+                self.keyframe_pts = Some(pts);
+                'I'
+            }
+            FrameType::Inter | FrameType::DisposableInter => {
+                if b_slice.unwrap_or(reordered) {
+                    self.b_frame_count += 1;
+                    if self.keyframe_pts.is_some_and(|k| pts < k) {
+                        self.open_gop = true;
+                    }
+                    'B'
+                } else {
+                    self.inter_frame_count += 1;
+                    'P'
+                }
+            }
+            _ => {
+                self.other_frame_count += 1;
+                '?'
+            }
+        };
+
+        if let Some(frames) = self.frames_since_keyframe.as_mut() {
+            *frames += 1;
+        }
+        if self.recent_frames.len() == GOP_PATTERN_LEN {
+            self.recent_frames.pop_front();
+        }
+        self.recent_frames.push_back(symbol);
     }
 
     fn parse_avc_sequence_header(&mut self, data: &[u8]) {
@@ -204,6 +623,24 @@ impl VideoAnalyzer {
             offset += sps_len;
         }
 
+        // The PPS array follows; a record cut off here has none
+        let mut pps_count = 0;
+        if let Some(&num_pps) = data.get(offset) {
+            offset += 1;
+            for _ in 0..num_pps {
+                if offset + 2 > data.len() {
+                    break;
+                }
+                let pps_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+                offset += 2 + pps_len;
+                if pps_len == 0 || offset > data.len() {
+                    break;
+                }
+                pps_count += 1;
+            }
+        }
+
+        self.avc_parameter_sets = Some((num_sps, pps_count));
         self.avc_config_received = true;
     }
 
@@ -235,16 +672,23 @@ impl VideoAnalyzer {
         self.level = Some(format!("{}.{}", level_idc / 10, level_idc % 10));
 
         // High profile and above have additional fields
+        let mut pixel_format = PixelFormat::DEFAULT;
+        let mut separate_colour_plane = false;
         if matches!(
             profile_idc,
             100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
         ) {
             let chroma_format_idc = reader.read_exp_golomb();
             if chroma_format_idc == 3 {
-                let _separate_colour_plane = reader.read_bits(1);
+                separate_colour_plane = reader.read_bits(1) != 0;
             }
-            let _bit_depth_luma = reader.read_exp_golomb(); // + 8
-            let _bit_depth_chroma = reader.read_exp_golomb(); // + 8
+            let bit_depth_luma = reader.read_exp_golomb() + 8;
+            let bit_depth_chroma = reader.read_exp_golomb() + 8;
+            pixel_format = PixelFormat {
+                chroma_format_idc: chroma_format_idc as u8,
+                bit_depth_luma: bit_depth_luma as u8,
+                bit_depth_chroma: bit_depth_chroma as u8,
+            };
             let _qpprime_y_zero = reader.read_bits(1);
             let scaling_matrix_present = reader.read_bits(1);
             if scaling_matrix_present != 0 {
@@ -258,6 +702,7 @@ impl VideoAnalyzer {
                 }
             }
         }
+        self.pixel_format = Some(pixel_format);
 
         // log2_max_frame_num_minus4
         let _log2_max_frame_num = reader.read_exp_golomb();
@@ -291,10 +736,10 @@ impl VideoAnalyzer {
         // frame_mbs_only_flag
         let frame_mbs_only = reader.read_bits(1);
 
-        if frame_mbs_only == 0 {
-            // mb_adaptive_frame_field_flag
-            let _mb_adaptive = reader.read_bits(1);
-        }
+        // mb_adaptive_frame_field_flag
+        let mb_adaptive = frame_mbs_only == 0 && reader.read_bits(1) != 0;
+        self.is_interlaced = frame_mbs_only == 0;
+        self.mb_adaptive_frame_field = mb_adaptive;
 
         // direct_8x8_inference_flag
         let _direct_8x8 = reader.read_bits(1);
@@ -314,17 +759,414 @@ impl VideoAnalyzer {
 
         // Calculate dimensions
         let width = pic_width_mbs * 16;
-        let height = pic_height_map_units * 16 * (2 - frame_mbs_only as u64);
+        let height = pic_height_map_units * 16 * (2 - frame_mbs_only);
 
-        // Apply cropping (crop units depend on chroma format, default 4:2:0 → cropUnitX=2, cropUnitY=2*(2-frame_mbs_only))
-        let crop_unit_x: u64 = 2;
-        let crop_unit_y: u64 = 2 * (2 - frame_mbs_only as u64);
+        // Apply cropping, counted in chroma samples (luma ones when there is
+        // no chroma array), and in field lines for interlaced video
+        let (sub_width_c, sub_height_c) = if separate_colour_plane {
+            (1, 1)
+        } else {
+            pixel_format.chroma_subsampling_factors()
+        };
+        let crop_unit_x = sub_width_c;
+        let crop_unit_y = sub_height_c * (2 - frame_mbs_only);
 
-        let final_width = width - crop_unit_x * (crop_left + crop_right);
-        let final_height = height - crop_unit_y * (crop_top + crop_bottom);
+        let final_width = width.saturating_sub(crop_unit_x * (crop_left + crop_right));
+        let final_height = height.saturating_sub(crop_unit_y * (crop_top + crop_bottom));
 
         self.width = Some(final_width as u32);
         self.height = Some(final_height as u32);
+
+        // vui_parameters_present_flag
+        if reader.read_bits(1) != 0 {
+            self.parse_vui(&mut reader);
+        }
+    }
+
+    fn parse_vui(&mut self, reader: &mut BitstreamReader) {
+        // aspect_ratio_info_present_flag
+        if reader.read_bits(1) != 0 {
+            let aspect_ratio_idc = reader.read_bits(8) as u8;
+            self.sample_aspect_ratio = if aspect_ratio_idc == 255 {
+                // Extended_SAR: sar_width(16) + sar_height(16)
+                let sar_width = reader.read_bits(16) as u32;
+                let sar_height = reader.read_bits(16) as u32;
+                if sar_width > 0 && sar_height > 0 {
+                    Some((sar_width, sar_height))
+                } else {
+                    None
+                }
+            } else {
+                h264_sample_aspect_ratio(aspect_ratio_idc)
+            };
+        }
+
+        // overscan_info_present_flag
+        if reader.read_bits(1) != 0 {
+            let _overscan_appropriate = reader.read_bits(1);
+        }
+
+        // video_signal_type_present_flag
+        if reader.read_bits(1) != 0 {
+            let _video_format = reader.read_bits(3);
+            let _full_range = reader.read_bits(1);
+            // colour_description_present_flag
+            if reader.read_bits(1) != 0 {
+                // colour_primaries + transfer_characteristics + matrix_coefficients
+                reader.skip_bits(24);
+            }
+        }
+
+        // chroma_loc_info_present_flag
+        if reader.read_bits(1) != 0 {
+            let _top_field = reader.read_exp_golomb();
+            let _bottom_field = reader.read_exp_golomb();
+        }
+
+        // timing_info_present_flag
+        if reader.read_bits(1) != 0 {
+            let num_units_in_tick = reader.read_bits(32);
+            let time_scale = reader.read_bits(32);
+            let _fixed_frame_rate = reader.read_bits(1);
+            // One frame is two field ticks: fps = time_scale / (2 * num_units_in_tick)
+            if num_units_in_tick > 0 && time_scale > 0 {
+                self.fps = Some(time_scale as f64 / (2.0 * num_units_in_tick as f64));
+            }
+        }
+    }
+
+    fn parse_hevc_sequence_header(&mut self, data: &[u8]) {
+        // HEVCDecoderConfigurationRecord: 22 fixed bytes, then numOfArrays
+        if data.len() < 23 {
+            return;
+        }
+
+        let _config_version = data[0]; // should be 1
+        let general_tier_flag = (data[1] >> 5) & 0x01;
+        let general_profile_idc = data[1] & 0x1F;
+        let general_level_idc = data[12];
+        self.nalu_length_size = (data[21] & 0x03) + 1;
+        let num_arrays = data[22] as usize;
+
+        // Set profile/level from the config record directly
+        self.profile = Some(hevc_profile_name(general_profile_idc, general_tier_flag));
+        self.level = Some(hevc_level_name(general_level_idc));
+
+        let mut offset = 23;
+        for _ in 0..num_arrays {
+            if offset + 3 > data.len() {
+                return;
+            }
+            let nal_unit_type = data[offset] & 0x3F;
+            let num_nalus = u16::from_be_bytes([data[offset + 1], data[offset + 2]]) as usize;
+            offset += 3;
+
+            for _ in 0..num_nalus {
+                if offset + 2 > data.len() {
+                    return;
+                }
+                let nalu_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
+                offset += 2;
+                if offset + nalu_len > data.len() {
+                    return;
+                }
+
+                // NAL type 33 = SPS
+                if nal_unit_type == 33 {
+                    self.parse_hevc_sps(&data[offset..offset + nalu_len]);
+                }
+                offset += nalu_len;
+            }
+        }
+
+        self.hevc_config_received = true;
+    }
+
+    fn parse_hevc_sps(&mut self, nalu: &[u8]) {
+        let rbsp = remove_emulation_prevention(nalu);
+
+        // Skip the 2-byte HEVC NAL header
+        if rbsp.len() < 3 {
+            return;
+        }
+        let mut reader = BitstreamReader::new(&rbsp[2..]);
+
+        let _vps_id = reader.read_bits(4);
+        let max_sub_layers_minus1 = reader.read_bits(3) as usize;
+        let _temporal_id_nesting = reader.read_bits(1);
+
+        // profile_tier_level(1, sps_max_sub_layers_minus1)
+        let _profile_space = reader.read_bits(2);
+        let tier_flag = reader.read_bits(1) as u8;
+        let profile_idc = reader.read_bits(5) as u8;
+        // compatibility flags(32) + source flags(4) + constraint flags(43) + inbld/reserved(1)
+        reader.skip_bits(80);
+        let level_idc = reader.read_bits(8) as u8;
+
+        self.profile = Some(hevc_profile_name(profile_idc, tier_flag));
+        self.level = Some(hevc_level_name(level_idc));
+
+        let mut sub_layer_profile_present = [false; 8];
+        let mut sub_layer_level_present = [false; 8];
+        for i in 0..max_sub_layers_minus1 {
+            sub_layer_profile_present[i] = reader.read_bits(1) != 0;
+            sub_layer_level_present[i] = reader.read_bits(1) != 0;
+        }
+        if max_sub_layers_minus1 > 0 {
+            for _ in max_sub_layers_minus1..8 {
+                let _reserved_zero_2bits = reader.read_bits(2);
+            }
+        }
+        for i in 0..max_sub_layers_minus1 {
+            if sub_layer_profile_present[i] {
+                reader.skip_bits(88);
+            }
+            if sub_layer_level_present[i] {
+                reader.skip_bits(8);
+            }
+        }
+
+        let _sps_id = reader.read_exp_golomb();
+        let chroma_format_idc = reader.read_exp_golomb();
+        if chroma_format_idc == 3 {
+            let _separate_colour_plane = reader.read_bits(1);
+        }
+
+        let width = reader.read_exp_golomb();
+        let height = reader.read_exp_golomb();
+
+        // conformance_window_flag — offsets are in chroma sample units
+        let (crop_left, crop_right, crop_top, crop_bottom) = if reader.read_bits(1) != 0 {
+            (
+                reader.read_exp_golomb(),
+                reader.read_exp_golomb(),
+                reader.read_exp_golomb(),
+                reader.read_exp_golomb(),
+            )
+        } else {
+            (0, 0, 0, 0)
+        };
+
+        let sub_width_c: u64 = if matches!(chroma_format_idc, 1 | 2) { 2 } else { 1 };
+        let sub_height_c: u64 = if chroma_format_idc == 1 { 2 } else { 1 };
+
+        let final_width = width.saturating_sub(sub_width_c * (crop_left + crop_right));
+        let final_height = height.saturating_sub(sub_height_c * (crop_top + crop_bottom));
+
+        self.width = Some(final_width as u32);
+        self.height = Some(final_height as u32);
+
+        let bit_depth_luma = reader.read_exp_golomb() + 8;
+        let bit_depth_chroma = reader.read_exp_golomb() + 8;
+        self.pixel_format = Some(PixelFormat {
+            chroma_format_idc: chroma_format_idc as u8,
+            bit_depth_luma: bit_depth_luma as u8,
+            bit_depth_chroma: bit_depth_chroma as u8,
+        });
+    }
+
+}
+
+impl Default for VideoAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// NAL unit tallies across the stream (AVC only)
+#[derive(Debug, Clone, Default)]
+pub struct NalStats {
+    /// Count per nal_unit_type (0-31)
+    pub counts: [u64; 32],
+    /// Keyframes that repeated the SPS inline (mid-stream joins and HLS cutting rely on this)
+    pub keyframes_with_sps: u64,
+    /// Frames whose last NAL unit claimed more bytes than the tag carried
+    pub truncated: u64,
+}
+
+/// Name for an AVC nal_unit_type (ITU-T H.264 Table 7-1).
+pub fn avc_nal_type_name(nal_type: u8) -> &'static str {
+    match nal_type {
+        1 => "slice",
+        2..=4 => "slice_partition",
+        5 => "idr_slice",
+        6 => "sei",
+        7 => "sps",
+        8 => "pps",
+        9 => "aud",
+        10 => "end_of_seq",
+        11 => "end_of_stream",
+        12 => "filler",
+        13 => "sps_ext",
+        14 | 20 => "slice_ext",
+        15 => "subset_sps",
+        19 => "aux_slice",
+        _ => "other",
+    }
+}
+
+/// Iterates length-prefixed NAL units (AVCC/HVCC framing), skipping empty ones.
+/// Every length is checked against the bytes left, so a corrupt prefix ends
+/// the walk instead of sending it into the payload.
+struct NaluIter<'a> {
+    data: &'a [u8],
+    length_size: usize,
+    /// Set when iteration stopped at a unit whose length overran the data
+    truncated: bool,
+    /// Set by `truncated`, by stray bytes too short for a length prefix, or by
+    /// a unit with the forbidden_zero_bit set; all point at a prefix size
+    /// that doesn't match the stream or a broken bitstream
+    malformed: bool,
+}
+
+impl<'a> NaluIter<'a> {
+    fn new(data: &'a [u8], length_size: usize) -> Self {
+        Self { data, length_size, truncated: false, malformed: false }
+    }
+
+    fn stop(&mut self) -> Option<&'a [u8]> {
+        self.malformed = true;
+        self.data = &[];
+        None
+    }
+}
+
+impl<'a> Iterator for NaluIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            if self.length_size == 0 || self.data.is_empty() {
+                return None;
+            }
+            if self.data.len() < self.length_size {
+                return self.stop();
+            }
+            let len = self.data[..self.length_size]
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            let rest = &self.data[self.length_size..];
+            if len > rest.len() {
+                // Truncated unit: stop rather than read garbage
+                self.truncated = true;
+                return self.stop();
+            }
+            let (nal, tail) = rest.split_at(len);
+            self.data = tail;
+            if nal.first().is_some_and(|header| header & 0x80 != 0) {
+                return self.stop();
+            }
+            if !nal.is_empty() {
+                return Some(nal);
+            }
+        }
+    }
+}
+
+/// Whether an SEI payload (after the NAL header) contains a recovery point message.
+fn sei_has_recovery_point(mut sei: &[u8]) -> bool {
+    // Each message: payload type and size, both coded as runs of 0xFF plus a final byte
+    fn read_sei_value(data: &mut &[u8]) -> Option<usize> {
+        let mut value = 0usize;
+        loop {
+            let (&b, rest) = data.split_first()?;
+            *data = rest;
+            value += b as usize;
+            if b != 0xFF {
+                return Some(value);
+            }
+        }
+    }
+
+    // Stop at the RBSP trailing bits
+    while !sei.is_empty() && sei[0] != 0x80 {
+        let Some(payload_type) = read_sei_value(&mut sei) else {
+            return false;
+        };
+        let Some(payload_size) = read_sei_value(&mut sei) else {
+            return false;
+        };
+        if payload_type == 6 {
+            return true;
+        }
+        if payload_size > sei.len() {
+            return false;
+        }
+        sei = &sei[payload_size..];
+    }
+    false
+}
+
+/// `slice_type % 5` of a B slice (types 1 and 6).
+const SLICE_TYPE_B: u64 = 1;
+
+/// The slice type (0-4, P/B/I/SP/SI) from an AVC slice header after the NAL
+/// header byte, or None if the header is cut short.
+fn avc_slice_type(slice: &[u8]) -> Option<u64> {
+    // Both fields fit well within the first few bytes
+    let rbsp = remove_emulation_prevention(&slice[..slice.len().min(8)]);
+    let mut reader = BitstreamReader::new(&rbsp);
+    let _first_mb_in_slice = reader.read_exp_golomb();
+    let slice_type = reader.read_exp_golomb();
+    // The header goes on past slice_type, so running out means it was cut
+    (reader.bits_left() > 0).then_some(slice_type % 5)
+}
+
+/// Read a signed 24-bit composition time offset.
+fn read_composition_time(bytes: &[u8]) -> i32 {
+    let composition_time = ((bytes[0] as i32) << 16)
+        | ((bytes[1] as i32) << 8)
+        | (bytes[2] as i32);
+    // Sign-extend from 24-bit
+    if composition_time & 0x800000 != 0 {
+        composition_time | !0xFFFFFF_u32 as i32
+    } else {
+        composition_time
+    }
+}
+
+fn hevc_profile_name(profile_idc: u8, tier_flag: u8) -> String {
+    let name = match profile_idc {
+        1 => "Main".to_string(),
+        2 => "Main 10".to_string(),
+        3 => "Main Still Picture".to_string(),
+        4 => "Range Extensions".to_string(),
+        5 => "High Throughput".to_string(),
+        9 => "Screen Content".to_string(),
+        _ => format!("Profile {}", profile_idc),
+    };
+    if tier_flag != 0 {
+        format!("{} (High tier)", name)
+    } else {
+        name
+    }
+}
+
+fn hevc_level_name(level_idc: u8) -> String {
+    // general_level_idc is 30x the level number (e.g. 153 → 5.1)
+    format!("{}.{}", level_idc / 30, (level_idc % 30) / 3)
+}
+
+/// H.264 Table E-1 predefined sample aspect ratios.
+fn h264_sample_aspect_ratio(aspect_ratio_idc: u8) -> Option<(u32, u32)> {
+    match aspect_ratio_idc {
+        1 => Some((1, 1)),
+        2 => Some((12, 11)),
+        3 => Some((10, 11)),
+        4 => Some((16, 11)),
+        5 => Some((40, 33)),
+        6 => Some((24, 11)),
+        7 => Some((20, 11)),
+        8 => Some((32, 11)),
+        9 => Some((80, 33)),
+        10 => Some((18, 11)),
+        11 => Some((15, 11)),
+        12 => Some((64, 33)),
+        13 => Some((160, 99)),
+        14 => Some((4, 3)),
+        15 => Some((3, 2)),
+        16 => Some((2, 1)),
+        _ => None, // 0 = unspecified
     }
 }
 
@@ -379,16 +1221,16 @@ fn skip_scaling_list(reader: &mut BitstreamReader, size: usize) {
     }
 }
 
-// ── Bitstream Reader (for H.264 SPS parsing) ──
+// ── Bitstream Reader (for H.264/HEVC SPS and AAC config parsing) ──
 
-struct BitstreamReader<'a> {
+pub(crate) struct BitstreamReader<'a> {
     data: &'a [u8],
     byte_offset: usize,
     bit_offset: u8, // 0-7, bits consumed in current byte
 }
 
 impl<'a> BitstreamReader<'a> {
-    fn new(data: &'a [u8]) -> Self {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
         Self {
             data,
             byte_offset: 0,
@@ -396,7 +1238,7 @@ impl<'a> BitstreamReader<'a> {
         }
     }
 
-    fn read_bits(&mut self, count: u8) -> u64 {
+    pub(crate) fn read_bits(&mut self, count: u8) -> u64 {
         let mut value: u64 = 0;
         for _ in 0..count {
             if self.byte_offset >= self.data.len() {
@@ -413,6 +1255,17 @@ impl<'a> BitstreamReader<'a> {
         value
     }
 
+    pub(crate) fn skip_bits(&mut self, count: u32) {
+        let total = self.bit_offset as usize + count as usize;
+        self.byte_offset += total / 8;
+        self.bit_offset = (total % 8) as u8;
+    }
+
+    /// Bits not yet consumed.
+    pub(crate) fn bits_left(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.byte_offset * 8 + self.bit_offset as usize)
+    }
+
     /// Read unsigned Exp-Golomb coded value.
     fn read_exp_golomb(&mut self) -> u64 {
         let mut leading_zeros: u32 = 0;
@@ -435,11 +1288,321 @@ impl<'a> BitstreamReader<'a> {
         if code == 0 {
             return 0;
         }
-        let value = ((code + 1) / 2) as i64;
-        if code % 2 == 0 {
+        let value = code.div_ceil(2) as i64;
+        if code.is_multiple_of(2) {
             -value
         } else {
             value
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionOptions;
+    use crate::diagnostics::{Diagnostic, ServiceProfile, Severity};
+    use crate::session::StreamSession;
+
+    /// HEVCDecoderConfigurationRecord of a 3840x2160 Main 10 stream at level
+    /// 5.1, with one VPS, SPS and PPS as x265 writes them.
+    const HVCC_2160P_MAIN10: [u8; 101] = [
+        0x01, 0x02, 0x20, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x99, 0xf0, 0x00, 0xfc,
+        0xfd, 0xfa, 0xfa, 0x00, 0x00, 0x0f, 0x03, 0xa0, 0x00, 0x01, 0x00, 0x18, 0x40, 0x01, 0x0c, 0x01,
+        0xff, 0xff, 0x02, 0x20, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00,
+        0x99, 0x95, 0x98, 0x09, 0xa1, 0x00, 0x01, 0x00, 0x20, 0x42, 0x01, 0x01, 0x02, 0x20, 0x00, 0x00,
+        0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x99, 0xa0, 0x01, 0xe0, 0x20, 0x02,
+        0x1c, 0x4d, 0x96, 0x56, 0x69, 0x24, 0x48, 0xac, 0x80, 0xa2, 0x00, 0x01, 0x00, 0x07, 0x44, 0x01,
+        0xc1, 0x72, 0xb4, 0x62, 0x40,
+    ];
+
+    /// SPS and PPS of a 1920x1080 High 4.0 stream as x264 writes them, with
+    /// SAR 1:1 and timing info of 1/60 (60 field ticks a second, 30 fps)
+    const X264_1080P30_SPS: [u8; 27] = [
+        0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0xc0, 0x44, 0x00, 0x00, 0x03,
+        0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xf0, 0x3c, 0x60, 0xc6, 0x58,
+    ];
+    const X264_PPS: [u8; 6] = [0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0];
+
+    /// A legacy AVC sequence header tag carrying one SPS and `pps`.
+    fn avc_sequence_header(sps: &[u8], pps: &[&[u8]]) -> Vec<u8> {
+        let mut tag = vec![0x17, 0x00, 0x00, 0x00, 0x00, 0x01, sps[1], sps[2], sps[3], 0xff, 0xe1];
+        tag.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        tag.extend_from_slice(sps);
+        tag.push(pps.len() as u8);
+        for pps in pps {
+            tag.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+            tag.extend_from_slice(pps);
+        }
+        tag
+    }
+
+    fn analyze(tags: &[Vec<u8>]) -> VideoAnalyzer {
+        let mut video = VideoAnalyzer::new();
+        for (i, tag) in tags.iter().enumerate() {
+            video.process(tag, i as u32 * 33);
+        }
+        video
+    }
+
+    #[test]
+    fn vui_timing_gives_the_frame_rate() {
+        let video = analyze(&[avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS])]);
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert_eq!(video.profile.as_deref(), Some("High"));
+        assert_eq!(video.level.as_deref(), Some("4.0"));
+        assert_eq!(video.fps, Some(30.0));
+
+        // 1001/60000: two ticks a frame give 29.97, not the 59.94 field rate
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0xc0, 0x44, 0x00, 0x00, 0x0f,
+            0xa4, 0x00, 0x03, 0xa9, 0x82, 0x3c, 0x60, 0xc6, 0x58,
+        ];
+        let video = analyze(&[avc_sequence_header(&sps, &[&X264_PPS])]);
+        assert!((video.fps.unwrap() - 29.97).abs() < 0.001, "{:?}", video.fps);
+    }
+
+    /// The diagnostics of a stream that sent `tags`.
+    fn session_checks(tags: &[Vec<u8>], profile: ServiceProfile) -> Vec<Diagnostic> {
+        let options = ConnectionOptions { profile, ..Default::default() };
+        let mut session = StreamSession::new(&options);
+        for (i, tag) in tags.iter().enumerate() {
+            session.handle_video(i as u32 * 33, tag);
+        }
+        session.check_now()
+    }
+
+    fn has_diagnostic(diagnostics: &[Diagnostic], severity: Severity, message: &str) -> bool {
+        diagnostics.iter().any(|d| d.severity == severity && d.message.starts_with(message))
+    }
+
+    #[test]
+    fn sar_comes_from_the_table_or_extended_sar() {
+        // 1440x1080, aspect_ratio_idc 14 (4:3)
+        let sps_4_3 = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x5a, 0x02, 0x27, 0xe5, 0xc3, 0x84, 0x00, 0x00, 0x03,
+            0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xca, 0x3c, 0x60, 0xc6, 0x58,
+        ];
+        let header = avc_sequence_header(&sps_4_3, &[&X264_PPS]);
+        let video = analyze(std::slice::from_ref(&header));
+        assert_eq!((video.width, video.height), (Some(1440), Some(1080)));
+        assert_eq!(video.sample_aspect_ratio, Some((4, 3)));
+        let diagnostics = session_checks(&[header], ServiceProfile::generic());
+        assert!(has_diagnostic(
+            &diagnostics,
+            Severity::Warning,
+            "Non-square pixels (SAR 4:3): coded 1440x1080 displays as 1920x1080"
+        ));
+
+        // 720x576 Main, aspect_ratio_idc 255 with sar_width 64, sar_height 45
+        let sps_extended = [
+            0x67, 0x4d, 0x00, 0x1e, 0xec, 0xa0, 0x5a, 0x09, 0x37, 0xfe, 0x00, 0x80, 0x00, 0x5a, 0x20, 0x00,
+            0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x06, 0x51, 0xe3, 0x06, 0x32, 0xc0,
+        ];
+        let header = avc_sequence_header(&sps_extended, &[&X264_PPS]);
+        let video = analyze(std::slice::from_ref(&header));
+        assert_eq!((video.width, video.height), (Some(720), Some(576)));
+        assert_eq!(video.sample_aspect_ratio, Some((64, 45)));
+        assert_eq!(video.fps, Some(25.0));
+        let diagnostics = session_checks(&[header], ServiceProfile::generic());
+        assert!(has_diagnostic(&diagnostics, Severity::Warning, "Non-square pixels (SAR 64:45): coded 720x576 displays as 1024x576"));
+
+        // Square pixels raise nothing
+        let diagnostics = session_checks(&[avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS])], ServiceProfile::generic());
+        assert!(!has_diagnostic(&diagnostics, Severity::Warning, "Non-square pixels"));
+    }
+
+    /// Slice header bytes after the NAL header: first_mb_in_slice 0, then
+    /// `slice_type` and pic_parameter_set_id 0
+    fn slice_header(slice_type: u8) -> Vec<u8> {
+        match slice_type {
+            0 => vec![0xe0, 0x00],
+            1 => vec![0xa8, 0x00],
+            5 => vec![0x9a, 0x00],
+            6 => vec![0x9e, 0x00],
+            7 => vec![0x88, 0x80],
+            _ => unimplemented!("slice type {}", slice_type),
+        }
+    }
+
+    fn nal(header: u8, body: &[u8]) -> Vec<u8> {
+        [&[header][..], body].concat()
+    }
+
+    /// A legacy AVC NALU tag with 4-byte length prefixes.
+    fn avc_frame(keyframe: bool, composition_time: i32, nalus: &[Vec<u8>]) -> Vec<u8> {
+        let mut tag = vec![if keyframe { 0x17 } else { 0x27 }, 0x01];
+        tag.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+        for nal in nalus {
+            tag.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            tag.extend_from_slice(nal);
+        }
+        tag
+    }
+
+    /// SEI with a recovery point message (payload type 6), then trailing bits
+    const RECOVERY_POINT_SEI: [u8; 4] = [0x06, 0x01, 0xc4, 0x80];
+
+    #[test]
+    fn keyframes_are_checked_for_an_idr_slice() {
+        let header = avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS]);
+        let idr = avc_frame(true, 0, &[nal(0x65, &slice_header(7))]);
+        // Flagged as a keyframe, but a non-IDR I slice behind a recovery point
+        let recovery = avc_frame(true, 0, &[nal(0x06, &RECOVERY_POINT_SEI), nal(0x41, &slice_header(7))]);
+        let bare = avc_frame(true, 0, &[nal(0x41, &slice_header(7))]);
+
+        let video = analyze(&[header, idr, recovery, bare]);
+        assert_eq!(video.keyframe_count, 3);
+        assert_eq!(video.idr_count, 1);
+        assert_eq!(video.non_idr_keyframe_count, 2);
+        assert_eq!(video.recovery_point_keyframe_count, 1);
+    }
+
+    #[test]
+    fn nal_lengths_past_the_payload_are_malformed() {
+        let header = avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS]);
+        // The last unit ends exactly at the end of the payload
+        let exact = avc_frame(true, 0, &[nal(0x06, &RECOVERY_POINT_SEI), nal(0x65, &slice_header(7))]);
+        let mut overrun = avc_frame(false, 0, &[nal(0x41, &slice_header(0))]);
+        // Claim one byte more than the unit has
+        overrun[8] += 1;
+        // Stray bytes too short for a length prefix
+        let mut stray = avc_frame(false, 0, &[nal(0x41, &slice_header(0))]);
+        stray.extend_from_slice(&[0x00, 0x00]);
+
+        let video = analyze(&[header.clone(), exact.clone()]);
+        assert_eq!(video.malformed_access_unit_count, 0);
+        assert_eq!(video.idr_count, 1);
+
+        let video = analyze(&[header, exact, overrun, stray]);
+        assert_eq!(video.malformed_access_unit_count, 2);
+        assert_eq!(video.total_video_frames, 3);
+    }
+
+    #[test]
+    fn b_slices_are_classified_by_slice_type() {
+        let header = avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS]);
+        // I0 P3 b1 b2 in decode order, with non-reference B slices of both
+        // slice_type codings
+        let video = analyze(&[
+            header,
+            avc_frame(true, 33, &[nal(0x65, &slice_header(7))]),
+            avc_frame(false, 99, &[nal(0x41, &slice_header(5))]),
+            avc_frame(false, 0, &[nal(0x01, &slice_header(1))]),
+            avc_frame(false, 0, &[nal(0x01, &slice_header(6))]),
+        ]);
+        assert_eq!(video.gop_pattern(), "IPBB");
+        assert_eq!(video.b_frame_count, 2);
+        assert!(!video.b_pyramid);
+    }
+
+    #[test]
+    fn reference_b_slices_show_a_pyramid() {
+        let header = avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS]);
+        // I0 P4 B2 b1 b3 in decode order, presented one frame apart in
+        // order I0 b1 B2 b3 P4; B2 is a reference the b-frames either side
+        // of it predict from
+        let video = analyze(&[
+            header,
+            avc_frame(true, 66, &[nal(0x65, &slice_header(7))]),
+            avc_frame(false, 165, &[nal(0x41, &slice_header(0))]),
+            avc_frame(false, 66, &[nal(0x21, &slice_header(6))]),
+            avc_frame(false, 0, &[nal(0x01, &slice_header(1))]),
+            avc_frame(false, 33, &[nal(0x01, &slice_header(1))]),
+        ]);
+        assert_eq!(video.gop_pattern(), "IPBBB");
+        assert_eq!(video.b_frame_count, 3);
+        assert!(video.b_pyramid);
+        assert_eq!(video.max_composition_offset, Some(165));
+    }
+
+    #[test]
+    fn config_record_without_pps_is_an_error() {
+        let with_pps = avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS]);
+        assert_eq!(analyze(std::slice::from_ref(&with_pps)).avc_parameter_sets, Some((1, 1)));
+        let diagnostics = session_checks(&[with_pps], ServiceProfile::generic());
+        assert!(!has_diagnostic(&diagnostics, Severity::Error, "AVC sequence header carries no PPS"));
+
+        let without_pps = avc_sequence_header(&X264_1080P30_SPS, &[]);
+        // Cut off before numOfPictureParameterSets
+        let cut_off = without_pps[..without_pps.len() - 1].to_vec();
+        for header in [without_pps, cut_off] {
+            assert_eq!(analyze(std::slice::from_ref(&header)).avc_parameter_sets, Some((1, 0)));
+            let diagnostics = session_checks(&[header], ServiceProfile::generic());
+            assert!(has_diagnostic(&diagnostics, Severity::Error, "AVC sequence header carries no PPS"));
+        }
+    }
+
+    /// 1920x1080 High 4.0 coded as 1088 lines of field pairs (frame_mbs_only_flag
+    /// 0) with mb_adaptive_frame_field_flag 1, cropped by 2 field lines, at 29.97 fps
+    const SPS_1080I_MBAFF: [u8; 25] = [
+        0x67, 0x64, 0x00, 0x28, 0xac, 0xd9, 0x40, 0x78, 0x04, 0x4f, 0xde, 0x02, 0x20, 0x00, 0x00, 0x7d,
+        0x20, 0x00, 0x1d, 0x4c, 0x11, 0xe3, 0x06, 0x32, 0xc0,
+    ];
+
+    #[test]
+    fn interlaced_sps_counts_in_field_pairs() {
+        let video = analyze(&[avc_sequence_header(&SPS_1080I_MBAFF, &[&X264_PPS])]);
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert!(video.is_interlaced);
+        assert_eq!(video.interlace_mode(), Some("MBAFF"));
+        assert!((video.fps.unwrap() - 29.97).abs() < 0.001);
+
+        // The same without mb_adaptive_frame_field_flag
+        let mut sps = SPS_1080I_MBAFF;
+        sps[9] = 0x47;
+        let video = analyze(&[avc_sequence_header(&sps, &[&X264_PPS])]);
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert_eq!(video.interlace_mode(), Some("field pictures"));
+
+        let video = analyze(&[avc_sequence_header(&X264_1080P30_SPS, &[&X264_PPS])]);
+        assert_eq!(video.interlace_mode(), None);
+    }
+
+    #[test]
+    fn interlacing_is_an_error_only_where_the_profile_disallows_it() {
+        let header = avc_sequence_header(&SPS_1080I_MBAFF, &[&X264_PPS]);
+
+        let twitch = ServiceProfile::twitch();
+        assert!(!twitch.allow_interlaced);
+        let diagnostics = session_checks(std::slice::from_ref(&header), twitch);
+        assert!(has_diagnostic(&diagnostics, Severity::Error, "Interlaced video (MBAFF) is rejected by Twitch"));
+
+        let generic = ServiceProfile::generic();
+        assert!(generic.allow_interlaced);
+        let diagnostics = session_checks(&[header], generic);
+        assert!(has_diagnostic(&diagnostics, Severity::Warning, "Interlaced video (MBAFF): players may show combing"));
+        assert!(!has_diagnostic(&diagnostics, Severity::Error, "Interlaced video"));
+    }
+
+    #[test]
+    fn hevc_config_record_gives_resolution_and_profile() {
+        // Enhanced RTMP sequence start: keyframe, packet type 0, "hvc1"
+        let mut tag = vec![0x90, b'h', b'v', b'c', b'1'];
+        tag.extend_from_slice(&HVCC_2160P_MAIN10);
+        let mut video = VideoAnalyzer::new();
+        video.process(&tag, 0);
+
+        assert_eq!(video.codec, Some(VideoCodec::Hevc));
+        assert_eq!(video.width, Some(3840));
+        assert_eq!(video.height, Some(2160));
+        assert_eq!(video.profile.as_deref(), Some("Main 10"));
+        assert_eq!(video.level.as_deref(), Some("5.1"));
+        let pixel_format = video.pixel_format.unwrap();
+        assert_eq!((pixel_format.chroma_format_idc, pixel_format.bit_depth_luma), (1, 10));
+        assert_eq!(video.nalu_length_size, 4);
+        assert_eq!(video.total_video_frames, 0);
+    }
+
+    #[test]
+    fn avc_crop_is_counted_in_chroma_samples() {
+        // High 4:4:4 Predictive, 1936x1088 coded, cropped by 16 on the right
+        // and 8 at the bottom. 4:2:0 crop units would give 1904x1072
+        let sps = [0x67, 0xf4, 0x00, 0x28, 0x91, 0x9b, 0x28, 0x0f, 0x20, 0x44, 0xf0, 0x8c, 0x4a];
+        let video = analyze(&[avc_sequence_header(&sps, &[&X264_PPS])]);
+
+        assert_eq!(video.pixel_format.map(|p| p.chroma_format_idc), Some(3));
+        assert_eq!(video.width, Some(1920));
+        assert_eq!(video.height, Some(1080));
+    }
+}
@@ -0,0 +1,32 @@
+//! RTMP stream analysis: the protocol, FLV and codec parsing, and the
+//! health checks behind the `rustmp` binary, for embedding in other servers.
+
+#[macro_use]
+mod trace;
+
+pub mod connection;
+pub mod diagnostics;
+pub mod display;
+pub mod encoder;
+pub mod file;
+pub mod flv;
+pub mod json;
+pub mod log;
+pub mod metrics;
+pub mod replay;
+pub mod report;
+pub mod rtmp;
+pub mod session;
+pub mod snapshot;
+pub mod stats;
+
+pub use diagnostics::{Diagnostic, Severity, StreamDiagnostics};
+pub use encoder::EncoderInfo;
+pub use flv::audio::AudioAnalyzer;
+pub use flv::video::VideoAnalyzer;
+pub use rtmp::amf0::{Amf0Decoder, Amf0Encoder, Amf0Value};
+pub use rtmp::amf3::Amf3Decoder;
+pub use rtmp::chunk::{ChunkReader, RtmpMessage};
+pub use session::StreamSession;
+pub use snapshot::StreamSnapshot;
+pub use stats::StreamStats;
@@ -0,0 +1,279 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diagnostics::Diagnostic;
+use crate::session::StreamSession;
+
+/// Once the log grows past this it's moved to `<path>.1` and a fresh file started
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Append-only log of diagnostic transitions, shared by all connections.
+#[derive(Debug)]
+pub struct DiagnosticLog {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+pub type SharedLog = Arc<Mutex<DiagnosticLog>>;
+
+impl DiagnosticLog {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = open_append(path)?;
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self { path: path.to_path_buf(), file, len })
+    }
+
+    /// Log the diagnostics that appeared or went away since the last call for this stream.
+    pub fn record(&mut self, app_name: &str, stream_key: &str, state: &mut LogState, current: &[Diagnostic]) {
+        let timestamp = iso8601_now();
+        let keys: Vec<String> = current.iter().map(Diagnostic::dedup_key).collect();
+
+        for (diag, key) in current.iter().zip(&keys) {
+            if !state.active.iter().any(|(k, _)| k == key) {
+                self.write_line(&format_line(&timestamp, "raised", app_name, stream_key, diag));
+            }
+        }
+        for (key, diag) in &state.active {
+            if !keys.contains(key) {
+                self.write_line(&format_line(&timestamp, "cleared", app_name, stream_key, diag));
+            }
+        }
+
+        state.active = keys.into_iter().zip(current.iter().cloned()).collect();
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.len + line.len() as u64 > MAX_LOG_BYTES {
+            self.rotate();
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.len += line.len() as u64;
+        }
+    }
+
+    fn rotate(&mut self) {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        if let Err(e) = fs::rename(&self.path, &rotated) {
+            eprintln!("Failed to rotate {}: {}", self.path.display(), e);
+            return;
+        }
+        match open_append(&self.path) {
+            Ok(file) => {
+                self.file = file;
+                self.len = 0;
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Diagnostics last logged for one stream.
+#[derive(Debug, Default)]
+pub struct LogState {
+    active: Vec<(String, Diagnostic)>,
+}
+
+/// Where `--dump-amf` writes decoded command and data messages, shared by
+/// all connections.
+#[derive(Debug)]
+pub enum AmfDump {
+    Stderr,
+    File(File),
+}
+
+pub type SharedDump = Arc<Mutex<AmfDump>>;
+
+impl AmfDump {
+    /// Append to the file at `path`, or write to stderr without one.
+    pub fn open(path: Option<&Path>) -> Result<Self, String> {
+        match path {
+            Some(path) => Ok(Self::File(open_append(path)?)),
+            None => Ok(Self::Stderr),
+        }
+    }
+
+    /// Write `line` with the current time in front.
+    pub fn write_line(&mut self, line: &str) {
+        let line = format!("{} {}\n", iso8601_now(), line);
+        match self {
+            Self::Stderr => eprint!("{}", line),
+            Self::File(file) => {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+}
+
+/// Columns of the `--csv` time series, after the stream name
+const CSV_COLUMNS: &str =
+    "elapsed_secs,video_kbps,audio_kbps,fps,keyframe_interval,video_frames,audio_frames,errors,warnings";
+
+/// Per-second metrics of every stream for offline plotting, shared by all
+/// connections. Rows are written as they are taken, so a crash still
+/// leaves everything up to it.
+#[derive(Debug)]
+pub struct CsvLog {
+    file: File,
+}
+
+pub type SharedCsv = Arc<Mutex<CsvLog>>;
+
+impl CsvLog {
+    /// Start a new file at `path` with the header row.
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let mut file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        writeln!(file, "stream,{}", CSV_COLUMNS).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(Self { file })
+    }
+
+    /// Append a row with `session`'s current values; unknown ones are left empty.
+    pub fn record(&mut self, app_name: &str, stream_key: &str, session: &StreamSession) {
+        let stats = &session.stats;
+        let cell = |value: Option<f64>, precision: usize| value.map_or_else(String::new, |v| format!("{:.*}", precision, v));
+        let line = format!(
+            "{},{:.3},{},{},{},{},{},{},{},{}\n",
+            csv_field(&format!("{}/{}", app_name, stream_key)),
+            stats.duration_secs,
+            cell(stats.current_video_bitrate_kbps(), 1),
+            cell(stats.current_audio_bitrate_kbps(), 1),
+            cell(stats.current_fps(), 2),
+            cell(stats.keyframe_interval_secs, 3),
+            session.video_analyzer.total_video_frames,
+            session.audio_analyzer.total_audio_frames,
+            session.diagnostics.error_count(),
+            session.diagnostics.warning_count(),
+        );
+        let _ = self.file.write_all(line.as_bytes());
+        let _ = self.file.flush();
+    }
+}
+
+/// Quote `value` if it would otherwise break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open log {}: {}", path.display(), e))
+}
+
+fn format_line(timestamp: &str, transition: &str, app_name: &str, stream_key: &str, diag: &Diagnostic) -> String {
+    format!(
+        "{} {} {}/{} {} [{}] {}\n",
+        timestamp,
+        transition,
+        app_name,
+        stream_key,
+        diag.severity.as_str(),
+        diag.category,
+        diag.message
+    )
+}
+
+/// Current UTC time as e.g. "2024-05-01T12:34:56.789Z".
+fn iso8601_now() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rustmp-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn days_since_the_epoch_become_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(20_088), (2024, 12, 31));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn only_raised_and_cleared_diagnostics_are_logged() {
+        let path = temp_log("transitions");
+        let mut log = DiagnosticLog::open(&path).unwrap();
+        let mut state = LogState::default();
+
+        log.record("live", "key", &mut state, &[Diagnostic::warning("video", "Bitrate 4500 kbps")]);
+        // Only the number changed: the same diagnostic, still active
+        log.record("live", "key", &mut state, &[Diagnostic::warning("video", "Bitrate 4620 kbps")]);
+        log.record("live", "key", &mut state, &[]);
+
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<_> = written.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+        assert_eq!(lines, [
+            "raised live/key warning [video] Bitrate 4500 kbps",
+            "cleared live/key warning [video] Bitrate 4620 kbps",
+        ]);
+    }
+
+    #[test]
+    fn the_log_is_rotated_when_full() {
+        let path = temp_log("rotation");
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        let _ = fs::remove_file(&rotated);
+
+        let mut log = DiagnosticLog::open(&path).unwrap();
+        let mut state = LogState::default();
+        log.record("live", "key", &mut state, &[Diagnostic::error("audio", "No audio")]);
+        // Pretend the file is nearly full rather than writing 10MB
+        log.len = MAX_LOG_BYTES - 10;
+        log.record("live", "key", &mut state, &[]);
+
+        let old = fs::read_to_string(&rotated).unwrap();
+        let new = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+        assert!(old.contains(" raised ") && !old.contains(" cleared "), "{}", old);
+        assert!(new.contains(" cleared ") && !new.contains(" raised "), "{}", new);
+        assert_eq!(log.len, new.len() as u64);
+    }
+}
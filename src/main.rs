@@ -1,58 +1,498 @@
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use clap::Parser;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use rustmp::connection::{self, ConnectionOptions, PeerAddr};
+use rustmp::diagnostics::{KnownProfile, ServiceProfile};
+use rustmp::log::{AmfDump, CsvLog, DiagnosticLog};
+use rustmp::metrics::{self, SharedMetrics};
+use rustmp::report::ReportFormat;
+use rustmp::{display, file, replay};
 
-mod connection;
-mod diagnostics;
-mod display;
-mod flv;
-mod rtmp;
-mod stats;
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  Without the TUI (--plain, --report, --duration, or stdout not a terminal),
+  the final checks of the streams that ended decide it:
+    0  no warnings or errors
+    1  warnings only
+    2  errors
+  Failing to start exits with 1, or 2 for invalid arguments.";
 
 #[derive(Parser, Debug)]
-#[command(name = "rustmp", about = "RTMP stream analyzer")]
+#[command(name = "rustmp", about = "RTMP stream analyzer", after_help = EXIT_STATUS_HELP)]
 struct Args {
-    /// Network interface to bind to (e.g., "0.0.0.0" or "127.0.0.1")
-    interface: String,
+    /// Network interface to bind to (e.g., "0.0.0.0", "127.0.0.1" or "::")
+    #[arg(required_unless_present_any = ["file", "replay", "listen", "unix"], value_parser = parse_interface)]
+    interface: Option<IpAddr>,
     /// Port to listen on (e.g., 1935)
-    port: u16,
+    #[arg(required_unless_present_any = ["file", "replay", "listen", "unix"])]
+    port: Option<u16>,
+    /// Also listen on ADDR:PORT (repeatable or comma-separated)
+    #[arg(long, value_name = "ADDR:PORT", value_delimiter = ',', value_parser = parse_listen_addr)]
+    listen: Vec<SocketAddr>,
+    /// Also listen on a Unix domain socket at PATH, removed again on exit
+    #[arg(long, value_name = "PATH")]
+    unix: Option<PathBuf>,
+    /// Analyze a recorded FLV file and print its report instead of listening
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["interface", "port", "listen", "unix", "metrics_port"])]
+    file: Option<PathBuf>,
+    /// Replay a capture of the bytes a client sent after the handshake
+    /// through the RTMP chunk parser and print its report
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "interface", "port", "listen", "unix", "metrics_port"])]
+    replay: Option<PathBuf>,
+    /// Print a report when the stream ends instead of showing the TUI
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+    /// Append the report to this file instead of printing it, one JSON line per stream
+    #[arg(long, value_name = "PATH", requires = "report")]
+    report_file: Option<PathBuf>,
+    /// Analyze the first stream for SECS seconds after it starts publishing,
+    /// then disconnect and exit
+    #[arg(long, value_name = "SECS", value_parser = parse_positive_secs, conflicts_with_all = ["file", "replay"])]
+    duration: Option<f64>,
+    /// Service whose ingest rules the stream is checked against
+    #[arg(long, value_enum, default_value_t = KnownProfile::Twitch)]
+    profile: KnownProfile,
+    /// Load custom ingest limits from a JSON file (overrides --profile)
+    #[arg(long, value_name = "PATH")]
+    profile_file: Option<PathBuf>,
+    /// Use a different profile for one app, e.g. "live=youtube" (repeatable)
+    #[arg(long, value_name = "APP=PROFILE", value_parser = parse_app_profile)]
+    app_profile: Vec<(String, KnownProfile)>,
+    /// Longest acceptable keyframe interval, overriding the profile's own
+    #[arg(long, value_name = "SECS", value_parser = parse_positive_secs)]
+    max_keyframe_interval: Option<f64>,
+    /// Drop RTMP messages larger than this many bytes, at least 65536 (video
+    /// and aggregate messages may be twice as large)
+    #[arg(long, value_name = "BYTES", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(65536..))]
+    max_message_size: Option<usize>,
+    /// Tally NAL unit types per stream (shown in the TUI and report)
+    #[arg(long)]
+    nal_stats: bool,
+    /// Serve Prometheus metrics on this port at /metrics
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
+    /// Append diagnostics as they are raised and cleared to this file
+    #[arg(long, value_name = "PATH")]
+    log: Option<PathBuf>,
+    /// Print every command and data message as decoded AMF, to stderr or
+    /// appended to PATH
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    dump_amf: Option<Option<PathBuf>>,
+    /// Write bitrate, fps and frame counts of every stream to PATH as CSV,
+    /// one row per stream per second
+    #[arg(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+    /// Print one status line per second instead of the TUI (default when stdout isn't a terminal),
+    /// and a key=value summary line per connection to stderr when it ends
+    #[arg(long)]
+    plain: bool,
+    /// Ping the encoder every SECS seconds, showing the round trip and
+    /// disconnecting after 3 unanswered pings
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["file", "replay"])]
+    ping_interval: Option<u64>,
+    /// Read from each client at no more than KBPS kilobits per second,
+    /// simulating a bandwidth-limited ingest
+    #[arg(long, value_name = "KBPS", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["file", "replay"])]
+    max_ingress_kbps: Option<u64>,
+    /// Only accept publishing with the stream keys listed in PATH, one per
+    /// line (blank lines and lines starting with # are ignored)
+    #[arg(long, value_name = "PATH", conflicts_with = "file")]
+    keys_file: Option<PathBuf>,
+    /// Disconnect a client that sends nothing for SECS seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    idle_timeout: u64,
+    /// Redraw the TUI or status line every MS milliseconds (default 1000);
+    /// diagnostics still only update as often as --check-interval allows
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(50..), conflicts_with_all = ["file", "replay"])]
+    refresh: Option<u64>,
+    /// Run the diagnostic checks at most once per MS milliseconds
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    check_interval: Option<u64>,
+    /// Average bitrate and fps over the last SECS seconds
+    #[arg(long, value_name = "SECS", default_value_t = 2.0, value_parser = parse_stats_window)]
+    stats_window: f64,
+    /// Measure fps over a window of its own, e.g. shorter to react faster
+    #[arg(long, value_name = "SECS", value_parser = parse_stats_window)]
+    fps_window: Option<f64>,
+    /// Don't log connects, publishes, disconnects and raised diagnostics to stderr
+    #[arg(long, short)]
+    quiet: bool,
+    /// Start the TUI on the full diagnostics list (toggle with d)
+    #[arg(long)]
+    full_diagnostics: bool,
+    /// Disable colored output (also off when NO_COLOR is set or stdout isn't a terminal)
+    #[arg(long)]
+    no_color: bool,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    let addr = format!("{}:{}", args.interface, args.port);
+    let stdout_is_terminal = std::io::stdout().is_terminal();
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    display::set_color_enabled(!args.no_color && !no_color_env && stdout_is_terminal);
+    let profile = match &args.profile_file {
+        Some(path) => match ServiceProfile::from_file(path) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Failed to load profile {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => args.profile.profile(),
+    };
+    // Command-line limits apply on top of whichever profile a stream gets
+    let tweak = |mut profile: ServiceProfile| {
+        if let Some(max) = args.max_keyframe_interval {
+            profile.max_keyframe_interval = max;
+        }
+        profile
+    };
+    // The positional address first, then each --listen
+    let mut addrs: Vec<SocketAddr> = match (args.interface, args.port) {
+        (Some(interface), Some(port)) => vec![SocketAddr::new(interface, port)],
+        _ => Vec::new(),
+    };
+    addrs.extend(&args.listen);
 
-    let listener = match TcpListener::bind(&addr).await {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("Failed to bind to {}: {}", addr, e);
+    // Metrics are served on the interface of the first RTMP address, or
+    // locally when there is only a Unix socket
+    let metrics_ip = addrs.first().map(|addr| addr.ip()).unwrap_or(Ipv4Addr::LOCALHOST.into());
+    let metrics = match args.metrics_port {
+        Some(port) => Some(start_metrics_server(SocketAddr::new(metrics_ip, port)).await),
+        None => None,
+    };
+    let log = match &args.log {
+        Some(path) => match DiagnosticLog::open(path) {
+            Ok(log) => Some(Arc::new(Mutex::new(log))),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let dump_amf = match &args.dump_amf {
+        Some(path) => match AmfDump::open(path.as_deref()) {
+            Ok(dump) => Some(Arc::new(Mutex::new(dump))),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let allowed_keys = match &args.keys_file {
+        Some(path) => match load_keys_file(path) {
+            Ok(keys) => Some(Arc::new(keys)),
+            Err(e) => {
+                eprintln!("Failed to load stream keys from {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let csv = match &args.csv {
+        Some(path) => match CsvLog::create(path) {
+            Ok(csv) => Some(Arc::new(Mutex::new(csv))),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let options = ConnectionOptions {
+        report: args.report,
+        report_file: args.report_file,
+        metrics,
+        log,
+        dump_amf,
+        csv,
+        profile: tweak(profile),
+        app_profiles: args
+            .app_profile
+            .iter()
+            .map(|(app, known)| (app.clone(), tweak(known.profile())))
+            .collect(),
+        max_message_size: args.max_message_size,
+        nal_stats: args.nal_stats,
+        plain: args.plain || !stdout_is_terminal,
+        ping_interval: args.ping_interval.map(Duration::from_secs),
+        idle_timeout: Some(Duration::from_secs(args.idle_timeout)),
+        quiet: args.quiet,
+        check_interval: args.check_interval.map(Duration::from_millis),
+        stats_window: Some(Duration::from_secs_f64(args.stats_window)),
+        fps_window: args.fps_window.map(Duration::from_secs_f64),
+        sinks: Vec::new(),
+        duration: args.duration.map(Duration::from_secs_f64),
+        max_ingress_kbps: args.max_ingress_kbps,
+        allowed_keys,
+        refresh: args.refresh.map(Duration::from_millis),
+    };
+
+    if let Some(path) = &args.file {
+        if let Err(e) = file::analyze_file(path, &options) {
+            eprintln!("Failed to analyze {}: {}", path.display(), e);
             std::process::exit(1);
         }
-    };
+        return;
+    }
+    if let Some(path) = &args.replay {
+        if let Err(e) = replay::replay_dump(path, &options) {
+            eprintln!("Failed to replay {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Each listener accepts on its own task and hands connections to the loop below
+    let (accepted_tx, mut accepted) = mpsc::channel(16);
+    for &addr in &addrs {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Failed to bind to {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        eprintln!("Listening for RTMP connections on {}", addr);
+        tokio::spawn(accept_loop(listener, accepted_tx.clone()));
+
+        // "::" is dual-stack on most systems, in which case the IPv4 port is
+        // already taken; where it isn't, listen for IPv4 clients separately
+        let ipv4_any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port());
+        if addr.ip() == Ipv6Addr::UNSPECIFIED
+            && !addrs.contains(&ipv4_any)
+            && let Ok(listener) = TcpListener::bind(ipv4_any).await
+        {
+            eprintln!("Listening for RTMP connections on {}", ipv4_any);
+            tokio::spawn(accept_loop(listener, accepted_tx.clone()));
+        }
+    }
+    if let Some(path) = &args.unix {
+        listen_unix(path, accepted_tx.clone()).await;
+    }
+    drop(accepted_tx);
 
-    eprintln!("Listening for RTMP connections on {}", addr);
+    // Keypresses switch TUI screens; without a terminal on stdin there are none
+    display::set_full_diagnostics(args.full_diagnostics);
+    if options.tui_enabled() && std::io::stdin().is_terminal() {
+        display::start_key_input();
+    }
 
-    // Handle Ctrl+C for clean shutdown
-    let shutdown = tokio::signal::ctrl_c();
+    // Handle Ctrl+C (and SIGTERM) for clean shutdown
+    let shutdown = shutdown_signal();
     tokio::pin!(shutdown);
 
+    // Connections report back how their streams fared once they end; without
+    // the TUI the worst of them sets the exit status
+    let (finished_tx, mut finished) = mpsc::channel(16);
+    let headless = !options.tui_enabled() || args.duration.is_some();
+    let mut exit_code = 0;
     loop {
         tokio::select! {
-            result = listener.accept() => {
-                match result {
-                    Ok((stream, peer_addr)) => {
-                        tokio::spawn(connection::handle_connection(stream, peer_addr));
-                    }
-                    Err(e) => {
-                        eprintln!("Accept error: {}", e);
-                    }
+            Some((stream, peer_addr)) = accepted.recv() => {
+                let options = options.clone();
+                let finished_tx = finished_tx.clone();
+                tokio::spawn(async move {
+                    let outcome = match stream {
+                        Accepted::Tcp(stream) => connection::handle_connection(stream, peer_addr, options).await,
+                        #[cfg(unix)]
+                        Accepted::Unix(stream) => connection::handle_connection(stream, peer_addr, options).await,
+                    };
+                    let _ = finished_tx.send(outcome).await;
+                });
+            }
+            Some(outcome) = finished.recv() => {
+                if headless {
+                    exit_code = exit_code.max(outcome.exit_code());
+                }
+                // With --duration, the first stream analyzed is the only one
+                if args.duration.is_some() && outcome.streams > 0 {
+                    break;
                 }
             }
             _ = &mut shutdown => {
                 eprintln!("\nShutting down...");
+                // Connection tasks don't get to drop their terminal guards
+                // before the process exits
                 display::restore_terminal();
                 break;
             }
         }
     }
+    display::stop_key_input();
+    if let Some(path) = &args.unix {
+        let _ = std::fs::remove_file(path);
+    }
+    std::process::exit(exit_code);
+}
+
+/// A client stream from one of the listeners
+enum Accepted {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+type AcceptedSender = mpsc::Sender<(Accepted, PeerAddr)>;
+
+/// Resolves on Ctrl+C, or on Unix also SIGTERM, so the terminal gets restored
+/// either way.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut terminate) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Forward connections accepted on `listener` until the receiver goes away.
+async fn accept_loop(listener: TcpListener, accepted: AcceptedSender) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                // Show IPv4 clients of a dual-stack listener as plain IPv4
+                let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                if accepted.send((Accepted::Tcp(stream), PeerAddr::Tcp(addr))).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                eprintln!("Accept error: {}", e);
+            }
+        }
+    }
+}
+
+/// Bind the Unix socket at `path` and forward its connections, exiting if it
+/// can't be bound. A socket file left behind by a crashed run is replaced;
+/// any other file at `path` is left alone.
+#[cfg(unix)]
+async fn listen_unix(path: &Path, accepted: AcceptedSender) {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            eprintln!("Failed to bind to {}: path exists and is not a socket", path.display());
+            std::process::exit(1);
+        }
+        // Nobody listening means a stale socket; a live one fails to bind below
+        if UnixStream::connect(path).await.is_err_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("Failed to bind to {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Listening for RTMP connections on unix:{}", path.display());
+    let path = path.to_path_buf();
+    tokio::spawn(async move {
+        for connection in 1.. {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let peer = PeerAddr::Unix { path: path.clone(), connection };
+                    if accepted.send((Accepted::Unix(stream), peer)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Accept error: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+async fn listen_unix(_path: &Path, _accepted: AcceptedSender) {
+    eprintln!("Unix sockets aren't supported on this platform");
+    std::process::exit(1);
+}
+
+/// An interface address; IPv6 may be given with or without brackets.
+fn parse_interface(s: &str) -> Result<IpAddr, String> {
+    let bare = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(s);
+    bare.parse()
+        .map_err(|_| format!("expected an IP address such as 0.0.0.0 or ::1, got \"{}\"", s))
+}
+
+fn parse_listen_addr(s: &str) -> Result<SocketAddr, String> {
+    s.parse().map_err(|_| {
+        format!("expected ADDR:PORT such as 0.0.0.0:1935 or [::]:1935, got \"{}\"", s)
+    })
+}
+
+fn parse_app_profile(s: &str) -> Result<(String, KnownProfile), String> {
+    let (app, profile) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected APP=PROFILE, got \"{}\"", s))?;
+    let profile = <KnownProfile as clap::ValueEnum>::from_str(profile, true)?;
+    Ok((app.to_string(), profile))
+}
+
+fn parse_positive_secs(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs > 0.0)
+        .ok_or_else(|| format!("expected a positive number of seconds, got \"{}\"", s))
+}
+
+/// Read the allowed stream keys, one per line.
+fn load_keys_file(path: &Path) -> Result<HashSet<String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Seconds a rolling stats window may span: enough frames to average, and
+/// still recent enough to mean "current"
+const STATS_WINDOW_SECS: RangeInclusive<f64> = 0.5..=60.0;
+
+fn parse_stats_window(s: &str) -> Result<f64, String> {
+    let secs = parse_positive_secs(s)?;
+    if !STATS_WINDOW_SECS.contains(&secs) {
+        return Err(format!("must be between {} and {} seconds", STATS_WINDOW_SECS.start(), STATS_WINDOW_SECS.end()));
+    }
+    Ok(secs)
+}
+
+async fn start_metrics_server(addr: SocketAddr) -> SharedMetrics {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("Failed to bind metrics listener to {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("Serving metrics on http://{}/metrics", addr);
+
+    let registry = SharedMetrics::default();
+    tokio::spawn(metrics::serve(listener, registry.clone()));
+    registry
 }
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::flv::audio::AudioAnalyzer;
+use crate::flv::video::VideoAnalyzer;
+use crate::stats::StreamStats;
+
+/// Latest published values for one stream.
+#[derive(Debug, Clone, Default)]
+pub struct StreamMetrics {
+    pub app_name: String,
+    pub stream_key: String,
+    pub video_bitrate_kbps: Option<f64>,
+    pub audio_bitrate_kbps: Option<f64>,
+    pub fps: Option<f64>,
+    pub keyframe_interval_secs: Option<f64>,
+    pub video_frames_total: u64,
+    pub audio_frames_total: u64,
+    /// Error diagnostics raised over the session
+    pub errors_total: u64,
+    /// Dedup keys of the errors present at the last update, so a persisting
+    /// error is only counted once even as the numbers in its message change
+    active_errors: Vec<String>,
+}
+
+impl StreamMetrics {
+    /// Refresh from the stream's analyzers and its latest diagnostic pass.
+    pub fn update(
+        &mut self,
+        app_name: &str,
+        stream_key: &str,
+        stats: &StreamStats,
+        video: &VideoAnalyzer,
+        audio: &AudioAnalyzer,
+        diagnostics: &[Diagnostic],
+    ) {
+        self.app_name = app_name.to_string();
+        self.stream_key = stream_key.to_string();
+        self.video_bitrate_kbps = stats.current_video_bitrate_kbps();
+        self.audio_bitrate_kbps = stats.current_audio_bitrate_kbps();
+        self.fps = stats.current_fps();
+        self.keyframe_interval_secs = stats.keyframe_interval_secs;
+        self.video_frames_total = video.total_video_frames;
+        self.audio_frames_total = audio.total_audio_frames;
+
+        let errors: Vec<String> = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(Diagnostic::dedup_key)
+            .collect();
+        self.errors_total += errors
+            .iter()
+            .filter(|e| !self.active_errors.contains(e))
+            .count() as u64;
+        self.active_errors = errors;
+    }
+}
+
+/// Snapshots of every active stream, keyed by stream id.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    streams: BTreeMap<u64, StreamMetrics>,
+}
+
+pub type SharedMetrics = Arc<Mutex<MetricsRegistry>>;
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Allocate a registry key for a newly published stream.
+pub fn next_stream_id() -> u64 {
+    NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+impl MetricsRegistry {
+    pub fn publish(&mut self, stream_id: u64, metrics: StreamMetrics) {
+        self.streams.insert(stream_id, metrics);
+    }
+
+    pub fn remove(&mut self, stream_id: u64) {
+        self.streams.remove(&stream_id);
+    }
+
+    /// Render all streams in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(2048);
+
+        // Gauges
+        self.write_family(&mut out, "rustmp_video_bitrate_kbps", "Video bitrate over the rolling window", "gauge", |m| m.video_bitrate_kbps);
+        self.write_family(&mut out, "rustmp_audio_bitrate_kbps", "Audio bitrate over the rolling window", "gauge", |m| m.audio_bitrate_kbps);
+        self.write_family(&mut out, "rustmp_fps", "Measured video frames per second", "gauge", |m| m.fps);
+        self.write_family(&mut out, "rustmp_keyframe_interval_seconds", "Most recent keyframe interval", "gauge", |m| m.keyframe_interval_secs);
+
+        // Counters
+        self.write_family(&mut out, "rustmp_video_frames_total", "Video frames received", "counter", |m| Some(m.video_frames_total as f64));
+        self.write_family(&mut out, "rustmp_audio_frames_total", "Audio frames received", "counter", |m| Some(m.audio_frames_total as f64));
+        self.write_family(&mut out, "rustmp_errors_total", "Error diagnostics raised", "counter", |m| Some(m.errors_total as f64));
+
+        out
+    }
+
+    /// Write one metric family; streams without a value yet are left out.
+    fn write_family(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        kind: &str,
+        value: impl Fn(&StreamMetrics) -> Option<f64>,
+    ) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (&stream_id, m) in &self.streams {
+            if let Some(v) = value(m) {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(stream_id, m), v);
+            }
+        }
+    }
+}
+
+/// The registry key tells apart streams that share an app and key, such as
+/// a reconnecting encoder overlapping its old connection; Prometheus rejects
+/// a scrape with duplicate series.
+fn labels(stream_id: u64, m: &StreamMetrics) -> String {
+    format!(
+        "app=\"{}\",stream_key=\"{}\",stream_id=\"{}\"",
+        escape_label(&m.app_name),
+        escape_label(&m.stream_key),
+        stream_id
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `/metrics` over plain HTTP until the process exits.
+pub async fn serve(listener: TcpListener, registry: SharedMetrics) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_request(stream, registry.clone()));
+            }
+            Err(e) => {
+                eprintln!("Metrics accept error: {}", e);
+            }
+        }
+    }
+}
+
+/// How long a scrape client gets to send its request headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn handle_request(mut stream: TcpStream, registry: SharedMetrics) {
+    // Only the request line matters; read until the end of the headers
+    let mut buf = vec![0u8; 4096];
+    let mut len = 0;
+    let read_headers = async {
+        while len < buf.len() {
+            match stream.read(&mut buf[len..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+            if buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+                break;
+            }
+        }
+    };
+    // A client that connects and never sends would otherwise hold its task forever
+    if tokio::time::timeout(REQUEST_TIMEOUT, read_headers).await.is_err() {
+        return;
+    }
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let (status, body) = if method == "GET" && (path == "/metrics" || path.starts_with("/metrics?")) {
+        let body = registry.lock().map(|r| r.render()).unwrap_or_default();
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", "Not Found\n".to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(app_name: &str, stream_key: &str, video_frames_total: u64) -> StreamMetrics {
+        StreamMetrics {
+            app_name: app_name.to_string(),
+            stream_key: stream_key.to_string(),
+            video_frames_total,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn a_persisting_error_is_counted_once() {
+        let stats = StreamStats::new();
+        let video = VideoAnalyzer::new();
+        let audio = AudioAnalyzer::new();
+        let mut metrics = StreamMetrics::default();
+
+        for kbps in [9100, 9350, 9020] {
+            let diagnostics = [Diagnostic::error("Bitrate", format!("Bitrate {} kbps exceeds 8000 kbps", kbps))];
+            metrics.update("live", "key", &stats, &video, &audio, &diagnostics);
+        }
+        assert_eq!(metrics.errors_total, 1);
+
+        metrics.update("live", "key", &stats, &video, &audio, &[]);
+        let diagnostics = [Diagnostic::error("Bitrate", "Bitrate 9100 kbps exceeds 8000 kbps")];
+        metrics.update("live", "key", &stats, &video, &audio, &diagnostics);
+        assert_eq!(metrics.errors_total, 2);
+    }
+
+    #[test]
+    fn streams_sharing_a_key_get_distinct_series() {
+        let mut registry = MetricsRegistry::default();
+        registry.publish(7, stream("live", "key", 10));
+        registry.publish(8, stream("live", "key", 20));
+
+        let out = registry.render();
+        assert!(out.contains("rustmp_video_frames_total{app=\"live\",stream_key=\"key\",stream_id=\"7\"} 10"));
+        assert!(out.contains("rustmp_video_frames_total{app=\"live\",stream_key=\"key\",stream_id=\"8\"} 20"));
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::connection::{ConnectionOptions, ConnectionState};
+use crate::report::{ReportFormat, StreamReport};
+use crate::rtmp::chunk::ChunkReader;
+use crate::rtmp::message::{MessageHandler, RtmpEvent};
+
+/// Bytes handed to the chunk reader at a time, as a socket read would
+const REPLAY_READ_SIZE: usize = 4096;
+
+/// Feed a capture of the bytes a client sent after the handshake through the
+/// chunk reader and message handler, and emit a report per published stream.
+///
+/// Unlike `--file`, this exercises the RTMP chunk layer itself. Timing checks
+/// follow the media timestamps, as for a recording.
+pub fn replay_dump(path: &Path, options: &ConnectionOptions) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let label = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut chunk_reader = ChunkReader::new();
+    if let Some(max) = options.max_message_size {
+        chunk_reader.set_max_message_size(max);
+    }
+    let mut handler = MessageHandler::new();
+    if let Some(dump) = &options.dump_amf {
+        handler.set_dump(dump.clone(), label.clone());
+    }
+    if let Some(keys) = &options.allowed_keys {
+        handler.set_allowed_keys(keys.clone());
+    }
+    // The capture has no handshake, and no wall-clock timing: the startup
+    // timeline follows the media clock from connect on
+    let mut state = ConnectionState::new(options, None);
+
+    let start = Instant::now();
+    // Checks run once per second of media time, like the live display tick
+    let mut first_timestamp: Option<u32> = None;
+    let mut next_check_ms: u64 = 1000;
+    let mut media_ms: u64 = 0;
+    let mut messages_read = 0u64;
+
+    for bytes in data.chunks(REPLAY_READ_SIZE) {
+        chunk_reader.extend(bytes);
+        let messages = chunk_reader.read_messages();
+
+        for rejected in chunk_reader.take_rejected() {
+            state.record_rejected_message(rejected.type_id, rejected.length);
+            eprintln!("{}: oversized message (type {}, {} bytes), skipped", label, rejected.type_id, rejected.length);
+        }
+
+        for msg in messages {
+            messages_read += 1;
+            // Media and data messages move the clock on
+            if matches!(msg.type_id, 8 | 9 | 18) && !state.streams.is_empty() {
+                let origin = *first_timestamp.get_or_insert(msg.timestamp);
+                media_ms = msg.timestamp.saturating_sub(origin) as u64;
+                while media_ms >= next_check_ms {
+                    for s in state.streams.iter_mut().filter(|s| s.active) {
+                        s.session.set_clock(start + Duration::from_millis(next_check_ms));
+                        s.session.stats.sample_history();
+                        let results = s.session.run_checks();
+                        if let Some(log) = &options.log
+                            && let Ok(mut log) = log.lock()
+                        {
+                            log.record(handler.app_name(), &s.stream_key, &mut s.log_state, &results);
+                        }
+                        if let Some(csv) = &options.csv
+                            && let Ok(mut csv) = csv.lock()
+                        {
+                            csv.record(handler.app_name(), &s.stream_key, &s.session);
+                        }
+                    }
+                    next_check_ms += 1000;
+                }
+                for s in state.streams.iter_mut().filter(|s| s.active) {
+                    s.session.set_clock(start + Duration::from_millis(media_ms));
+                }
+            }
+
+            state.check_timestamp(&msg);
+
+            let clock = start + Duration::from_millis(media_ms);
+            for event in handler.handle(msg).events {
+                state.apply(&event, handler.app_name(), options, Some(clock));
+                match event {
+                    RtmpEvent::PublishRejected { stream_key, .. } => {
+                        eprintln!("{}: stream key \"{}\" is not in the keys file, rejected", label, stream_key);
+                    }
+                    RtmpEvent::ShortCommand { command, .. } => {
+                        eprintln!("{}: {} has {} of {} expected values ({} bytes undecoded)",
+                            label, command.name, command.values, command.required, command.undecoded_bytes);
+                    }
+                    RtmpEvent::BufferLength { stream_id, buffer_ms } => {
+                        eprintln!("{}: client set a {} ms buffer on stream {}", label, buffer_ms, stream_id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if messages_read == 0 {
+        return Err("no RTMP messages in dump (is it the bytes after the handshake?)".into());
+    }
+    if state.streams.is_empty() {
+        return Err("no stream was published".into());
+    }
+
+    // The last pass is logged before the streams still going end, which
+    // clears their entries
+    let results: Vec<_> = state.streams.iter_mut().map(|s| s.session.check_now()).collect();
+    if let Some(log) = &options.log
+        && let Ok(mut log) = log.lock()
+    {
+        for (s, results) in state.streams.iter_mut().zip(&results).filter(|(s, _)| s.active) {
+            log.record(handler.app_name(), &s.stream_key, &mut s.log_state, results);
+        }
+    }
+    for s in state.streams.iter_mut().filter(|s| s.active) {
+        s.end(handler.app_name(), options);
+    }
+
+    let chunk_streams = chunk_reader.chunk_stream_stats();
+    let reports: Vec<_> = state.streams
+        .iter()
+        .zip(&results)
+        .map(|(s, results)| StreamReport {
+            chunk_streams: &chunk_streams,
+            connect: Some(handler.connect_params()),
+            ..s.session.report(handler.app_name(), &s.stream_key, results)
+        })
+        .collect();
+    StreamReport::emit_all(&reports, options.report.unwrap_or(ReportFormat::Json), options.report_file.as_deref())
+        .map_err(|e| format!("writing report: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::JsonValue;
+    use crate::rtmp::amf0::{Amf0Encoder, Amf0Value};
+    use crate::rtmp::chunk::ChunkWriter;
+
+    fn command(name: &str, txn_id: f64, args: impl FnOnce(&mut Amf0Encoder)) -> Vec<u8> {
+        let mut enc = Amf0Encoder::new();
+        enc.write_string(name);
+        enc.write_number(txn_id);
+        args(&mut enc);
+        enc.into_bytes()
+    }
+
+    /// A client that connects, trips two connection-wide diagnostics before
+    /// publishing, then sends two seconds of 30fps video.
+    fn capture() -> Vec<u8> {
+        let writer = ChunkWriter::new();
+        let mut data = writer.write_message(2, 0, 1, 0, &4096u32.to_be_bytes());
+        let connect = command("connect", 1.0, |enc| {
+            enc.write_object(&[
+                ("app", Amf0Value::String("live".to_string())),
+                ("tcUrl", Amf0Value::String("rtmp://localhost/live".to_string())),
+                ("objectEncoding", Amf0Value::Number(3.0)),
+            ]);
+        });
+        data.extend(writer.write_message(3, 0, 20, 0, &connect));
+        // Over the 2KB cap set in the test
+        data.extend(writer.write_message(3, 0, 18, 0, &[0; 3000]));
+        let status = command("onStatus", 0.0, |enc| {
+            enc.write_null();
+            enc.write_object(&[
+                ("level", Amf0Value::String("error".to_string())),
+                ("code", Amf0Value::String("NetConnection.Call.Failed".to_string())),
+                ("description", Amf0Value::String("Method not found".to_string())),
+            ]);
+        });
+        data.extend(writer.write_message(3, 0, 20, 0, &status));
+        data.extend(writer.write_message(3, 0, 20, 0, &command("createStream", 2.0, |_| {})));
+        let publish = command("publish", 3.0, |enc| {
+            enc.write_null();
+            enc.write_string("key");
+            enc.write_string("live");
+        });
+        data.extend(writer.write_message(3, 0, 20, 1, &publish));
+        for frame in 0..60u32 {
+            let payload: &[u8] = if frame % 30 == 0 {
+                &[0x17, 0x01, 0, 0, 0, 0, 0, 0, 2, 0x65, 0x88]
+            } else {
+                &[0x27, 0x01, 0, 0, 0, 0, 0, 0, 2, 0x41, 0x9A]
+            };
+            data.extend(writer.write_message(6, frame * 1000 / 30, 9, 1, payload));
+        }
+        data
+    }
+
+    #[test]
+    fn replayed_capture_is_reported_like_a_live_connection() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rustmp-replay-{}.bin", std::process::id()));
+        let report_path = dir.join(format!("rustmp-replay-{}.json", std::process::id()));
+        std::fs::write(&path, capture()).unwrap();
+        let _ = std::fs::remove_file(&report_path);
+        let options = ConnectionOptions {
+            report: Some(ReportFormat::Json),
+            report_file: Some(report_path.clone()),
+            max_message_size: Some(2048),
+            ..Default::default()
+        };
+
+        let result = replay_dump(&path, &options);
+        let written = std::fs::read_to_string(&report_path).unwrap_or_default();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&report_path);
+        result.unwrap();
+
+        let report = JsonValue::parse(written.trim()).unwrap();
+        assert_eq!(report.get("app").and_then(JsonValue::as_str), Some("live"));
+        assert_eq!(report.get("stream_key").and_then(JsonValue::as_str), Some("key"));
+        assert_eq!(report.get("object_encoding").and_then(JsonValue::as_f64), Some(3.0));
+        // Connect was recorded, on the media clock
+        let startup = report.get("startup").unwrap();
+        assert_eq!(startup.get("publish_ms").and_then(JsonValue::as_f64), Some(0.0));
+        let video = report.get("video").unwrap();
+        assert_eq!(video.get("keyframes").and_then(JsonValue::as_f64), Some(2.0));
+
+        let messages: Vec<_> = report
+            .get("diagnostics")
+            .and_then(JsonValue::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(|diag| diag.get("message").and_then(JsonValue::as_str))
+            .collect();
+        // Both came before the stream existed
+        assert!(messages.contains(&"1 oversized message(s) dropped (largest: type 18, 3000 bytes)"), "{:?}", messages);
+        assert!(messages.contains(&"NetConnection.Call.Failed: Method not found"), "{:?}", messages);
+    }
+}
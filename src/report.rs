@@ -0,0 +1,337 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::diagnostics::{Diagnostic, HealthScore, KeyframeIntervalStats, StartupTimeline};
+use crate::encoder::EncoderInfo;
+use crate::flv::audio::{AudioAnalyzer, Mp3FrameHeader};
+use crate::flv::metadata::StreamMetadata;
+use crate::flv::video::{avc_nal_type_name, NalStats, VideoAnalyzer};
+use crate::json::JsonValue;
+use crate::rtmp::chunk::{ChunkStreamStats, CHUNK_SIZE_BUCKETS};
+use crate::rtmp::message::ConnectParams;
+use crate::stats::StreamStats;
+
+/// Output format for the end-of-stream report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+}
+
+/// Final state of a published stream, assembled when it ends.
+pub struct StreamReport<'a> {
+    pub app_name: &'a str,
+    pub stream_key: &'a str,
+    pub encoder: Option<&'a str>,
+    pub stats: &'a StreamStats,
+    pub video: &'a VideoAnalyzer,
+    pub audio: &'a AudioAnalyzer,
+    pub metadata: Option<&'a StreamMetadata>,
+    pub startup: StartupTimeline,
+    pub keyframe_intervals: Option<KeyframeIntervalStats>,
+    /// objectEncoding negotiated in connect (None for a recording)
+    pub object_encoding: Option<u8>,
+    /// What the client sent in connect (None for a recording)
+    pub connect: Option<&'a ConnectParams>,
+    /// How the connection's chunk streams were framed (empty for a recording)
+    pub chunk_streams: &'a [ChunkStreamStats],
+    pub health: &'a HealthScore,
+    pub diagnostics: &'a [Diagnostic],
+}
+
+impl StreamReport<'_> {
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object([
+            ("app", self.app_name.into()),
+            ("stream_key", self.stream_key.into()),
+            ("encoder", self.encoder.into()),
+            (
+                "encoder_name",
+                self.encoder.and_then(EncoderInfo::identify).map(|e| e.to_string()).into(),
+            ),
+            ("object_encoding", self.object_encoding.into()),
+            ("connect", self.connect.map_or(JsonValue::Null, connect_json)),
+            ("duration_secs", self.stats.duration_secs.into()),
+            ("health", health_json(self.health)),
+            ("stats", self.stats_json()),
+            ("video", self.video_json()),
+            ("audio", self.audio_json()),
+            ("metadata", self.metadata.map_or(JsonValue::Null, metadata_json)),
+            ("startup", startup_json(&self.startup)),
+            (
+                "chunk_streams",
+                JsonValue::Array(self.chunk_streams.iter().map(chunk_stream_json).collect()),
+            ),
+            (
+                "diagnostics",
+                JsonValue::Array(self.diagnostics.iter().map(diagnostic_json).collect()),
+            ),
+        ])
+    }
+
+    fn stats_json(&self) -> JsonValue {
+        let stats = self.stats;
+        JsonValue::object([
+            ("total_video_bytes", stats.total_video_bytes.into()),
+            ("total_audio_bytes", stats.total_audio_bytes.into()),
+            ("video_bitrate_kbps", stats.current_video_bitrate_kbps().into()),
+            ("audio_bitrate_kbps", stats.current_audio_bitrate_kbps().into()),
+            ("average_video_bitrate_kbps", stats.average_video_bitrate_kbps().into()),
+            ("average_audio_bitrate_kbps", stats.average_audio_bitrate_kbps().into()),
+            ("peak_video_bitrate_kbps", stats.peak_video_bitrate_kbps.into()),
+            ("peak_audio_bitrate_kbps", stats.peak_audio_bitrate_kbps.into()),
+            ("fps", stats.current_fps().into()),
+            ("keyframe_interval_secs", stats.keyframe_interval_secs.into()),
+            ("keyframe_interval", self.keyframe_intervals.map_or(JsonValue::Null, |k| self.keyframe_interval_json(&k))),
+            ("estimated_dropped_frames", stats.estimated_dropped_frames.into()),
+            ("off_cadence_rate", stats.off_cadence_rate().into()),
+            ("network_jitter_ms", stats.network_jitter_ms().into()),
+            ("min_arrival_interval_ms", stats.arrival_interval_range_ms().map(|(min, _)| min).into()),
+            ("max_arrival_interval_ms", stats.arrival_interval_range_ms().map(|(_, max)| max).into()),
+            ("rtt_ms", stats.rtt_ms.into()),
+            ("ingress_kbps", stats.ingress_kbps.into()),
+        ])
+    }
+
+    fn keyframe_interval_json(&self, intervals: &KeyframeIntervalStats) -> JsonValue {
+        let fps = self.video.fps.or_else(|| self.stats.current_fps());
+        JsonValue::object([
+            ("mean_secs", intervals.mean.into()),
+            ("min_secs", intervals.min.into()),
+            ("max_secs", intervals.max.into()),
+            ("stddev_secs", intervals.stddev.into()),
+            ("samples", intervals.samples.into()),
+            ("mean_frames", fps.map(|fps| intervals.mean_frames(fps)).into()),
+        ])
+    }
+
+    fn video_json(&self) -> JsonValue {
+        let video = self.video;
+        JsonValue::object([
+            ("codec", video.codec.map(|c| c.to_string()).into()),
+            ("width", video.width.into()),
+            ("height", video.height.into()),
+            ("profile", video.profile.clone().into()),
+            ("level", video.level.clone().into()),
+            ("pixel_format", video.pixel_format.map(|f| f.to_string()).into()),
+            ("interlaced", video.is_interlaced.into()),
+            ("mb_adaptive_frame_field", video.mb_adaptive_frame_field.into()),
+            ("sps_count", video.avc_parameter_sets.map(|(sps, _)| sps as u64).into()),
+            ("pps_count", video.avc_parameter_sets.map(|(_, pps)| pps as u64).into()),
+            ("fps", video.fps.into()),
+            (
+                "sample_aspect_ratio",
+                video
+                    .sample_aspect_ratio
+                    .map(|(w, h)| format!("{}:{}", w, h))
+                    .into(),
+            ),
+            ("keyframes", video.keyframe_count.into()),
+            ("idr_keyframes", video.idr_count.into()),
+            ("non_idr_keyframes", video.non_idr_keyframe_count.into()),
+            ("malformed_frames", video.malformed_access_unit_count.into()),
+            ("p_frames", video.inter_frame_count.into()),
+            ("b_frames", video.b_frame_count.into()),
+            ("other_frames", video.other_frame_count.into()),
+            ("sequence_headers", video.sequence_header_count.into()),
+            (
+                "composition_offset_ms",
+                JsonValue::object([
+                    ("min", video.min_composition_offset.map(f64::from).into()),
+                    ("max", video.max_composition_offset.map(f64::from).into()),
+                    ("mean", video.mean_composition_offset().into()),
+                ]),
+            ),
+            ("max_reorder_depth", video.max_reorder_depth().into()),
+            ("gop_length", video.gop_length.into()),
+            ("open_gop", video.open_gop.into()),
+            ("b_pyramid", video.b_pyramid.into()),
+            ("gop_pattern", video.gop_pattern().into()),
+            ("total_frames", video.total_video_frames.into()),
+            ("total_bytes", video.total_video_bytes.into()),
+            ("nal_units", video.nal_stats().map_or(JsonValue::Null, nal_stats_json)),
+        ])
+    }
+
+    fn audio_json(&self) -> JsonValue {
+        let audio = self.audio;
+        JsonValue::object([
+            ("codec", audio.codec.map(|c| c.to_string()).into()),
+            ("profile", audio.aac_profile.clone().into()),
+            ("sample_rate", audio.effective_sample_rate().into()),
+            ("core_sample_rate", audio.sbr_sample_rate.and(audio.asc_sample_rate).into()),
+            ("input_sample_rate", audio.opus_input_sample_rate.into()),
+            ("channels", audio.effective_channels().into()),
+            ("sample_size", audio.sample_size.into()),
+            ("frame_duration_ms", audio.frame_duration_ms().into()),
+            ("aac_bitrate_kbps", audio.aac_bitrate_kbps().into()),
+            ("nominal_bitrate_kbps", audio.nominal_aac_bitrate_kbps().into()),
+            ("mp3", audio.mp3_header.map_or(JsonValue::Null, mp3_header_json)),
+            ("total_frames", audio.total_audio_frames.into()),
+            ("total_bytes", audio.total_audio_bytes.into()),
+        ])
+    }
+
+    /// Write the report in `format` to the end of `path`, or to stdout when no
+    /// path is given.
+    pub fn emit(&self, format: ReportFormat, path: Option<&Path>) -> io::Result<()> {
+        Self::emit_all(std::slice::from_ref(self), format, path)
+    }
+
+    /// Write several reports together, one per line. A file is appended to,
+    /// so reports from every stream and connection a server sees are kept.
+    pub fn emit_all(reports: &[StreamReport], format: ReportFormat, path: Option<&Path>) -> io::Result<()> {
+        let body = reports
+            .iter()
+            .map(|report| match format {
+                ReportFormat::Json => report.to_json().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        match path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all((body + "\n").as_bytes()),
+            None => {
+                let mut stdout = io::stdout().lock();
+                writeln!(stdout, "{}", body)?;
+                stdout.flush()
+            }
+        }
+    }
+}
+
+fn startup_json(startup: &StartupTimeline) -> JsonValue {
+    JsonValue::object([
+        ("handshake_ms", startup.handshake_ms.into()),
+        ("connect_ms", startup.connect_ms.into()),
+        ("publish_ms", startup.publish_ms.into()),
+        ("first_keyframe_ms", startup.first_keyframe_ms.into()),
+    ])
+}
+
+fn connect_json(params: &ConnectParams) -> JsonValue {
+    JsonValue::object([
+        ("tc_url", params.tc_url.clone().into()),
+        ("flash_ver", params.flash_ver.clone().into()),
+        ("swf_url", params.swf_url.clone().into()),
+        ("page_url", params.page_url.clone().into()),
+    ])
+}
+
+fn metadata_json(meta: &StreamMetadata) -> JsonValue {
+    JsonValue::object([
+        ("width", meta.width.into()),
+        ("height", meta.height.into()),
+        ("framerate", meta.framerate.into()),
+        ("video_data_rate_kbps", meta.video_data_rate.into()),
+        ("audio_data_rate_kbps", meta.audio_data_rate.into()),
+        ("video_codec_id", meta.video_codec_id.clone().into()),
+        ("audio_codec_id", meta.audio_codec_id.clone().into()),
+        ("stereo", meta.stereo.into()),
+        ("encoder", meta.encoder.clone().into()),
+    ])
+}
+
+fn nal_stats_json(stats: &NalStats) -> JsonValue {
+    let counts = stats
+        .counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(nal_type, count)| {
+            JsonValue::object([
+                ("type", (nal_type as u8).into()),
+                ("name", avc_nal_type_name(nal_type as u8).into()),
+                ("count", (*count).into()),
+            ])
+        })
+        .collect();
+    JsonValue::object([
+        ("counts", JsonValue::Array(counts)),
+        ("keyframes_with_sps", stats.keyframes_with_sps.into()),
+        ("truncated", stats.truncated.into()),
+    ])
+}
+
+fn health_json(health: &HealthScore) -> JsonValue {
+    let deductions = health
+        .deductions
+        .iter()
+        .map(|(reason, points)| JsonValue::object([("reason", reason.clone().into()), ("points", (*points).into())]))
+        .collect();
+    JsonValue::object([
+        ("score", health.score.into()),
+        ("deductions", JsonValue::Array(deductions)),
+    ])
+}
+
+fn chunk_stream_json(stats: &ChunkStreamStats) -> JsonValue {
+    let bucket_names = CHUNK_SIZE_BUCKETS
+        .iter()
+        .map(|bound| format!("le_{}", bound))
+        .chain(std::iter::once("larger".to_string()));
+    JsonValue::object([
+        ("cs_id", stats.cs_id.into()),
+        ("chunks", stats.chunks().into()),
+        (
+            "header_formats",
+            JsonValue::object([
+                ("fmt0", stats.header_formats[0].into()),
+                ("fmt1", stats.header_formats[1].into()),
+                ("fmt2", stats.header_formats[2].into()),
+                ("fmt3", stats.header_formats[3].into()),
+            ]),
+        ),
+        ("extended_timestamps", stats.extended_timestamps.into()),
+        (
+            "chunk_sizes",
+            JsonValue::Object(bucket_names.zip(stats.chunk_sizes.iter().map(|&n| n.into())).collect()),
+        ),
+    ])
+}
+
+fn mp3_header_json(header: Mp3FrameHeader) -> JsonValue {
+    JsonValue::object([
+        ("version", header.version.into()),
+        ("layer", header.layer.into()),
+        ("bitrate_kbps", header.bitrate_kbps.into()),
+        ("channel_mode", header.channel_mode.into()),
+    ])
+}
+
+fn diagnostic_json(diag: &Diagnostic) -> JsonValue {
+    JsonValue::object([
+        ("severity", diag.severity.as_str().into()),
+        ("category", diag.category.into()),
+        ("message", diag.message.clone().into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionOptions;
+    use crate::session::StreamSession;
+
+    #[test]
+    fn reports_from_separate_connections_are_all_kept() {
+        let path = std::env::temp_dir().join(format!("rustmp-report-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let session = StreamSession::new(&ConnectionOptions::default());
+        let first = session.report("live", "first", &[]);
+        let second = session.report("live", "second", &[]);
+        StreamReport::emit_all(&[first], ReportFormat::Json, Some(&path)).unwrap();
+        StreamReport::emit_all(&[second], ReportFormat::Json, Some(&path)).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"stream_key\":\"first\""));
+        assert!(lines[1].contains("\"stream_key\":\"second\""));
+    }
+}
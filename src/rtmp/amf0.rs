@@ -1,6 +1,8 @@
 use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::rtmp::amf3::Amf3Decoder;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Amf0Value {
     Number(f64),
     Boolean(bool),
@@ -10,6 +12,13 @@ pub enum Amf0Value {
     Undefined,
     EcmaArray(Vec<(String, Amf0Value)>),
     StrictArray(Vec<Amf0Value>),
+    /// Milliseconds since the Unix epoch plus a (normally zero) timezone offset in minutes
+    Date { millis: f64, tz: i16 },
+    /// Object tagged with an ActionScript class name (marker 0x10)
+    TypedObject {
+        class_name: String,
+        properties: Vec<(String, Amf0Value)>,
+    },
 }
 
 impl Amf0Value {
@@ -29,7 +38,9 @@ impl Amf0Value {
 
     pub fn as_object(&self) -> Option<&[(String, Amf0Value)]> {
         match self {
-            Amf0Value::Object(pairs) | Amf0Value::EcmaArray(pairs) => Some(pairs),
+            Amf0Value::Object(pairs)
+            | Amf0Value::EcmaArray(pairs)
+            | Amf0Value::TypedObject { properties: pairs, .. } => Some(pairs),
             _ => None,
         }
     }
@@ -39,6 +50,21 @@ impl Amf0Value {
             pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v)
         })
     }
+
+    /// Values in the tree plus the bytes of its strings and keys, which is
+    /// what copying it out of a reference table costs.
+    pub fn decoded_size(&self) -> usize {
+        let pairs_size = |pairs: &[(String, Amf0Value)]| -> usize {
+            pairs.iter().map(|(k, v)| k.len() + v.decoded_size()).sum()
+        };
+        1 + match self {
+            Amf0Value::String(s) => s.len(),
+            Amf0Value::Object(pairs) | Amf0Value::EcmaArray(pairs) => pairs_size(pairs),
+            Amf0Value::TypedObject { class_name, properties } => class_name.len() + pairs_size(properties),
+            Amf0Value::StrictArray(items) => items.iter().map(Amf0Value::decoded_size).sum(),
+            _ => 0,
+        }
+    }
 }
 
 impl fmt::Display for Amf0Value {
@@ -47,7 +73,12 @@ impl fmt::Display for Amf0Value {
             Amf0Value::Number(n) => write!(f, "{}", n),
             Amf0Value::Boolean(b) => write!(f, "{}", b),
             Amf0Value::String(s) => write!(f, "\"{}\"", s),
-            Amf0Value::Object(pairs) | Amf0Value::EcmaArray(pairs) => {
+            Amf0Value::Object(pairs)
+            | Amf0Value::EcmaArray(pairs)
+            | Amf0Value::TypedObject { properties: pairs, .. } => {
+                if let Amf0Value::TypedObject { class_name, .. } = self {
+                    write!(f, "{} ", class_name)?;
+                }
                 write!(f, "{{")?;
                 for (i, (k, v)) in pairs.iter().enumerate() {
                     if i > 0 {
@@ -69,28 +100,65 @@ impl fmt::Display for Amf0Value {
                 }
                 write!(f, "]")
             }
+            Amf0Value::Date { millis, .. } => write!(f, "Date({})", millis),
         }
     }
 }
 
 // ── Decoder ──
 
+/// Deepest nesting of objects and arrays either decoder will follow; past it
+/// the value is treated as malformed rather than risking the stack.
+pub const MAX_NESTING_DEPTH: usize = 32;
+
+/// Total `decoded_size` either decoder will copy out of its reference
+/// tables. A reference can point at a value made of references, so copies
+/// grow exponentially with the input. Past this, object references resolve
+/// to Undefined (an AMF3 string reference ends the decode instead).
+pub const MAX_REFERENCED_SIZE: usize = 1 << 16;
+
 pub struct Amf0Decoder<'a> {
     data: &'a [u8],
     pos: usize,
+    // AMF3 state for avmplus-object switches; kept for the whole decode pass
+    // so AMF3 reference tables are shared between values of one message.
+    amf3: Option<Amf3Decoder<'a>>,
+    // Complex values (objects, arrays) in order of appearance with their
+    // decoded sizes, for 0x07 references
+    references: Vec<(Amf0Value, usize)>,
+    // How much references have copied so far, up to MAX_REFERENCED_SIZE
+    referenced: usize,
+    // Values currently being decoded, outermost included
+    depth: usize,
 }
 
 impl<'a> Amf0Decoder<'a> {
     pub fn new(data: &'a [u8]) -> Self {
-        Self { data, pos: 0 }
+        Self {
+            data,
+            pos: 0,
+            amf3: None,
+            references: Vec::new(),
+            referenced: 0,
+            depth: 0,
+        }
     }
 
-    #[allow(dead_code)]
     pub fn remaining(&self) -> usize {
         self.data.len().saturating_sub(self.pos)
     }
 
     pub fn decode(&mut self) -> Option<Amf0Value> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = self.decode_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn decode_value(&mut self) -> Option<Amf0Value> {
         if self.pos >= self.data.len() {
             return None;
         }
@@ -104,9 +172,13 @@ impl<'a> Amf0Decoder<'a> {
             0x03 => self.read_object(),
             0x05 => Some(Amf0Value::Null),
             0x06 => Some(Amf0Value::Undefined),
+            0x07 => self.read_reference(),
             0x08 => self.read_ecma_array(),
             0x0A => self.read_strict_array(),
+            0x0B => self.read_date(),
             0x0C => self.read_long_string(),
+            0x10 => self.read_typed_object(),
+            0x11 => self.read_avmplus(),
             _ => {
                 // Unknown marker — cannot continue decoding
                 None
@@ -122,6 +194,18 @@ impl<'a> Amf0Decoder<'a> {
         values
     }
 
+    /// AVM+ object marker: the next value is AMF3-encoded.
+    fn read_avmplus(&mut self) -> Option<Amf0Value> {
+        let data = self.data;
+        let amf3 = self.amf3.get_or_insert_with(|| Amf3Decoder::new(data));
+        amf3.seek(self.pos);
+        // The AMF3 value sits inside ours, so it shares the nesting budget
+        amf3.set_depth(self.depth);
+        let value = amf3.decode();
+        self.pos = amf3.position();
+        value
+    }
+
     fn read_number(&mut self) -> Option<Amf0Value> {
         if self.pos + 8 > self.data.len() {
             return None;
@@ -131,6 +215,47 @@ impl<'a> Amf0Decoder<'a> {
         Some(Amf0Value::Number(f64::from_be_bytes(bytes)))
     }
 
+    fn read_date(&mut self) -> Option<Amf0Value> {
+        if self.pos + 10 > self.data.len() {
+            return None;
+        }
+        let bytes: [u8; 8] = self.data[self.pos..self.pos + 8].try_into().ok()?;
+        let tz = i16::from_be_bytes([self.data[self.pos + 8], self.data[self.pos + 9]]);
+        self.pos += 10;
+        Some(Amf0Value::Date {
+            millis: f64::from_be_bytes(bytes),
+            tz,
+        })
+    }
+
+    fn read_reference(&mut self) -> Option<Amf0Value> {
+        if self.pos + 2 > self.data.len() {
+            return None;
+        }
+        let index = u16::from_be_bytes([self.data[self.pos], self.data[self.pos + 1]]) as usize;
+        self.pos += 2;
+        // An out-of-range reference is a broken encoder; keep going with a placeholder
+        let Some((value, size)) = self.references.get(index) else {
+            return Some(Amf0Value::Undefined);
+        };
+        if self.referenced + size > MAX_REFERENCED_SIZE {
+            return Some(Amf0Value::Undefined);
+        }
+        self.referenced += size;
+        Some(value.clone())
+    }
+
+    /// Reserve a reference-table slot for a complex value before decoding its
+    /// children, so nested values get the indices the encoder assigned.
+    fn reserve_reference(&mut self) -> usize {
+        self.references.push((Amf0Value::Null, 1));
+        self.references.len() - 1
+    }
+
+    fn store_reference(&mut self, slot: usize, value: &Amf0Value) {
+        self.references[slot] = (value.clone(), value.decoded_size());
+    }
+
     fn read_boolean(&mut self) -> Option<Amf0Value> {
         if self.pos >= self.data.len() {
             return None;
@@ -177,9 +302,17 @@ impl<'a> Amf0Decoder<'a> {
         Some(Amf0Value::String(s))
     }
 
-    fn read_object_properties(&mut self) -> Option<Vec<(String, Amf0Value)>> {
+    /// Key/value pairs up to the object end marker. Given the `count` an
+    /// ECMA array declares, data that runs out without the marker still
+    /// yields the first `count` pairs.
+    fn read_object_properties(&mut self, count: Option<usize>) -> Option<Vec<(String, Amf0Value)>> {
         let mut pairs = Vec::new();
+        // Position and reference count where the declared count ran out
+        let mut counted_end = None;
         loop {
+            if counted_end.is_none() && count == Some(pairs.len()) {
+                counted_end = Some((self.pos, self.references.len()));
+            }
             // Check for end marker: 0x00 0x00 0x09
             if self.pos + 3 <= self.data.len()
                 && self.data[self.pos] == 0x00
@@ -189,31 +322,59 @@ impl<'a> Amf0Decoder<'a> {
                 self.pos += 3;
                 break;
             }
-            let key = self.read_utf8()?;
-            if key.is_empty() {
-                // Some encoders use empty key followed by end marker
-                if self.pos < self.data.len() && self.data[self.pos] == 0x09 {
-                    self.pos += 1;
+            let key = self.read_utf8();
+            // Some encoders use empty key followed by end marker
+            if key.as_deref() == Some("") && self.pos < self.data.len() && self.data[self.pos] == 0x09 {
+                self.pos += 1;
+                break;
+            }
+            match key.and_then(|key| self.decode().map(|value| (key, value))) {
+                Some(pair) => pairs.push(pair),
+                None => {
+                    // Counts are often wrong, so pairs past the count are
+                    // only kept if an end marker follows them
+                    let (pos, references) = counted_end?;
+                    self.pos = pos;
+                    self.references.truncate(references);
+                    pairs.truncate(count.unwrap_or_default());
                     break;
                 }
             }
-            let value = self.decode()?;
-            pairs.push((key, value));
         }
         Some(pairs)
     }
 
     fn read_object(&mut self) -> Option<Amf0Value> {
-        self.read_object_properties().map(Amf0Value::Object)
+        let slot = self.reserve_reference();
+        let value = Amf0Value::Object(self.read_object_properties(None)?);
+        self.store_reference(slot, &value);
+        Some(value)
+    }
+
+    fn read_typed_object(&mut self) -> Option<Amf0Value> {
+        let class_name = self.read_utf8()?;
+        let slot = self.reserve_reference();
+        let value = Amf0Value::TypedObject { class_name, properties: self.read_object_properties(None)? };
+        self.store_reference(slot, &value);
+        Some(value)
     }
 
     fn read_ecma_array(&mut self) -> Option<Amf0Value> {
         if self.pos + 4 > self.data.len() {
             return None;
         }
-        // Skip the count — it's often inaccurate
+        let count = u32::from_be_bytes([
+            self.data[self.pos],
+            self.data[self.pos + 1],
+            self.data[self.pos + 2],
+            self.data[self.pos + 3],
+        ]) as usize;
         self.pos += 4;
-        self.read_object_properties().map(Amf0Value::EcmaArray)
+        let slot = self.reserve_reference();
+        // The count is often inaccurate, so the end marker decides when there is one
+        let value = Amf0Value::EcmaArray(self.read_object_properties(Some(count))?);
+        self.store_reference(slot, &value);
+        Some(value)
     }
 
     fn read_strict_array(&mut self) -> Option<Amf0Value> {
@@ -227,14 +388,21 @@ impl<'a> Amf0Decoder<'a> {
             self.data[self.pos + 3],
         ]) as usize;
         self.pos += 4;
+        // Every item takes at least a byte, so a count beyond what's left is a lie
+        let count = count.min(self.remaining());
+        let slot = self.reserve_reference();
         let mut items = Vec::with_capacity(count.min(1024));
         for _ in 0..count {
+            // An item that consumed nothing would repeat for the whole count
+            let start = self.pos;
             match self.decode() {
-                Some(v) => items.push(v),
-                None => break,
+                Some(v) if self.pos > start => items.push(v),
+                _ => break,
             }
         }
-        Some(Amf0Value::StrictArray(items))
+        let value = Amf0Value::StrictArray(items);
+        self.store_reference(slot, &value);
+        Some(value)
     }
 }
 
@@ -267,9 +435,16 @@ impl Amf0Encoder {
         self
     }
 
+    /// Strings too long for the 16-bit length go out as a long string (0x0C).
     pub fn write_string(&mut self, val: &str) -> &mut Self {
-        self.buf.push(0x02);
-        self.write_utf8(val);
+        if val.len() > u16::MAX as usize {
+            self.buf.push(0x0C);
+            self.buf.extend_from_slice(&(val.len() as u32).to_be_bytes());
+            self.buf.extend_from_slice(val.as_bytes());
+        } else {
+            self.buf.push(0x02);
+            self.write_utf8(val);
+        }
         self
     }
 
@@ -280,19 +455,52 @@ impl Amf0Encoder {
 
     pub fn write_object(&mut self, pairs: &[(&str, Amf0Value)]) -> &mut Self {
         self.buf.push(0x03);
+        self.write_properties(pairs);
+        self
+    }
+
+    /// ECMA array with its entry count, as onMetaData is usually sent.
+    pub fn write_ecma_array(&mut self, pairs: &[(&str, Amf0Value)]) -> &mut Self {
+        self.buf.push(0x08);
+        let count = pairs.len() as u32;
+        self.buf.extend_from_slice(&count.to_be_bytes());
+        self.write_properties(pairs);
+        self
+    }
+
+    pub fn write_strict_array(&mut self, items: &[Amf0Value]) -> &mut Self {
+        self.buf.push(0x0A);
+        let count = items.len() as u32;
+        self.buf.extend_from_slice(&count.to_be_bytes());
+        for item in items {
+            self.write_value(item);
+        }
+        self
+    }
+
+    /// Object registered under an ActionScript class name.
+    pub fn write_typed_object(&mut self, class_name: &str, pairs: &[(&str, Amf0Value)]) -> &mut Self {
+        self.buf.push(0x10);
+        self.write_utf8(class_name);
+        self.write_properties(pairs);
+        self
+    }
+
+    /// Key/value pairs followed by the object end marker.
+    fn write_properties(&mut self, pairs: &[(&str, Amf0Value)]) {
         for (key, value) in pairs {
             self.write_utf8(key);
             self.write_value(value);
         }
-        // Object end marker
         self.buf.extend_from_slice(&[0x00, 0x00, 0x09]);
-        self
     }
 
+    /// Keys and class names have no long form, so they're cut to fit,
+    /// keeping whole characters.
     fn write_utf8(&mut self, val: &str) {
-        let len = val.len().min(u16::MAX as usize) as u16;
-        self.buf.extend_from_slice(&len.to_be_bytes());
-        self.buf.extend_from_slice(&val.as_bytes()[..len as usize]);
+        let val = &val[..val.floor_char_boundary(u16::MAX as usize)];
+        self.buf.extend_from_slice(&(val.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(val.as_bytes());
     }
 
     fn write_value(&mut self, val: &Amf0Value) {
@@ -310,24 +518,264 @@ impl Amf0Encoder {
                 self.write_null();
             }
             Amf0Value::Object(pairs) => {
-                let refs: Vec<(&str, Amf0Value)> =
-                    pairs.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
-                self.write_object(&refs);
+                self.write_object(&borrow_keys(pairs));
             }
             Amf0Value::EcmaArray(pairs) => {
-                // Encode ECMA arrays as objects for responses
-                let refs: Vec<(&str, Amf0Value)> =
-                    pairs.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
-                self.write_object(&refs);
+                self.write_ecma_array(&borrow_keys(pairs));
+            }
+            Amf0Value::TypedObject { class_name, properties } => {
+                self.write_typed_object(class_name, &borrow_keys(properties));
+            }
+            Amf0Value::Date { millis, tz } => {
+                self.buf.push(0x0B);
+                self.buf.extend_from_slice(&millis.to_be_bytes());
+                self.buf.extend_from_slice(&tz.to_be_bytes());
             }
             Amf0Value::StrictArray(items) => {
-                self.buf.push(0x0A);
-                let count = items.len() as u32;
-                self.buf.extend_from_slice(&count.to_be_bytes());
-                for item in items {
-                    self.write_value(item);
-                }
+                self.write_strict_array(items);
             }
         }
     }
 }
+
+impl Default for Amf0Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn borrow_keys(pairs: &[(String, Amf0Value)]) -> Vec<(&str, Amf0Value)> {
+    pairs.iter().map(|(k, v)| (k.as_str(), v.clone())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Vec<u8> {
+        let mut bytes = (name.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(name.as_bytes());
+        bytes
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        [vec![0x02], key(value)].concat()
+    }
+
+    fn number(value: f64) -> Vec<u8> {
+        [vec![0x00], value.to_be_bytes().to_vec()].concat()
+    }
+
+    #[test]
+    fn metadata_decodes_past_a_date() {
+        // onMetaData as OBS sends it, with a creation date in the middle
+        let properties = [
+            ("duration", number(0.0)),
+            ("width", number(1920.0)),
+            ("height", number(1080.0)),
+            ("videodatarate", number(6000.0)),
+            ("framerate", number(60.0)),
+            ("videocodecid", number(7.0)),
+            ("creationdate", [vec![0x0B], 1_700_000_000_000f64.to_be_bytes().to_vec(), vec![0, 0]].concat()),
+            ("audiodatarate", number(160.0)),
+            ("audiosamplerate", number(48000.0)),
+            ("stereo", vec![0x01, 0x01]),
+            ("audiocodecid", number(10.0)),
+            ("encoder", string("obs-output module (libobs version 30.0.2)")),
+            ("filesize", number(0.0)),
+        ];
+        let mut data = string("onMetaData");
+        data.push(0x08);
+        data.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+        for (name, value) in &properties {
+            data.extend(key(name));
+            data.extend_from_slice(value);
+        }
+        data.extend_from_slice(&[0x00, 0x00, 0x09]);
+
+        let mut decoder = Amf0Decoder::new(&data);
+        let values = decoder.decode_all();
+        assert_eq!(decoder.remaining(), 0);
+        assert_eq!(values.len(), 2);
+        let pairs = values[1].as_object().unwrap();
+        let names: Vec<_> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+        let expected: Vec<_> = properties.iter().map(|(k, _)| *k).collect();
+        assert_eq!(names, expected);
+        assert!(matches!(values[1].get_property("creationdate"), Some(Amf0Value::Date { millis, tz: 0 }) if *millis == 1_700_000_000_000.0));
+        assert_eq!(values[1].get_property("audiosamplerate").and_then(Amf0Value::as_f64), Some(48000.0));
+        assert_eq!(
+            values[1].get_property("encoder").and_then(Amf0Value::as_str),
+            Some("obs-output module (libobs version 30.0.2)")
+        );
+    }
+
+    fn ecma_array(count: u32, pairs: &[(&str, f64)]) -> Vec<u8> {
+        let mut data = vec![0x08];
+        data.extend_from_slice(&count.to_be_bytes());
+        for (name, value) in pairs {
+            data.extend(key(name));
+            data.extend(number(*value));
+        }
+        data
+    }
+
+    #[test]
+    fn ecma_array_ends_at_the_end_marker() {
+        // The count is wrong, as it often is; the marker wins
+        let mut data = ecma_array(1, &[("width", 1280.0), ("height", 720.0), ("framerate", 30.0)]);
+        data.extend_from_slice(&[0x00, 0x00, 0x09]);
+        data.extend(string("next"));
+
+        let values = Amf0Decoder::new(&data).decode_all();
+        assert_eq!(values.len(), 2);
+        let pairs = values[0].as_object().unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(values[0].get_property("framerate").and_then(Amf0Value::as_f64), Some(30.0));
+        assert_eq!(values[1].as_str(), Some("next"));
+    }
+
+    #[test]
+    fn ecma_array_without_end_marker_stops_at_its_count() {
+        let array = ecma_array(2, &[("width", 1280.0), ("height", 720.0)]);
+        let trailer = string("next");
+        let data = [array, trailer.clone()].concat();
+
+        let mut decoder = Amf0Decoder::new(&data);
+        let value = decoder.decode().unwrap();
+        let pairs = value.as_object().unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(value.get_property("height").and_then(Amf0Value::as_f64), Some(720.0));
+        // Right after the second pair, so what follows still decodes
+        assert_eq!(decoder.remaining(), trailer.len());
+        assert_eq!(decoder.decode().and_then(|v| v.as_str().map(str::to_string)).as_deref(), Some("next"));
+    }
+
+    fn owned(pairs: &[(&str, Amf0Value)]) -> Vec<(String, Amf0Value)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn ecma_array_round_trips_with_nested_values() {
+        let video = [("codec", Amf0Value::String("avc1".into())), ("width", Amf0Value::Number(1920.0))];
+        let point = [("x", Amf0Value::Number(1.0)), ("y", Amf0Value::Number(-2.5))];
+        let pairs = [
+            ("duration", Amf0Value::Number(0.0)),
+            ("stereo", Amf0Value::Boolean(true)),
+            ("video", Amf0Value::Object(owned(&video))),
+            ("tracks", Amf0Value::EcmaArray(owned(&[("0", Amf0Value::Object(owned(&video)))]))),
+            ("origin", Amf0Value::TypedObject { class_name: "flash.geom.Point".into(), properties: owned(&point) }),
+            ("keyframes", Amf0Value::StrictArray(vec![Amf0Value::Number(0.0), Amf0Value::Number(2.0), Amf0Value::Null])),
+            ("created", Amf0Value::Date { millis: 1_700_000_000_000.0, tz: 0 }),
+        ];
+        let mut encoder = Amf0Encoder::new();
+        encoder.write_ecma_array(&pairs);
+        let data = encoder.into_bytes();
+        assert_eq!(data[0], 0x08);
+        assert_eq!(&data[1..5], &(pairs.len() as u32).to_be_bytes());
+        assert_eq!(&data[data.len() - 3..], &[0x00, 0x00, 0x09]);
+
+        let mut decoder = Amf0Decoder::new(&data);
+        assert_eq!(decoder.decode(), Some(Amf0Value::EcmaArray(owned(&pairs))));
+        assert_eq!(decoder.remaining(), 0);
+    }
+
+    #[test]
+    fn typed_object_and_strict_array_round_trip() {
+        let properties = [
+            ("name", Amf0Value::String("ad break".into())),
+            ("parameters", Amf0Value::Object(owned(&[("duration", Amf0Value::Number(30.0))]))),
+        ];
+        let items = vec![
+            Amf0Value::TypedObject { class_name: "CuePoint".into(), properties: owned(&properties) },
+            Amf0Value::StrictArray(vec![Amf0Value::String("nested".into())]),
+            Amf0Value::Boolean(false),
+        ];
+        let mut encoder = Amf0Encoder::new();
+        encoder.write_typed_object("CuePoint", &properties).write_strict_array(&items);
+        let data = encoder.into_bytes();
+        assert_eq!(data[0], 0x10);
+
+        let values = Amf0Decoder::new(&data).decode_all();
+        assert_eq!(values, vec![items[0].clone(), Amf0Value::StrictArray(items)]);
+    }
+
+    #[test]
+    fn long_string_round_trips() {
+        let description = "Ünïcödé stream description. ".repeat(3000);
+        assert!(description.len() > u16::MAX as usize);
+        let mut encoder = Amf0Encoder::new();
+        encoder.write_object(&[("description", Amf0Value::String(description.clone()))]);
+        let data = encoder.into_bytes();
+        // Object marker, then the key, then the value's marker
+        let value_start = 1 + 2 + "description".len();
+        assert_eq!(data[value_start], 0x0C);
+        assert_eq!(&data[value_start + 1..value_start + 5], &(description.len() as u32).to_be_bytes());
+
+        let value = Amf0Decoder::new(&data).decode().unwrap();
+        assert_eq!(value.get_property("description").and_then(Amf0Value::as_str), Some(description.as_str()));
+    }
+
+    #[test]
+    fn oversized_key_is_cut_on_a_char_boundary() {
+        // Two bytes per char, so 65535 lands mid-character
+        let name = "é".repeat(40_000);
+        let mut encoder = Amf0Encoder::new();
+        encoder.write_object(&[(&name, Amf0Value::Null)]);
+        let data = encoder.into_bytes();
+        assert_eq!(&data[1..3], &65534u16.to_be_bytes());
+        assert!(std::str::from_utf8(&data[3..3 + 65534]).is_ok());
+
+        let value = Amf0Decoder::new(&data).decode().unwrap();
+        let pairs = value.as_object().unwrap();
+        assert_eq!(pairs[0].0, "é".repeat(32_767));
+        assert_eq!(pairs[0].1, Amf0Value::Null);
+    }
+
+    #[test]
+    fn strict_array_count_is_bounded_by_the_data() {
+        let mut data = vec![0x0A];
+        data.extend_from_slice(&1_000_000u32.to_be_bytes());
+        data.extend(number(1.0));
+        data.extend_from_slice(&[0x05, 0x01, 0x00]);
+
+        let start = std::time::Instant::now();
+        let mut decoder = Amf0Decoder::new(&data);
+        let value = decoder.decode();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        let Some(Amf0Value::StrictArray(items)) = value else {
+            panic!("expected a strict array, got {:?}", value);
+        };
+        assert_eq!(items, vec![Amf0Value::Number(1.0), Amf0Value::Null, Amf0Value::Boolean(false)]);
+        assert!(items.capacity() <= data.len());
+        assert_eq!(decoder.remaining(), 0);
+    }
+
+    /// `count` objects in a row, each `{a: previous, b: previous}` by reference.
+    fn doubling_references(count: u16) -> Vec<u8> {
+        let mut data = vec![0x03, 0x00, 0x00, 0x09];
+        for index in 0..count - 1 {
+            data.push(0x03);
+            for key in [b'a', b'b'] {
+                data.extend_from_slice(&[0x00, 0x01, key, 0x07]);
+                data.extend_from_slice(&index.to_be_bytes());
+            }
+            data.extend_from_slice(&[0x00, 0x00, 0x09]);
+        }
+        data
+    }
+
+    #[test]
+    fn references_stop_copying_past_the_budget() {
+        let data = doubling_references(64);
+        let start = std::time::Instant::now();
+        let values = Amf0Decoder::new(&data).decode_all();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        assert_eq!(values.len(), 64);
+        let total: usize = values.iter().map(Amf0Value::decoded_size).sum();
+        assert!(total <= MAX_REFERENCED_SIZE + data.len(), "decoded {} units", total);
+        // Early objects are copied in full
+        assert_eq!(values[3].get_property("a").map(Amf0Value::decoded_size), Some(values[2].decoded_size()));
+        assert!(matches!(values[63].get_property("a"), Some(Amf0Value::Undefined)));
+    }
+}
@@ -0,0 +1,503 @@
+use crate::rtmp::amf0::{Amf0Value, MAX_NESTING_DEPTH, MAX_REFERENCED_SIZE};
+
+/// Class definition shared by AMF3 objects via the traits reference table.
+#[derive(Debug, Clone)]
+struct Amf3Traits {
+    /// Empty for anonymous objects
+    class_name: String,
+    dynamic: bool,
+    sealed_names: Vec<String>,
+}
+
+// ── Decoder ──
+
+/// Decodes AMF3 values into the same `Amf0Value` tree used for AMF0, so the
+/// metadata and command handling code doesn't care which encoding was used.
+///
+/// The string, object and traits reference tables live on the decoder, so a
+/// single decoder must be used for a whole message.
+pub struct Amf3Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    strings: Vec<String>,
+    // With their decoded sizes, to charge references for copying them
+    objects: Vec<(Amf0Value, usize)>,
+    traits: Vec<Amf3Traits>,
+    // How much object and string references have copied, each up to
+    // MAX_REFERENCED_SIZE
+    referenced: usize,
+    referenced_strings: usize,
+    // Values currently being decoded, including any AMF0 ones around us
+    depth: usize,
+}
+
+impl<'a> Amf3Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            strings: Vec::new(),
+            objects: Vec::new(),
+            traits: Vec::new(),
+            referenced: 0,
+            referenced_strings: 0,
+            depth: 0,
+        }
+    }
+
+    /// Current read offset into the underlying buffer.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Move the read offset (used when AMF0 hands over via the avmplus marker).
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// Start counting nesting from `depth` (the AMF0 values enclosing this one).
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    pub fn decode(&mut self) -> Option<Amf0Value> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = self.decode_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn decode_value(&mut self) -> Option<Amf0Value> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let marker = self.data[self.pos];
+        self.pos += 1;
+
+        match marker {
+            0x00 => Some(Amf0Value::Undefined),
+            0x01 => Some(Amf0Value::Null),
+            0x02 => Some(Amf0Value::Boolean(false)),
+            0x03 => Some(Amf0Value::Boolean(true)),
+            0x04 => self.read_integer(),
+            0x05 => self.read_double(),
+            0x06 => self.read_string().map(Amf0Value::String),
+            0x07 | 0x0B => self.read_xml(),
+            0x08 => self.read_date(),
+            0x09 => self.read_array(),
+            0x0A => self.read_object(),
+            0x0C => self.read_byte_array(),
+            _ => {
+                // Vectors, dictionaries and unknown markers — cannot continue decoding
+                None
+            }
+        }
+    }
+
+    pub fn decode_all(&mut self) -> Vec<Amf0Value> {
+        let mut values = Vec::new();
+        while let Some(val) = self.decode() {
+            values.push(val);
+        }
+        values
+    }
+
+    /// Read a variable-length 29-bit unsigned integer (U29).
+    fn read_u29(&mut self) -> Option<u32> {
+        let mut value: u32 = 0;
+        for i in 0..4 {
+            if self.pos >= self.data.len() {
+                return None;
+            }
+            let byte = self.data[self.pos];
+            self.pos += 1;
+            if i == 3 {
+                // Fourth byte contributes all 8 bits
+                value = (value << 8) | byte as u32;
+                break;
+            }
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Some(value)
+    }
+
+    fn read_integer(&mut self) -> Option<Amf0Value> {
+        let raw = self.read_u29()?;
+        // Sign-extend from 29-bit
+        let value = if raw & 0x1000_0000 != 0 {
+            raw as i32 - 0x2000_0000
+        } else {
+            raw as i32
+        };
+        Some(Amf0Value::Number(value as f64))
+    }
+
+    fn read_f64(&mut self) -> Option<f64> {
+        if self.pos + 8 > self.data.len() {
+            return None;
+        }
+        let bytes: [u8; 8] = self.data[self.pos..self.pos + 8].try_into().ok()?;
+        self.pos += 8;
+        Some(f64::from_be_bytes(bytes))
+    }
+
+    fn read_double(&mut self) -> Option<Amf0Value> {
+        self.read_f64().map(Amf0Value::Number)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.pos + len > self.data.len() {
+            return None;
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Some(bytes)
+    }
+
+    /// Read a UTF-8-vr string, resolving or populating the string reference table.
+    fn read_string(&mut self) -> Option<String> {
+        let header = self.read_u29()?;
+        if header & 1 == 0 {
+            let s = self.strings.get((header >> 1) as usize)?;
+            // Keys are strings, so one can't be left out; give up on the value instead
+            if self.referenced_strings + s.len() > MAX_REFERENCED_SIZE {
+                return None;
+            }
+            self.referenced_strings += s.len();
+            return Some(s.clone());
+        }
+        let len = (header >> 1) as usize;
+        let s = String::from_utf8_lossy(self.read_bytes(len)?).into_owned();
+        // The empty string is never sent by reference
+        if !s.is_empty() {
+            self.strings.push(s.clone());
+        }
+        Some(s)
+    }
+
+    /// Resolve an object reference if the low bit of `header` is clear.
+    /// Past the copy budget, references resolve to Undefined.
+    fn object_reference(&mut self, header: u32) -> Option<Option<Amf0Value>> {
+        if header & 1 != 0 {
+            return None;
+        }
+        let Some((value, size)) = self.objects.get((header >> 1) as usize) else {
+            return Some(None);
+        };
+        if self.referenced + size > MAX_REFERENCED_SIZE {
+            return Some(Some(Amf0Value::Undefined));
+        }
+        self.referenced += size;
+        Some(Some(value.clone()))
+    }
+
+    /// Reserve a slot in the object table before decoding children, so that
+    /// nested references see the correct indices. Self-references resolve to
+    /// whatever the slot holds at that point (Null).
+    fn reserve_object(&mut self) -> usize {
+        self.objects.push((Amf0Value::Null, 1));
+        self.objects.len() - 1
+    }
+
+    fn store_object(&mut self, slot: usize, value: &Amf0Value) {
+        self.objects[slot] = (value.clone(), value.decoded_size());
+    }
+
+    fn read_xml(&mut self) -> Option<Amf0Value> {
+        let header = self.read_u29()?;
+        if let Some(resolved) = self.object_reference(header) {
+            return resolved;
+        }
+        let len = (header >> 1) as usize;
+        let s = String::from_utf8_lossy(self.read_bytes(len)?).into_owned();
+        let value = Amf0Value::String(s);
+        self.objects.push((value.clone(), value.decoded_size()));
+        Some(value)
+    }
+
+    fn read_byte_array(&mut self) -> Option<Amf0Value> {
+        let header = self.read_u29()?;
+        if let Some(resolved) = self.object_reference(header) {
+            return resolved;
+        }
+        // Raw bytes have no equivalent in the value tree; skip them
+        let len = (header >> 1) as usize;
+        self.read_bytes(len)?;
+        self.objects.push((Amf0Value::Undefined, 1));
+        Some(Amf0Value::Undefined)
+    }
+
+    fn read_date(&mut self) -> Option<Amf0Value> {
+        let header = self.read_u29()?;
+        if let Some(resolved) = self.object_reference(header) {
+            return resolved;
+        }
+        let value = Amf0Value::Date {
+            millis: self.read_f64()?,
+            tz: 0,
+        };
+        self.objects.push((value.clone(), value.decoded_size()));
+        Some(value)
+    }
+
+    fn read_array(&mut self) -> Option<Amf0Value> {
+        let header = self.read_u29()?;
+        if let Some(resolved) = self.object_reference(header) {
+            return resolved;
+        }
+        let dense_count = (header >> 1) as usize;
+        let slot = self.reserve_object();
+
+        // Associative portion: key/value pairs terminated by an empty key
+        let mut pairs = Vec::new();
+        loop {
+            let key = self.read_string()?;
+            if key.is_empty() {
+                break;
+            }
+            let value = self.decode()?;
+            pairs.push((key, value));
+        }
+
+        // Dense portion — each element is at least one byte
+        let mut items = Vec::with_capacity(dense_count.min(self.data.len() - self.pos));
+        for _ in 0..dense_count {
+            items.push(self.decode()?);
+        }
+
+        let value = if pairs.is_empty() {
+            Amf0Value::StrictArray(items)
+        } else {
+            for (i, item) in items.into_iter().enumerate() {
+                pairs.push((i.to_string(), item));
+            }
+            Amf0Value::EcmaArray(pairs)
+        };
+        self.store_object(slot, &value);
+        Some(value)
+    }
+
+    fn read_object(&mut self) -> Option<Amf0Value> {
+        let header = self.read_u29()?;
+        if let Some(resolved) = self.object_reference(header) {
+            return resolved;
+        }
+
+        let traits = if header & 0x02 == 0 {
+            // Traits reference
+            self.traits.get((header >> 2) as usize)?.clone()
+        } else if header & 0x04 != 0 {
+            // Externalizable — class-specific encoding we can't interpret
+            return None;
+        } else {
+            let dynamic = header & 0x08 != 0;
+            let sealed_count = (header >> 4) as usize;
+            let class_name = self.read_string()?;
+            let mut sealed_names = Vec::with_capacity(sealed_count.min(64));
+            for _ in 0..sealed_count {
+                sealed_names.push(self.read_string()?);
+            }
+            let traits = Amf3Traits {
+                class_name,
+                dynamic,
+                sealed_names,
+            };
+            self.traits.push(traits.clone());
+            traits
+        };
+
+        let slot = self.reserve_object();
+
+        let mut pairs = Vec::with_capacity(traits.sealed_names.len());
+        for name in &traits.sealed_names {
+            let value = self.decode()?;
+            pairs.push((name.clone(), value));
+        }
+
+        if traits.dynamic {
+            loop {
+                let key = self.read_string()?;
+                if key.is_empty() {
+                    break;
+                }
+                let value = self.decode()?;
+                pairs.push((key, value));
+            }
+        }
+
+        // Same shape the AMF0 decoder gives typed objects (marker 0x10)
+        let value = if traits.class_name.is_empty() {
+            Amf0Value::Object(pairs)
+        } else {
+            Amf0Value::TypedObject { class_name: traits.class_name, properties: pairs }
+        };
+        self.store_object(slot, &value);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` anonymous dynamic objects in a row, each `{a: previous, b: previous}`
+    /// by reference, sharing one traits entry and the two key strings.
+    fn doubling_references(count: u8) -> Vec<u8> {
+        // Inline traits, dynamic, no sealed members, anonymous: {}
+        let mut data = vec![0x0A, 0x0B, 0x01, 0x01];
+        for index in 0..count - 1 {
+            // Traits reference 0, then "a" and "b" inline the first time
+            data.extend_from_slice(&[0x0A, 0x01]);
+            if index == 0 {
+                data.extend_from_slice(&[0x03, b'a', 0x0A, 0x00, 0x03, b'b', 0x0A, 0x00]);
+            } else {
+                data.extend_from_slice(&[0x00, 0x0A, index << 1, 0x02, 0x0A, index << 1]);
+            }
+            data.push(0x01);
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Vec<Amf0Value> {
+        Amf3Decoder::new(data).decode_all()
+    }
+
+    fn string(s: &str) -> Amf0Value {
+        Amf0Value::String(s.to_string())
+    }
+
+    fn number(n: f64) -> Amf0Value {
+        Amf0Value::Number(n)
+    }
+
+    fn pairs(items: &[(&str, Amf0Value)]) -> Vec<(String, Amf0Value)> {
+        items.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+
+    #[test]
+    fn u29_integers_at_each_length_boundary() {
+        let data = [
+            0x04, 0x7F,
+            0x04, 0x81, 0x00,
+            0x04, 0xFF, 0x7F,
+            0x04, 0x81, 0x80, 0x00,
+            0x04, 0xFF, 0xFF, 0x7F,
+            // The fourth byte carries all 8 bits
+            0x04, 0x80, 0xC0, 0x80, 0x00,
+            0x04, 0xBF, 0xFF, 0xFF, 0xFF,
+            // 29-bit two's complement
+            0x04, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x04, 0xC0, 0x80, 0x80, 0x00,
+        ];
+        let expected = [127.0, 128.0, 16383.0, 16384.0, 2097151.0, 2097152.0, 268435455.0, -1.0, -268435456.0];
+        assert_eq!(decode(&data), expected.map(number));
+    }
+
+    #[test]
+    fn truncated_u29_stops_decoding() {
+        assert_eq!(decode(&[0x04, 0x7F, 0x04, 0xFF, 0xFF]), [number(127.0)]);
+    }
+
+    #[test]
+    fn strings_are_referenced_by_index_except_the_empty_one() {
+        let mut data = vec![0x06, 0x01, 0x06, 0x07];
+        data.extend_from_slice(b"abc");
+        data.extend_from_slice(&[0x06, 0x07]);
+        data.extend_from_slice(b"def");
+        data.extend_from_slice(&[0x06, 0x00, 0x06, 0x02, 0x06, 0x04]);
+        assert_eq!(decode(&data), [string(""), string("abc"), string("def"), string("abc"), string("def")]);
+    }
+
+    #[test]
+    fn typed_objects_share_traits_and_strings_by_reference() {
+        // class Pt with sealed members x and y, inline
+        let mut data = vec![0x0A, 0x23, 0x05];
+        data.extend_from_slice(b"Pt");
+        data.extend_from_slice(&[0x03, b'x', 0x03, b'y', 0x04, 0x01, 0x04, 0x02]);
+        // Traits reference 0
+        data.extend_from_slice(&[0x0A, 0x01, 0x04, 0x03, 0x04, 0x04]);
+        // String reference 1 ("x", after the class name) and object reference 0
+        data.extend_from_slice(&[0x06, 0x02, 0x0A, 0x00]);
+
+        let first = Amf0Value::TypedObject {
+            class_name: "Pt".to_string(),
+            properties: pairs(&[("x", number(1.0)), ("y", number(2.0))]),
+        };
+        let second = Amf0Value::TypedObject {
+            class_name: "Pt".to_string(),
+            properties: pairs(&[("x", number(3.0)), ("y", number(4.0))]),
+        };
+        assert_eq!(decode(&data), [first.clone(), second, string("x"), first]);
+    }
+
+    #[test]
+    fn dynamic_objects_follow_their_sealed_members() {
+        // Anonymous, dynamic, no sealed members: {name: "obs"}
+        let mut data = vec![0x0A, 0x0B, 0x01, 0x09];
+        data.extend_from_slice(b"name");
+        data.extend_from_slice(&[0x06, 0x07]);
+        data.extend_from_slice(b"obs");
+        data.push(0x01);
+        // Anonymous, dynamic, one sealed member: {a: true, b: false}
+        data.extend_from_slice(&[0x0A, 0x1B, 0x01, 0x03, b'a', 0x03, 0x03, b'b', 0x02, 0x01]);
+
+        assert_eq!(
+            decode(&data),
+            [
+                Amf0Value::Object(pairs(&[("name", string("obs"))])),
+                Amf0Value::Object(pairs(&[("a", Amf0Value::Boolean(true)), ("b", Amf0Value::Boolean(false))])),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_with_an_associative_part_become_ecma_arrays() {
+        // Two dense items after {k: 7}
+        let mut data = vec![0x09, 0x05, 0x03, b'k', 0x04, 0x07, 0x01, 0x04, 0x01, 0x06, 0x03, b'x'];
+        // Dense only
+        data.extend_from_slice(&[0x09, 0x05, 0x01, 0x04, 0x01, 0x04, 0x02]);
+
+        assert_eq!(
+            decode(&data),
+            [
+                Amf0Value::EcmaArray(pairs(&[("k", number(7.0)), ("0", number(1.0)), ("1", string("x"))])),
+                Amf0Value::StrictArray(vec![number(1.0), number(2.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn dates_decode_and_are_referenced_as_objects() {
+        let mut data = vec![0x08, 0x01];
+        data.extend_from_slice(&1_700_000_000_000f64.to_be_bytes());
+        data.extend_from_slice(&[0x08, 0x00]);
+        let date = Amf0Value::Date { millis: 1_700_000_000_000.0, tz: 0 };
+        assert_eq!(decode(&data), [date.clone(), date]);
+    }
+
+    #[test]
+    fn byte_arrays_are_skipped() {
+        let data = [0x0C, 0x07, 0xDE, 0xAD, 0xBE, 0x0C, 0x00, 0x04, 0x05];
+        assert_eq!(decode(&data), [Amf0Value::Undefined, Amf0Value::Undefined, number(5.0)]);
+    }
+
+    #[test]
+    fn references_stop_copying_past_the_budget() {
+        let data = doubling_references(60);
+        let start = std::time::Instant::now();
+        let values = Amf3Decoder::new(&data).decode_all();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        assert_eq!(values.len(), 60);
+        let total: usize = values.iter().map(Amf0Value::decoded_size).sum();
+        assert!(total <= MAX_REFERENCED_SIZE + data.len(), "decoded {} units", total);
+        assert_eq!(values[3].get_property("b").map(Amf0Value::decoded_size), Some(values[2].decoded_size()));
+        assert!(matches!(values[59].get_property("a"), Some(Amf0Value::Undefined)));
+    }
+}
@@ -1,37 +1,154 @@
 use std::collections::HashMap;
 
+/// Largest chunk size we accept from a peer. The spec allows up to 0x7FFFFFFF,
+/// but nothing legitimate comes close to this.
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Default cap on a reassembled message; video and aggregate messages get twice this.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Validate a peer-requested chunk size: the high bit, which the spec
+/// reserves as zero, is dropped, then 0 is ignored and oversized values are capped.
+pub fn sanitize_chunk_size(requested: u32) -> Option<u32> {
+    match requested & 0x7FFF_FFFF {
+        0 => None,
+        n => Some(n.min(MAX_CHUNK_SIZE)),
+    }
+}
+
 /// A fully reassembled RTMP message.
 #[derive(Debug, Clone)]
 pub struct RtmpMessage {
+    /// Chunk stream the message arrived on; 0 if it didn't come in chunks
+    pub chunk_stream_id: u32,
     pub timestamp: u32,
     pub type_id: u8,
     pub stream_id: u32,
     pub payload: Vec<u8>,
 }
 
-/// Per-chunk-stream state for reassembly.
+/// A message whose declared length exceeded the limit and was skipped.
 #[derive(Debug, Clone)]
+pub struct RejectedMessage {
+    pub type_id: u8,
+    pub length: u32,
+}
+
+/// Upper bounds of the chunk size buckets in `ChunkStreamStats::chunk_sizes`;
+/// a last bucket takes everything larger.
+pub const CHUNK_SIZE_BUCKETS: [usize; 4] = [128, 1024, 4096, 65536];
+
+/// How the peer has framed one chunk stream, for debugging odd encoders.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkStreamStats {
+    pub cs_id: u32,
+    /// Chunks received with each header format, indexed by fmt
+    pub header_formats: [u64; 4],
+    /// Chunks carrying the 4-byte extended timestamp field
+    pub extended_timestamps: u64,
+    /// Chunk payload sizes, bucketed by `CHUNK_SIZE_BUCKETS`
+    pub chunk_sizes: [u64; CHUNK_SIZE_BUCKETS.len() + 1],
+}
+
+impl ChunkStreamStats {
+    pub fn chunks(&self) -> u64 {
+        self.header_formats.iter().sum()
+    }
+
+    fn record(&mut self, fmt: u8, extended_timestamp: bool, size: usize) {
+        self.header_formats[fmt as usize] += 1;
+        if extended_timestamp {
+            self.extended_timestamps += 1;
+        }
+        let bucket = CHUNK_SIZE_BUCKETS
+            .iter()
+            .position(|&bound| size <= bound)
+            .unwrap_or(CHUNK_SIZE_BUCKETS.len());
+        self.chunk_sizes[bucket] += 1;
+    }
+}
+
+/// How far either side of a timestamp counter's wrap point a backward step
+/// may land and still count as the counter wrapping, in ms
+const TIMESTAMP_WRAP_WINDOW: u32 = 60_000;
+
+/// How a message timestamp moved from the previous one of its kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampStep {
+    /// Forward or unchanged, or the first message of its kind
+    Forward,
+    /// Back to near zero from near 0xFFFFFF (encoders that never switch to
+    /// extended timestamps) or from near the end of the 32-bit range
+    Wraparound,
+    /// Genuinely backward from `previous`
+    Regression { previous: u32 },
+}
+
+/// Checks that audio, video and aggregate message timestamps only move
+/// forward on each chunk stream, before any media parsing. Each message
+/// type is judged on its own since some encoders interleave audio and video
+/// on one chunk stream with only loosely ordered timestamps.
+pub struct TimestampMonotonicity {
+    /// Last timestamp by (chunk stream id, type id, message stream id)
+    last: HashMap<(u32, u8, u32), u32>,
+}
+
+impl TimestampMonotonicity {
+    pub fn new() -> Self {
+        Self { last: HashMap::new() }
+    }
+
+    pub fn check(&mut self, msg: &RtmpMessage) -> TimestampStep {
+        if !matches!(msg.type_id, 8 | 9 | 22) {
+            return TimestampStep::Forward;
+        }
+        let key = (msg.chunk_stream_id, msg.type_id, msg.stream_id);
+        let Some(previous) = self.last.insert(key, msg.timestamp) else {
+            return TimestampStep::Forward;
+        };
+        if msg.timestamp >= previous {
+            TimestampStep::Forward
+        } else if is_wraparound(previous, msg.timestamp) {
+            TimestampStep::Wraparound
+        } else {
+            TimestampStep::Regression { previous }
+        }
+    }
+
+    /// Forget a message stream's timestamps, as when it is published again
+    /// and starts over from zero.
+    pub fn reset_stream(&mut self, stream_id: u32) {
+        self.last.retain(|&(_, _, id), _| id != stream_id);
+    }
+}
+
+impl Default for TimestampMonotonicity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_wraparound(previous: u32, timestamp: u32) -> bool {
+    let near_wrap_point = |max: u32| previous <= max && previous > max - TIMESTAMP_WRAP_WINDOW;
+    timestamp < TIMESTAMP_WRAP_WINDOW && (near_wrap_point(0xFFFFFF) || near_wrap_point(u32::MAX))
+}
+
+/// Per-chunk-stream state for reassembly.
+#[derive(Debug, Clone, Default)]
 struct ChunkStreamState {
     timestamp: u32,
     timestamp_delta: u32,
     message_length: u32,
     type_id: u8,
     stream_id: u32,
+    // Whether the last fmt 0/1/2 header escaped its timestamp with 0xFFFFFF;
+    // fmt 3 chunks on this stream then carry the 4-byte extended field too
+    extended_timestamp_present: bool,
     // Accumulation buffer for the current message being reassembled
     buffer: Vec<u8>,
-}
-
-impl Default for ChunkStreamState {
-    fn default() -> Self {
-        Self {
-            timestamp: 0,
-            timestamp_delta: 0,
-            message_length: 0,
-            type_id: 0,
-            stream_id: 0,
-            buffer: Vec::new(),
-        }
-    }
+    // Set while skipping the chunks of an oversized message, so framing stays intact
+    discarding: bool,
+    bytes_discarded: usize,
 }
 
 /// Reads RTMP chunks from a byte buffer and reassembles them into messages.
@@ -39,6 +156,11 @@ pub struct ChunkReader {
     states: HashMap<u32, ChunkStreamState>,
     max_chunk_size: usize,
     buf: Vec<u8>,
+    // Start of unconsumed data in `buf`; the consumed prefix is compacted lazily
+    read_pos: usize,
+    max_message_size: usize,
+    rejected: Vec<RejectedMessage>,
+    stats: HashMap<u32, ChunkStreamStats>,
 }
 
 impl ChunkReader {
@@ -47,11 +169,45 @@ impl ChunkReader {
             states: HashMap::new(),
             max_chunk_size: 128,
             buf: Vec::with_capacity(65536),
+            read_pos: 0,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            rejected: Vec::new(),
+            stats: HashMap::new(),
         }
     }
 
+    /// Cap the size of messages; video and aggregate messages, which carry
+    /// whole keyframes, may be twice as large.
+    pub fn set_max_message_size(&mut self, bytes: usize) {
+        self.max_message_size = bytes;
+    }
+
+    /// Messages skipped for exceeding the size limit since the last call.
+    pub fn take_rejected(&mut self) -> Vec<RejectedMessage> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// Header and size tallies of every chunk stream seen, by chunk stream id.
+    pub fn chunk_stream_stats(&self) -> Vec<ChunkStreamStats> {
+        let mut stats: Vec<_> = self.stats.values().cloned().collect();
+        stats.sort_by_key(|s| s.cs_id);
+        stats
+    }
+
     pub fn set_chunk_size(&mut self, size: u32) {
-        self.max_chunk_size = size as usize;
+        if let Some(size) = sanitize_chunk_size(size) {
+            self.max_chunk_size = size as usize;
+        }
+    }
+
+    /// Drop the partly received message on chunk stream `cs_id`, so its next
+    /// chunk starts a new one. The header state stays for later chunks.
+    pub fn abort_message(&mut self, cs_id: u32) {
+        if let Some(state) = self.states.get_mut(&cs_id) {
+            state.buffer.clear();
+            state.discarding = false;
+            state.bytes_discarded = 0;
+        }
     }
 
     /// Append incoming bytes to the internal buffer.
@@ -64,27 +220,52 @@ impl ChunkReader {
     pub fn read_messages(&mut self) -> Vec<RtmpMessage> {
         let mut messages = Vec::new();
 
-        loop {
-            match self.try_read_chunk() {
-                Some(msg) => {
-                    if let Some(m) = msg {
-                        messages.push(m);
+        // Each consumed chunk may or may not complete a message; stop when
+        // there isn't enough data left for another chunk.
+        while let Some(msg) = self.try_read_chunk() {
+            if let Some(m) = msg {
+                // Set Chunk Size and Abort govern the very next chunk, which may
+                // already be in the buffer, so apply them here rather than
+                // waiting for the handler
+                if matches!(m.type_id, 1 | 2) && m.payload.len() >= 4 {
+                    let value = u32::from_be_bytes([
+                        m.payload[0],
+                        m.payload[1],
+                        m.payload[2],
+                        m.payload[3],
+                    ]);
+                    if m.type_id == 1 {
+                        self.set_chunk_size(value);
+                    } else {
+                        self.abort_message(value);
                     }
-                    // Chunk was consumed, possibly produced a message; try again
                 }
-                None => break, // Not enough data
+                messages.push(m);
             }
         }
 
+        self.compact();
         messages
     }
 
+    /// Drop consumed bytes once they make up most of the buffer, so the memmove
+    /// happens once per read rather than once per chunk.
+    fn compact(&mut self) {
+        if self.read_pos == self.buf.len() {
+            self.buf.clear();
+            self.read_pos = 0;
+        } else if self.read_pos > self.buf.len() / 2 {
+            self.buf.drain(..self.read_pos);
+            self.read_pos = 0;
+        }
+    }
+
     /// Try to read one chunk. Returns:
     /// - Some(Some(msg)) if a chunk was read and completed a message
     /// - Some(None) if a chunk was read but message is still incomplete
-    /// - None if there's not enough data to read a chunk
+    /// - None if there's not enough data to read a chunk (nothing is consumed)
     fn try_read_chunk(&mut self) -> Option<Option<RtmpMessage>> {
-        let mut pos = 0;
+        let mut pos = self.read_pos;
 
         if pos >= self.buf.len() {
             return None;
@@ -132,23 +313,24 @@ impl ChunkReader {
             return None;
         }
 
+        let max_message_size = self.max_message_size;
         let state = self.states.entry(cs_id).or_default();
 
-        #[allow(unused_assignments)]
+        // Parse into locals first: if the chunk turns out to be incomplete we
+        // return without touching the stream state, and re-parse it next time.
+        let mut message_length = state.message_length;
+        let mut type_id = state.type_id;
+        let mut stream_id = state.stream_id;
         let mut timestamp_field: u32 = 0;
 
         match fmt {
             0 => {
                 // Full header: timestamp(3) + message_length(3) + type_id(1) + stream_id(4, little-endian)
-                timestamp_field = (self.buf[pos] as u32) << 16
-                    | (self.buf[pos + 1] as u32) << 8
-                    | self.buf[pos + 2] as u32;
-                state.message_length = (self.buf[pos + 3] as u32) << 16
-                    | (self.buf[pos + 4] as u32) << 8
-                    | self.buf[pos + 5] as u32;
-                state.type_id = self.buf[pos + 6];
+                timestamp_field = read_u24(&self.buf[pos..]);
+                message_length = read_u24(&self.buf[pos + 3..]);
+                type_id = self.buf[pos + 6];
                 // Stream ID is little-endian
-                state.stream_id = u32::from_le_bytes([
+                stream_id = u32::from_le_bytes([
                     self.buf[pos + 7],
                     self.buf[pos + 8],
                     self.buf[pos + 9],
@@ -158,35 +340,28 @@ impl ChunkReader {
             }
             1 => {
                 // timestamp_delta(3) + message_length(3) + type_id(1)
-                timestamp_field = (self.buf[pos] as u32) << 16
-                    | (self.buf[pos + 1] as u32) << 8
-                    | self.buf[pos + 2] as u32;
-                state.message_length = (self.buf[pos + 3] as u32) << 16
-                    | (self.buf[pos + 4] as u32) << 8
-                    | self.buf[pos + 5] as u32;
-                state.type_id = self.buf[pos + 6];
+                timestamp_field = read_u24(&self.buf[pos..]);
+                message_length = read_u24(&self.buf[pos + 3..]);
+                type_id = self.buf[pos + 6];
                 pos += 7;
             }
             2 => {
                 // timestamp_delta(3) only
-                timestamp_field = (self.buf[pos] as u32) << 16
-                    | (self.buf[pos + 1] as u32) << 8
-                    | self.buf[pos + 2] as u32;
+                timestamp_field = read_u24(&self.buf[pos..]);
                 pos += 3;
             }
             3 => {
-                // No header bytes — reuse everything
-                // For fmt 3, we reuse the previous timestamp delta
-                timestamp_field = state.timestamp_delta;
+                // No header bytes — reuse everything, including the delta
             }
             _ => unreachable!(),
         }
 
         // ── Extended Timestamp ──
-        let has_extended = if fmt == 0 {
-            timestamp_field == 0xFFFFFF
+        // fmt 0/1/2 signal it in their own header; fmt 3 inherits whatever
+        // the chunk stream's last full header signalled
+        let has_extended = if fmt == 3 {
+            state.extended_timestamp_present
         } else {
-            // For fmt 1/2/3, extended timestamp present if the stored delta was 0xFFFFFF
             timestamp_field == 0xFFFFFF
         };
 
@@ -201,49 +376,98 @@ impl ChunkReader {
                 self.buf[pos + 3],
             ]);
             pos += 4;
-            timestamp_field = ext;
+            // A fmt 3 chunk repeats the stored value, which is already applied
+            if fmt != 3 {
+                timestamp_field = ext;
+            }
+        }
+
+        // ── Chunk Data ──
+        let received = if state.discarding {
+            state.bytes_discarded
+        } else {
+            state.buffer.len()
+        };
+        // The chunk size is read per chunk, so a Set Chunk Size arriving
+        // between two chunks of a message governs the rest of that message
+        let remaining_in_message = (message_length as usize).saturating_sub(received);
+        let chunk_data_size = remaining_in_message.min(self.max_chunk_size);
+
+        if pos + chunk_data_size > self.buf.len() {
+            return None;
         }
 
+        // The whole chunk is available; commit the header to the stream state
+        self.stats
+            .entry(cs_id)
+            .or_insert_with(|| ChunkStreamStats { cs_id, ..Default::default() })
+            .record(fmt, has_extended, chunk_data_size);
+        state.message_length = message_length;
+        state.type_id = type_id;
+        state.stream_id = stream_id;
+        if fmt != 3 {
+            state.extended_timestamp_present = has_extended;
+        }
+
+        // Continuation chunks of a message in progress must not advance the timestamp
+        let starts_message = received == 0;
+
         // Update timestamp
         match fmt {
             0 => {
+                // A fmt 3 message following a fmt 0 one reuses its timestamp as the delta
                 state.timestamp = timestamp_field;
-                state.timestamp_delta = 0;
+                state.timestamp_delta = timestamp_field;
             }
             1 | 2 => {
                 state.timestamp_delta = timestamp_field;
                 state.timestamp = state.timestamp.wrapping_add(timestamp_field);
             }
-            3 => {
+            3 if starts_message => {
                 state.timestamp = state.timestamp.wrapping_add(state.timestamp_delta);
             }
             _ => {}
         }
 
-        // Start a new message buffer if this is the first chunk of a message
-        if state.buffer.is_empty() && state.message_length > 0 {
-            state.buffer.reserve(state.message_length as usize);
+        if starts_message {
+            let limit = if matches!(state.type_id, 9 | 22) {
+                max_message_size.saturating_mul(2)
+            } else {
+                max_message_size
+            };
+            if state.message_length as usize > limit {
+                // Most likely a desynced or hostile peer: skip the declared
+                // length without buffering it rather than allocating up front
+                state.discarding = true;
+                self.rejected.push(RejectedMessage {
+                    type_id: state.type_id,
+                    length: state.message_length,
+                });
+            } else if state.message_length > 0 {
+                // Start a new message buffer
+                state.buffer.reserve(state.message_length as usize);
+            }
         }
 
-        // ── Chunk Data ──
-        let remaining_in_message = (state.message_length as usize).saturating_sub(state.buffer.len());
-        let chunk_data_size = remaining_in_message.min(self.max_chunk_size);
+        // Consume the bytes we're about to process
+        let data = &self.buf[pos..pos + chunk_data_size];
+        self.read_pos = pos + chunk_data_size;
 
-        if pos + chunk_data_size > self.buf.len() {
-            return None;
+        if state.discarding {
+            state.bytes_discarded += data.len();
+            if state.bytes_discarded >= state.message_length as usize {
+                state.discarding = false;
+                state.bytes_discarded = 0;
+            }
+            return Some(None);
         }
 
-        state
-            .buffer
-            .extend_from_slice(&self.buf[pos..pos + chunk_data_size]);
-        pos += chunk_data_size;
-
-        // Consume the bytes we've processed
-        self.buf.drain(..pos);
+        state.buffer.extend_from_slice(data);
 
         // Check if message is complete
         if state.buffer.len() >= state.message_length as usize {
             let msg = RtmpMessage {
+                chunk_stream_id: cs_id,
                 timestamp: state.timestamp,
                 type_id: state.type_id,
                 stream_id: state.stream_id,
@@ -256,6 +480,17 @@ impl ChunkReader {
     }
 }
 
+impl Default for ChunkReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read a 24-bit big-endian integer.
+fn read_u24(b: &[u8]) -> u32 {
+    (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32
+}
+
 /// Writes RTMP messages as chunks.
 pub struct ChunkWriter {
     chunk_size: usize,
@@ -311,9 +546,9 @@ impl ChunkWriter {
     }
 
     fn write_basic_header(&self, out: &mut Vec<u8>, fmt: u8, cs_id: u32) {
-        if cs_id >= 2 && cs_id <= 63 {
+        if (2..=63).contains(&cs_id) {
             out.push((fmt << 6) | cs_id as u8);
-        } else if cs_id >= 64 && cs_id <= 319 {
+        } else if (64..=319).contains(&cs_id) {
             out.push(fmt << 6); // cs_id_low = 0
             out.push((cs_id - 64) as u8);
         } else {
@@ -358,3 +593,289 @@ impl ChunkWriter {
         }
     }
 }
+
+impl Default for ChunkWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader that has been told the chunk size `ChunkWriter` uses.
+    fn reader_for_writer() -> ChunkReader {
+        let mut reader = ChunkReader::new();
+        reader.set_chunk_size(4096);
+        reader
+    }
+
+    /// Basic header plus a fmt 0 message header, escaping large timestamps.
+    fn fmt0_header(cs_id: u8, timestamp: u32, length: usize, type_id: u8, stream_id: u32) -> Vec<u8> {
+        let mut out = vec![cs_id];
+        out.extend_from_slice(&timestamp.min(0xFFFFFF).to_be_bytes()[1..]);
+        out.extend_from_slice(&(length as u32).to_be_bytes()[1..]);
+        out.push(type_id);
+        out.extend_from_slice(&stream_id.to_le_bytes());
+        if timestamp >= 0xFFFFFF {
+            out.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        out
+    }
+
+    fn set_chunk_size_message(size: u32) -> Vec<u8> {
+        let mut out = fmt0_header(2, 0, 4, 1, 0);
+        out.extend_from_slice(&size.to_be_bytes());
+        out
+    }
+
+    /// Feed `data` in slices of varying sizes, as socket reads would arrive.
+    fn read_unevenly(reader: &mut ChunkReader, data: &[u8]) -> Vec<RtmpMessage> {
+        let mut messages = Vec::new();
+        for (i, slice) in data.chunks(65_537).enumerate() {
+            let cut = (i * 7919) % slice.len();
+            for part in [&slice[..cut], &slice[cut..]] {
+                reader.extend(part);
+                messages.extend(reader.read_messages());
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn large_keyframe_survives_a_chunk_size_change_mid_message() {
+        // 12MB of bytes that differ from chunk to chunk
+        let payload: Vec<u8> = (0..12 * 1024 * 1024u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut data = set_chunk_size_message(4096);
+        data.extend(fmt0_header(6, 40, payload.len(), 9, 1));
+        let mut offset = 0;
+        let mut chunk_size = 4096;
+        let mut chunks = 0;
+        while offset < payload.len() {
+            if chunks > 0 {
+                data.push(0xC0 | 6);
+            }
+            let end = (offset + chunk_size).min(payload.len());
+            data.extend_from_slice(&payload[offset..end]);
+            offset = end;
+            chunks += 1;
+            // Between two chunks of the keyframe, on the control chunk stream
+            match chunks {
+                1000 => {
+                    data.extend(set_chunk_size_message(65_536));
+                    chunk_size = 65_536;
+                }
+                1050 => {
+                    data.extend(set_chunk_size_message(1000));
+                    chunk_size = 1000;
+                }
+                _ => {}
+            }
+        }
+        assert!(chunks > 500);
+
+        let mut reader = ChunkReader::new();
+        let messages = read_unevenly(&mut reader, &data);
+        assert!(reader.take_rejected().is_empty());
+        let sizes: Vec<_> = messages.iter().filter(|m| m.type_id == 1).map(|m| m.payload.clone()).collect();
+        assert_eq!(sizes, [4096u32, 65_536, 1000].map(|n| n.to_be_bytes().to_vec()));
+        let video: Vec<_> = messages.iter().filter(|m| m.type_id == 9).collect();
+        assert_eq!(video.len(), 1);
+        assert_eq!((video[0].timestamp, video[0].stream_id), (40, 1));
+        assert!(video[0].payload == payload, "reassembled keyframe differs");
+    }
+
+    /// `header` for the first chunk, then fmt 3 continuations of 128 bytes on
+    /// chunk stream 6, each repeating the `extended` timestamp if given.
+    fn chunked(header: Vec<u8>, payload: &[u8], extended: Option<u32>) -> Vec<u8> {
+        let mut out = header;
+        for (i, chunk) in payload.chunks(128).enumerate() {
+            if i > 0 {
+                out.push(0xC0 | 6);
+                if let Some(timestamp) = extended {
+                    out.extend_from_slice(&timestamp.to_be_bytes());
+                }
+            }
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn fmt3_chunks_follow_the_extended_timestamp_of_their_stream() {
+        let payload: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+        let mut data = Vec::new();
+        // Just below the escape: continuations carry no extended field
+        data.extend(chunked(fmt0_header(6, 0xFFFFF0, payload.len(), 9, 1), &payload, None));
+        // Past it: the header escapes and every continuation repeats the field
+        data.extend(chunked(fmt0_header(6, 0x1000010, payload.len(), 9, 1), &payload, Some(0x1000010)));
+        // A small delta needs no escape, so continuations stop carrying it
+        let mut fmt1 = vec![0x40 | 6, 0x00, 0x00, 33];
+        fmt1.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        fmt1.push(9);
+        data.extend(chunked(fmt1, &payload, None));
+        // Again past 0xFFFFFF on a fresh absolute timestamp
+        data.extend(chunked(fmt0_header(6, 0x2000000, payload.len(), 9, 1), &payload, Some(0x2000000)));
+
+        let mut reader = ChunkReader::new();
+        let messages = read_unevenly(&mut reader, &data);
+        let timestamps: Vec<_> = messages.iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, [0xFFFFF0, 0x1000010, 0x1000031, 0x2000000]);
+        assert!(messages.iter().all(|m| m.payload == payload));
+        let stats = reader.chunk_stream_stats();
+        // Two escaped headers with two continuations each
+        assert_eq!(stats[0].extended_timestamps, 6);
+    }
+
+    /// Time reading `data` in `read_size` slices, checking every message came out.
+    fn time_reads(data: &[u8], read_size: usize, expected: usize) -> std::time::Duration {
+        let mut reader = ChunkReader::new();
+        let start = std::time::Instant::now();
+        let mut messages = 0;
+        for slice in data.chunks(read_size) {
+            reader.extend(slice);
+            messages += reader.read_messages().len();
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(messages, expected);
+        elapsed
+    }
+
+    /// Reading cost should grow with the input and not with how much of it
+    /// sits in the buffer at once. Run with
+    /// `cargo test --release -- --ignored chunk_reader_time_is_linear --nocapture`.
+    #[test]
+    #[ignore]
+    fn chunk_reader_time_is_linear() {
+        // 4KB video messages in 128-byte chunks, about 100MB in all
+        let payload = vec![0x5A; 4096];
+        let message = chunked(fmt0_header(6, 0, payload.len(), 9, 1), &payload, None);
+        let count = 100 * 1024 * 1024 / message.len();
+        let data = message.repeat(count);
+
+        let tenth = time_reads(&data[..message.len() * (count / 10)], 65_536, count / 10);
+        let full = time_reads(&data, 65_536, count);
+        let big_reads = time_reads(&data, 4 * 1024 * 1024, count);
+        let mb_per_sec = |elapsed: std::time::Duration| data.len() as f64 / 1e6 / elapsed.as_secs_f64();
+        println!("10MB: {:?}, 100MB: {:?} ({:.0} MB/s), 100MB in 4MB reads: {:?} ({:.0} MB/s)",
+            tenth, full, mb_per_sec(full), big_reads, mb_per_sec(big_reads));
+        // Ten times the input takes about ten times as long
+        assert!(full < tenth * 20, "100MB took {:?}, 10MB {:?}", full, tenth);
+        // Per-chunk draining would make 64x larger reads about 64x slower
+        assert!(big_reads < full * 4, "4MB reads took {:?}, 64KB reads {:?}", big_reads, full);
+    }
+
+    #[test]
+    fn requested_chunk_sizes_are_sanitized() {
+        assert_eq!(sanitize_chunk_size(0), None);
+        assert_eq!(sanitize_chunk_size(4096), Some(4096));
+        assert_eq!(sanitize_chunk_size(MAX_CHUNK_SIZE), Some(MAX_CHUNK_SIZE));
+        assert_eq!(sanitize_chunk_size(MAX_CHUNK_SIZE + 1), Some(MAX_CHUNK_SIZE));
+        assert_eq!(sanitize_chunk_size(0x7FFF_FFFF), Some(MAX_CHUNK_SIZE));
+        assert_eq!(sanitize_chunk_size(0x8000_1000), Some(4096));
+        assert_eq!(sanitize_chunk_size(0x8000_0000), None);
+
+        let mut reader = ChunkReader::new();
+        reader.set_chunk_size(0);
+        assert_eq!(reader.max_chunk_size, 128);
+        reader.set_chunk_size(u32::MAX);
+        assert_eq!(reader.max_chunk_size, MAX_CHUNK_SIZE as usize);
+    }
+
+    #[test]
+    fn a_new_chunk_size_applies_to_chunks_in_the_same_read() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut data = set_chunk_size_message(1000);
+        data.extend(fmt0_header(6, 0, payload.len(), 9, 1));
+        data.extend_from_slice(&payload);
+        // A zero size is ignored, so the next message still uses 1000
+        data.extend(set_chunk_size_message(0));
+        data.extend(fmt0_header(6, 33, payload.len(), 9, 1));
+        data.extend_from_slice(&payload);
+
+        let mut reader = ChunkReader::new();
+        reader.extend(&data);
+        let messages = reader.read_messages();
+        let video: Vec<_> = messages.iter().filter(|m| m.type_id == 9).collect();
+        assert_eq!(video.len(), 2);
+        assert!(video.iter().all(|m| m.payload == payload));
+        assert_eq!(reader.read_pos, 0, "all input consumed");
+    }
+
+    #[test]
+    fn abort_drops_the_partial_message_on_its_chunk_stream() {
+        let payload: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+        // The first 128-byte chunk of a 300-byte message, then an Abort for chunk stream 6
+        let mut data = fmt0_header(6, 0, payload.len(), 9, 1);
+        data.extend_from_slice(&payload[..128]);
+        data.extend(fmt0_header(2, 0, 4, 2, 0));
+        data.extend_from_slice(&6u32.to_be_bytes());
+        // A fresh message on the same chunk stream
+        data.extend(chunked(fmt0_header(6, 40, payload.len(), 9, 1), &payload, None));
+
+        let mut reader = ChunkReader::new();
+        let messages = read_unevenly(&mut reader, &data);
+        let types: Vec<_> = messages.iter().map(|m| m.type_id).collect();
+        assert_eq!(types, [2, 9]);
+        assert_eq!(messages[1].timestamp, 40);
+        assert!(messages[1].payload == payload, "partial payload leaked into the next message");
+    }
+
+    fn media(type_id: u8, stream_id: u32, timestamp: u32) -> RtmpMessage {
+        RtmpMessage { chunk_stream_id: 6, timestamp, type_id, stream_id, payload: Vec::new() }
+    }
+
+    #[test]
+    fn backward_media_timestamps_are_regressions() {
+        let mut timestamps = TimestampMonotonicity::new();
+        assert_eq!(timestamps.check(&media(9, 1, 1000)), TimestampStep::Forward);
+        assert_eq!(timestamps.check(&media(9, 1, 1000)), TimestampStep::Forward);
+        assert_eq!(timestamps.check(&media(9, 1, 900)), TimestampStep::Regression { previous: 1000 });
+        // Audio on the same chunk stream is judged on its own
+        assert_eq!(timestamps.check(&media(8, 1, 500)), TimestampStep::Forward);
+        // Commands aren't checked at all
+        assert_eq!(timestamps.check(&media(20, 1, 0)), TimestampStep::Forward);
+    }
+
+    #[test]
+    fn timestamps_wrapping_around_are_not_regressions() {
+        let mut timestamps = TimestampMonotonicity::new();
+        timestamps.check(&media(9, 1, u32::MAX - 20));
+        assert_eq!(timestamps.check(&media(9, 1, 13)), TimestampStep::Wraparound);
+        // Encoders that never switch to extended timestamps wrap at 24 bits
+        timestamps.check(&media(8, 1, 0xFFFFFF - 20));
+        assert_eq!(timestamps.check(&media(8, 1, 10)), TimestampStep::Wraparound);
+        // Far from either wrap point, going back to zero is a regression
+        timestamps.check(&media(9, 1, 0x7FFF_FFFF));
+        assert_eq!(timestamps.check(&media(9, 1, 10)), TimestampStep::Regression { previous: 0x7FFF_FFFF });
+    }
+
+    #[test]
+    fn republished_streams_start_their_timestamps_over() {
+        let mut timestamps = TimestampMonotonicity::new();
+        timestamps.check(&media(9, 1, 60_000));
+        timestamps.check(&media(9, 2, 60_000));
+        timestamps.reset_stream(1);
+        assert_eq!(timestamps.check(&media(9, 1, 0)), TimestampStep::Forward);
+        assert_eq!(timestamps.check(&media(9, 2, 0)), TimestampStep::Regression { previous: 60_000 });
+    }
+
+    #[test]
+    fn aggregates_get_the_video_allowance() {
+        let mut reader = reader_for_writer();
+        reader.set_max_message_size(64 * 1024);
+        let writer = ChunkWriter::new();
+        let payload = vec![0xAB; 100 * 1024];
+        for type_id in [22, 20] {
+            reader.extend(&writer.write_message(6, 0, type_id, 1, &payload));
+        }
+
+        let messages = reader.read_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!((messages[0].type_id, messages[0].payload.len()), (22, payload.len()));
+        let rejected = reader.take_rejected();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!((rejected[0].type_id, rejected[0].length), (20, payload.len() as u32));
+    }
+}
@@ -1,43 +1,209 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::rtmp::sha256::{hmac_sha256, DIGEST_LEN};
 
 const HANDSHAKE_SIZE: usize = 1536;
 
+/// Mixed into the fallback filler seed
+const FILLER_SEED_MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+/// Bumped per handshake so connections within a clock tick still differ
+static HANDSHAKE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Where the S1/S2 filler bytes come from.
+#[derive(Debug, Clone, Copy)]
+enum Filler {
+    /// OS randomness, or a per-handshake xorshift seed without it
+    Random,
+    /// Xorshift from a fixed seed, so every response is byte-identical
+    #[cfg_attr(not(test), allow(dead_code))]
+    Seeded(u64),
+}
+
+/// Milliseconds on the server clock, for the S1 time field (wraps every
+/// ~49 days, as the field allows).
+fn server_timestamp() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u32)
+}
+
+// ── Complex (digest) handshake ──
+// C1/S1 are time[4] + version[4] + two 764-byte blocks. One block holds a
+// 32-byte HMAC digest at an offset derived from the block's first 4 bytes;
+// which block comes first depends on the scheme the client picked.
+
+/// Shared suffix of the Flash Player and Flash Media Server keys
+const KEY_SUFFIX: [u8; 32] = [
+    0xF0, 0xEE, 0xC2, 0x4A, 0x80, 0x68, 0xBE, 0xE8, 0x2E, 0x00, 0xD0, 0xD1, 0x02, 0x9E, 0x7E, 0x57,
+    0x6E, 0xEC, 0x5D, 0x2D, 0x29, 0x80, 0x6F, 0xAB, 0x93, 0xB8, 0xE6, 0x36, 0xCF, 0xEB, 0x31, 0xAE,
+];
+const FP_KEY_TEXT: &[u8] = b"Genuine Adobe Flash Player 001";
+const FMS_KEY_TEXT: &[u8] = b"Genuine Adobe Flash Media Server 001";
+
+/// Version advertised in S1 when answering a complex handshake
+const SERVER_VERSION: [u8; 4] = [0x04, 0x05, 0x00, 0x01];
+
+/// Where the digest block sits within C1/S1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DigestScheme {
+    /// Digest block first (offset base 8), key block second
+    DigestFirst,
+    /// Key block first, digest block second (offset base 772)
+    KeyFirst,
+}
+
+impl DigestScheme {
+    fn digest_offset(self, packet: &[u8]) -> usize {
+        let base = match self {
+            DigestScheme::DigestFirst => 8,
+            DigestScheme::KeyFirst => 772,
+        };
+        let sum: usize = packet[base..base + 4].iter().map(|&b| b as usize).sum();
+        base + 4 + sum % 728
+    }
+}
+
+/// HMAC over the packet with the 32 digest bytes at `offset` left out.
+fn packet_digest(packet: &[u8], offset: usize, key: &[u8]) -> [u8; DIGEST_LEN] {
+    hmac_sha256(key, &[&packet[..offset], &packet[offset + DIGEST_LEN..]])
+}
+
+/// Find which scheme (if any) the client used to sign C1, returning it with the digest.
+fn find_client_digest(c1: &[u8]) -> Option<(DigestScheme, [u8; DIGEST_LEN])> {
+    [DigestScheme::KeyFirst, DigestScheme::DigestFirst]
+        .into_iter()
+        .find_map(|scheme| {
+            let offset = scheme.digest_offset(c1);
+            let expected = packet_digest(c1, offset, FP_KEY_TEXT);
+            (c1[offset..offset + DIGEST_LEN] == expected).then_some((scheme, expected))
+        })
+}
+
+fn fms_key() -> Vec<u8> {
+    [FMS_KEY_TEXT, &KEY_SUFFIX].concat()
+}
+
+/// Filler for S1/S2, from /dev/urandom where there is one. Players don't
+/// validate it, so otherwise xorshift is plenty as long as the seed differs
+/// per handshake; it comes from the OS-seeded hasher keys, the clock and a
+/// counter.
+fn fill(out: &mut [u8], filler: Filler) {
+    let seed = match filler {
+        Filler::Seeded(seed) => seed,
+        Filler::Random if read_os_random(out) => return,
+        Filler::Random => {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(HANDSHAKE_COUNTER.fetch_add(1, Ordering::Relaxed));
+            hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos()));
+            hasher.finish() ^ FILLER_SEED_MIX
+        }
+    };
+    let mut x = seed | 1;
+    for b in out {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        *b = (x >> 24) as u8;
+    }
+}
+
+#[cfg(unix)]
+fn read_os_random(out: &mut [u8]) -> bool {
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(out)).is_ok()
+}
+
+#[cfg(not(unix))]
+fn read_os_random(_out: &mut [u8]) -> bool {
+    false
+}
+
+/// Build S1 + S2 for a client that signed C1 with `scheme`.
+fn complex_response(
+    scheme: DigestScheme,
+    client_digest: &[u8; DIGEST_LEN],
+    server_time: u32,
+    filler: Filler,
+) -> Vec<u8> {
+    let fms_key = fms_key();
+
+    // S1: time + version + random, with our digest spliced in at the scheme's offset
+    let mut s1 = vec![0u8; HANDSHAKE_SIZE];
+    fill(&mut s1[8..], filler);
+    s1[0..4].copy_from_slice(&server_time.to_be_bytes());
+    s1[4..8].copy_from_slice(&SERVER_VERSION);
+    let offset = scheme.digest_offset(&s1);
+    let digest = packet_digest(&s1, offset, FMS_KEY_TEXT);
+    s1[offset..offset + DIGEST_LEN].copy_from_slice(&digest);
+
+    // S2: random, signed with a key derived from the client's digest
+    let mut s2 = vec![0u8; HANDSHAKE_SIZE];
+    fill(&mut s2, filler);
+    let temp_key = hmac_sha256(&fms_key, &[client_digest]);
+    let sig_offset = HANDSHAKE_SIZE - DIGEST_LEN;
+    let signature = hmac_sha256(&temp_key, &[&s2[..sig_offset]]);
+    s2[sig_offset..].copy_from_slice(&signature);
+
+    [s1, s2].concat()
+}
+
 /// Performs the RTMP server-side handshake.
+/// Clients that sign C1 get a digest-signed S1/S2; everyone else gets the simple echo.
 /// Returns any remaining bytes that arrived after the handshake completed.
-pub async fn perform_handshake(stream: &mut TcpStream) -> Result<Vec<u8>, String> {
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "handshake", skip_all))]
+pub async fn perform_handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Vec<u8>, String> {
+    handshake_with(stream, server_timestamp(), Filler::Random).await
+}
+
+/// The handshake, with S1's time field and filler source supplied by the caller.
+async fn handshake_with<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    server_time: u32,
+    filler: Filler,
+) -> Result<Vec<u8>, String> {
     // ── Read C0 + C1 ──
     // C0: 1 byte (version, should be 3 but we accept anything)
-    // C1: 1536 bytes (timestamp[4] + zero[4] + random[1528])
+    // C1: 1536 bytes (timestamp[4] + zero/version[4] + random[1528])
     let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
     read_exact(stream, &mut c0c1).await?;
 
     let _version = c0c1[0]; // Typically 3; we accept any value for compatibility
     let c1 = &c0c1[1..];
 
-    // Extract client timestamp from C1
-    let _client_timestamp = u32::from_be_bytes([c1[0], c1[1], c1[2], c1[3]]);
-
     // ── Send S0 + S1 + S2 ──
     let mut response = Vec::with_capacity(1 + HANDSHAKE_SIZE * 2);
 
     // S0: version byte
     response.push(3u8);
 
-    // S1: our timestamp[4] + zero[4] + random[1528]
-    let server_timestamp: u32 = 0;
-    response.extend_from_slice(&server_timestamp.to_be_bytes());
-    response.extend_from_slice(&[0u8; 4]); // zero
-    // Fill random data (simple deterministic fill — doesn't need to be cryptographic)
-    for i in 0..1528 {
-        response.push((i % 256) as u8);
-    }
+    // A non-zero version field means the client may be using the digest scheme
+    let client_digest = if c1[4..8] != [0, 0, 0, 0] {
+        find_client_digest(c1)
+    } else {
+        None
+    };
 
-    // S2: echo client's C1 with our timestamp2
-    // S2 format: client_timestamp[4] + server_timestamp[4] + echo_of_c1_random[1528]
-    response.extend_from_slice(&c1[0..4]); // echo client timestamp
-    response.extend_from_slice(&server_timestamp.to_be_bytes()); // our timestamp2
-    response.extend_from_slice(&c1[8..]); // echo client random data
+    trace_event!(debug, digest = client_digest.is_some(), "answering {} handshake",
+        if client_digest.is_some() { "digest" } else { "simple" });
+    if let Some((scheme, digest)) = client_digest {
+        response.extend_from_slice(&complex_response(scheme, &digest, server_time, filler));
+    } else {
+        // S1: our timestamp[4] + zero[4] + random[1528]
+        response.extend_from_slice(&server_time.to_be_bytes());
+        response.extend_from_slice(&[0u8; 4]); // zero
+        let mut random = [0u8; HANDSHAKE_SIZE - 8];
+        fill(&mut random, filler);
+        response.extend_from_slice(&random);
+
+        // S2: echo client's C1 with our timestamp2
+        // S2 format: client_timestamp[4] + server_timestamp[4] + echo_of_c1_random[1528]
+        response.extend_from_slice(&c1[0..4]); // echo client timestamp
+        response.extend_from_slice(&server_time.to_be_bytes()); // our timestamp2
+        response.extend_from_slice(&c1[8..]); // echo client random data
+    }
 
     stream
         .write_all(&response)
@@ -70,7 +236,7 @@ pub async fn perform_handshake(stream: &mut TcpStream) -> Result<Vec<u8>, String
     Ok(remaining)
 }
 
-async fn read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), String> {
+async fn read_exact<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8]) -> Result<(), String> {
     let mut offset = 0;
     while offset < buf.len() {
         let n = stream
@@ -84,3 +250,74 @@ async fn read_exact(stream: &mut TcpStream, buf: &mut [u8]) -> Result<(), String
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// C0 + C1 signed the way Flash Player does, with `scheme`.
+    fn signed_c0c1(scheme: DigestScheme) -> Vec<u8> {
+        let mut c1: Vec<u8> = (0..HANDSHAKE_SIZE).map(|i| (i * 7 % 251) as u8).collect();
+        c1[4..8].copy_from_slice(&[0x80, 0x00, 0x07, 0x02]);
+        let offset = scheme.digest_offset(&c1);
+        let digest = packet_digest(&c1, offset, FP_KEY_TEXT);
+        c1[offset..offset + DIGEST_LEN].copy_from_slice(&digest);
+        [&[3u8][..], &c1].concat()
+    }
+
+    /// Run the server side against `c0c1` followed by C2 and `extra`, with
+    /// `filler` for S1/S2, returning S0 + S1 + S2 and what the handshake handed back.
+    async fn handshake(c0c1: &[u8], extra: &[u8], filler: Filler) -> (Vec<u8>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(16384);
+        client.write_all(c0c1).await.unwrap();
+        client.write_all(&[0u8; HANDSHAKE_SIZE]).await.unwrap();
+        client.write_all(extra).await.unwrap();
+
+        let remaining = handshake_with(&mut server, 0, filler).await.unwrap();
+        let mut response = vec![0u8; 1 + HANDSHAKE_SIZE * 2];
+        client.read_exact(&mut response).await.unwrap();
+        (response, remaining)
+    }
+
+    #[tokio::test]
+    async fn digest_handshake_is_signed_and_reproducible() {
+        let filler = Filler::Seeded(0x2545_F491_4F6C_DD1D);
+        for scheme in [DigestScheme::DigestFirst, DigestScheme::KeyFirst] {
+            let c0c1 = signed_c0c1(scheme);
+            let (_, client_digest) = find_client_digest(&c0c1[1..]).unwrap();
+            let (response, remaining) = handshake(&c0c1, b"connect", filler).await;
+            assert_eq!(response[0], 3);
+            assert_eq!(remaining, b"connect");
+
+            let s1 = &response[1..1 + HANDSHAKE_SIZE];
+            assert_eq!(s1[0..4], [0, 0, 0, 0]);
+            assert_eq!(s1[4..8], SERVER_VERSION);
+            let offset = scheme.digest_offset(s1);
+            assert_eq!(s1[offset..offset + DIGEST_LEN], packet_digest(s1, offset, FMS_KEY_TEXT));
+
+            let s2 = &response[1 + HANDSHAKE_SIZE..];
+            let temp_key = hmac_sha256(&fms_key(), &[&client_digest]);
+            let sig_offset = HANDSHAKE_SIZE - DIGEST_LEN;
+            assert_eq!(s2[sig_offset..], hmac_sha256(&temp_key, &[&s2[..sig_offset]]));
+
+            let (again, _) = handshake(&c0c1, b"", filler).await;
+            assert_eq!(again, response);
+        }
+    }
+
+    #[tokio::test]
+    async fn simple_handshake_echoes_c1() {
+        let mut c0c1 = vec![3u8; 1 + HANDSHAKE_SIZE];
+        c0c1[1..5].copy_from_slice(&[0, 0, 0x12, 0x34]);
+        c0c1[5..9].copy_from_slice(&[0, 0, 0, 0]);
+        let (response, remaining) = handshake(&c0c1, b"", Filler::Random).await;
+        assert!(remaining.is_empty());
+
+        let s1 = &response[1..1 + HANDSHAKE_SIZE];
+        assert_eq!(s1[4..8], [0, 0, 0, 0]);
+        let s2 = &response[1 + HANDSHAKE_SIZE..];
+        assert_eq!(s2[0..4], c0c1[1..5]);
+        assert_eq!(s2[4..8], s1[0..4]);
+        assert_eq!(s2[8..], c0c1[9..]);
+    }
+}
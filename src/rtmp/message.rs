@@ -1,45 +1,205 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::flv::tag::TagParser;
+use crate::log::SharedDump;
 use crate::rtmp::amf0::{Amf0Decoder, Amf0Encoder, Amf0Value};
-use crate::rtmp::chunk::{ChunkWriter, RtmpMessage};
+use crate::rtmp::chunk::{sanitize_chunk_size, ChunkWriter, RtmpMessage};
+
+/// Stream keys a client may publish with, shared by all connections
+pub type AllowedKeys = Arc<HashSet<String>>;
+
+/// Span the ingress rate is measured over
+const INGRESS_WINDOW: Duration = Duration::from_secs(2);
 
 /// Result of processing a single RTMP message.
 pub struct HandleResult {
     /// Bytes to send back to the client.
     pub responses: Vec<Vec<u8>>,
-    /// If a new chunk size was requested by the client.
-    pub new_chunk_size: Option<u32>,
-    /// Event raised for the connection handler.
-    pub event: Option<RtmpEvent>,
+    /// Events raised for the connection handler, in message order.
+    pub events: Vec<RtmpEvent>,
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum RtmpEvent {
     /// Client connected with app name
-    Connected { app_name: String },
-    /// Client started publishing
+    Connected {
+        app_name: String,
+        /// objectEncoding from the connect command, if it sent one
+        requested_encoding: Option<f64>,
+        /// Encoding we replied with: 0 (AMF0) or 3 (AMF3)
+        object_encoding: u8,
+    },
+    /// Client started publishing on a message stream
     Publishing {
         app_name: String,
         stream_key: String,
+        stream_id: u32,
     },
     /// Stream metadata received (onMetaData)
     Metadata {
+        stream_id: u32,
+        properties: Vec<(String, Amf0Value)>,
+    },
+    /// Stored metadata invalidated (@clearDataFrame)
+    MetadataCleared { stream_id: u32 },
+    /// Caption or subtitle text (onTextData)
+    TextData {
+        stream_id: u32,
+        timestamp: u32,
         properties: Vec<(String, Amf0Value)>,
     },
+    /// Cue point, e.g. an ad break marker (onCuePoint)
+    CuePoint {
+        stream_id: u32,
+        timestamp: u32,
+        properties: Vec<(String, Amf0Value)>,
+    },
+    /// Data message with a name we don't handle
+    UnknownData { stream_id: u32, name: String },
     /// Video data received
-    VideoData { timestamp: u32, data: Vec<u8> },
+    VideoData { stream_id: u32, timestamp: u32, data: Vec<u8> },
     /// Audio data received
-    AudioData { timestamp: u32, data: Vec<u8> },
-    /// Client disconnected / stream ended
-    StreamEnded,
+    AudioData { stream_id: u32, timestamp: u32, data: Vec<u8> },
+    /// A player asked to play a stream; we answer but have no media to send
+    Playing { stream_key: String, stream_id: u32 },
+    /// Publishing refused because the stream key isn't allowed
+    PublishRejected { stream_key: String, stream_id: u32 },
+    /// A published stream was unpublished or deleted
+    StreamEnded { stream_id: u32 },
+    /// A command decoded to fewer values than its handler reads
+    ShortCommand { stream_id: u32, command: ShortCommand },
+    /// Client sent a Set Chunk Size that was ignored (None) or capped
+    InvalidChunkSize { requested: u32, applied: Option<u32> },
+    /// Client answered our last Ping Request
+    PingResponse { rtt: Duration },
+    /// Publisher signalled it has no more data for now (StreamDry)
+    StreamDry { stream_id: u32 },
+    /// Client advertised the buffer it keeps for a stream (SetBufferLength)
+    BufferLength { stream_id: u32, buffer_ms: u32 },
+    /// Peer sent onStatus, onFCPublish or _error
+    Status { stream_id: u32, status: NetStatus },
+    /// An Aggregate message had tags whose sizes didn't add up
+    CorruptAggregate { stream_id: u32, back_pointer_mismatches: u32, skipped_bytes: usize },
+}
+
+/// The information object of an onStatus-style message.
+#[derive(Debug, Clone)]
+pub struct NetStatus {
+    /// "status", "warning" or "error"
+    pub level: String,
+    /// e.g. "NetStream.Publish.BadName"
+    pub code: String,
+    pub description: String,
+}
+
+impl NetStatus {
+    /// Read `level`, `code` and `description` from an info object. `_error`
+    /// replies often leave out the level, so the caller supplies one.
+    fn from_info(info: &Amf0Value, default_level: &str) -> Option<Self> {
+        let code = info.get_property("code").and_then(|v| v.as_str())?;
+        let field = |key| info.get_property(key).and_then(|v| v.as_str()).unwrap_or_default();
+        let level = field("level");
+        Some(Self {
+            level: if level.is_empty() { default_level } else { level }.to_ascii_lowercase(),
+            code: code.to_string(),
+            description: field("description").to_string(),
+        })
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == "error"
+    }
+}
+
+/// A command message that decoded to fewer values than it needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShortCommand {
+    /// The command name, empty if not even that decoded
+    pub name: String,
+    pub values: usize,
+    pub required: usize,
+    /// Payload bytes left after the last value that decoded. None left means
+    /// the client really sent a short command; some left means decoding
+    /// stopped on something malformed or unsupported.
+    pub undecoded_bytes: usize,
+}
+
+impl ShortCommand {
+    /// Values a command needs, counting its name and transaction id, for the
+    /// handlers below to find everything they read.
+    fn required_values(name: &str) -> usize {
+        match name {
+            "connect" => 3,
+            "publish" | "play" | "deleteStream" | "FCUnpublish" | "onStatus" | "onFCPublish" | "_error" => 4,
+            _ => 2,
+        }
+    }
+
+    fn check(values: &[Amf0Value], undecoded_bytes: usize) -> Option<Self> {
+        let name = values.first().and_then(|v| v.as_str()).unwrap_or_default();
+        let required = Self::required_values(name);
+        (values.len() < required).then(|| Self {
+            name: name.to_string(),
+            values: values.len(),
+            required,
+            undecoded_bytes,
+        })
+    }
+}
+
+/// How the client described itself in the connect command object.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectParams {
+    /// The ingest URL the encoder was configured with, e.g. "rtmp://host/live"
+    pub tc_url: Option<String>,
+    /// Client identification, e.g. "FMLE/3.0 (compatible; FMSc/1.0)"
+    pub flash_ver: Option<String>,
+    pub swf_url: Option<String>,
+    pub page_url: Option<String>,
+}
+
+impl ConnectParams {
+    fn from_command_object(obj: &Amf0Value) -> Self {
+        let field = |key| obj.get_property(key).and_then(|v| v.as_str()).map(str::to_string);
+        Self {
+            tc_url: field("tcUrl"),
+            flash_ver: field("flashVer"),
+            swf_url: field("swfUrl"),
+            page_url: field("pageUrl"),
+        }
+    }
 }
 
 pub struct MessageHandler {
     writer: ChunkWriter,
     app_name: String,
+    connect_params: ConnectParams,
     stream_key: String,
+    /// Message stream id handed out by the last createStream
+    last_stream_id: u32,
+    /// Streams currently publishing, with their stream keys
+    published: Vec<(u32, String)>,
+    /// Negotiated in connect: 0 for AMF0, 3 for AMF3
+    object_encoding: u8,
     window_ack_size: u32,
     bytes_received: u64,
     last_ack_sent: u64,
+    /// Reads within the ingress window, with their byte total
+    ingress_window: VecDeque<(Instant, usize)>,
+    ingress_window_bytes: usize,
+    /// Ping timestamps count milliseconds from here
+    ping_epoch: Instant,
+    /// Timestamp and send time of the ping still awaiting its response
+    pending_ping: Option<(u32, Instant)>,
+    missed_pings: u32,
+    /// Where to dump decoded command and data messages, with the label
+    /// naming this connection
+    dump: Option<(SharedDump, String)>,
+    /// Publishing with any other key is refused; None allows every key
+    allowed_keys: Option<AllowedKeys>,
 }
 
 impl MessageHandler {
@@ -47,24 +207,86 @@ impl MessageHandler {
         Self {
             writer: ChunkWriter::new(),
             app_name: String::new(),
+            connect_params: ConnectParams::default(),
             stream_key: String::new(),
+            last_stream_id: 0,
+            published: Vec::new(),
+            object_encoding: 0,
             window_ack_size: 2500000,
             bytes_received: 0,
             last_ack_sent: 0,
+            ingress_window: VecDeque::new(),
+            ingress_window_bytes: 0,
+            ping_epoch: Instant::now(),
+            pending_ping: None,
+            missed_pings: 0,
+            dump: None,
+            allowed_keys: None,
         }
     }
 
+    /// Write every command and data message to `dump` as readable text,
+    /// prefixed with `label`.
+    pub fn set_dump(&mut self, dump: SharedDump, label: String) {
+        self.dump = Some((dump, label));
+    }
+
+    /// Refuse to publish with a stream key not in `keys`.
+    pub fn set_allowed_keys(&mut self, keys: AllowedKeys) {
+        self.allowed_keys = Some(keys);
+    }
+
     pub fn app_name(&self) -> &str {
         &self.app_name
     }
 
-    pub fn stream_key(&self) -> &str {
-        &self.stream_key
+    pub fn connect_params(&self) -> &ConnectParams {
+        &self.connect_params
+    }
+
+    /// Build a Ping Request (User Control event 6). A ping still unanswered
+    /// when the next one is due counts as missed.
+    pub fn ping_request(&mut self) -> Vec<u8> {
+        if self.pending_ping.is_some() {
+            self.missed_pings += 1;
+        }
+        let now = Instant::now();
+        let timestamp = now.duration_since(self.ping_epoch).as_millis() as u32;
+        self.pending_ping = Some((timestamp, now));
+
+        let mut payload = vec![0u8, 6];
+        payload.extend_from_slice(&timestamp.to_be_bytes());
+        self.writer.write_message(2, 0, 4, 0, &payload)
+    }
+
+    /// Bytes per second received over the ingress window, RTMP framing and
+    /// all; None until the window could be measured.
+    pub fn ingress_bytes_per_sec(&self) -> Option<f64> {
+        let &(first, first_bytes) = self.ingress_window.front()?;
+        let elapsed = first.elapsed().as_secs_f64();
+        // The first read's bytes arrived before the window started
+        let bytes = self.ingress_window_bytes - first_bytes;
+        (elapsed >= 0.5).then(|| bytes as f64 / elapsed)
+    }
+
+    /// Consecutive pings that went unanswered.
+    pub fn missed_pings(&self) -> u32 {
+        self.missed_pings
     }
 
     /// Track bytes received for window acknowledgement.
     pub fn track_bytes(&mut self, count: usize) -> Option<Vec<u8>> {
         self.bytes_received += count as u64;
+
+        let now = Instant::now();
+        self.ingress_window.push_back((now, count));
+        self.ingress_window_bytes += count;
+        while let Some(&(at, bytes)) = self.ingress_window.front()
+            && now.duration_since(at) > INGRESS_WINDOW
+        {
+            self.ingress_window_bytes -= bytes;
+            self.ingress_window.pop_front();
+        }
         if self.window_ack_size > 0
             && self.bytes_received - self.last_ack_sent >= self.window_ack_size as u64
         {
@@ -82,23 +304,35 @@ impl MessageHandler {
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(
+        level = "trace",
+        name = "message",
+        skip_all,
+        fields(type_id = msg.type_id, stream_id = msg.stream_id, len = msg.payload.len()),
+    ))]
     pub fn handle(&mut self, msg: RtmpMessage) -> HandleResult {
         match msg.type_id {
             1 => self.handle_set_chunk_size(&msg),
+            2 => HandleResult::empty(), // Abort — applied by the chunk reader
             3 => HandleResult::empty(), // Acknowledgement — ignore
             4 => self.handle_user_control(&msg),
             5 => self.handle_window_ack_size(&msg),
             6 => self.handle_set_peer_bandwidth(&msg),
             8 => HandleResult::event(RtmpEvent::AudioData {
+                stream_id: msg.stream_id,
                 timestamp: msg.timestamp,
                 data: msg.payload,
             }),
             9 => HandleResult::event(RtmpEvent::VideoData {
+                stream_id: msg.stream_id,
                 timestamp: msg.timestamp,
                 data: msg.payload,
             }),
+            15 => self.handle_amf3_data(&msg),
+            17 => self.handle_amf3_command(&msg),
             18 => self.handle_amf0_data(&msg),
             20 => self.handle_amf0_command(&msg),
+            22 => self.handle_aggregate(&msg),
             _ => HandleResult::empty(), // Unknown type — silently ignore
         }
     }
@@ -111,21 +345,42 @@ impl MessageHandler {
                 msg.payload[2],
                 msg.payload[3],
             ]);
-            HandleResult {
-                responses: vec![],
-                new_chunk_size: Some(size),
-                event: None,
+            // The chunk reader has already applied the (sanitized) size;
+            // only report values it had to ignore or cap
+            match sanitize_chunk_size(size) {
+                Some(applied) if applied == size => HandleResult::empty(),
+                applied => HandleResult::event(RtmpEvent::InvalidChunkSize {
+                    requested: size,
+                    applied,
+                }),
             }
         } else {
             HandleResult::empty()
         }
     }
 
-    fn handle_user_control(&self, msg: &RtmpMessage) -> HandleResult {
+    fn handle_user_control(&mut self, msg: &RtmpMessage) -> HandleResult {
         if msg.payload.len() >= 6 {
             let event_type =
                 u16::from_be_bytes([msg.payload[0], msg.payload[1]]);
+            let stream_id = u32::from_be_bytes([msg.payload[2], msg.payload[3], msg.payload[4], msg.payload[5]]);
             match event_type {
+                1 => {
+                    // StreamEOF — the stream is over, but unlike deleteStream
+                    // nothing is owed in reply
+                    match self.published.iter().position(|(id, _)| *id == stream_id) {
+                        Some(pos) => {
+                            self.published.remove(pos);
+                            HandleResult::event(RtmpEvent::StreamEnded { stream_id })
+                        }
+                        None => HandleResult::empty(),
+                    }
+                }
+                2 => HandleResult::event(RtmpEvent::StreamDry { stream_id }),
+                3 if msg.payload.len() >= 10 => {
+                    let buffer_ms = u32::from_be_bytes([msg.payload[6], msg.payload[7], msg.payload[8], msg.payload[9]]);
+                    HandleResult::event(RtmpEvent::BufferLength { stream_id, buffer_ms })
+                }
                 6 => {
                     // Ping Request — respond with Pong
                     let mut pong_payload = vec![0u8; 6];
@@ -135,6 +390,18 @@ impl MessageHandler {
                     let response = self.writer.write_message(2, 0, 4, 0, &pong_payload);
                     HandleResult::response(response)
                 }
+                7 => {
+                    // Ping Response — echoes the timestamp of our request
+                    let timestamp = u32::from_be_bytes([msg.payload[2], msg.payload[3], msg.payload[4], msg.payload[5]]);
+                    match self.pending_ping {
+                        Some((sent_ts, sent_at)) if sent_ts == timestamp => {
+                            self.pending_ping = None;
+                            self.missed_pings = 0;
+                            HandleResult::event(RtmpEvent::PingResponse { rtt: sent_at.elapsed() })
+                        }
+                        _ => HandleResult::empty(),
+                    }
+                }
                 _ => HandleResult::empty(),
             }
         } else {
@@ -161,34 +428,159 @@ impl MessageHandler {
         HandleResult::response(response)
     }
 
+    /// Split an Aggregate message into its FLV-tag-formatted sub-messages.
+    /// Sub-message timestamps are rebased so the first one lands on the
+    /// aggregate's own timestamp.
+    fn handle_aggregate(&mut self, msg: &RtmpMessage) -> HandleResult {
+        let mut result = HandleResult::empty();
+        let mut reader = TagParser::new(&msg.payload);
+        let mut first_timestamp = None;
+
+        while let Some(tag) = reader.next_tag() {
+            // Only media and data belong in an aggregate; nested commands are ignored
+            if !matches!(tag.tag_type, 8 | 9 | 15 | 18) {
+                continue;
+            }
+            let first = *first_timestamp.get_or_insert(tag.timestamp);
+            let sub_msg = RtmpMessage {
+                chunk_stream_id: msg.chunk_stream_id,
+                timestamp: msg.timestamp.wrapping_add(tag.timestamp.wrapping_sub(first)),
+                type_id: tag.tag_type,
+                stream_id: msg.stream_id,
+                payload: tag.data,
+            };
+            let sub_result = self.handle(sub_msg);
+            result.responses.extend(sub_result.responses);
+            result.events.extend(sub_result.events);
+        }
+
+        if reader.back_pointer_mismatches() > 0 || reader.skipped_bytes() > 0 {
+            result.events.push(RtmpEvent::CorruptAggregate {
+                stream_id: msg.stream_id,
+                back_pointer_mismatches: reader.back_pointer_mismatches(),
+                skipped_bytes: reader.skipped_bytes(),
+            });
+        }
+
+        result
+    }
+
     fn handle_amf0_data(&self, msg: &RtmpMessage) -> HandleResult {
         let mut decoder = Amf0Decoder::new(&msg.payload);
         let values = decoder.decode_all();
+        self.dump_values(msg, &values, decoder.remaining());
+        self.handle_data_values(&values, msg.stream_id, msg.timestamp)
+    }
 
-        // Look for onMetaData / @setDataFrame
-        for (i, val) in values.iter().enumerate() {
-            if let Some(name) = val.as_str() {
-                if name == "@setDataFrame" || name == "onMetaData" {
-                    // The metadata object is the next value (or the one after "@setDataFrame" + "onMetaData")
-                    let meta_idx = if name == "@setDataFrame" { i + 2 } else { i + 1 };
-                    if let Some(meta_val) = values.get(meta_idx).or_else(|| values.get(i + 1)) {
-                        if let Some(props) = meta_val.as_object() {
-                            return HandleResult::event(RtmpEvent::Metadata {
-                                properties: props.to_vec(),
-                            });
-                        }
-                    }
-                }
-            }
+    fn handle_amf3_data(&self, msg: &RtmpMessage) -> HandleResult {
+        let (values, undecoded_bytes) = decode_amf3_payload(&msg.payload);
+        self.dump_values(msg, &values, undecoded_bytes);
+        self.handle_data_values(&values, msg.stream_id, msg.timestamp)
+    }
+
+    /// e.g. `AMF0 command "connect" (transaction 1) on stream 0, 187 bytes: "connect", 1, {app: "live"}`,
+    /// ending in e.g. `(12 bytes undecoded)` when decoding stopped early
+    fn dump_values(&self, msg: &RtmpMessage, values: &[Amf0Value], undecoded_bytes: usize) {
+        let Some((dump, label)) = &self.dump else {
+            return;
+        };
+        let (encoding, is_command) = match msg.type_id {
+            15 => ("AMF3", false),
+            17 => ("AMF3", true),
+            18 => ("AMF0", false),
+            _ => ("AMF0", true),
+        };
+        let name = values.first().and_then(|v| v.as_str()).unwrap_or("");
+        let transaction = match values.get(1).and_then(|v| v.as_f64()) {
+            Some(id) if is_command => format!(" (transaction {})", id),
+            _ => String::new(),
+        };
+        let rendered: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        let undecoded = if undecoded_bytes > 0 {
+            format!(" ({} bytes undecoded)", undecoded_bytes)
+        } else {
+            String::new()
+        };
+        let line = format!("{} {} {} \"{}\"{} on stream {}, {} bytes: {}{}",
+            label,
+            encoding,
+            if is_command { "command" } else { "data" },
+            name,
+            transaction,
+            msg.stream_id,
+            msg.payload.len(),
+            rendered.join(", "),
+            undecoded);
+        if let Ok(mut dump) = dump.lock() {
+            dump.write_line(&line);
         }
+    }
 
-        HandleResult::empty()
+    fn handle_data_values(&self, values: &[Amf0Value], stream_id: u32, timestamp: u32) -> HandleResult {
+        let Some(first) = values.first().and_then(|v| v.as_str()) else {
+            return HandleResult::empty();
+        };
+        // "@setDataFrame"/"@clearDataFrame" wrap the name of the frame they
+        // set or clear; some encoders leave the name out
+        let (wrapper, name, body) = match (first, values.get(1)) {
+            ("@setDataFrame" | "@clearDataFrame", Some(Amf0Value::String(name))) => {
+                (Some(first), name.as_str(), values.get(2))
+            }
+            ("@setDataFrame" | "@clearDataFrame", _) => (Some(first), "onMetaData", values.get(1)),
+            _ => (None, first, values.get(1)),
+        };
+        let properties = || body.and_then(|v| v.as_object()).map(|props| props.to_vec());
+
+        match (wrapper, name) {
+            (Some("@clearDataFrame"), "onMetaData") => HandleResult::event(RtmpEvent::MetadataCleared { stream_id }),
+            (_, "onMetaData") => match properties() {
+                Some(properties) => HandleResult::event(RtmpEvent::Metadata { stream_id, properties }),
+                None => HandleResult::empty(),
+            },
+            (_, "onTextData") => HandleResult::event(RtmpEvent::TextData {
+                stream_id,
+                timestamp,
+                properties: properties().unwrap_or_default(),
+            }),
+            (_, "onCuePoint") => HandleResult::event(RtmpEvent::CuePoint {
+                stream_id,
+                timestamp,
+                properties: properties().unwrap_or_default(),
+            }),
+            _ => HandleResult::event(RtmpEvent::UnknownData {
+                stream_id,
+                name: match wrapper {
+                    Some(wrapper) => format!("{} {}", wrapper, name),
+                    None => name.to_string(),
+                },
+            }),
+        }
     }
 
     fn handle_amf0_command(&mut self, msg: &RtmpMessage) -> HandleResult {
         let mut decoder = Amf0Decoder::new(&msg.payload);
         let values = decoder.decode_all();
+        self.dump_values(msg, &values, decoder.remaining());
+        self.handle_checked_command(&values, decoder.remaining(), msg.stream_id)
+    }
+
+    fn handle_amf3_command(&mut self, msg: &RtmpMessage) -> HandleResult {
+        let (values, undecoded_bytes) = decode_amf3_payload(&msg.payload);
+        self.dump_values(msg, &values, undecoded_bytes);
+        self.handle_checked_command(&values, undecoded_bytes, msg.stream_id)
+    }
 
+    /// Handle a command, flagging it first if it came up short.
+    fn handle_checked_command(&mut self, values: &[Amf0Value], undecoded_bytes: usize, msg_stream_id: u32) -> HandleResult {
+        let short = ShortCommand::check(values, undecoded_bytes);
+        let mut result = self.handle_command_values(values, msg_stream_id);
+        if let Some(command) = short {
+            result.events.insert(0, RtmpEvent::ShortCommand { stream_id: msg_stream_id, command });
+        }
+        result
+    }
+
+    fn handle_command_values(&mut self, values: &[Amf0Value], msg_stream_id: u32) -> HandleResult {
         let command_name = values
             .first()
             .and_then(|v| v.as_str())
@@ -198,15 +590,33 @@ impl MessageHandler {
         let transaction_id = values.get(1).and_then(|v| v.as_f64()).unwrap_or(0.0);
 
         match command_name.as_str() {
-            "connect" => self.handle_connect(&values, transaction_id),
+            "connect" => self.handle_connect(values, transaction_id),
             "releaseStream" => self.handle_release_stream(transaction_id),
             "FCPublish" => self.handle_fc_publish(transaction_id),
             "createStream" => self.handle_create_stream(transaction_id),
-            "publish" => self.handle_publish(&values, transaction_id, msg.stream_id),
-            "FCUnpublish" | "deleteStream" => {
-                HandleResult::event(RtmpEvent::StreamEnded)
+            "publish" => self.handle_publish(values, transaction_id, msg_stream_id),
+            "play" => self.handle_play(values, msg_stream_id),
+            "deleteStream" => {
+                // ["deleteStream", txn, null, stream_id]
+                match values.get(3).and_then(|v| v.as_f64()) {
+                    Some(id) => self.end_stream(id as u32),
+                    None => HandleResult::empty(),
+                }
             }
-            "_checkbw" | "_result" | "_error" | "onStatus" => {
+            // Sent on the stream being closed
+            "closeStream" => self.end_stream(msg_stream_id),
+            "FCUnpublish" => {
+                // ["FCUnpublish", txn, null, stream_key]
+                let key = values.get(3).and_then(|v| v.as_str());
+                match self.published.iter().find(|(_, k)| Some(k.as_str()) == key) {
+                    Some(&(id, _)) => self.end_stream(id),
+                    None => HandleResult::empty(),
+                }
+            }
+            // ["onStatus", txn, null, {level, code, description}]
+            "onStatus" | "onFCPublish" => Self::status_event(values, "status", msg_stream_id),
+            "_error" => Self::status_event(values, "error", msg_stream_id),
+            "_checkbw" | "_result" => {
                 // Responses/internal commands — ignore
                 HandleResult::empty()
             }
@@ -217,16 +627,34 @@ impl MessageHandler {
         }
     }
 
+    fn status_event(values: &[Amf0Value], default_level: &str, stream_id: u32) -> HandleResult {
+        match values.get(3).and_then(|info| NetStatus::from_info(info, default_level)) {
+            Some(status) => HandleResult::event(RtmpEvent::Status { stream_id, status }),
+            None => HandleResult::empty(),
+        }
+    }
+
     fn handle_connect(&mut self, values: &[Amf0Value], txn_id: f64) -> HandleResult {
         // Extract app name from the command object (3rd value, index 2)
+        if let Some(name) = values
+            .get(2)
+            .and_then(|obj| obj.get_property("app"))
+            .and_then(|app| app.as_str())
+        {
+            self.app_name = name.to_string();
+        }
         if let Some(obj) = values.get(2) {
-            if let Some(app) = obj.get_property("app") {
-                if let Some(name) = app.as_str() {
-                    self.app_name = name.to_string();
-                }
-            }
+            self.connect_params = ConnectParams::from_command_object(obj);
         }
 
+        // We decode AMF3 messages (types 15/17), so AMF3 is accepted;
+        // anything else falls back to AMF0
+        let requested_encoding = values
+            .get(2)
+            .and_then(|obj| obj.get_property("objectEncoding"))
+            .and_then(|enc| enc.as_f64());
+        self.object_encoding = if requested_encoding == Some(3.0) { 3 } else { 0 };
+
         let mut responses = Vec::new();
 
         // 1. Window Acknowledgement Size (type 5)
@@ -277,7 +705,7 @@ impl MessageHandler {
                 "description",
                 Amf0Value::String("Connection succeeded.".to_string()),
             ),
-            ("objectEncoding", Amf0Value::Number(0.0)),
+            ("objectEncoding", Amf0Value::Number(self.object_encoding as f64)),
         ]);
 
         let result_msg = self.writer.write_message(3, 0, 20, 0, &enc.into_bytes());
@@ -285,10 +713,11 @@ impl MessageHandler {
 
         HandleResult {
             responses,
-            new_chunk_size: None,
-            event: Some(RtmpEvent::Connected {
+            events: vec![RtmpEvent::Connected {
                 app_name: self.app_name.clone(),
-            }),
+                requested_encoding,
+                object_encoding: self.object_encoding,
+            }],
         }
     }
 
@@ -310,12 +739,13 @@ impl MessageHandler {
         HandleResult::response(response)
     }
 
-    fn handle_create_stream(&self, txn_id: f64) -> HandleResult {
+    fn handle_create_stream(&mut self, txn_id: f64) -> HandleResult {
+        self.last_stream_id += 1;
         let mut enc = Amf0Encoder::new();
         enc.write_string("_result");
         enc.write_number(txn_id);
         enc.write_null();
-        enc.write_number(1.0); // Stream ID = 1
+        enc.write_number(self.last_stream_id as f64);
         let response = self.writer.write_message(3, 0, 20, 0, &enc.into_bytes());
         HandleResult::response(response)
     }
@@ -331,14 +761,20 @@ impl MessageHandler {
             self.stream_key = key.to_string();
         }
 
+        if self.allowed_keys.as_ref().is_some_and(|keys| !keys.contains(&self.stream_key)) {
+            return self.reject_publish(msg_stream_id);
+        }
+
         let mut responses = Vec::new();
 
-        // Stream Begin for stream ID 1
+        self.published.retain(|(id, _)| *id != msg_stream_id);
+        self.published.push((msg_stream_id, self.stream_key.clone()));
+
+        // Stream Begin for the publishing stream
         let mut stream_begin = vec![0u8; 6];
         stream_begin[0] = 0;
         stream_begin[1] = 0; // StreamBegin event
-        stream_begin[4] = 0;
-        stream_begin[5] = 1; // stream id = 1
+        stream_begin[2..6].copy_from_slice(&msg_stream_id.to_be_bytes());
         let sb_msg = self.writer.write_message(2, 0, 4, 0, &stream_begin);
         responses.push(sb_msg);
 
@@ -365,11 +801,105 @@ impl MessageHandler {
 
         HandleResult {
             responses,
-            new_chunk_size: None,
-            event: Some(RtmpEvent::Publishing {
+            events: vec![RtmpEvent::Publishing {
                 app_name: self.app_name.clone(),
                 stream_key: self.stream_key.clone(),
-            }),
+                stream_id: msg_stream_id,
+            }],
+        }
+    }
+
+    /// An onStatus message on `stream_id` with the given info object.
+    fn status_message(&self, stream_id: u32, level: &str, code: &str, description: &str) -> Vec<u8> {
+        let mut enc = Amf0Encoder::new();
+        enc.write_string("onStatus");
+        enc.write_number(0.0);
+        enc.write_null();
+        enc.write_object(&[
+            ("level", Amf0Value::String(level.to_string())),
+            ("code", Amf0Value::String(code.to_string())),
+            ("description", Amf0Value::String(description.to_string())),
+        ]);
+        self.writer.write_message(3, 0, 20, stream_id, &enc.into_bytes())
+    }
+
+    /// Answer play the way a server starting playback does, so players
+    /// don't stall waiting. No media follows: nothing is relayed.
+    fn handle_play(&mut self, values: &[Amf0Value], msg_stream_id: u32) -> HandleResult {
+        // play command: ["play", txn, null, stream_key, start?, duration?, reset?]
+        let stream_key = values.get(3).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+        let mut responses = Vec::new();
+
+        // Stream Begin for the playing stream
+        let mut stream_begin = vec![0u8; 6];
+        stream_begin[2..6].copy_from_slice(&msg_stream_id.to_be_bytes());
+        responses.push(self.writer.write_message(2, 0, 4, 0, &stream_begin));
+
+        responses.push(self.status_message(msg_stream_id, "status", "NetStream.Play.Reset", &format!("Playing and resetting {}.", stream_key)));
+        responses.push(self.status_message(msg_stream_id, "status", "NetStream.Play.Start", &format!("Started playing {}.", stream_key)));
+
+        // Lets Flash-era players read the media; others ignore it
+        let mut enc = Amf0Encoder::new();
+        enc.write_string("|RtmpSampleAccess");
+        enc.write_boolean(true);
+        enc.write_boolean(true);
+        responses.push(self.writer.write_message(3, 0, 18, msg_stream_id, &enc.into_bytes()));
+
+        let mut enc = Amf0Encoder::new();
+        enc.write_string("onStatus");
+        enc.write_object(&[("code", Amf0Value::String("NetStream.Data.Start".to_string()))]);
+        responses.push(self.writer.write_message(3, 0, 18, msg_stream_id, &enc.into_bytes()));
+
+        HandleResult {
+            responses,
+            events: vec![RtmpEvent::Playing { stream_key, stream_id: msg_stream_id }],
+        }
+    }
+
+    /// Answer a publish with NetStream.Publish.BadName instead of starting
+    /// the stream.
+    fn reject_publish(&mut self, msg_stream_id: u32) -> HandleResult {
+        let status_msg = self.status_message(msg_stream_id, "error", "NetStream.Publish.BadName", "Stream key not allowed.");
+
+        HandleResult {
+            responses: vec![status_msg],
+            events: vec![RtmpEvent::PublishRejected {
+                stream_key: self.stream_key.clone(),
+                stream_id: msg_stream_id,
+            }],
+        }
+    }
+
+    /// Stop tracking `stream_id` and confirm the unpublish. Streams that
+    /// were never published (or already ended) are ignored, so the
+    /// FCUnpublish + deleteStream pair encoders send only ends a stream once.
+    fn end_stream(&mut self, stream_id: u32) -> HandleResult {
+        let Some(pos) = self.published.iter().position(|(id, _)| *id == stream_id) else {
+            return HandleResult::empty();
+        };
+        self.published.remove(pos);
+
+        let mut enc = Amf0Encoder::new();
+        enc.write_string("onStatus");
+        enc.write_number(0.0);
+        enc.write_null();
+        enc.write_object(&[
+            ("level", Amf0Value::String("status".to_string())),
+            (
+                "code",
+                Amf0Value::String("NetStream.Unpublish.Success".to_string()),
+            ),
+            (
+                "description",
+                Amf0Value::String("Stream unpublished.".to_string()),
+            ),
+        ]);
+        let status_msg = self.writer.write_message(3, 0, 20, stream_id, &enc.into_bytes());
+
+        HandleResult {
+            responses: vec![status_msg],
+            events: vec![RtmpEvent::StreamEnded { stream_id }],
         }
     }
 
@@ -388,28 +918,381 @@ impl MessageHandler {
     }
 }
 
+impl Default for MessageHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decode the body of an AMF3 data/command message (types 15/17), also
+/// returning how many bytes were left over.
+///
+/// The body is a format selector byte (always 0) followed by AMF0 values,
+/// which switch to AMF3 through the avmplus marker. Decoding a body without
+/// the selector as raw AMF3 would misread an AMF0 string marker (0x02) as
+/// AMF3 `false`, so the first byte is skipped whatever its value.
+fn decode_amf3_payload(payload: &[u8]) -> (Vec<Amf0Value>, usize) {
+    let mut decoder = Amf0Decoder::new(payload.get(1..).unwrap_or_default());
+    (decoder.decode_all(), decoder.remaining())
+}
+
 impl HandleResult {
     pub fn empty() -> Self {
         Self {
             responses: vec![],
-            new_chunk_size: None,
-            event: None,
+            events: vec![],
         }
     }
 
     pub fn response(data: Vec<u8>) -> Self {
         Self {
             responses: vec![data],
-            new_chunk_size: None,
-            event: None,
+            events: vec![],
         }
     }
 
     pub fn event(evt: RtmpEvent) -> Self {
         Self {
             responses: vec![],
-            new_chunk_size: None,
-            event: Some(evt),
+            events: vec![evt],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtmp::chunk::ChunkReader;
+
+    fn message(type_id: u8, stream_id: u32, timestamp: u32, payload: Vec<u8>) -> RtmpMessage {
+        RtmpMessage { chunk_stream_id: 3, timestamp, type_id, stream_id, payload }
+    }
+
+    fn command(name: &str, txn_id: f64, args: impl FnOnce(&mut Amf0Encoder)) -> Vec<u8> {
+        let mut enc = Amf0Encoder::new();
+        enc.write_string(name);
+        enc.write_number(txn_id);
+        args(&mut enc);
+        enc.into_bytes()
+    }
+
+    fn connect_command() -> Vec<u8> {
+        command("connect", 1.0, |enc| {
+            enc.write_object(&[("app", Amf0Value::String("live".to_string()))]);
+        })
+    }
+
+    /// Reassemble the messages a handler sent back.
+    fn replies(result: &HandleResult) -> Vec<RtmpMessage> {
+        let mut reader = ChunkReader::new();
+        reader.set_chunk_size(4096);
+        for response in &result.responses {
+            reader.extend(response);
+        }
+        reader.read_messages()
+    }
+
+    /// The AMF0 values of every command among the replies.
+    fn reply_commands(result: &HandleResult) -> Vec<Vec<Amf0Value>> {
+        replies(result)
+            .iter()
+            .filter(|msg| msg.type_id == 20)
+            .map(|msg| Amf0Decoder::new(&msg.payload).decode_all())
+            .collect()
+    }
+
+    #[test]
+    fn amf3_command_skips_the_format_selector() {
+        let mut handler = MessageHandler::new();
+        let payload = [&[0x00][..], &connect_command()].concat();
+        let result = handler.handle(message(17, 0, 0, payload));
+        assert!(matches!(&result.events[..], [RtmpEvent::Connected { app_name, .. }] if app_name == "live"));
+        assert_eq!(reply_commands(&result)[0][0], Amf0Value::String("_result".to_string()));
+    }
+
+    #[test]
+    fn amf3_data_switches_to_amf3_through_the_avmplus_marker() {
+        let mut payload = vec![0x00, 0x02, 0x00, 0x0A];
+        payload.extend_from_slice(b"onMetaData");
+        // avmplus, then a dynamic anonymous object {width: 1280}
+        payload.extend_from_slice(&[0x11, 0x0A, 0x0B, 0x01, 0x0B]);
+        payload.extend_from_slice(b"width");
+        payload.extend_from_slice(&[0x04, 0x8A, 0x00, 0x01]);
+
+        let mut handler = MessageHandler::new();
+        let result = handler.handle(message(15, 1, 0, payload));
+        match &result.events[..] {
+            [RtmpEvent::Metadata { stream_id: 1, properties }] => {
+                assert_eq!(properties, &[("width".to_string(), Amf0Value::Number(1280.0))]);
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn amf3_command_without_a_selector_is_not_read_as_amf3() {
+        // An AMF0 connect sent as type 17 without the selector: as raw AMF3
+        // its string marker would decode as `false`
+        let mut handler = MessageHandler::new();
+        let result = handler.handle(message(17, 0, 0, connect_command()));
+        assert!(!result.events.iter().any(|e| matches!(e, RtmpEvent::Connected { .. })));
+    }
+
+    /// An FLV tag as found in an Aggregate message, with the given back-pointer.
+    fn aggregate_tag(tag_type: u8, timestamp: u32, body: &[u8], back_pointer: u32) -> Vec<u8> {
+        let size = (body.len() as u32).to_be_bytes();
+        let ts = timestamp.to_be_bytes();
+        let mut tag = vec![tag_type, size[1], size[2], size[3], ts[1], ts[2], ts[3], ts[0], 0, 0, 0];
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&back_pointer.to_be_bytes());
+        tag
+    }
+
+    fn intact_tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        aggregate_tag(tag_type, timestamp, body, 11 + body.len() as u32)
+    }
+
+    #[test]
+    fn aggregate_sub_messages_are_rebased_onto_the_aggregate_timestamp() {
+        let mut enc = Amf0Encoder::new();
+        enc.write_string("onMetaData");
+        enc.write_object(&[("width", Amf0Value::Number(1280.0))]);
+        let metadata = enc.into_bytes();
+        let payload = [
+            intact_tag(18, 1000, &metadata),
+            intact_tag(9, 1000, &[0x17, 0x01, 0, 0, 0]),
+            intact_tag(8, 1010, &[0xAF, 0x01, 0x21]),
+            intact_tag(9, 1033, &[0x27, 0x01, 0, 0, 0]),
+        ]
+        .concat();
+
+        let mut handler = MessageHandler::new();
+        let result = handler.handle(message(22, 1, 5000, payload));
+        match &result.events[..] {
+            [
+                RtmpEvent::Metadata { stream_id: 1, properties },
+                RtmpEvent::VideoData { stream_id: 1, timestamp: 5000, data: keyframe },
+                RtmpEvent::AudioData { stream_id: 1, timestamp: 5010, data: audio },
+                RtmpEvent::VideoData { stream_id: 1, timestamp: 5033, .. },
+            ] => {
+                assert_eq!(properties, &[("width".to_string(), Amf0Value::Number(1280.0))]);
+                assert_eq!(keyframe[0], 0x17);
+                assert_eq!(audio, &[0xAF, 0x01, 0x21]);
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn aggregate_with_a_wrong_back_pointer_is_reported_corrupt() {
+        let payload = [
+            aggregate_tag(9, 0, &[0x17, 0x01, 0, 0, 0], 99),
+            intact_tag(9, 33, &[0x27, 0x01, 0, 0, 0]),
+        ]
+        .concat();
+
+        let mut handler = MessageHandler::new();
+        let result = handler.handle(message(22, 1, 0, payload));
+        let video = result.events.iter().filter(|e| matches!(e, RtmpEvent::VideoData { .. })).count();
+        assert_eq!(video, 2);
+        assert!(matches!(
+            result.events.last(),
+            Some(RtmpEvent::CorruptAggregate { stream_id: 1, back_pointer_mismatches: 1, skipped_bytes: 0 })
+        ));
+
+        let clean = intact_tag(9, 0, &[0x17, 0x01, 0, 0, 0]);
+        let result = handler.handle(message(22, 1, 0, clean));
+        assert!(!result.events.iter().any(|e| matches!(e, RtmpEvent::CorruptAggregate { .. })));
+    }
+
+    fn publish_command(key: &str) -> Vec<u8> {
+        command("publish", 5.0, |enc| {
+            enc.write_null();
+            enc.write_string(key);
+            enc.write_string("live");
+        })
+    }
+
+    /// Level and code of every onStatus among the replies.
+    fn reply_statuses(result: &HandleResult) -> Vec<(String, String)> {
+        let field = |info: &Amf0Value, key| info.get_property(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        reply_commands(result)
+            .iter()
+            .filter(|values| values[0].as_str() == Some("onStatus"))
+            .map(|values| (field(&values[3], "level"), field(&values[3], "code")))
+            .collect()
+    }
+
+    #[test]
+    fn publishing_with_a_key_outside_the_allowlist_is_rejected() {
+        let mut handler = MessageHandler::new();
+        handler.set_allowed_keys(Arc::new(HashSet::from(["good".to_string()])));
+        handler.handle(message(20, 0, 0, connect_command()));
+
+        let result = handler.handle(message(20, 1, 0, publish_command("bad")));
+        assert_eq!(reply_statuses(&result), [("error".to_string(), "NetStream.Publish.BadName".to_string())]);
+        assert!(matches!(&result.events[..], [RtmpEvent::PublishRejected { stream_key, stream_id: 1 }] if stream_key == "bad"));
+        // No StreamBegin either: the stream never started
+        assert!(replies(&result).iter().all(|msg| msg.type_id == 20));
+
+        let result = handler.handle(message(20, 1, 0, publish_command("good")));
+        assert_eq!(reply_statuses(&result), [("status".to_string(), "NetStream.Publish.Start".to_string())]);
+        assert!(matches!(&result.events[..], [RtmpEvent::Publishing { stream_key, .. }] if stream_key == "good"));
+    }
+
+    #[test]
+    fn any_key_may_publish_without_an_allowlist() {
+        let mut handler = MessageHandler::new();
+        handler.handle(message(20, 0, 0, connect_command()));
+        let result = handler.handle(message(20, 1, 0, publish_command("anything")));
+        assert_eq!(reply_statuses(&result), [("status".to_string(), "NetStream.Publish.Start".to_string())]);
+        assert!(matches!(&result.events[..], [RtmpEvent::Publishing { stream_id: 1, .. }]));
+    }
+
+    fn user_control(event_type: u16, stream_id: u32, extra: &[u8]) -> RtmpMessage {
+        let mut payload = event_type.to_be_bytes().to_vec();
+        payload.extend_from_slice(&stream_id.to_be_bytes());
+        payload.extend_from_slice(extra);
+        message(4, 0, 0, payload)
+    }
+
+    #[test]
+    fn user_control_stream_events_are_reported() {
+        let mut handler = MessageHandler::new();
+        handler.handle(message(20, 0, 0, connect_command()));
+        handler.handle(message(20, 1, 0, publish_command("key")));
+
+        let result = handler.handle(user_control(2, 1, &[]));
+        assert!(matches!(&result.events[..], [RtmpEvent::StreamDry { stream_id: 1 }]));
+        let result = handler.handle(user_control(3, 1, &1500u32.to_be_bytes()));
+        assert!(matches!(&result.events[..], [RtmpEvent::BufferLength { stream_id: 1, buffer_ms: 1500 }]));
+        // SetBufferLength without its buffer length is ignored
+        assert!(handler.handle(user_control(3, 1, &[])).events.is_empty());
+
+        let result = handler.handle(user_control(1, 1, &[]));
+        assert!(matches!(&result.events[..], [RtmpEvent::StreamEnded { stream_id: 1 }]));
+        assert!(result.responses.is_empty());
+        // The stream is already over
+        assert!(handler.handle(user_control(1, 1, &[])).events.is_empty());
+    }
+
+    #[test]
+    fn ping_requests_are_answered_and_timed() {
+        let mut handler = MessageHandler::new();
+        let result = handler.handle(user_control(6, 1234, &[]));
+        let pong = replies(&result);
+        assert_eq!((pong[0].type_id, &pong[0].payload[..]), (4, &[0, 7, 0, 0, 0x04, 0xD2][..]));
+
+        let mut reader = ChunkReader::new();
+        reader.extend(&handler.ping_request());
+        let timestamp = u32::from_be_bytes(reader.read_messages()[0].payload[2..6].try_into().unwrap());
+        std::thread::sleep(Duration::from_millis(20));
+        // A response to some other ping doesn't count
+        assert!(handler.handle(user_control(7, timestamp.wrapping_add(1), &[])).events.is_empty());
+        match &handler.handle(user_control(7, timestamp, &[])).events[..] {
+            [RtmpEvent::PingResponse { rtt }] => assert!(*rtt >= Duration::from_millis(20), "{:?}", rtt),
+            events => panic!("unexpected events {:?}", events),
         }
     }
+
+    #[test]
+    fn unanswered_pings_count_until_one_is_answered() {
+        let mut handler = MessageHandler::new();
+        handler.ping_request();
+        assert_eq!(handler.missed_pings(), 0);
+        handler.ping_request();
+        let mut reader = ChunkReader::new();
+        reader.extend(&handler.ping_request());
+        assert_eq!(handler.missed_pings(), 2);
+
+        let timestamp = u32::from_be_bytes(reader.read_messages()[0].payload[2..6].try_into().unwrap());
+        handler.handle(user_control(7, timestamp, &[]));
+        assert_eq!(handler.missed_pings(), 0);
+    }
+
+    #[test]
+    fn play_is_answered_like_a_server_starting_playback() {
+        let mut handler = MessageHandler::new();
+        handler.handle(message(20, 0, 0, connect_command()));
+        let play = command("play", 4.0, |enc| {
+            enc.write_null();
+            enc.write_string("key");
+        });
+        let result = handler.handle(message(20, 1, 0, play));
+        assert!(matches!(&result.events[..], [RtmpEvent::Playing { stream_key, stream_id: 1 }] if stream_key == "key"));
+
+        let messages = replies(&result);
+        assert!(messages[1..].iter().all(|msg| msg.stream_id == 1));
+        let summary: Vec<_> = messages
+            .iter()
+            .map(|msg| match msg.type_id {
+                4 => format!("user control {:?}", msg.payload),
+                _ => {
+                    let values = Amf0Decoder::new(&msg.payload).decode_all();
+                    let name = values[0].as_str().unwrap_or_default().to_string();
+                    // Commands carry the info object fourth, data messages second
+                    let info = if msg.type_id == 20 { values.get(3) } else { values.get(1) };
+                    match info.and_then(|info| info.get_property("code")).and_then(|code| code.as_str()) {
+                        Some(code) => format!("{} {}", name, code),
+                        None => name,
+                    }
+                }
+            })
+            .collect();
+        assert_eq!(summary, [
+            "user control [0, 0, 0, 0, 0, 1]",
+            "onStatus NetStream.Play.Reset",
+            "onStatus NetStream.Play.Start",
+            "|RtmpSampleAccess",
+            "onStatus NetStream.Data.Start",
+        ]);
+    }
+
+    fn short_command(result: &HandleResult) -> Option<&ShortCommand> {
+        result.events.iter().find_map(|event| match event {
+            RtmpEvent::ShortCommand { command, .. } => Some(command),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn commands_missing_required_values_are_flagged() {
+        let mut handler = MessageHandler::new();
+        // A publish that really stops after its transaction id
+        let result = handler.handle(message(20, 1, 0, command("publish", 5.0, |_| {})));
+        let short = short_command(&result).unwrap();
+        assert_eq!((short.name.as_str(), short.values, short.required, short.undecoded_bytes), ("publish", 2, 4, 0));
+
+        // A connect cut off inside its command object
+        let connect = connect_command();
+        let result = handler.handle(message(20, 0, 0, connect[..connect.len() - 6].to_vec()));
+        let short = short_command(&result).unwrap();
+        assert_eq!((short.name.as_str(), short.values, short.required), ("connect", 2, 3));
+        assert!(short.undecoded_bytes > 0);
+        assert!(matches!(result.events[0], RtmpEvent::ShortCommand { stream_id: 0, .. }));
+
+        // Complete commands, and ones with no requirements beyond a name, aren't flagged
+        assert!(short_command(&handler.handle(message(20, 0, 0, connect_command()))).is_none());
+        assert!(short_command(&handler.handle(message(20, 1, 0, publish_command("key")))).is_none());
+        assert!(short_command(&handler.handle(message(20, 0, 0, command("createStream", 2.0, |_| {})))).is_none());
+    }
+
+    #[test]
+    fn undecoded_bytes_of_amf3_data_are_dumped() {
+        let path = std::env::temp_dir().join(format!("rustmp-dump-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let dump = Arc::new(std::sync::Mutex::new(crate::log::AmfDump::open(Some(&path)).unwrap()));
+
+        let mut handler = MessageHandler::new();
+        handler.set_dump(dump, "test".to_string());
+        let mut payload = vec![0x00, 0x02, 0x00, 0x05];
+        payload.extend_from_slice(b"onFoo");
+        // An unsupported marker (AMF0 unsupported, 0x0D) stops decoding
+        payload.extend_from_slice(&[0x0D, 0x01, 0x02, 0x03]);
+        handler.handle(message(15, 1, 0, payload));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(written.contains("AMF3 data \"onFoo\" on stream 1, 13 bytes: \"onFoo\" (3 bytes undecoded)"), "{}", written);
+    }
 }
@@ -1,4 +1,6 @@
 pub mod amf0;
+pub mod amf3;
 pub mod chunk;
 pub mod handshake;
 pub mod message;
+pub mod sha256;
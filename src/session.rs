@@ -0,0 +1,290 @@
+use std::time::Instant;
+
+use crate::connection::ConnectionOptions;
+use crate::diagnostics::{CheckInputs, Diagnostic, StreamDiagnostics};
+use crate::flv::audio::{AudioAnalyzer, AudioCodec};
+use crate::flv::metadata::StreamMetadata;
+use crate::flv::video::VideoAnalyzer;
+use crate::report::StreamReport;
+use crate::rtmp::amf0::Amf0Value;
+use crate::snapshot::StreamSnapshot;
+use crate::stats::StreamStats;
+
+/// Analysis state of one published stream, fed with its media and metadata
+/// whether they come from a live connection or a recording.
+pub struct StreamSession {
+    pub video_analyzer: VideoAnalyzer,
+    pub audio_analyzer: AudioAnalyzer,
+    pub stats: StreamStats,
+    pub diagnostics: StreamDiagnostics,
+    pub encoder_name: Option<String>,
+    /// The latest cue point or caption, e.g. "cue point \"ad\" (event) at 12.3s"
+    pub last_data_frame: Option<String>,
+}
+
+impl StreamSession {
+    pub fn new(options: &ConnectionOptions) -> Self {
+        let mut video_analyzer = VideoAnalyzer::new();
+        if options.nal_stats {
+            video_analyzer.enable_nal_stats();
+        }
+        let mut stats = StreamStats::new();
+        if let Some(window) = options.stats_window {
+            stats.set_bitrate_window(window);
+            stats.set_fps_window(window);
+        }
+        if let Some(window) = options.fps_window {
+            stats.set_fps_window(window);
+        }
+        let mut diagnostics = StreamDiagnostics::new();
+        // Refined from the app name once the client connects
+        diagnostics.set_profile(options.profile.clone());
+        if let Some(interval) = options.check_interval {
+            diagnostics.set_check_interval(interval);
+        }
+        for sink in &options.sinks {
+            diagnostics.add_sink(Box::new(sink.clone()));
+        }
+
+        Self {
+            video_analyzer,
+            audio_analyzer: AudioAnalyzer::new(),
+            stats,
+            diagnostics,
+            encoder_name: None,
+            last_data_frame: None,
+        }
+    }
+
+    /// Drive all timing from `now` instead of the wall clock.
+    pub fn set_clock(&mut self, now: Instant) {
+        self.stats.set_clock(now);
+        self.diagnostics.set_clock(now);
+    }
+
+    pub fn handle_metadata(&mut self, properties: &[(String, Amf0Value)]) {
+        let metadata = StreamMetadata::from_properties(properties);
+        if metadata.encoder.is_some() {
+            self.encoder_name = metadata.encoder.clone();
+        }
+        self.diagnostics.record_metadata(&metadata, self.video_analyzer.total_video_frames);
+    }
+
+    /// The encoder withdrew its metadata (@clearDataFrame)
+    pub fn handle_metadata_cleared(&mut self) {
+        self.diagnostics.clear_metadata();
+    }
+
+    pub fn handle_text_data(&mut self, timestamp: u32, properties: &[(String, Amf0Value)]) {
+        let text = property_str(properties, "text").unwrap_or_default();
+        self.last_data_frame = Some(format!("text \"{}\" at {:.1}s", text, timestamp as f64 / 1000.0));
+    }
+
+    pub fn handle_cue_point(&mut self, timestamp: u32, properties: &[(String, Amf0Value)]) {
+        let name = property_str(properties, "name").unwrap_or("unnamed");
+        let kind = property_str(properties, "type").map(|t| format!(" ({})", t)).unwrap_or_default();
+        self.last_data_frame = Some(format!("cue point \"{}\"{} at {:.1}s", name, kind, timestamp as f64 / 1000.0));
+    }
+
+    pub fn handle_video(&mut self, timestamp: u32, data: &[u8]) {
+        let byte_count = data.len();
+
+        // Track diagnostics before processing
+        self.diagnostics.record_video_timestamp(timestamp);
+
+        // Process video
+        let b_frames_before = self.video_analyzer.b_frame_count;
+        let frames_before = self.video_analyzer.total_video_frames;
+        let headers_before = self.video_analyzer.sequence_header_count;
+        let config_before = (headers_before > 0).then(|| self.video_analyzer.config_summary());
+        self.video_analyzer.process(data, timestamp);
+
+        // AVC sequence headers and Enhanced RTMP sequence starts alike
+        if self.video_analyzer.sequence_header_count > headers_before {
+            self.diagnostics.record_video_config(self.video_analyzer.codec);
+        }
+
+        // Repeating the same sequence header is fine; changing it isn't
+        if self.video_analyzer.sequence_header_count > headers_before
+            && let Some(before) = config_before
+        {
+            let after = self.video_analyzer.config_summary();
+            if after != before {
+                self.diagnostics.record_video_config_change(before, after);
+            }
+        }
+
+        // Track frame types (the 3-bit mask covers both legacy and enhanced headers).
+        // Sequence headers carry the keyframe type too but aren't frames
+        let is_frame = self.video_analyzer.total_video_frames > frames_before;
+        let is_keyframe = is_frame && ((data[0] >> 4) & 0x07) == 1;
+
+        // B-frames, by slice type where the analyzer parses it, else by reordering
+        if self.video_analyzer.b_frame_count > b_frames_before {
+            self.diagnostics.record_b_frame();
+        }
+
+        self.stats.record_video_frame(byte_count, is_keyframe);
+        if is_frame {
+            self.diagnostics.record_media_frame();
+        }
+        // After the stats so the interval ending at this keyframe is recorded
+        if is_keyframe {
+            self.diagnostics.record_keyframe(self.stats.keyframe_interval_secs);
+        }
+
+        // Frames only; sequence headers share the first frame's timestamp
+        if is_frame {
+            let declared_fps = self.diagnostics.metadata.as_ref().and_then(|m| m.framerate);
+            self.stats.record_video_timestamp(timestamp, self.video_analyzer.fps.or(declared_fps));
+        }
+    }
+
+    pub fn handle_audio(&mut self, timestamp: u32, data: &[u8]) {
+        let byte_count = data.len();
+
+        // Track diagnostics
+        self.diagnostics.record_audio_timestamp(timestamp);
+
+        // Check for AAC sequence header
+        let is_aac_seq_header = data.len() >= 2
+            && ((data[0] >> 4) & 0x0F) == 10
+            && data[1] == 0;
+
+        // Enhanced RTMP sequence start (SoundFormat 9, packet type 0)
+        let is_ex_seq_start = data.len() >= 5 && data[0] == 0x90;
+
+        if is_aac_seq_header || (is_ex_seq_start && &data[1..5] == b"mp4a") {
+            self.diagnostics.record_aac_seq_header();
+        }
+
+        // Process audio
+        let is_seq_header = is_aac_seq_header || is_ex_seq_start;
+        let config_before = (is_seq_header && self.audio_analyzer.asc_received)
+            .then(|| self.audio_analyzer.config_summary());
+        self.audio_analyzer.process(data, timestamp);
+
+        if let Some(before) = config_before {
+            let after = self.audio_analyzer.config_summary();
+            if after != before {
+                self.diagnostics.record_audio_config_change(before, after);
+            }
+        }
+        if matches!(self.audio_analyzer.codec, Some(AudioCodec::Mp3 | AudioCodec::Mp3_8k)) {
+            self.diagnostics.record_mp3_audio();
+        }
+
+        if !is_aac_seq_header && !is_ex_seq_start {
+            self.stats.record_audio_frame(byte_count);
+            self.diagnostics.record_media_frame();
+        }
+    }
+
+    pub fn run_checks(&mut self) -> Vec<Diagnostic> {
+        let video = &self.video_analyzer;
+        let audio = &self.audio_analyzer;
+        let stats = &self.stats;
+        self.diagnostics.check_all(&CheckInputs {
+            video_codec: video.codec,
+            video_width: video.width,
+            video_height: video.height,
+            video_profile: video.profile.as_deref(),
+            sample_aspect_ratio: video.sample_aspect_ratio,
+            pixel_format: video.pixel_format,
+            interlace_mode: video.interlace_mode(),
+            avc_parameter_sets: video.avc_parameter_sets,
+            audio_sample_rate: audio.effective_sample_rate(),
+            audio_channels: audio.effective_channels(),
+            aac_profile: audio.aac_description().as_deref(),
+            audio_frame_duration_ms: audio.frame_duration_ms(),
+            aac_bitrate_kbps: audio.aac_bitrate_kbps().zip(audio.nominal_aac_bitrate_kbps()),
+            current_keyframe_interval: stats.keyframe_interval_secs,
+            video_bitrate_kbps: stats.current_video_bitrate_kbps(),
+            audio_bitrate_kbps: stats.current_audio_bitrate_kbps(),
+            max_reorder_depth: video.max_reorder_depth(),
+            max_composition_offset: video.max_composition_offset,
+            b_pyramid: video.b_pyramid,
+            gop_length: video.gop_length,
+            fps: video.fps.or_else(|| stats.current_fps()),
+            non_idr_keyframes: video.non_idr_keyframe_count,
+            recovery_point_keyframes: video.recovery_point_keyframe_count,
+            malformed_access_units: video.malformed_access_unit_count,
+            dropped_frame_rate: stats.dropped_frame_rate(),
+            off_cadence_rate: stats.off_cadence_rate(),
+            peak_video_bitrate_kbps: stats.peak_video_bitrate_kbps,
+            network_jitter_ms: stats.network_jitter_ms(),
+        })
+    }
+
+    /// Run the checks even if they ran moments ago, for a final verdict.
+    pub fn check_now(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.force_next_check();
+        self.run_checks()
+    }
+
+    pub fn report<'a>(&'a self, app_name: &'a str, stream_key: &'a str, diagnostics: &'a [Diagnostic]) -> StreamReport<'a> {
+        StreamReport {
+            app_name,
+            stream_key,
+            encoder: self.encoder_name.as_deref(),
+            stats: &self.stats,
+            video: &self.video_analyzer,
+            audio: &self.audio_analyzer,
+            metadata: self.diagnostics.metadata.as_ref(),
+            startup: self.diagnostics.startup_timeline(),
+            keyframe_intervals: self.diagnostics.keyframe_interval_stats(),
+            object_encoding: self.diagnostics.object_encoding,
+            connect: None,
+            chunk_streams: &[],
+            health: self.diagnostics.health(),
+            diagnostics,
+        }
+    }
+
+    /// Copy out the current state of every analyzer, with the diagnostics
+    /// from the last check pass.
+    pub fn snapshot(&self) -> StreamSnapshot {
+        let video = &self.video_analyzer;
+        let audio = &self.audio_analyzer;
+        let stats = &self.stats;
+        StreamSnapshot {
+            encoder: self.encoder_name.clone(),
+            duration_secs: stats.duration_secs,
+            video_codec: video.codec,
+            width: video.width,
+            height: video.height,
+            video_profile: video.profile.clone(),
+            video_level: video.level.clone(),
+            pixel_format: video.pixel_format,
+            fps: video.fps.or_else(|| stats.current_fps()),
+            keyframe_interval_secs: stats.keyframe_interval_secs,
+            keyframe_intervals: self.diagnostics.keyframe_interval_stats(),
+            gop_length: video.gop_length,
+            video_frames: video.total_video_frames,
+            keyframes: video.keyframe_count,
+            b_frames: video.b_frame_count,
+            b_pyramid: video.b_pyramid,
+            estimated_dropped_frames: stats.estimated_dropped_frames,
+            audio_codec: audio.codec,
+            audio_profile: audio.aac_description().or_else(|| audio.mp3_header.map(|h| h.to_string())),
+            sample_rate: audio.effective_sample_rate(),
+            channels: audio.effective_channels(),
+            audio_frames: audio.total_audio_frames,
+            video_bitrate_kbps: stats.current_video_bitrate_kbps(),
+            audio_bitrate_kbps: stats.current_audio_bitrate_kbps(),
+            peak_video_bitrate_kbps: stats.peak_video_bitrate_kbps,
+            total_video_bytes: stats.total_video_bytes,
+            total_audio_bytes: stats.total_audio_bytes,
+            rtt_ms: stats.rtt_ms,
+            ingress_kbps: stats.ingress_kbps,
+            metadata: self.diagnostics.metadata.clone(),
+            startup: self.diagnostics.startup_timeline(),
+            health: self.diagnostics.health().clone(),
+            diagnostics: self.diagnostics.latest().to_vec(),
+        }
+    }
+}
+
+fn property_str<'a>(properties: &'a [(String, Amf0Value)], key: &str) -> Option<&'a str> {
+    properties.iter().find(|(k, _)| k == key).and_then(|(_, v)| v.as_str())
+}
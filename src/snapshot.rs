@@ -0,0 +1,53 @@
+use crate::diagnostics::{Diagnostic, HealthScore, KeyframeIntervalStats, StartupTimeline};
+use crate::flv::audio::AudioCodec;
+use crate::flv::metadata::StreamMetadata;
+use crate::flv::video::{PixelFormat, VideoCodec};
+
+/// Point-in-time copy of everything known about a stream, detached from the
+/// analyzers so it can be kept or sent to another thread.
+#[derive(Debug, Clone)]
+pub struct StreamSnapshot {
+    pub encoder: Option<String>,
+    pub duration_secs: f64,
+
+    // Video
+    pub video_codec: Option<VideoCodec>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_profile: Option<String>,
+    pub video_level: Option<String>,
+    pub pixel_format: Option<PixelFormat>,
+    /// Frame rate from the SPS, else as measured
+    pub fps: Option<f64>,
+    pub keyframe_interval_secs: Option<f64>,
+    pub keyframe_intervals: Option<KeyframeIntervalStats>,
+    pub gop_length: Option<u32>,
+    pub video_frames: u64,
+    pub keyframes: u64,
+    pub b_frames: u64,
+    pub b_pyramid: bool,
+    pub estimated_dropped_frames: u64,
+
+    // Audio
+    pub audio_codec: Option<AudioCodec>,
+    /// AAC profile (with SBR rates) or MP3 frame header summary
+    pub audio_profile: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub audio_frames: u64,
+
+    // Throughput
+    pub video_bitrate_kbps: Option<f64>,
+    pub audio_bitrate_kbps: Option<f64>,
+    pub peak_video_bitrate_kbps: Option<f64>,
+    pub total_video_bytes: u64,
+    pub total_audio_bytes: u64,
+    pub rtt_ms: Option<f64>,
+    pub ingress_kbps: Option<f64>,
+
+    pub metadata: Option<StreamMetadata>,
+    pub startup: StartupTimeline,
+    pub health: HealthScore,
+    /// Results of the most recent check pass
+    pub diagnostics: Vec<Diagnostic>,
+}
@@ -1,23 +1,70 @@
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
+/// Samples kept for the TUI history graphs (one per second)
+pub const HISTORY_LEN: usize = 60;
+/// Span of the bitrate and fps windows unless configured otherwise
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(2);
+/// Frame intervals the variable frame rate measure looks back over
+const CADENCE_WINDOW: usize = 120;
+/// Intervals needed before the measure is reported
+const CADENCE_MIN_SAMPLES: usize = 30;
+/// How far an interval may stray from the typical one and still be on cadence
+const CADENCE_TOLERANCE: f64 = 0.25;
+
 pub struct StreamStats {
     pub stream_start: Option<Instant>,
     pub duration_secs: f64,
 
-    // Rolling window for FPS
+    // Rolling window for FPS, also the one drops are counted over
     video_frame_times: VecDeque<Instant>,
 
-    // Rolling window for bitrate
+    // Rolling window for bitrate, with running byte sums so sampling the peak is cheap
     video_byte_window: VecDeque<(Instant, usize)>,
     audio_byte_window: VecDeque<(Instant, usize)>,
+    video_window_bytes: usize,
+    audio_window_bytes: usize,
+
+    // Highest rolling bitrate seen over the session
+    pub peak_video_bitrate_kbps: Option<f64>,
+    pub peak_audio_bitrate_kbps: Option<f64>,
 
-    window_duration: Duration,
+    bitrate_window: Duration,
+    fps_window: Duration,
+
+    // Replaces the wall clock when replaying a recording
+    clock: Option<Instant>,
+    // When the frame being recorded arrived, if not now
+    arrival_clock: Option<Instant>,
 
     // Keyframe interval tracking
     last_keyframe_time: Option<Instant>,
     pub keyframe_interval_secs: Option<f64>,
 
+    // Dropped frame estimation from video timestamp gaps
+    last_video_ts: Option<u32>,
+    pub estimated_dropped_frames: u64,
+    dropped_frame_window: VecDeque<(Instant, u64)>,
+
+    // Recent frame intervals in ms, for the variable frame rate measure
+    cadence_window: VecDeque<f64>,
+
+    // Wall-clock arrival and timestamp of the first and the last video frame
+    // (live only); transit times are measured from the first
+    arrival_origin: Option<(Instant, u32)>,
+    last_arrival: Option<Instant>,
+    // Recent (arrival interval, relative transit time) pairs in ms
+    arrival_window: VecDeque<(f64, f64)>,
+
+    // Once-a-second history for the sparklines, oldest first
+    pub video_bitrate_history: VecDeque<f64>,
+    pub fps_history: VecDeque<f64>,
+
+    // Round trip of the last answered ping, when keepalive pings are on
+    pub rtt_ms: Option<f64>,
+    // Everything the connection receives per second, framing and all (live only)
+    pub ingress_kbps: Option<f64>,
+
     // Cumulative
     pub total_video_bytes: u64,
     pub total_audio_bytes: u64,
@@ -31,40 +78,87 @@ impl StreamStats {
             video_frame_times: VecDeque::with_capacity(256),
             video_byte_window: VecDeque::with_capacity(256),
             audio_byte_window: VecDeque::with_capacity(256),
-            window_duration: Duration::from_secs(2),
+            video_window_bytes: 0,
+            audio_window_bytes: 0,
+            peak_video_bitrate_kbps: None,
+            peak_audio_bitrate_kbps: None,
+            bitrate_window: DEFAULT_WINDOW,
+            fps_window: DEFAULT_WINDOW,
+            clock: None,
+            arrival_clock: None,
             last_keyframe_time: None,
             keyframe_interval_secs: None,
+            last_video_ts: None,
+            estimated_dropped_frames: 0,
+            dropped_frame_window: VecDeque::new(),
+            cadence_window: VecDeque::with_capacity(CADENCE_WINDOW),
+            arrival_origin: None,
+            last_arrival: None,
+            arrival_window: VecDeque::with_capacity(CADENCE_WINDOW),
+            video_bitrate_history: VecDeque::with_capacity(HISTORY_LEN),
+            fps_history: VecDeque::with_capacity(HISTORY_LEN),
+            rtt_ms: None,
+            ingress_kbps: None,
             total_video_bytes: 0,
             total_audio_bytes: 0,
         }
     }
 
+    /// Measure time from `now` instead of the wall clock from here on.
+    pub fn set_clock(&mut self, now: Instant) {
+        self.clock = Some(now);
+    }
+
+    /// Take `arrival` as the time the next video frames reached us. Without
+    /// it arrivals are timed by the wall clock, and not at all once
+    /// `set_clock` has replaced it, as media timestamps say nothing about
+    /// the network.
+    pub fn set_arrival_clock(&mut self, arrival: Instant) {
+        self.arrival_clock = Some(arrival);
+    }
+
+    /// Average bitrates over `window`; longer gives smoother readings.
+    pub fn set_bitrate_window(&mut self, window: Duration) {
+        self.bitrate_window = window;
+    }
+
+    /// Measure fps and drops over `window`; shorter reacts faster.
+    pub fn set_fps_window(&mut self, window: Duration) {
+        self.fps_window = window;
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.unwrap_or_else(Instant::now)
+    }
+
     pub fn record_video_frame(&mut self, byte_count: usize, is_keyframe: bool) {
-        let now = Instant::now();
+        let now = self.now();
         if self.stream_start.is_none() {
             self.stream_start = Some(now);
         }
 
         self.video_frame_times.push_back(now);
         self.video_byte_window.push_back((now, byte_count));
+        self.video_window_bytes += byte_count;
         self.total_video_bytes += byte_count as u64;
 
         // Trim old entries
-        let cutoff = now - self.window_duration;
+        let cutoff = now - self.fps_window;
         while self
             .video_frame_times
             .front()
-            .map_or(false, |t| *t < cutoff)
+            .is_some_and(|t| *t < cutoff)
         {
             self.video_frame_times.pop_front();
         }
-        while self
-            .video_byte_window
-            .front()
-            .map_or(false, |(t, _)| *t < cutoff)
-        {
+        let cutoff = now - self.bitrate_window;
+        while let Some((_, bytes)) = self.video_byte_window.front().filter(|(t, _)| *t < cutoff) {
+            self.video_window_bytes -= bytes;
             self.video_byte_window.pop_front();
         }
+        if let Some(kbps) = self.settled_bitrate_kbps(&self.video_byte_window, self.video_window_bytes) {
+            self.peak_video_bitrate_kbps = Some(self.peak_video_bitrate_kbps.map_or(kbps, |p| p.max(kbps)));
+        }
 
         if is_keyframe {
             if let Some(last_kf) = self.last_keyframe_time {
@@ -77,24 +171,140 @@ impl StreamStats {
     }
 
     pub fn record_audio_frame(&mut self, byte_count: usize) {
-        let now = Instant::now();
+        let now = self.now();
         if self.stream_start.is_none() {
             self.stream_start = Some(now);
         }
 
         self.audio_byte_window.push_back((now, byte_count));
+        self.audio_window_bytes += byte_count;
         self.total_audio_bytes += byte_count as u64;
 
-        let cutoff = now - self.window_duration;
+        let cutoff = now - self.bitrate_window;
+        while let Some((_, bytes)) = self.audio_byte_window.front().filter(|(t, _)| *t < cutoff) {
+            self.audio_window_bytes -= bytes;
+            self.audio_byte_window.pop_front();
+        }
+        if let Some(kbps) = self.settled_bitrate_kbps(&self.audio_byte_window, self.audio_window_bytes) {
+            self.peak_audio_bitrate_kbps = Some(self.peak_audio_bitrate_kbps.map_or(kbps, |p| p.max(kbps)));
+        }
+
+        self.duration_secs = now.duration_since(self.stream_start.unwrap()).as_secs_f64();
+    }
+
+    /// Count frames missing between consecutive video timestamps, given the
+    /// nominal framerate. Gaps under 1.5 frame intervals are treated as jitter.
+    pub fn record_video_timestamp(&mut self, timestamp: u32, fps: Option<f64>) {
+        let now = self.now();
+        let last = self.last_video_ts.replace(timestamp);
+
+        if let (Some(last), Some(fps)) = (last, fps)
+            && fps > 0.0
+            && timestamp > last
+        {
+            let interval_ms = 1000.0 / fps;
+            let gap = (timestamp - last) as f64;
+            if gap >= interval_ms * 1.5 {
+                let missing = (gap / interval_ms).round() as u64 - 1;
+                self.estimated_dropped_frames += missing;
+                self.dropped_frame_window.push_back((now, missing));
+            }
+        }
+
+        if let Some(last) = last
+            && timestamp >= last
+        {
+            if self.cadence_window.len() == CADENCE_WINDOW {
+                self.cadence_window.pop_front();
+            }
+            self.cadence_window.push_back((timestamp - last) as f64);
+        }
+
+        // Arrival timing only means something against real arrival times
+        if let Some(arrival) = self.arrival_clock.or(self.clock.is_none().then_some(now)) {
+            // Timestamps that jump back start the measure over
+            if self.arrival_origin.is_none_or(|(_, origin_ts)| timestamp < origin_ts) {
+                self.arrival_origin = Some((arrival, timestamp));
+                self.arrival_window.clear();
+            }
+            let (origin, origin_ts) = self.arrival_origin.unwrap();
+            let transit_ms = arrival.duration_since(origin).as_secs_f64() * 1000.0 - (timestamp - origin_ts) as f64;
+            if let Some(last) = self.last_arrival.replace(arrival) {
+                if self.arrival_window.len() == CADENCE_WINDOW {
+                    self.arrival_window.pop_front();
+                }
+                self.arrival_window.push_back((arrival.duration_since(last).as_secs_f64() * 1000.0, transit_ms));
+            }
+        }
+
+        let cutoff = now - self.fps_window;
         while self
-            .audio_byte_window
+            .dropped_frame_window
             .front()
-            .map_or(false, |(t, _)| *t < cutoff)
+            .is_some_and(|(t, _)| *t < cutoff)
         {
-            self.audio_byte_window.pop_front();
+            self.dropped_frame_window.pop_front();
         }
+    }
 
-        self.duration_secs = now.duration_since(self.stream_start.unwrap()).as_secs_f64();
+    /// Share of expected frames that went missing over the rolling window.
+    pub fn dropped_frame_rate(&self) -> Option<f64> {
+        let dropped: u64 = self.dropped_frame_window.iter().map(|(_, n)| *n).sum();
+        let received = self.video_frame_times.len() as u64;
+        if received + dropped == 0 {
+            return None;
+        }
+        Some(dropped as f64 / (received + dropped) as f64)
+    }
+
+    /// Share of recent frame intervals that strayed from the typical one.
+    /// The median is the reference rather than the declared frame rate, which
+    /// encoders get wrong, and gaps of whole missing frames are left to the
+    /// drop estimate.
+    pub fn off_cadence_rate(&self) -> Option<f64> {
+        if self.cadence_window.len() < CADENCE_MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.cadence_window.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let median = sorted[sorted.len() / 2];
+        if median <= 0.0 {
+            return None;
+        }
+        // Allow for timestamps rounded to the millisecond
+        let tolerance = (median * CADENCE_TOLERANCE).max(1.0);
+        let intervals = self.cadence_window.iter().filter(|gap| **gap < median * 1.5);
+        let (mut total, mut off) = (0usize, 0usize);
+        for gap in intervals {
+            total += 1;
+            if (gap - median).abs() > tolerance {
+                off += 1;
+            }
+        }
+        (total > 0).then(|| off as f64 / total as f64)
+    }
+
+    /// How unevenly video frames reach us, in ms: the mean absolute deviation
+    /// of recent frames' transit times, arrival time less timestamp. Measuring
+    /// against the timestamps leaves out the encoder's own cadence, which
+    /// `off_cadence_rate` covers, so this is the network and buffering
+    /// between encoder and server.
+    pub fn network_jitter_ms(&self) -> Option<f64> {
+        if self.arrival_window.len() < CADENCE_MIN_SAMPLES {
+            return None;
+        }
+        let count = self.arrival_window.len() as f64;
+        let mean = self.arrival_window.iter().map(|(_, transit)| transit).sum::<f64>() / count;
+        Some(self.arrival_window.iter().map(|(_, transit)| (transit - mean).abs()).sum::<f64>() / count)
+    }
+
+    /// Shortest and longest recent gap between video frame arrivals, in ms.
+    pub fn arrival_interval_range_ms(&self) -> Option<(f64, f64)> {
+        if self.arrival_window.len() < CADENCE_MIN_SAMPLES {
+            return None;
+        }
+        let intervals = self.arrival_window.iter().map(|(interval, _)| *interval);
+        Some((intervals.clone().fold(f64::INFINITY, f64::min), intervals.fold(0.0, f64::max)))
     }
 
     /// Current video FPS over the rolling window.
@@ -111,17 +321,43 @@ impl StreamStats {
         Some((self.video_frame_times.len() - 1) as f64 / elapsed)
     }
 
+    /// Append the current video bitrate and fps to the history. Called from the
+    /// display tick rather than per frame.
+    pub fn sample_history(&mut self) {
+        let kbps = self.current_video_bitrate_kbps().unwrap_or(0.0);
+        let fps = self.current_fps().unwrap_or(0.0);
+        push_sample(&mut self.video_bitrate_history, kbps);
+        push_sample(&mut self.fps_history, fps);
+    }
+
     /// Video bitrate in kbps over the rolling window.
     pub fn current_video_bitrate_kbps(&self) -> Option<f64> {
-        self.rolling_bitrate_kbps(&self.video_byte_window)
+        self.rolling_bitrate_kbps(&self.video_byte_window, self.video_window_bytes)
     }
 
     /// Audio bitrate in kbps over the rolling window.
     pub fn current_audio_bitrate_kbps(&self) -> Option<f64> {
-        self.rolling_bitrate_kbps(&self.audio_byte_window)
+        self.rolling_bitrate_kbps(&self.audio_byte_window, self.audio_window_bytes)
+    }
+
+    /// Video bitrate in kbps averaged over the whole session.
+    pub fn average_video_bitrate_kbps(&self) -> Option<f64> {
+        self.average_bitrate_kbps(self.total_video_bytes)
+    }
+
+    /// Audio bitrate in kbps averaged over the whole session.
+    pub fn average_audio_bitrate_kbps(&self) -> Option<f64> {
+        self.average_bitrate_kbps(self.total_audio_bytes)
+    }
+
+    fn average_bitrate_kbps(&self, total_bytes: u64) -> Option<f64> {
+        if self.duration_secs < 0.001 {
+            return None;
+        }
+        Some((total_bytes as f64 * 8.0) / (self.duration_secs * 1000.0))
     }
 
-    fn rolling_bitrate_kbps(&self, window: &VecDeque<(Instant, usize)>) -> Option<f64> {
+    fn rolling_bitrate_kbps(&self, window: &VecDeque<(Instant, usize)>, total_bytes: usize) -> Option<f64> {
         if window.len() < 2 {
             return None;
         }
@@ -131,7 +367,153 @@ impl StreamStats {
         if elapsed < 0.001 {
             return None;
         }
-        let total_bytes: usize = window.iter().map(|(_, b)| *b).sum();
         Some((total_bytes as f64 * 8.0) / (elapsed * 1000.0))
     }
+
+    /// Rolling bitrate, but only once the window spans at least half its length;
+    /// the first few frames of a stream would otherwise register as a huge peak.
+    fn settled_bitrate_kbps(&self, window: &VecDeque<(Instant, usize)>, total_bytes: usize) -> Option<f64> {
+        let span = window.back()?.0.duration_since(window.front()?.0);
+        if span < self.bitrate_window / 2 {
+            return None;
+        }
+        self.rolling_bitrate_kbps(window, total_bytes)
+    }
+}
+
+impl Default for StreamStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn push_sample(history: &mut VecDeque<f64>, value: f64) {
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record a video frame with each timestamp, the clock following the
+    /// timestamps as in a recording.
+    fn play(stats: &mut StreamStats, start: Instant, timestamps: impl IntoIterator<Item = u32>, fps: f64) {
+        for timestamp in timestamps {
+            stats.set_clock(start + Duration::from_millis(timestamp as u64));
+            stats.record_video_frame(1000, false);
+            stats.record_video_timestamp(timestamp, Some(fps));
+        }
+    }
+
+    /// Timestamps of frames `frames` of a 30fps stream, rounded to the ms.
+    fn at_30fps(frames: impl IntoIterator<Item = u32>) -> Vec<u32> {
+        frames.into_iter().map(|frame| frame * 1000 / 30).collect()
+    }
+
+    #[test]
+    fn frames_missing_from_the_timestamps_are_counted_as_dropped() {
+        let mut stats = StreamStats::new();
+        let start = Instant::now();
+        assert_eq!(stats.dropped_frame_rate(), None);
+        // Frames 20 and 21, then 40 to 42 never arrive
+        play(&mut stats, start, at_30fps((0..60).filter(|frame| !matches!(frame, 20 | 21 | 40..=42))), 30.0);
+        assert_eq!(stats.estimated_dropped_frames, 5);
+        assert_eq!(stats.dropped_frame_rate(), Some(5.0 / 60.0));
+    }
+
+    #[test]
+    fn gaps_under_one_and_a_half_intervals_are_jitter() {
+        let mut stats = StreamStats::new();
+        let start = Instant::now();
+        // 25fps is 40ms a frame, so a 59ms gap is a late frame and 60ms a dropped one
+        play(&mut stats, start, [0, 40, 99, 139], 25.0);
+        assert_eq!(stats.estimated_dropped_frames, 0);
+        play(&mut stats, start, [199], 25.0);
+        assert_eq!(stats.estimated_dropped_frames, 1);
+        // Two and a half intervals round to two missing frames
+        play(&mut stats, start, [299], 25.0);
+        assert_eq!(stats.estimated_dropped_frames, 3);
+    }
+
+    /// Timestamps starting at 0 and separated by `intervals`.
+    fn from_intervals(intervals: impl IntoIterator<Item = u32>) -> Vec<u32> {
+        std::iter::once(0)
+            .chain(intervals.into_iter().scan(0, |timestamp, interval| {
+                *timestamp += interval;
+                Some(*timestamp)
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn constant_frame_rate_is_on_cadence() {
+        let mut stats = StreamStats::new();
+        let start = Instant::now();
+        play(&mut stats, start, at_30fps(0..20), 30.0);
+        assert_eq!(stats.off_cadence_rate(), None, "too few intervals yet");
+        // Rounded to the ms, the intervals alternate between 33 and 34
+        play(&mut stats, start, at_30fps(20..90), 30.0);
+        assert_eq!(stats.off_cadence_rate(), Some(0.0));
+    }
+
+    #[test]
+    fn variable_frame_rate_is_off_cadence() {
+        let mut stats = StreamStats::new();
+        let timestamps = from_intervals([33, 33, 45].repeat(20));
+        play(&mut stats, Instant::now(), timestamps, 30.0);
+        let rate = stats.off_cadence_rate().unwrap();
+        assert!((rate - 1.0 / 3.0).abs() < 1e-9, "{}", rate);
+    }
+
+    #[test]
+    fn whole_missing_frames_are_left_to_the_drop_estimate() {
+        let mut stats = StreamStats::new();
+        // Every tenth interval spans a missing frame
+        let mut intervals = [33; 10];
+        intervals[9] = 67;
+        play(&mut stats, Instant::now(), from_intervals(intervals.repeat(6)), 30.0);
+        assert_eq!(stats.off_cadence_rate(), Some(0.0));
+        assert_eq!(stats.estimated_dropped_frames, 6);
+    }
+
+    /// Video frames at 30fps, each arriving `delay_ms(frame)` after its
+    /// timestamp, with the media clock following the timestamps.
+    fn arrive(stats: &mut StreamStats, start: Instant, frames: u32, delay_ms: impl Fn(u32) -> u64) {
+        for frame in 0..frames {
+            let timestamp = frame * 1000 / 30;
+            stats.set_clock(start + Duration::from_millis(timestamp as u64));
+            stats.set_arrival_clock(start + Duration::from_millis(timestamp as u64 + delay_ms(frame)));
+            stats.record_video_timestamp(timestamp, Some(30.0));
+        }
+    }
+
+    #[test]
+    fn steady_arrivals_have_no_network_jitter() {
+        let mut stats = StreamStats::new();
+        // Always 80ms in transit: latency, but no jitter
+        arrive(&mut stats, Instant::now(), 90, |_| 80);
+        assert!(stats.network_jitter_ms().unwrap() < 1e-9);
+        assert_eq!(stats.arrival_interval_range_ms(), Some((33.0, 34.0)));
+    }
+
+    #[test]
+    fn bursty_arrivals_show_network_jitter() {
+        let mut stats = StreamStats::new();
+        // Held back and delivered a second's worth of frames at a time
+        arrive(&mut stats, Instant::now(), 90, |frame| 1000 - (frame % 30) as u64 * 1000 / 30);
+        let jitter = stats.network_jitter_ms().unwrap();
+        assert!(jitter > crate::diagnostics::NETWORK_JITTER_LIMIT_MS, "{}", jitter);
+        let (shortest, longest) = stats.arrival_interval_range_ms().unwrap();
+        assert!(shortest == 0.0 && longest > 900.0, "{} to {}", shortest, longest);
+    }
+
+    #[test]
+    fn media_clock_alone_gives_no_arrival_timing() {
+        let mut stats = StreamStats::new();
+        play(&mut stats, Instant::now(), at_30fps(0..90), 30.0);
+        assert_eq!(stats.network_jitter_ms(), None);
+    }
 }
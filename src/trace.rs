@@ -0,0 +1,17 @@
+//! Structured events through the `tracing` crate, compiled in with the
+//! `tracing` feature. Without it the macros expand to nothing, so call sites
+//! don't need their own `cfg`.
+
+/// `trace_event!(warn, field = value, "message {}", arg)` forwards to
+/// `tracing::warn!` when the feature is on.
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        tracing::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {};
+}